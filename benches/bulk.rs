@@ -0,0 +1,51 @@
+//! Compares the VM running programs dominated by scans and range clears
+//! with and without the `bulk` pass. Run with `cargo bench --bench bulk`.
+
+extern crate brainfuck;
+
+use std::time::{Duration, Instant};
+
+use brainfuck::optimize::Registry;
+use brainfuck::vm::Vm;
+use brainfuck::Dialect;
+
+const RUNS: usize = 5;
+
+/// Clears 1000 neighbouring cells, 10000 times over.
+fn clears() -> String {
+    format!("{}[>{}<-]>[>{}{}-]", "+".repeat(100), "+".repeat(100),
+            "[-]>".repeat(1000), "<".repeat(1001))
+}
+
+/// Walks across 10000 nonzero cells and back, 1000 times over.
+fn scans() -> String {
+    format!("{}{}<<[<]<{}[>>[>]<[<]<-]", ">>", "+>".repeat(10000),
+            "+".repeat(1000))
+}
+
+fn time(source: &str, passes: &[&str]) -> Duration {
+    let program = Dialect::Brainfuck.parse(source).unwrap();
+    let program = Registry::default().pipeline(passes).unwrap().run(program);
+
+    (0..RUNS).map(|_| {
+        let start = Instant::now();
+
+        Vm::new(&program, std::io::sink(), std::io::empty()).run().unwrap();
+
+        start.elapsed()
+    }).min().unwrap()
+}
+
+fn main() {
+    let without = ["rle", "clear", "mul", "dce", "offset"];
+    let with = ["rle", "clear", "mul", "dce", "offset", "bulk"];
+
+    for &(name, ref source) in &[("clears", clears()), ("scans", scans())] {
+        let before = time(source, &without);
+        let after = time(source, &with);
+
+        println!("{:<8} {:>10.2?} without bulk ops, {:>10.2?} with ({:.1}x)",
+                 name, before, after,
+                 before.as_secs_f64() / after.as_secs_f64());
+    }
+}
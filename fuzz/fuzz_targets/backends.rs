@@ -0,0 +1,36 @@
+//! Runs generated programs on every backend, failing on any divergence
+//! from the unoptimized interpreter.
+
+#![no_main]
+
+extern crate brainfuck;
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use brainfuck::fuzz::Source;
+use brainfuck::optimize::Registry;
+use brainfuck::Dialect;
+
+// Programs that the evaluator can't finish in this many steps are skipped,
+// since they might never halt.
+const MAX_STEPS: u64 = 100_000;
+
+fuzz_target!(|case: (Source, Vec<u8>)| {
+    let (source, input) = case;
+    let program = Dialect::Brainfuck.parse(&source.0).unwrap();
+
+    if !brainfuck::eval::partially_evaluate(&program, &input, MAX_STEPS)
+        .finished {
+        return;
+    }
+
+    for level in 1..4 {
+        let optimized = Registry::default().preset(level).run(program.clone());
+
+        if let Err(divergence) = brainfuck::verify::verify(&program,
+                                                           &optimized,
+                                                           &input) {
+            panic!("-O{}: {}", level, divergence);
+        }
+    }
+});
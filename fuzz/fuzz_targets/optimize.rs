@@ -0,0 +1,29 @@
+//! Runs every pass and preset over generated programs, checking that none
+//! panic and that optimized programs survive a trip through `.bfc`.
+
+#![no_main]
+
+extern crate brainfuck;
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use brainfuck::fuzz::Source;
+use brainfuck::optimize::Registry;
+use brainfuck::Dialect;
+
+fuzz_target!(|source: Source| {
+    let program = Dialect::Brainfuck.parse(&source.0).unwrap();
+    let registry = Registry::default();
+
+    for name in registry.names() {
+        registry.pipeline(&[name]).unwrap().run(program.clone());
+    }
+
+    for level in 0..4 {
+        let optimized = registry.preset(level).run(program.clone());
+        let encoded = brainfuck::bfc::encode(&optimized, &source.0);
+
+        assert_eq!(brainfuck::bfc::decode(&encoded).unwrap(),
+                   (optimized, source.0.clone()));
+    }
+});
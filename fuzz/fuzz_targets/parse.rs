@@ -0,0 +1,22 @@
+//! Feeds raw bytes to every frontend, which must reject bad input with an
+//! error rather than a panic.
+
+#![no_main]
+
+extern crate brainfuck;
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use brainfuck::Dialect;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        for &name in Dialect::NAMES {
+            let _ = Dialect::from_name(name).unwrap().parse(source);
+        }
+
+        let _ = brainfuck::asm::assemble(source, std::path::Path::new("fuzz"));
+    }
+
+    let _ = brainfuck::bfc::decode(data);
+});
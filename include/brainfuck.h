@@ -0,0 +1,94 @@
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#ifndef BRAINFUCK_H
+#define BRAINFUCK_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * The instruction ran and there are more to go.
+ */
+#define BF_OK 0
+
+/**
+ * The program has finished.
+ */
+#define BF_DONE 1
+
+/**
+ * The program stopped because reading or writing failed, or it ran out of
+ * input.
+ */
+#define BF_ERROR -1
+
+/**
+ * A program loaded into an interpreter, opaque to C.
+ */
+typedef struct Bf Bf;
+
+/**
+ * Returns the next byte of input, or a negative number at the end of it.
+ */
+typedef int (*BfReadFn)(void *user);
+
+/**
+ * Writes `length` bytes of output, returning a negative number if that
+ * failed.
+ */
+typedef int (*BfWriteFn)(void *user, const uint8_t *data, size_t length);
+
+/**
+ * Parses `length` bytes of brainfuck at `source` and optimizes it at the
+ * default level. Returns null if the source isn't valid UTF-8 or doesn't
+ * parse. Until callbacks are set, the program reads stdin and writes
+ * stdout.
+ *
+ * # Safety
+ *
+ * `source` must point to `length` readable bytes.
+ */
+Bf *bf_new(const char *source, size_t length);
+
+/**
+ * Sends the program's input and output through `read` and `write`, which
+ * are passed `user` each time they are called. Either may be null to go
+ * back to stdin or stdout.
+ *
+ * # Safety
+ *
+ * `bf` must have come from `bf_new` and not yet been freed. The callbacks
+ * must be safe to call with `user` for as long as they are set.
+ */
+void bf_set_io_callbacks(Bf *bf, BfReadFn read, BfWriteFn write, void *user);
+
+/**
+ * Runs one instruction, returning `BF_OK`, or `BF_DONE` if the program had
+ * already finished, or `BF_ERROR`.
+ *
+ * # Safety
+ *
+ * `bf` must have come from `bf_new` and not yet been freed.
+ */
+int bf_step(Bf *bf);
+
+/**
+ * Runs the program until it finishes, returning `BF_DONE`, or `BF_ERROR`
+ * if it stopped early.
+ *
+ * # Safety
+ *
+ * `bf` must have come from `bf_new` and not yet been freed.
+ */
+int bf_run(Bf *bf);
+
+/**
+ * Frees a program. Does nothing if `bf` is null.
+ *
+ * # Safety
+ *
+ * `bf` must be null or have come from `bf_new` and not yet been freed.
+ */
+void bf_free(Bf *bf);
+
+#endif  /* BRAINFUCK_H */
@@ -0,0 +1,137 @@
+//! `bf!`, which parses and optimizes brainfuck while the Rust around it
+//! compiles, so a malformed program is a compile error and nothing is
+//! parsed at run time.
+//!
+//! With just the source, `bf!` is a `brainfuck::Program`. Given input too,
+//! anything that is `AsRef<[u8]>`, it runs the program on it and is the
+//! `Vec<u8>` of its output, ending early if the program runs out of input:
+//!
+//! ```
+//! extern crate brainfuck;
+//! #[macro_use]
+//! extern crate brainfuck_macros;
+//!
+//! # fn main() {
+//! let program: brainfuck::Program = bf!("+[,.]");
+//! let output = bf!(",[.,]", "hello");
+//!
+//! assert_eq!(program.nodes.len(), 2);
+//! assert_eq!(output, b"hello");
+//! # }
+//! ```
+
+extern crate brainfuck;
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use brainfuck::{Dialect, Node, Op};
+use proc_macro2::TokenStream;
+use syn::parse::{Parse, ParseStream};
+use syn::{Expr, LitStr, Token};
+
+struct Invocation {
+    source: LitStr,
+    input: Option<Expr>,
+}
+
+impl Parse for Invocation {
+    fn parse(stream: ParseStream) -> syn::Result<Invocation> {
+        let source = stream.parse()?;
+        let input = if stream.is_empty() {
+            None
+        } else {
+            stream.parse::<Token![,]>()?;
+
+            Some(stream.parse()?)
+        };
+
+        // Allows a trailing comma.
+        if !stream.is_empty() {
+            stream.parse::<Token![,]>()?;
+        }
+
+        Ok(Invocation { source, input })
+    }
+}
+
+fn op(op: &Op) -> TokenStream {
+    match *op {
+        Op::Add(n) => quote!(Add(#n)),
+        Op::Move(n) => quote!(Move(#n)),
+        Op::Output => quote!(Output),
+        Op::Input => quote!(Input),
+        Op::Random => quote!(Random),
+        Op::Fork => quote!(Fork),
+        Op::SwitchTape(n) => quote!(SwitchTape(#n)),
+        Op::Loop(ref body) => {
+            let body = nodes(body);
+
+            quote!(Loop(#body))
+        }
+        Op::Clear => quote!(Clear),
+        Op::MulAdd { offset, factor } => quote!(MulAdd {
+            offset: #offset,
+            factor: #factor,
+        }),
+        Op::AddAt { offset, amount } => quote!(AddAt {
+            offset: #offset,
+            amount: #amount,
+        }),
+        Op::Print(ref values) => quote!(Print(::std::vec![#(#values),*])),
+        Op::Scan(step) => quote!(Scan(#step)),
+        Op::ClearRange(extent) => quote!(ClearRange(#extent)),
+    }
+}
+
+/// An expression building `nodes`, spans included.
+fn nodes(nodes: &[Node]) -> TokenStream {
+    let nodes = nodes.iter().map(|node| {
+        let op = op(&node.op);
+        let (start, end) = (node.span.start, node.span.end);
+
+        quote!(::brainfuck::Node::new(::brainfuck::Op::#op,
+                                      ::brainfuck::Span::new(#start, #end)))
+    });
+
+    quote!(::std::vec![#(#nodes),*])
+}
+
+/// Parses and optimizes a brainfuck program at compile time. See the crate
+/// documentation.
+#[proc_macro]
+pub fn bf(tokens: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let Invocation { source, input } = syn::parse_macro_input!(tokens);
+    let text = source.value();
+    let program = match Dialect::Brainfuck.parse(&text) {
+        Ok(program) => brainfuck::optimize::optimize(program),
+        Err(e) => {
+            // The compiler adds its own `error: `.
+            let rendered = e.diagnostic().render("bf!", &text, false);
+            let message = rendered.trim_start_matches("error: ");
+
+            return syn::Error::new(source.span(), message)
+                .to_compile_error()
+                .into();
+        }
+    };
+    let nodes = nodes(&program.nodes);
+    let program = quote!(::brainfuck::Program::new(#nodes));
+
+    match input {
+        None => program.into(),
+        Some(input) => quote!({
+            let program = #program;
+            let mut output = ::std::vec::Vec::new();
+            let _ = ::brainfuck::vm::Vm::new(
+                &program,
+                &mut output,
+                ::std::convert::AsRef::<[u8]>::as_ref(&#input),
+            ).run();
+
+            output
+        }).into(),
+    }
+}
@@ -0,0 +1,304 @@
+use alloc::vec::Vec;
+
+use ir::{Node, Op, Program};
+
+/// Net effect of straight-line code made only of `Add` and `Move`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Effects {
+    /// Amount added to each cell relative to the starting pointer, in the
+    /// order the cells are first touched. Cells with a net change of zero
+    /// are omitted.
+    pub adds: Vec<(isize, i32)>,
+    pub movement: isize,
+}
+
+impl Effects {
+    pub fn at(&self, offset: isize) -> i32 {
+        self.adds.iter()
+            .find(|&&(o, _)| o == offset)
+            .map_or(0, |&(_, n)| n)
+    }
+}
+
+fn add(adds: &mut Vec<(isize, i32)>, offset: isize, amount: i32) {
+    match adds.iter_mut().find(|a| a.0 == offset) {
+        Some(a) => a.1 = a.1.wrapping_add(amount),
+        None => adds.push((offset, amount)),
+    }
+}
+
+/// Returns `None` if `nodes` contain anything other than `Add`, `AddAt` and
+/// `Move`.
+pub fn effects(nodes: &[Node]) -> Option<Effects> {
+    let mut adds: Vec<(isize, i32)> = Vec::new();
+    let mut movement = 0isize;
+
+    for node in nodes {
+        match node.op {
+            Op::Add(n) => add(&mut adds, movement, n),
+            Op::AddAt { offset, amount } => {
+                add(&mut adds, movement + offset, amount)
+            }
+            Op::Move(n) => movement += n,
+            _ => return None,
+        }
+    }
+
+    adds.retain(|&(_, n)| n != 0);
+
+    Some(Effects { adds, movement })
+}
+
+/// Whether every cell that straight-line `nodes` add to only ever moves in
+/// one direction, so that their net effect is the same whether arithmetic
+/// wraps, traps or saturates.
+pub fn is_monotonic(nodes: &[Node]) -> bool {
+    let mut signs: Vec<(isize, bool)> = Vec::new();
+    let mut movement = 0isize;
+
+    for node in nodes {
+        let (offset, amount) = match node.op {
+            Op::Add(n) => (movement, n),
+            Op::AddAt { offset, amount } => (movement + offset, amount),
+            Op::Move(n) => {
+                movement += n;
+                continue;
+            }
+            _ => return false,
+        };
+
+        match signs.iter().find(|s| s.0 == offset) {
+            Some(&(_, positive)) if positive != (amount > 0) => return false,
+            Some(_) => (),
+            None => signs.push((offset, amount > 0)),
+        }
+    }
+
+    true
+}
+
+/// How far the pointer moves when `nodes` run, if that is the same every
+/// time: any nested loop must return the pointer to where it started.
+pub fn net_movement(nodes: &[Node]) -> Option<isize> {
+    let mut movement = 0;
+
+    for node in nodes {
+        match node.op {
+            Op::Move(n) | Op::ClearRange(n) => movement += n,
+            Op::Scan(_) => return None,
+            Op::Loop(ref body) if net_movement(body)? != 0 => return None,
+            _ => (),
+        }
+    }
+
+    Some(movement)
+}
+
+pub fn is_balanced(body: &[Node]) -> bool {
+    net_movement(body) == Some(0)
+}
+
+/// Recognizes loops like `[->+++>+<<]` that run once per unit of the loop
+/// cell, adding a multiple of its value to other cells and leaving it zero.
+/// Returns the `(offset, factor)` pairs of the cells added to.
+pub fn multiply_loop(body: &[Node]) -> Option<Vec<(isize, i32)>> {
+    let effects = effects(body)?;
+
+    if effects.movement != 0 || effects.at(0) != -1 {
+        return None;
+    }
+
+    Some(effects.adds.into_iter().filter(|&(o, _)| o != 0).collect())
+}
+
+/// Recognizes `[-]` and `[+]`, which always leave the loop cell zero.
+pub fn is_clear_loop(body: &[Node]) -> bool {
+    match effects(body) {
+        Some(ref e) => e.movement == 0 && e.adds.len() == 1
+                       && (e.at(0) == 1 || e.at(0) == -1),
+        None => false,
+    }
+}
+
+/// The lowest and highest cells, relative to the loop cell, that a loop
+/// with this body reads or writes, if those are the same every time it
+/// runs: every loop in it must be balanced, and it must not scan or do
+/// I/O. The whole loop is then a function of those cells.
+pub fn footprint(body: &[Node]) -> Option<(isize, isize)> {
+    fn widen(span: &mut (isize, isize), offset: isize) {
+        span.0 = core::cmp::min(span.0, offset);
+        span.1 = core::cmp::max(span.1, offset);
+    }
+
+    // Returns where the pointer ends up, widening `span` to each cell
+    // touched on the way.
+    fn walk(nodes: &[Node], start: isize, span: &mut (isize, isize))
+    -> Option<isize> {
+        let mut position = start;
+
+        for node in nodes {
+            let touched = match node.op {
+                Op::Add(_) | Op::Clear => position,
+                Op::Move(n) => {
+                    position = position.checked_add(n)?;
+                    continue;
+                }
+                Op::AddAt { offset, .. } => position.checked_add(offset)?,
+                Op::MulAdd { offset, .. } => {
+                    widen(span, position);
+
+                    position.checked_add(offset)?
+                }
+                Op::ClearRange(extent) => {
+                    widen(span, position);
+                    position = position.checked_add(extent)?;
+
+                    position
+                }
+                Op::Loop(ref body) => {
+                    if walk(body, position, span)? != position {
+                        return None;
+                    }
+
+                    position
+                }
+                Op::Output | Op::Input | Op::Random | Op::Fork
+                | Op::SwitchTape(_) | Op::Print(_) | Op::Scan(_) => {
+                    return None;
+                }
+            };
+
+            widen(span, touched);
+        }
+
+        Some(position)
+    }
+
+    let mut span = (0, 0);
+
+    if walk(body, 0, &mut span)? != 0 {
+        return None;
+    }
+
+    Some(span)
+}
+
+/// Statistics about a whole program, as reported by `bfi analyze`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// How many of each kind of op there are, by the name `ir::dump` gives
+    /// it, in the order the kinds are first seen.
+    pub counts: Vec<(&'static str, usize)>,
+    /// How deeply loops nest, 0 if there are none.
+    pub max_depth: usize,
+    /// Loops that return the pointer to where it started each iteration.
+    pub balanced_loops: usize,
+    pub unbalanced_loops: usize,
+    /// The lowest and highest cells the program touches, relative to the
+    /// first. Once the pointer moves by an amount that depends on the tape,
+    /// this only covers the cells touched before.
+    pub span: (isize, isize),
+    /// Whether `span` covers every cell the program could touch.
+    pub span_exact: bool,
+    pub reads_input: bool,
+}
+
+fn kind(op: &Op) -> &'static str {
+    match *op {
+        Op::Add(_) => "add",
+        Op::Move(_) => "move",
+        Op::Output => "output",
+        Op::Input => "input",
+        Op::Random => "random",
+        Op::Fork => "fork",
+        Op::SwitchTape(_) => "switch-tape",
+        Op::Loop(_) => "loop",
+        Op::Clear => "clear",
+        Op::MulAdd { .. } => "mul-add",
+        Op::AddAt { .. } => "add-at",
+        Op::Print(_) => "print",
+        Op::Scan(_) => "scan",
+        Op::ClearRange(_) => "clear-range",
+    }
+}
+
+impl Report {
+    fn count(&mut self, op: &Op) {
+        let kind = kind(op);
+
+        match self.counts.iter_mut().find(|c| c.0 == kind) {
+            Some(c) => c.1 += 1,
+            None => self.counts.push((kind, 1)),
+        }
+    }
+
+    fn touch(&mut self, position: Option<isize>, offset: isize) {
+        if let Some(p) = position {
+            self.span = (core::cmp::min(self.span.0, p + offset),
+                         core::cmp::max(self.span.1, p + offset));
+        }
+    }
+
+    /// Walks `nodes` starting with the pointer at `position`, if known, and
+    /// returns where it ends up.
+    fn walk(&mut self, nodes: &[Node], depth: usize,
+            mut position: Option<isize>) -> Option<isize> {
+        for node in nodes {
+            self.count(&node.op);
+
+            match node.op {
+                Op::Move(n) => position = position.map(|p| p + n),
+                Op::Input => {
+                    self.reads_input = true;
+                    self.touch(position, 0);
+                }
+                Op::AddAt { offset, .. } | Op::MulAdd { offset, .. } => {
+                    self.touch(position, 0);
+                    self.touch(position, offset);
+                }
+                Op::ClearRange(extent) => {
+                    self.touch(position, 0);
+                    self.touch(position, extent);
+                    position = position.map(|p| p + extent);
+                }
+                Op::Scan(_) => {
+                    self.touch(position, 0);
+                    position = None;
+                }
+                Op::Loop(ref body) => {
+                    self.max_depth = core::cmp::max(self.max_depth,
+                                                    depth + 1);
+                    self.touch(position, 0);
+
+                    if is_balanced(body) {
+                        self.balanced_loops += 1;
+                        self.walk(body, depth + 1, position);
+                    } else {
+                        self.unbalanced_loops += 1;
+                        self.walk(body, depth + 1, None);
+                        position = None;
+                    }
+                }
+                Op::Fork => {
+                    self.touch(position, 0);
+                    self.touch(position, 1);
+                }
+                // The span only covers the first tape.
+                Op::SwitchTape(_) => position = None,
+                _ => self.touch(position, 0),
+            }
+        }
+
+        position
+    }
+}
+
+/// Counts what `program` is made of and works out, without running it, how
+/// much of the tape it uses.
+pub fn report(program: &Program) -> Report {
+    let mut report = Report::default();
+
+    report.span_exact = report.walk(&program.nodes, 0, Some(0)).is_some();
+
+    report
+}
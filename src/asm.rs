@@ -0,0 +1,450 @@
+//! An assembler for a small macro language that compiles to brainfuck.
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! include "lib.bfa"        ; paste another file in place
+//! cell counter = 0         ; name tape cell 0
+//! cell tmp                 ; name the next unused cell
+//! label clear { [-] }      ; define a named block of code
+//! @counter +*8             ; move to `counter`, then add 8
+//! [ @tmp +*9 @counter - ]
+//! @tmp . clear
+//! ```
+//!
+//! Moving to a named cell requires the assembler to know where the pointer
+//! is, so `@name` is an error after a loop whose body does not return the
+//! pointer to where it started.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use sourcemap::SourceMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError { }
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TokenKind {
+    Command(char, usize),
+    Cell(String),
+    Word(String, usize),
+    Number(usize),
+    Str(String),
+    Equals,
+    Open,
+    Close,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    path: Rc<PathBuf>,
+    line: usize,
+}
+
+impl Token {
+    fn error<S: Into<String>>(&self, message: S) -> AsmError {
+        AsmError { path: self.path.as_ref().clone(), line: self.line,
+                   message: message.into() }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    path: Rc<PathBuf>,
+    line: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn error<S: Into<String>>(&self, message: S) -> AsmError {
+        AsmError { path: self.path.as_ref().clone(), line: self.line,
+                   message: message.into() }
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> String {
+        let mut taken = String::new();
+
+        while let Some(&c) = self.chars.peek() {
+            if !predicate(c) {
+                break;
+            }
+
+            taken.push(c);
+            self.chars.next();
+        }
+
+        taken
+    }
+
+    fn number(&mut self) -> Result<usize, AsmError> {
+        let digits = self.take_while(|c| c.is_ascii_digit());
+
+        digits.parse()
+            .map_err(|_| self.error(format!("expected a number, found '{}'",
+                                            digits)))
+    }
+
+    fn repetitions(&mut self) -> Result<usize, AsmError> {
+        if self.chars.peek() != Some(&'*') {
+            return Ok(1);
+        }
+
+        self.chars.next();
+
+        self.number()
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, AsmError> {
+        let mut tokens = Vec::new();
+
+        while let Some(c) = self.chars.next() {
+            let kind = match c {
+                '\n' => {
+                    self.line += 1;
+
+                    continue;
+                }
+                ';' => {
+                    self.take_while(|c| c != '\n');
+
+                    continue;
+                }
+                c if c.is_whitespace() => continue,
+                '+' | '-' | '<' | '>' | '.' | ',' | '[' | ']' => {
+                    TokenKind::Command(c, self.repetitions()?)
+                }
+                '@' => TokenKind::Cell(self.take_while(is_word_char)),
+                '=' => TokenKind::Equals,
+                '{' => TokenKind::Open,
+                '}' => TokenKind::Close,
+                '"' => {
+                    let s = self.take_while(|c| c != '"' && c != '\n');
+
+                    if self.chars.next() != Some('"') {
+                        return Err(self.error("unterminated string"));
+                    }
+
+                    TokenKind::Str(s)
+                }
+                c if c.is_ascii_digit() => {
+                    let rest = self.take_while(|c| c.is_ascii_digit());
+
+                    TokenKind::Number(format!("{}{}", c, rest).parse()
+                        .map_err(|_| self.error("number too large"))?)
+                }
+                c if is_word_char(c) => {
+                    let word = format!("{}{}", c,
+                                       self.take_while(is_word_char));
+
+                    TokenKind::Word(word, self.repetitions()?)
+                }
+                c => {
+                    return Err(self.error(format!("unexpected character '{}'",
+                                                  c)));
+                }
+            };
+
+            tokens.push(Token { kind, path: self.path.clone(),
+                                line: self.line });
+        }
+
+        Ok(tokens)
+    }
+}
+
+fn tokenize(source: &str, path: &Path) -> Result<Vec<Token>, AsmError> {
+    Lexer { chars: source.chars().peekable(),
+            path: Rc::new(path.to_path_buf()), line: 1 }.tokenize()
+}
+
+fn read_source(path: &Path) -> std::io::Result<String> {
+    let mut source = String::new();
+
+    std::fs::File::open(path)?.read_to_string(&mut source)?;
+
+    Ok(source)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+struct Assembler {
+    cells: HashMap<String, isize>,
+    next_cell: isize,
+    labels: HashMap<String, Rc<Vec<Token>>>,
+    expanding: Vec<String>,
+    includes: Vec<PathBuf>,
+    position: Option<isize>,
+    loops: Vec<(Option<isize>, Token)>,
+    output: String,
+    map: SourceMap,
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler { cells: HashMap::new(), next_cell: 0,
+                    labels: HashMap::new(), expanding: Vec::new(),
+                    includes: Vec::new(), position: Some(0),
+                    loops: Vec::new(), output: String::new(),
+                    map: SourceMap::default() }
+    }
+
+    fn emit(&mut self, command: char, token: &Token) -> Result<(), AsmError> {
+        match command {
+            '>' => self.position = self.position.map(|p| p + 1),
+            '<' => self.position = self.position.map(|p| p - 1),
+            '[' => self.loops.push((self.position, token.clone())),
+            ']' => match self.loops.pop() {
+                Some((start, _)) => if start != self.position {
+                    self.position = None;
+                },
+                None => return Err(token.error("no matching '[' for ']'")),
+            },
+            _ => (),
+        }
+
+        self.map.push(self.output.len(), &token.path.to_string_lossy(),
+                      token.line);
+        self.output.push(command);
+
+        Ok(())
+    }
+
+    fn move_to(&mut self, name: &str, token: &Token) -> Result<(), AsmError> {
+        let target = match self.cells.get(name) {
+            Some(&t) => t,
+            None => return Err(token.error(format!("unknown cell '{}'", name))),
+        };
+
+        let position = match self.position {
+            Some(p) => p,
+            None => {
+                return Err(token.error(format!(
+                    "cannot move to '{}': pointer position is unknown after \
+                     an unbalanced loop", name)));
+            }
+        };
+
+        let command = if target > position { '>' } else { '<' };
+
+        for _ in 0..(target - position).abs() {
+            self.emit(command, token)?;
+        }
+
+        Ok(())
+    }
+
+    fn define_cell(&mut self, tokens: &[Token], i: &mut usize, keyword: &Token)
+    -> Result<(), AsmError> {
+        let name = match next(tokens, i, keyword)?.kind {
+            TokenKind::Word(ref w, 1) => w.clone(),
+            _ => return Err(keyword.error("expected a cell name after 'cell'")),
+        };
+
+        let index = match tokens.get(*i).map(|t| &t.kind) {
+            Some(&TokenKind::Equals) => {
+                *i += 1;
+
+                match next(tokens, i, keyword)?.kind {
+                    TokenKind::Number(n) => n as isize,
+                    _ => return Err(keyword.error("expected a cell index")),
+                }
+            }
+            _ => self.next_cell,
+        };
+
+        if self.cells.insert(name.clone(), index).is_some() {
+            return Err(keyword.error(format!("cell '{}' is already defined",
+                                             name)));
+        }
+
+        self.next_cell = std::cmp::max(self.next_cell, index + 1);
+
+        Ok(())
+    }
+
+    fn define_label(&mut self, tokens: &[Token], i: &mut usize,
+                    keyword: &Token) -> Result<(), AsmError> {
+        let name = match next(tokens, i, keyword)?.kind {
+            TokenKind::Word(ref w, 1) => w.clone(),
+            _ => {
+                return Err(keyword.error("expected a label name after \
+                                          'label'"));
+            }
+        };
+
+        if next(tokens, i, keyword)?.kind != TokenKind::Open {
+            return Err(keyword.error(format!("expected '{{' after label '{}'",
+                                             name)));
+        }
+
+        let start = *i;
+        let mut depth = 1;
+
+        while depth > 0 {
+            match next(tokens, i, keyword)?.kind {
+                TokenKind::Open => depth += 1,
+                TokenKind::Close => depth -= 1,
+                _ => (),
+            }
+        }
+
+        let body = Rc::new(tokens[start..*i - 1].to_vec());
+
+        if self.labels.insert(name.clone(), body).is_some() {
+            return Err(keyword.error(format!("label '{}' is already defined",
+                                             name)));
+        }
+
+        Ok(())
+    }
+
+    fn include(&mut self, tokens: &[Token], i: &mut usize, keyword: &Token)
+    -> Result<(), AsmError> {
+        let relative = match next(tokens, i, keyword)?.kind {
+            TokenKind::Str(ref s) => PathBuf::from(s),
+            _ => return Err(keyword.error("expected a path after 'include'")),
+        };
+
+        let path = match keyword.path.parent() {
+            Some(dir) => dir.join(relative),
+            None => relative,
+        };
+
+        if self.includes.contains(&canonical(&path)) {
+            return Err(keyword.error(format!("'{}' includes itself",
+                                             path.display())));
+        }
+
+        let source = read_source(&path)
+            .map_err(|e| keyword.error(format!("could not read '{}': {}",
+                                               path.display(), e)))?;
+        let included = tokenize(&source, &path)?;
+
+        self.includes.push(canonical(&path));
+        self.assemble(&included)?;
+        self.includes.pop();
+
+        Ok(())
+    }
+
+    fn expand(&mut self, name: &str, count: usize, token: &Token)
+    -> Result<(), AsmError> {
+        let body = match self.labels.get(name) {
+            Some(b) => b.clone(),
+            None => {
+                return Err(token.error(format!("unknown label '{}'", name)));
+            }
+        };
+
+        if self.expanding.iter().any(|n| n == name) {
+            return Err(token.error(format!("label '{}' expands to itself",
+                                           name)));
+        }
+
+        self.expanding.push(name.to_string());
+
+        for _ in 0..count {
+            self.assemble(&body)?;
+        }
+
+        self.expanding.pop();
+
+        Ok(())
+    }
+
+    fn assemble(&mut self, tokens: &[Token]) -> Result<(), AsmError> {
+        let mut i = 0;
+
+        while i < tokens.len() {
+            let token = &tokens[i];
+
+            i += 1;
+
+            match token.kind {
+                TokenKind::Command(c, count) => for _ in 0..count {
+                    self.emit(c, token)?;
+                },
+                TokenKind::Cell(ref name) => self.move_to(name, token)?,
+                TokenKind::Word(ref w, 1) if w == "cell" => {
+                    self.define_cell(tokens, &mut i, token)?;
+                }
+                TokenKind::Word(ref w, 1) if w == "label" => {
+                    self.define_label(tokens, &mut i, token)?;
+                }
+                TokenKind::Word(ref w, 1) if w == "include" => {
+                    self.include(tokens, &mut i, token)?;
+                }
+                TokenKind::Word(ref name, count) => {
+                    self.expand(name, count, token)?;
+                }
+                _ => return Err(token.error("unexpected token")),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn next<'a>(tokens: &'a [Token], i: &mut usize, keyword: &Token)
+-> Result<&'a Token, AsmError> {
+    match tokens.get(*i) {
+        Some(t) => {
+            *i += 1;
+
+            Ok(t)
+        }
+        None => Err(keyword.error("unexpected end of input")),
+    }
+}
+
+/// Assembles `source` to brainfuck, resolving includes relative to `path`.
+pub fn assemble(source: &str, path: &Path) -> Result<String, AsmError> {
+    assemble_with_map(source, path).map(|(output, _)| output)
+}
+
+/// Assembles `source` like `assemble`, along with a map from the output
+/// back to the line, in `path` or a file it includes, each part came from.
+pub fn assemble_with_map(source: &str, path: &Path)
+-> Result<(String, SourceMap), AsmError> {
+    let tokens = tokenize(source, path)?;
+    let mut assembler = Assembler::new();
+
+    assembler.includes.push(canonical(path));
+    assembler.assemble(&tokens)?;
+
+    if let Some((_, token)) = assembler.loops.pop() {
+        return Err(token.error("no matching ']' for '['"));
+    }
+
+    Ok((assembler.output, assembler.map))
+}
+
+pub fn assemble_file(path: &Path) -> Result<String, AsmError> {
+    let source = read_source(path).map_err(|e| AsmError {
+        path: path.to_path_buf(),
+        line: 0,
+        message: format!("could not read file: {}", e),
+    })?;
+
+    assemble(&source, path)
+}
@@ -0,0 +1,232 @@
+//! An interpreter for async runtimes, enabled by the `async` feature.
+//!
+//! `AsyncInterpreter` reads and writes through tokio's `AsyncRead` and
+//! `AsyncWrite`, so a task running a program waits for input, or for room to
+//! write output, without blocking its thread. It also yields back to the
+//! runtime after running a set amount of instructions, its fuel, so a
+//! program that computes for a long time doesn't starve other tasks.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use interpreter::InterpreterState;
+use io;
+use ir::Program;
+use utf8;
+
+/// Instructions run between yields, unless set with `with_fuel`.
+pub const DEFAULT_FUEL: u64 = 10_000;
+
+// Bytes read from the reader at a time.
+const READ_SIZE: usize = 4096;
+
+pub struct AsyncInterpreter<R, W> {
+    /// Runs on input already read and collects output still to be written.
+    state: InterpreterState<VecDeque<u8>, Vec<u8>>,
+    reader: R,
+    writer: W,
+    end_of_input: bool,
+    /// The error that stopped the program, returned once its output has
+    /// been written.
+    error: Option<io::Error>,
+    /// Whether `outputs` has returned the error that stopped the program.
+    finished: bool,
+    fuel: u64,
+    remaining: u64,
+}
+
+impl<R, W> AsyncInterpreter<R, W>
+where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    pub fn new(program: &Program, reader: R, writer: W)
+    -> AsyncInterpreter<R, W> {
+        AsyncInterpreter {
+            state: InterpreterState::new(program, Vec::new(), VecDeque::new()),
+            reader,
+            writer,
+            end_of_input: false,
+            error: None,
+            finished: false,
+            fuel: DEFAULT_FUEL,
+            remaining: DEFAULT_FUEL,
+        }
+    }
+
+    /// Yields to the runtime after every `fuel` instructions (at least one).
+    pub fn with_fuel(mut self, fuel: u64) -> AsyncInterpreter<R, W> {
+        self.fuel = std::cmp::max(1, fuel);
+        self.remaining = self.fuel;
+
+        self
+    }
+
+    pub fn tape(&self) -> &[u32] {
+        self.state.tape()
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.state.pointer()
+    }
+
+    /// Runs the program to completion, or until reading input fails, and
+    /// flushes the writer.
+    pub fn run(&mut self) -> Run<'_, R, W> {
+        Run { interpreter: self }
+    }
+
+    /// Runs the program lazily, only as far as it takes to produce each
+    /// byte of output, which is returned instead of being written. The
+    /// stream ends when the program does, or after returning the error that
+    /// stopped it.
+    pub fn outputs(&mut self) -> Outputs<'_, R, W> {
+        Outputs { interpreter: self }
+    }
+
+    fn poll_write_output(&mut self, cx: &mut Context<'_>)
+    -> Poll<io::Result<()>> {
+        let output = self.state.writer_mut();
+
+        while !output.is_empty() {
+            let written = match Pin::new(&mut self.writer)
+                .poll_write(cx, output) {
+                Poll::Ready(r) => r?,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+
+            output.drain(..written);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_read_input(&mut self, cx: &mut Context<'_>)
+    -> Poll<io::Result<()>> {
+        let mut bytes = [0; READ_SIZE];
+        let mut buffer = ReadBuf::new(&mut bytes);
+
+        match Pin::new(&mut self.reader).poll_read(cx, &mut buffer) {
+            Poll::Ready(r) => r?,
+            Poll::Pending => return Poll::Pending,
+        }
+
+        if buffer.filled().is_empty() {
+            self.end_of_input = true;
+        }
+
+        self.state.reader_mut().extend(buffer.filled());
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Reads input if the program needs more, yields if it has run out
+    /// of fuel, or otherwise runs one instruction.
+    fn poll_step(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.state.reads_input() && !self.end_of_input
+           && !utf8::has_char(self.state.reader_mut()) {
+            return self.poll_read_input(cx);
+        }
+
+        if self.remaining == 0 {
+            self.remaining = self.fuel;
+            cx.waker().wake_by_ref();
+
+            return Poll::Pending;
+        }
+
+        self.remaining -= 1;
+
+        if let Err(e) = self.state.repl() {
+            self.error = Some(e);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_run(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match self.poll_write_output(cx) {
+                Poll::Ready(r) => r?,
+                Poll::Pending => return Poll::Pending,
+            }
+
+            if self.state.span().is_none() || self.error.is_some() {
+                match Pin::new(&mut self.writer).poll_flush(cx) {
+                    Poll::Ready(r) => r?,
+                    Poll::Pending => return Poll::Pending,
+                }
+
+                return Poll::Ready(self.error.take().map_or(Ok(()), Err));
+            }
+
+            match self.poll_step(cx) {
+                Poll::Ready(r) => r?,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn poll_next_output(&mut self, cx: &mut Context<'_>)
+    -> Poll<Option<io::Result<u8>>> {
+        loop {
+            let output = self.state.writer_mut();
+
+            if !output.is_empty() {
+                return Poll::Ready(Some(Ok(output.remove(0))));
+            }
+
+            if let Some(e) = self.error.take() {
+                self.finished = true;
+
+                return Poll::Ready(Some(Err(e)));
+            }
+
+            if self.finished || self.state.span().is_none() {
+                return Poll::Ready(None);
+            }
+
+            match self.poll_step(cx) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// The future returned by `AsyncInterpreter::run`.
+pub struct Run<'a, R: 'a, W: 'a> {
+    interpreter: &'a mut AsyncInterpreter<R, W>,
+}
+
+impl<'a, R, W> Future for Run<'a, R, W>
+where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>)
+    -> Poll<io::Result<()>> {
+        self.get_mut().interpreter.poll_run(cx)
+    }
+}
+
+/// The stream returned by `AsyncInterpreter::outputs`.
+pub struct Outputs<'a, R: 'a, W: 'a> {
+    interpreter: &'a mut AsyncInterpreter<R, W>,
+}
+
+impl<'a, R, W> Stream for Outputs<'a, R, W>
+where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    type Item = io::Result<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>)
+    -> Poll<Option<io::Result<u8>>> {
+        self.get_mut().interpreter.poll_next_output(cx)
+    }
+}
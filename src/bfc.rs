@@ -0,0 +1,455 @@
+//! The `.bfc` format, which stores a parsed and optimized program so it can
+//! be run again without redoing either.
+//!
+//! All integers are little-endian. A file is the magic `BFC\0`, a `u16`
+//! format version, then three sections in order: the source text (a `u64`
+//! length and UTF-8 bytes), the IR (a `u64` node count for the top level,
+//! then each node as a tag byte and its operands, with loop bodies nested
+//! in place), and the span table (a `u64` count, then a `u64` start and end
+//! for every node in the order the IR lists them).
+
+use ir::{self, Node, Op, Program, Span};
+
+pub const MAGIC: &[u8] = b"BFC\0";
+pub const VERSION: u16 = 1;
+
+const ADD: u8 = 0;
+const MOVE: u8 = 1;
+const OUTPUT: u8 = 2;
+const INPUT: u8 = 3;
+const LOOP: u8 = 4;
+const CLEAR: u8 = 5;
+const MUL_ADD: u8 = 6;
+const ADD_AT: u8 = 7;
+const PRINT: u8 = 8;
+const SCAN: u8 = 9;
+const CLEAR_RANGE: u8 = 10;
+const RANDOM: u8 = 11;
+const FORK: u8 = 12;
+const SWITCH_TAPE: u8 = 13;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    NotCompiled,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidSource,
+    InvalidTag(u8),
+    /// The span table doesn't have one entry per node.
+    SpanCount,
+    /// A span ends before it starts or past the end of the source, or
+    /// splits a character.
+    InvalidSpan,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            DecodeError::NotCompiled => write!(f, "not a compiled program"),
+            DecodeError::UnsupportedVersion(v) => {
+                write!(f, "unsupported format version {} (expected {})", v,
+                       VERSION)
+            }
+            DecodeError::Truncated => write!(f, "file is truncated"),
+            DecodeError::InvalidSource => {
+                write!(f, "embedded source is not valid UTF-8")
+            }
+            DecodeError::InvalidTag(t) => write!(f, "invalid op tag {}", t),
+            DecodeError::SpanCount => {
+                write!(f, "span table does not match the program")
+            }
+            DecodeError::InvalidSpan => {
+                write!(f, "span table does not fit the source")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError { }
+
+/// Whether `bytes` start like a compiled program.
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn encode_nodes(nodes: &[Node], out: &mut Vec<u8>, spans: &mut Vec<Span>) {
+    put_u64(out, nodes.len() as u64);
+
+    for node in nodes {
+        spans.push(node.span);
+
+        match node.op {
+            Op::Add(n) => {
+                out.push(ADD);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            Op::Move(n) => {
+                out.push(MOVE);
+                out.extend_from_slice(&(n as i64).to_le_bytes());
+            }
+            Op::Output => out.push(OUTPUT),
+            Op::Input => out.push(INPUT),
+            Op::Random => out.push(RANDOM),
+            Op::Fork => out.push(FORK),
+            Op::SwitchTape(n) => {
+                out.push(SWITCH_TAPE);
+                out.extend_from_slice(&(n as i64).to_le_bytes());
+            }
+            Op::Loop(ref body) => {
+                out.push(LOOP);
+                encode_nodes(body, out, spans);
+            }
+            Op::Clear => out.push(CLEAR),
+            Op::MulAdd { offset, factor } => {
+                out.push(MUL_ADD);
+                out.extend_from_slice(&(offset as i64).to_le_bytes());
+                out.extend_from_slice(&factor.to_le_bytes());
+            }
+            Op::AddAt { offset, amount } => {
+                out.push(ADD_AT);
+                out.extend_from_slice(&(offset as i64).to_le_bytes());
+                out.extend_from_slice(&amount.to_le_bytes());
+            }
+            Op::Print(ref values) => {
+                out.push(PRINT);
+                put_u64(out, values.len() as u64);
+
+                for value in values {
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            Op::Scan(step) => {
+                out.push(SCAN);
+                out.extend_from_slice(&(step as i64).to_le_bytes());
+            }
+            Op::ClearRange(extent) => {
+                out.push(CLEAR_RANGE);
+                out.extend_from_slice(&(extent as i64).to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Serializes `program` along with the source its spans refer to.
+pub fn encode(program: &Program, source: &str) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    let mut spans = Vec::new();
+
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    put_u64(&mut out, source.len() as u64);
+    out.extend_from_slice(source.as_bytes());
+    encode_nodes(&program.nodes, &mut out, &mut spans);
+    put_u64(&mut out, spans.len() as u64);
+
+    for span in spans {
+        put_u64(&mut out, span.start as u64);
+        put_u64(&mut out, span.end as u64);
+    }
+
+    out
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        if count > self.bytes.len() {
+            return Err(DecodeError::Truncated);
+        }
+
+        let (taken, rest) = self.bytes.split_at(count);
+
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+
+        array.copy_from_slice(self.take(N)?);
+
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, DecodeError> {
+        self.array().map(i32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn isize(&mut self) -> Result<isize, DecodeError> {
+        self.array().map(|a| i64::from_le_bytes(a) as isize)
+    }
+
+    /// Reads a count of items that each take at least `size` bytes,
+    /// rejecting counts the rest of the input couldn't hold.
+    fn count(&mut self, size: usize) -> Result<usize, DecodeError> {
+        let count = self.u64()?;
+
+        if count > (self.bytes.len() / size) as u64 {
+            return Err(DecodeError::Truncated);
+        }
+
+        Ok(count as usize)
+    }
+
+    fn nodes(&mut self) -> Result<Vec<Node>, DecodeError> {
+        let count = self.count(1)?;
+        let mut nodes = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let op = match self.u8()? {
+                ADD => Op::Add(self.i32()?),
+                MOVE => Op::Move(self.isize()?),
+                OUTPUT => Op::Output,
+                INPUT => Op::Input,
+                RANDOM => Op::Random,
+                FORK => Op::Fork,
+                SWITCH_TAPE => Op::SwitchTape(self.isize()?),
+                LOOP => Op::Loop(self.nodes()?),
+                CLEAR => Op::Clear,
+                MUL_ADD => Op::MulAdd { offset: self.isize()?,
+                                        factor: self.i32()? },
+                ADD_AT => Op::AddAt { offset: self.isize()?,
+                                      amount: self.i32()? },
+                PRINT => {
+                    let count = self.count(4)?;
+
+                    Op::Print((0..count).map(|_| self.u32())
+                              .collect::<Result<_, _>>()?)
+                }
+                SCAN => Op::Scan(self.isize()?),
+                CLEAR_RANGE => Op::ClearRange(self.isize()?),
+                tag => return Err(DecodeError::InvalidTag(tag)),
+            };
+
+            nodes.push(Node::new(op, Span::default()));
+        }
+
+        Ok(nodes)
+    }
+}
+
+fn apply_spans(nodes: &mut [Node], spans: &mut std::slice::Iter<Span>)
+-> Result<(), DecodeError> {
+    for node in nodes {
+        node.span = *spans.next().ok_or(DecodeError::SpanCount)?;
+
+        if let Op::Loop(ref mut body) = node.op {
+            apply_spans(body, spans)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads back a program written by `encode`, returning it and its source.
+pub fn decode(bytes: &[u8]) -> Result<(Program, String), DecodeError> {
+    if !is_compiled(bytes) {
+        return Err(DecodeError::NotCompiled);
+    }
+
+    let mut reader = Reader { bytes: &bytes[MAGIC.len()..] };
+    let version = u16::from_le_bytes(reader.array()?);
+
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let length = reader.count(1)?;
+    let source = String::from_utf8(reader.take(length)?.to_vec())
+        .map_err(|_| DecodeError::InvalidSource)?;
+    let mut nodes = reader.nodes()?;
+    let count = reader.count(16)?;
+    let spans = (0..count)
+        .map(|_| Ok(Span::new(reader.u64()? as usize, reader.u64()? as usize)))
+        .collect::<Result<Vec<_>, DecodeError>>()?;
+
+    let valid = |s: &Span| s.start <= s.end && s.end <= source.len()
+        && source.is_char_boundary(s.start) && source.is_char_boundary(s.end);
+
+    if !spans.iter().all(valid) {
+        return Err(DecodeError::InvalidSpan);
+    }
+
+    let mut iter = spans.iter();
+
+    apply_spans(&mut nodes, &mut iter)?;
+
+    if iter.next().is_some() {
+        return Err(DecodeError::SpanCount);
+    }
+
+    Ok((Program::new(nodes), source))
+}
+
+// Characters of source shown beside each instruction in a listing.
+const EXCERPT: usize = 24;
+
+/// The source `span` covers, on one line and cut short if it is long, or
+/// its byte offsets if it isn't in `source`.
+fn excerpt(source: &str, span: Span) -> String {
+    let text = match source.get(span.start..span.end) {
+        Some(text) => text,
+        None => return format!("@{}..{}", span.start, span.end),
+    };
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if text.chars().count() > EXCERPT {
+        text.chars().take(EXCERPT - 3).collect::<String>() + "..."
+    } else {
+        text
+    }
+}
+
+/// Where `span` is in `source`, as `ir::dump` shows it.
+fn location(source: &str, span: Span) -> String {
+    if source.get(span.start..span.end).is_none() {
+        return String::new();
+    }
+
+    let (line, column) = ir::line_col(source, span.start);
+    let (end_line, end_column) = ir::line_col(source, span.end);
+
+    format!("{}:{}-{}:{}", line, column, end_line, end_column)
+}
+
+/// Each instruction of `nodes` as its index, depth, text and span.
+fn list(nodes: &[Node], depth: usize,
+        lines: &mut Vec<(usize, usize, String, Span)>) {
+    for node in nodes {
+        let body = match node.op {
+            Op::Loop(ref body) => body,
+            ref op => {
+                lines.push((lines.len(), depth, ir::describe(op), node.span));
+
+                continue;
+            }
+        };
+        let start = lines.len();
+
+        lines.push((start, depth, String::new(),
+                    Span::new(node.span.start, node.span.start + 1)));
+        list(body, depth + 1, lines);
+
+        let end = lines.len();
+
+        lines[start].2 = format!("jump-if-zero {}", end);
+        lines.push((end, depth, format!("jump-if-nonzero {}", start),
+                    Span::new(node.span.end.saturating_sub(1),
+                              node.span.end)));
+    }
+}
+
+/// A listing of `program` as the interpreter runs it, one instruction per
+/// line: its index, counting as `InterpreterState::instruction_pointer`
+/// does, the op and its operands, where a loop is a jump at each end to
+/// the index of the other, then the source it came from.
+///
+/// ```
+/// use brainfuck::bfc;
+/// use brainfuck::Dialect;
+///
+/// let program = Dialect::Brainfuck.parse("+[-]").unwrap();
+/// let listing = bfc::disassemble(&program, "+[-]");
+/// let lines: Vec<_> = listing.lines().collect();
+///
+/// assert!(lines[1].starts_with("0  add +1 "));
+/// assert!(lines[2].starts_with("1  jump-if-zero 3 "));
+/// assert!(lines[3].starts_with("2    add -1 "));
+/// assert!(lines[4].starts_with("3  jump-if-nonzero 1 "));
+/// assert!(lines[4].contains(" 1:4-1:5 ") && lines[4].ends_with("]"));
+/// ```
+pub fn disassemble(program: &Program, source: &str) -> String {
+    let mut lines = Vec::new();
+
+    list(&program.nodes, 0, &mut lines);
+
+    let width = lines.len().saturating_sub(1).to_string().len();
+    let mut out = format!("; {} instructions from {} bytes of source\n",
+                          lines.len(), source.len());
+
+    for (index, depth, text, span) in lines {
+        let text = format!("{:indent$}{}", "", text, indent = depth * 2);
+        let line = format!("{:>width$}  {:<32} {:<16} {}", index, text,
+                           location(source, span), excerpt(source, span),
+                           width = width);
+
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use frontend::{Dialect, Extensions};
+    use optimize::Registry;
+    use super::{decode, encode, DecodeError, VERSION};
+
+    fn round_trip(source: &str, extensions: Extensions) {
+        let program = Dialect::Brainfuck.parse_with(source, extensions)
+            .unwrap();
+
+        for level in 0..4 {
+            let optimized = Registry::default().preset(level)
+                .run(program.clone());
+            let decoded = decode(&encode(&optimized, source)).unwrap();
+
+            assert_eq!(decoded, (optimized, source.to_string()),
+                       "at -O{}", level);
+        }
+    }
+
+    #[test]
+    fn programs_round_trip() {
+        round_trip(include_str!("../hello_world.bf"), Extensions::default());
+        round_trip(include_str!("../rot13.bf"), Extensions::default());
+        round_trip(include_str!("../392quine.bf"), Extensions::default());
+        // Every op, and a source that isn't all ASCII.
+        round_trip("é+[->++<]>[-]>[-]<<[>].,?Y}{",
+                   Extensions { rand: true, fork: true, tapes: true,
+                                ..Extensions::default() });
+    }
+
+    #[test]
+    fn bad_files() {
+        let program = Dialect::Brainfuck.parse("é+").unwrap();
+        let bytes = encode(&program, "é+");
+
+        assert_eq!(decode(b"BF"), Err(DecodeError::NotCompiled));
+        assert_eq!(decode(&bytes[..bytes.len() - 1]),
+                   Err(DecodeError::Truncated));
+
+        let mut newer = bytes.clone();
+
+        newer[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(decode(&newer),
+                   Err(DecodeError::UnsupportedVersion(VERSION + 1)));
+
+        // The `+` starting inside the `é`.
+        let mut split = bytes.clone();
+        let start = split.len() - 16;
+
+        split[start..start + 8].copy_from_slice(&1u64.to_le_bytes());
+        assert_eq!(decode(&split), Err(DecodeError::InvalidSpan));
+    }
+}
@@ -0,0 +1,206 @@
+//! `bfi bench`, which times the programs bundled with bfi on each backend
+//! at each optimization level, so the choices can be compared on the
+//! machine they will run on.
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use brainfuck::optimize::Registry;
+use brainfuck::vm::Vm;
+use brainfuck::{Dialect, InterpreterState, Program};
+use clap::ArgMatches;
+
+use perf::{Counters, Counts};
+
+/// Each bundled program's name, what it does and its source. None of them
+/// read input.
+pub const PROGRAMS: &[(&str, &str, &str)] = &[
+    ("hello", "prints Hello World! the long way round",
+     include_str!("../../../hello_world.bf")),
+    ("squares", "prints the squares from 0 to 10000",
+     include_str!("bench/squares.bf")),
+    ("sierpinski", "draws a Sierpinski triangle",
+     include_str!("bench/sierpinski.bf")),
+    ("quine", "prints its own source",
+     include_str!("../../../392quine.bf")),
+    ("loops", "passes a million times through three nested loops",
+     include_str!("bench/loops.bf")),
+];
+
+pub const BACKENDS: &[&str] = &["interpreter", "vm", "tiered"];
+
+/// What one run printed, how long it took, and what the counters saw, if
+/// they were read.
+type Timed = (Vec<u8>, Duration, Option<Counts>);
+
+/// Runs `program` once on `backend`, reading `counters` around it if they
+/// are given.
+fn time(program: &Program, backend: &str, jit_threshold: u64,
+        counters: &mut Option<Counters>) -> Result<Timed, String> {
+    let mut output = Vec::new();
+
+    if let Some(ref mut counters) = *counters {
+        counters.start();
+    }
+
+    let start = Instant::now();
+
+    if backend == "interpreter" {
+        let mut interpreter = InterpreterState::new(program, &mut output,
+                                                    std::io::empty());
+
+        let error = loop {
+            if let Err(e) = interpreter.repl() {
+                break e;
+            }
+        };
+
+        if interpreter.span().is_some() {
+            return Err(error.to_string());
+        }
+    } else {
+        let mut vm = Vm::new(program, &mut output, std::io::empty());
+
+        if backend == "tiered" {
+            vm = vm.with_jit_threshold(jit_threshold);
+        }
+
+        vm.run().map_err(|e| e.to_string())?;
+    }
+
+    let elapsed = start.elapsed();
+    let counts = counters.as_mut().map(Counters::stop);
+
+    Ok((output, elapsed, counts))
+}
+
+/// How long the fastest of `runs` runs took and what the counters saw of
+/// it, or why one of them failed or printed something other than
+/// `expected`.
+fn best(program: &Program, backend: &str, jit_threshold: u64, runs: usize,
+        expected: &[u8], counters: &mut Option<Counters>)
+-> Result<(Duration, Option<Counts>), &'static str> {
+    let mut best = (Duration::MAX, None);
+
+    for _ in 0..runs {
+        match time(program, backend, jit_threshold, counters) {
+            Ok((ref output, _, _)) if output != expected => {
+                return Err("wrong output");
+            }
+            Ok((_, elapsed, counts)) if elapsed < best.0 => {
+                best = (elapsed, counts);
+            }
+            Ok(_) => (),
+            Err(_) => return Err("failed"),
+        }
+    }
+
+    Ok(best)
+}
+
+pub fn bench(matches: &ArgMatches) {
+    if matches.is_present("list") {
+        for &(name, description, _) in PROGRAMS {
+            println!("{:<12} {}", name, description);
+        }
+
+        return;
+    }
+
+    let names: Vec<&str> = match matches.values_of("PROGRAMS") {
+        Some(names) => names.collect(),
+        None => PROGRAMS.iter().map(|&(name, _, _)| name).collect(),
+    };
+    let backends: Vec<&str> = matches.values_of("backends").unwrap()
+        .collect();
+    let levels: Vec<u32> = matches.values_of("opt-levels").unwrap()
+        .map(|level| match level.parse() {
+            Ok(l) => l,
+            Err(_) => {
+                eprintln!("invalid optimization level '{}'", level);
+                std::process::exit(1);
+            }
+        })
+        .collect();
+    let runs = std::cmp::max(1, ::number(matches, "runs"));
+    let jit_threshold = ::number(matches, "jit-threshold");
+    let registry = Registry::default();
+    let mut counters = if matches.is_present("counters") {
+        match Counters::open() {
+            Ok(counters) => Some(counters),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    print!("{:<12} {:<12}", "program", "backend");
+
+    for level in &levels {
+        print!(" {:>12}", format!("-O{}", level));
+    }
+
+    println!();
+
+    for name in names {
+        let &(_, _, source) = PROGRAMS.iter().find(|p| p.0 == name).unwrap();
+        let program = Dialect::Brainfuck.parse(source).unwrap();
+        // What every configuration should print, from the interpreter on
+        // the program as written.
+        let expected = match time(&program, "interpreter", jit_threshold,
+                                  &mut None) {
+            Ok((output, _, _)) => output,
+            Err(e) => {
+                eprintln!("{} failed: {}", name, e);
+                std::process::exit(1);
+            }
+        };
+        let optimized: Vec<Program> = levels.iter()
+            .map(|&level| registry.preset(level).run(program.clone()))
+            .collect();
+
+        for &backend in &backends {
+            let mut counts = Vec::new();
+
+            print!("{:<12} {:<12}", name, backend);
+
+            for program in &optimized {
+                let result = best(program, backend, jit_threshold, runs,
+                                  &expected, &mut counters);
+
+                match result {
+                    Ok((elapsed, _)) => print!(" {:>12}",
+                                               format!("{:.2?}", elapsed)),
+                    Err(e) => print!(" {:>12}", e),
+                }
+
+                counts.push(result.ok().and_then(|(_, c)| c));
+                let _ = std::io::stdout().flush();
+            }
+
+            println!();
+
+            if counters.is_none() {
+                continue;
+            }
+
+            // A row under the times for each counter.
+            for (i, &(counter, _)) in Counts::default().named().iter()
+                .enumerate() {
+                print!("{:<12} {:<12}", "", counter);
+
+                for counts in &counts {
+                    match *counts {
+                        Some(ref c) => print!(" {:>12}", c.named()[i].1),
+                        None => print!(" {:>12}", "-"),
+                    }
+                }
+
+                println!();
+            }
+        }
+    }
+}
@@ -0,0 +1,15 @@
+[Three loops nested a hundred deep each; the innermost moves a hundred
+into a running total; so a million passes through a loop the optimizer
+can turn into a multiplication; then prints a newline]
+
+++++++++++[>++++++++++<-]>
+[
+    >++++++++++[>++++++++++<-]>
+    [
+        >++++++++++[>++++++++++<-]>
+        [>>+<<-]
+        <<-
+    ]
+    <<-
+]
+>>>>>>>[-]++++++++++.
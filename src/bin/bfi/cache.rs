@@ -0,0 +1,143 @@
+//! The cache of optimized programs, which saves `bfi FILE` parsing and
+//! optimizing a program it has run before with the same arguments.
+//!
+//! Each entry is a `.bfc` file in `$XDG_CACHE_HOME/bfi`, or
+//! `~/.cache/bfi`, named by a hash of the source and of every argument
+//! that changes what the optimizer makes of it. An entry is only used if
+//! the source it holds is FILE's, so a collision can't run the wrong
+//! program.
+
+use std::path::PathBuf;
+
+use brainfuck::bfc;
+use brainfuck::Program;
+use clap::ArgMatches;
+
+/// The directory entries live in, if there is a home to put it in.
+fn dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| {
+            PathBuf::from(h).join(".cache")
+        }))
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+
+    Some(base.join("bfi"))
+}
+
+/// 64-bit FNV-1a, which is stable across builds, unlike `DefaultHasher`.
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for part in parts {
+        // Each part ends in a byte it can't contain, so moving bytes
+        // between parts changes the hash.
+        for &byte in part.iter().chain(&[0xff]) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    hash
+}
+
+/// The key for `source` run as `matches` says.
+pub fn key(matches: &ArgMatches, source: &[u8]) -> u64 {
+    let passes = ::pipeline(matches).names().join(",");
+    let extensions = format!("{:?}", ::extensions(matches));
+
+    fnv1a(&[env!("CARGO_PKG_VERSION").as_bytes(),
+            &bfc::VERSION.to_le_bytes(),
+            matches.value_of("dialect").unwrap().as_bytes(),
+            extensions.as_bytes(),
+            matches.value_of("overflow").unwrap().as_bytes(),
+            passes.as_bytes(),
+            source])
+}
+
+fn path(key: u64) -> Option<PathBuf> {
+    Some(dir()?.join(format!("{:016x}.bfc", key)))
+}
+
+/// The optimized program cached under `key`, if it was compiled from
+/// `source`.
+pub fn get(key: u64, source: &str) -> Option<Program> {
+    let bytes = std::fs::read(path(key)?).ok()?;
+
+    match bfc::decode(&bytes) {
+        Ok((program, cached)) if cached == source => Some(program),
+        _ => None,
+    }
+}
+
+/// Caches `program` under `key`. Failing to is no reason to stop the run,
+/// so errors are ignored.
+pub fn put(key: u64, program: &Program, source: &str) {
+    let path = match path(key) {
+        Some(path) => path,
+        None => return,
+    };
+    // Another run may be reading the entry, so it is written in full
+    // elsewhere first.
+    let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+    let written = std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| std::fs::write(&partial, bfc::encode(program, source)))
+        .and_then(|_| std::fs::rename(&partial, &path));
+
+    if written.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+}
+
+/// `bfi cache clear`, which deletes every entry.
+fn clear() {
+    let dir = match dir() {
+        Some(dir) => dir,
+        None => {
+            eprintln!("there is no cache directory");
+            std::process::exit(::EXIT_ERROR);
+        }
+    };
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!("the cache in '{}' is empty", dir.display());
+
+            return;
+        }
+        Err(e) => {
+            eprintln!("could not read '{}': {}", dir.display(), e);
+            std::process::exit(::EXIT_ERROR);
+        }
+    };
+    let mut removed = 0;
+
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        if !name.ends_with(".bfc") && !name.ends_with(".tmp") {
+            continue;
+        }
+
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(e) => {
+                eprintln!("could not remove '{}': {}", path.display(), e);
+                std::process::exit(::EXIT_ERROR);
+            }
+        }
+    }
+
+    println!("removed {} cached program{} from '{}'", removed,
+             if removed == 1 { "" } else { "s" }, dir.display());
+}
+
+pub fn cache(matches: &ArgMatches) {
+    match matches.subcommand() {
+        ("clear", Some(_)) => clear(),
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
@@ -0,0 +1,398 @@
+//! `bfi debug`, which runs debugger commands from a script, or from stdin
+//! without one, and prints what each did as a line of JSON, so checks on
+//! how a program runs can be part of a test suite.
+//!
+//! Each line of a script is a command. Blank lines and those starting with
+//! `#` are skipped:
+//!
+//! ```text
+//! # Stop before instruction 12, whenever the program reaches it.
+//! break 12
+//! continue
+//! assert cell 0 == 0
+//! assert cell 1 == 72
+//! delete 12
+//! step 3
+//! print cell
+//! continue
+//! assert output == "Hello World!\n"
+//! assert finished
+//! ```
+//!
+//! Instructions count as `InterpreterState::instruction_pointer` and `bfi
+//! gdb`'s `break *N` count them. `step N` runs N of them, and `continue`
+//! runs until a breakpoint or the end of the program. At a `[`,
+//! `step-over` runs the loop as one step, and anywhere in a loop,
+//! `step-out` runs until it exits, though breakpoints stop either sooner.
+//!
+//! `print` and `assert` take `cell N`, `cell` for the one under the
+//! pointer, `ptr`, `ip`, `steps` or `output`, everything the program has
+//! written. `assert` compares it with `==` or `!=` against a number, or a
+//! JSON string for `output`, and `assert finished` checks that the program
+//! has ended or run out of input.
+//!
+//! Each result has the script's `line`, the `command` and whether it was
+//! `ok`. Those that run the program also say why it `stop`ped, and the
+//! `ip`, `ptr`, `steps`, `output` written while it ran and, unless it has
+//! ended, the `line` and `column` in the source it is `at`. A result isn't
+//! `ok` if an assertion fails or the program stops with an `error`, and
+//! bfi then exits with status 4 once the script is done.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Read, Write};
+
+use brainfuck::io::is_end_of_input;
+use brainfuck::InterpreterState;
+use clap::ArgMatches;
+use serde_json::Value;
+
+/// Something `print` or `assert` looks at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Subject {
+    /// A cell, or the one under the pointer.
+    Cell(Option<usize>),
+    Pointer,
+    InstructionPointer,
+    Steps,
+    Output,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Command {
+    Break(usize),
+    Delete(usize),
+    Step(u64),
+    Continue,
+    StepOver,
+    StepOut,
+    Print(Subject),
+    /// Whether the subject should equal the value, and the value.
+    Assert(Subject, bool, Value),
+    Finished,
+}
+
+fn subject(words: &[&str]) -> Result<Subject, String> {
+    match *words {
+        ["cell"] => Ok(Subject::Cell(None)),
+        ["cell", n] => n.parse().map(|n| Subject::Cell(Some(n)))
+            .map_err(|_| format!("expected a cell number, found '{}'", n)),
+        ["ptr"] => Ok(Subject::Pointer),
+        ["ip"] => Ok(Subject::InstructionPointer),
+        ["steps"] => Ok(Subject::Steps),
+        ["output"] => Ok(Subject::Output),
+        _ => Err(format!("expected cell, ptr, ip, steps or output, found \
+                          '{}'", words.join(" "))),
+    }
+}
+
+fn assertion(rest: &str) -> Result<Command, String> {
+    if rest == "finished" {
+        return Ok(Command::Finished);
+    }
+
+    let operator = ["==", "!="].iter()
+        .filter_map(|o| rest.find(o).map(|i| (i, *o)))
+        .min();
+    let (at, operator) = match operator {
+        Some(found) => found,
+        None => return Err("expected == or != in assertion".to_string()),
+    };
+    let words: Vec<_> = rest[..at].split_whitespace().collect();
+    let subject = subject(&words)?;
+    let text = rest[at + operator.len()..].trim();
+    let value: Value = serde_json::from_str(text)
+        .map_err(|_| format!("expected a number or string, found '{}'",
+                             text))?;
+    let fits = match subject {
+        Subject::Output => value.is_string(),
+        _ => value.is_u64(),
+    };
+
+    if !fits {
+        let wanted = if subject == Subject::Output { "string" }
+                     else { "number" };
+
+        return Err(format!("expected a {} to compare with, found '{}'",
+                           wanted, text));
+    }
+
+    Ok(Command::Assert(subject, operator == "==", value))
+}
+
+fn index(word: Option<&str>) -> Result<usize, String> {
+    word.and_then(|w| w.parse().ok())
+        .ok_or_else(|| "expected an instruction number".to_string())
+}
+
+/// The command on a line, or `None` if it is blank or a comment.
+fn parse(line: &str) -> Result<Option<Command>, String> {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let (name, rest) = line.split_once(char::is_whitespace)
+        .unwrap_or((line, ""));
+    let rest = rest.trim();
+    let mut words = rest.split_whitespace();
+    let command = match name {
+        "break" => Command::Break(index(words.next())?),
+        "delete" => Command::Delete(index(words.next())?),
+        "step" => match words.next() {
+            None => Command::Step(1),
+            Some(n) => match n.parse() {
+                Ok(n) if n > 0 => Command::Step(n),
+                _ => return Err(format!("expected a number of steps, found \
+                                         '{}'", n)),
+            },
+        },
+        "continue" => Command::Continue,
+        "step-over" => Command::StepOver,
+        "step-out" => Command::StepOut,
+        "print" => {
+            let words: Vec<_> = words.by_ref().collect();
+
+            Command::Print(subject(&words)?)
+        }
+        "assert" => return assertion(rest).map(Some),
+        _ => return Err(format!("unknown command '{}'", name)),
+    };
+
+    match words.next() {
+        Some(extra) => Err(format!("unexpected '{}' after {}", extra, name)),
+        None => Ok(Some(command)),
+    }
+}
+
+struct Session<R: Read> {
+    interpreter: InterpreterState<R, Vec<u8>>,
+    breakpoints: BTreeSet<usize>,
+    /// The program's source, for where in it each stop is.
+    source: String,
+    /// Everything the program has written.
+    output: Vec<u8>,
+    /// Whether the program has ended or run out of input.
+    finished: bool,
+}
+
+impl<R: Read> Session<R> {
+    fn new(interpreter: InterpreterState<R, Vec<u8>>, source: String)
+    -> Session<R> {
+        Session { interpreter, breakpoints: BTreeSet::new(), source,
+                  output: Vec::new(), finished: false }
+    }
+
+    fn value(&self, subject: Subject) -> Value {
+        let interpreter = &self.interpreter;
+
+        match subject {
+            Subject::Cell(n) => {
+                let n = n.unwrap_or(interpreter.pointer());
+
+                json!(interpreter.tape().get(n).cloned().unwrap_or(0))
+            }
+            Subject::Pointer => json!(interpreter.pointer()),
+            Subject::InstructionPointer => {
+                json!(interpreter.instruction_pointer())
+            }
+            Subject::Steps => json!(interpreter.steps_executed()),
+            Subject::Output => json!(String::from_utf8_lossy(&self.output)),
+        }
+    }
+
+    /// Runs up to `steps` instructions, or until a breakpoint, the end of
+    /// the program or the instruction at `until`.
+    fn run(&mut self, steps: Option<u64>, until: Option<usize>) -> Value {
+        let mut taken = 0;
+        let mut error = None;
+        let stop = loop {
+            if self.interpreter.span().is_none() {
+                self.finished = true;
+
+                break "end";
+            }
+
+            if let Err(e) = self.interpreter.repl() {
+                if is_end_of_input(&e) {
+                    self.finished = true;
+
+                    break "end";
+                }
+
+                error = Some(e.to_string());
+
+                break "error";
+            }
+
+            let ip = self.interpreter.instruction_pointer();
+
+            taken += 1;
+
+            if self.breakpoints.contains(&ip) {
+                break "breakpoint";
+            } else if until == Some(ip) {
+                break "loop";
+            } else if steps == Some(taken) {
+                break "step";
+            }
+        };
+        let written = std::mem::take(self.interpreter.writer_mut());
+
+        self.output.extend_from_slice(&written);
+
+        let mut result = json!({
+            "ok": error.is_none(),
+            "stop": stop,
+            "ip": self.interpreter.instruction_pointer(),
+            "ptr": self.interpreter.pointer(),
+            "steps": self.interpreter.steps_executed(),
+            "output": String::from_utf8_lossy(&written),
+        });
+
+        if let Some(span) = self.interpreter.span() {
+            let (line, column) =
+                brainfuck::ir::line_col(&self.source, span.start);
+
+            result["at"] = json!({ "line": line, "column": column });
+        }
+
+        if let Some(error) = error {
+            result["error"] = json!(error);
+        }
+
+        result
+    }
+
+    fn execute(&mut self, command: &Command) -> Value {
+        let ip = self.interpreter.instruction_pointer();
+
+        match *command {
+            Command::Break(ip) => {
+                self.breakpoints.insert(ip);
+
+                json!({ "ok": true })
+            }
+            Command::Delete(ip) => {
+                json!({ "ok": self.breakpoints.remove(&ip) })
+            }
+            Command::Step(n) => self.run(Some(n), None),
+            Command::Continue => self.run(None, None),
+            Command::StepOver => {
+                match self.interpreter.enclosing_loop(ip + 1) {
+                    Some((start, end)) if start == ip => {
+                        self.run(None, Some(end + 1))
+                    }
+                    _ => json!({ "ok": false,
+                                 "error": "not at the start of a loop" }),
+                }
+            }
+            Command::StepOut => match self.interpreter.enclosing_loop(ip) {
+                Some((_, end)) => self.run(None, Some(end + 1)),
+                None => json!({ "ok": false, "error": "not in a loop" }),
+            },
+            Command::Print(subject) => {
+                json!({ "ok": true, "value": self.value(subject) })
+            }
+            Command::Assert(subject, equal, ref expected) => {
+                let actual = self.value(subject);
+
+                json!({ "ok": (actual == *expected) == equal,
+                        "actual": actual, "expected": expected })
+            }
+            Command::Finished => {
+                json!({ "ok": self.finished })
+            }
+        }
+    }
+}
+
+/// Runs each command as it is read, printing its result, and returns
+/// whether every one was ok. A line that isn't a command gets a result
+/// saying so.
+fn session<R, C>(mut session: Session<R>, commands: C) -> io::Result<bool>
+where R: Read, C: BufRead {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut passed = true;
+
+    for (i, line) in commands.lines().enumerate() {
+        let line = line?;
+        let mut result = match parse(&line) {
+            Ok(Some(command)) => session.execute(&command),
+            Ok(None) => continue,
+            Err(e) => json!({ "ok": false, "error": e }),
+        };
+
+        passed &= result["ok"] == json!(true);
+        result["line"] = json!(i + 1);
+        result["command"] = json!(line.trim());
+        writeln!(stdout, "{}", result)?;
+        stdout.flush()?;
+    }
+
+    Ok(passed)
+}
+
+pub fn debug(matches: &ArgMatches) {
+    let ::Loaded { source, program, compiled, .. } = ::parse(matches);
+    let program = if compiled { program } else {
+        ::pipeline(matches).run(program)
+    };
+    let script = matches.value_of("script").map(|filename| {
+        let text = String::from_utf8_lossy(&::read_input(filename))
+            .into_owned();
+
+        // Every line is checked before any runs, so that a typo near the
+        // end doesn't show up only after a long run.
+        for (i, line) in text.lines().enumerate() {
+            if let Err(e) = parse(line) {
+                eprintln!("{}:{}: {}", filename, i + 1, e);
+                std::process::exit(::EXIT_USAGE);
+            }
+        }
+
+        text
+    });
+    let stdin = io::stdin();
+    let result = match (matches.value_of("input"), script) {
+        (Some(filename), script) => {
+            let input = ::read_input(filename);
+            let session = Session::new(
+                InterpreterState::new(&program, Vec::new(), &input[..]),
+                source
+            );
+
+            match script {
+                Some(script) => self::session(session, script.as_bytes()),
+                None => self::session(session, stdin.lock()),
+            }
+        }
+        // The commands come from the script, so the program can read stdin.
+        (None, Some(script)) => {
+            let session = Session::new(
+                InterpreterState::new(&program, Vec::new(), stdin.lock()),
+                source
+            );
+
+            self::session(session, script.as_bytes())
+        }
+        (None, None) => {
+            let session = Session::new(
+                InterpreterState::new(&program, Vec::new(), io::empty()),
+                source
+            );
+
+            self::session(session, stdin.lock())
+        }
+    };
+
+    match result {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(::EXIT_MISMATCH),
+        Err(e) => {
+            eprintln!("debug session failed: {}", e);
+            std::process::exit(::EXIT_ERROR);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! `bfi dump`, which shows a saved tape as a table of each cell in hex,
+//! decimal and ASCII, with the pointer's cell marked, and `bfi tdiff`,
+//! which shows the cells that differ between two.
+
+use std::io::IsTerminal;
+
+use brainfuck::checkpoint::{self, Checkpoint};
+use brainfuck::snapshot::{self, Snapshot};
+use clap::ArgMatches;
+
+/// The cell as a character, if it is printable ASCII.
+fn ascii(cell: u32) -> char {
+    match char::from_u32(cell) {
+        Some(c) if c.is_ascii_graphic() || c == ' ' => c,
+        _ => '.',
+    }
+}
+
+/// A tape's cells, and the pointer if it was saved too.
+pub type Saved = (Vec<u32>, Option<usize>);
+
+/// The cells of the tape saved in `filename`, and the pointer if it was
+/// saved too. Anything but a snapshot or a checkpoint is taken to be a tape
+/// as `--dump-tape` writes it, without the pointer.
+pub fn read_tape(filename: &str) -> Result<Saved, String> {
+    let bytes = std::fs::read(filename)
+        .map_err(|e| format!("could not read file '{}': {}", filename, e))?;
+
+    if snapshot::is_snapshot(&bytes) {
+        return match Snapshot::decode(&bytes) {
+            Some(s) => Ok((s.tape, Some(s.pointer))),
+            None => Err(format!("could not read snapshot '{}'", filename)),
+        };
+    }
+
+    if checkpoint::is_checkpoint(&bytes) {
+        // Only the current tape, from its first cell on.
+        let current = Checkpoint::decode(&bytes)
+            .and_then(|c| c.tapes.get(c.current_tape).cloned().flatten());
+
+        return match current {
+            Some(t) => Ok((t.cells, Some(t.pointer))),
+            None => Err(format!("could not read checkpoint '{}'", filename)),
+        };
+    }
+
+    ::tape_cells(filename, &bytes)
+        .map(|cells| (cells, None))
+        .map_err(|e| format!("could not read tape '{}': {}", filename, e))
+}
+
+/// What differs between the tape `old` and the tape `new`, as
+/// `diff::tapes` renders it, after their pointers if both are known and
+/// they differ. Empty if nothing does.
+pub fn diff(old: &Saved, new: &Saved) -> String {
+    let cells = brainfuck::diff::tapes(&old.0, &new.0);
+
+    match (old.1, new.1) {
+        (Some(old), Some(new)) if old != new => {
+            format!("pointer at cell {} in old, {} in new\n{}", old, new,
+                    cells)
+        }
+        _ => cells,
+    }
+}
+
+/// `bfi tdiff`, which exits with 1 if the tapes differ, as `diff` does.
+pub fn tdiff(matches: &ArgMatches) {
+    let tapes: Vec<_> = ["OLD", "NEW"].iter()
+        .map(|&name| {
+            read_tape(matches.value_of(name).unwrap()).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            })
+        })
+        .collect();
+    let diff = diff(&tapes[0], &tapes[1]);
+
+    if !diff.is_empty() {
+        print!("{}", diff);
+        std::process::exit(1);
+    }
+}
+
+pub fn dump(matches: &ArgMatches) {
+    let filename = matches.value_of("FILE").unwrap();
+    let (tape, pointer) = read_tape(filename).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let from: usize = matches.value_of("from").map_or(0, |_| {
+        ::number(matches, "from")
+    });
+    let to = match matches.value_of("to") {
+        Some(_) => ::number(matches, "to"),
+        None => std::cmp::max(tape.len(), pointer.map_or(0, |p| p + 1)),
+    };
+    let color = std::io::stdout().is_terminal();
+    // Zero cells in a row so far. After the first, a run of them is shown
+    // as a single `*`.
+    let mut zeros = 0;
+
+    if let Some(p) = pointer {
+        println!("pointer at cell {}", p);
+    }
+
+    println!("{:>10}  {:>8}  {:>10}  ascii", "cell", "hex", "decimal");
+
+    for i in from..to {
+        let cell = tape.get(i).cloned().unwrap_or(0);
+        let here = pointer == Some(i);
+
+        if cell == 0 && !here && i + 1 < to {
+            zeros += 1;
+
+            if zeros == 2 {
+                println!("*");
+            }
+
+            if zeros >= 2 {
+                continue;
+            }
+        } else {
+            zeros = 0;
+        }
+
+        let row = format!("{:>10}  {:08x}  {:>10}  {}", i, cell, cell,
+                          ascii(cell));
+
+        match (here, color) {
+            (true, true) => println!("\x1b[7m{}\x1b[0m <- pointer", row),
+            (true, false) => println!("{} <- pointer", row),
+            _ => println!("{}", row),
+        }
+    }
+}
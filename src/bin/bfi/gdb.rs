@@ -0,0 +1,689 @@
+//! `bfi gdb`, a stub speaking gdb's remote serial protocol, so gdb and the
+//! front-ends built on it can step through a program, stop it at
+//! breakpoints and read its tape.
+//!
+//! The program has two registers: `ip`, the index of the instruction that
+//! will execute next, as `InterpreterState::instruction_pointer` counts
+//! them, and `ptr`, the address of the current cell. The tape is memory,
+//! each cell a 32-bit little-endian word, so cell N is at address 4N and
+//! `x/8wx $ptr` shows the current cell and those right of it. Breakpoints
+//! are set on instruction indices, as in `break *12`.
+//!
+//! `monitor break-output "error:"` stops the program as soon as it prints
+//! `error:`, and `monitor break-output /err.r/` as soon as what it prints
+//! matches a regex of literal characters, `.`, `^`, `$`, `\` escapes and
+//! `*`, `+` and `?` after any of those. Either looks at the output as UTF-8
+//! from when it is set, a regex at no more than the last 256 characters,
+//! and stops the program once. `monitor break-output` with nothing after
+//! cancels it.
+//!
+//! At a `[`, `monitor step-over` makes the next `continue` stop after the
+//! loop, running it as one step, and anywhere in a loop, `monitor
+//! step-out` makes it stop once the loop exits. Breakpoints still stop it
+//! sooner. gdb can pair each with `continue` as a command of its own:
+//!
+//! ```text
+//! define over
+//!   monitor step-over
+//!   continue
+//! end
+//! ```
+//!
+//! `monitor loops` shows the loops the program is in, innermost first like
+//! a backtrace, with where each starts and how many times round it is.
+
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use brainfuck::InterpreterState;
+use brainfuck::sourcemap::SourceMap;
+use clap::ArgMatches;
+
+const TARGET_XML: &str = "<?xml version=\"1.0\"?>\n\
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+<target version=\"1.0\">\n\
+<feature name=\"org.brainfuck.core\">\n\
+<reg name=\"ip\" bitsize=\"64\" type=\"code_ptr\" regnum=\"0\"/>\n\
+<reg name=\"ptr\" bitsize=\"64\" type=\"data_ptr\"/>\n\
+</feature>\n\
+</target>\n";
+
+// Bytes in a cell, as memory.
+const CELL_BYTES: usize = 4;
+
+// Instructions run between checks for gdb interrupting a continue.
+const INTERRUPT_INTERVAL: u64 = 4096;
+
+// Characters of output a regex given to `break-output` is matched against.
+const WATCHED_CHARS: usize = 256;
+
+fn read_byte(stream: &mut TcpStream) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Reads the next packet and acknowledges it, or returns `None` once gdb
+/// has disconnected. Acknowledgements from gdb are skipped.
+fn read_packet(stream: &mut TcpStream) -> io::Result<Option<String>> {
+    loop {
+        match read_byte(stream)? {
+            Some(b'$') => break,
+            Some(_) => continue,
+            None => return Ok(None),
+        }
+    }
+
+    let mut data = Vec::new();
+
+    loop {
+        match read_byte(stream)? {
+            Some(b'#') => break,
+            Some(byte) => data.push(byte),
+            None => return Ok(None),
+        }
+    }
+
+    let mut checksum = [0; 2];
+
+    stream.read_exact(&mut checksum)?;
+    stream.write_all(b"+")?;
+
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+fn write_packet(stream: &mut TcpStream, data: &str) -> io::Result<()> {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+
+    write!(stream, "${}#{:02x}", data, checksum)
+}
+
+/// Whether gdb has sent an interrupt, as it does on Ctrl-C, which it sends
+/// as a bare byte rather than a packet.
+fn interrupted(stream: &mut TcpStream) -> io::Result<bool> {
+    let mut byte = [0];
+
+    stream.set_nonblocking(true)?;
+
+    let peeked = stream.peek(&mut byte);
+
+    stream.set_nonblocking(false)?;
+
+    match peeked {
+        Ok(1) if byte[0] == 0x03 => {
+            stream.read_exact(&mut byte)?;
+
+            Ok(true)
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+        Ok(_) => Ok(false),
+    }
+}
+
+/// Whether `pattern` matches the start of `text`.
+fn match_here(pattern: &[char], text: &[char]) -> bool {
+    let (atom, width) = match *pattern {
+        [] => return true,
+        ['$'] => return text.is_empty(),
+        ['\\', c, ..] => (Some(c), 2),
+        ['.', ..] => (None, 1),
+        [c, ..] => (Some(c), 1),
+    };
+    let rest = &pattern[width..];
+    let fits = |c: &&char| atom.is_none_or(|a| a == **c);
+    let (min, max, rest) = match rest.first() {
+        Some('*') => (0, usize::MAX, &rest[1..]),
+        Some('+') => (1, usize::MAX, &rest[1..]),
+        Some('?') => (0, 1, &rest[1..]),
+        _ => (1, 1, rest),
+    };
+    let available = text.iter().take(max).take_while(fits).count();
+
+    // Longest first, like most regex engines.
+    (min..=available).rev().any(|n| match_here(rest, &text[n..]))
+}
+
+/// Whether `pattern` matches anywhere in `text`.
+fn search(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        Some((&'^', rest)) => match_here(rest, text),
+        _ => (0..=text.len()).any(|i| match_here(pattern, &text[i..])),
+    }
+}
+
+/// Whether the last character of `regex` is escaped by a `\` before it.
+fn escaped(regex: &[char]) -> bool {
+    regex.iter().rev().skip(1).take_while(|&&c| c == '\\').count() % 2 == 1
+}
+
+/// What `monitor break-output` waits for the program to print.
+enum Pattern {
+    Text(Vec<char>),
+    Regex(Vec<char>),
+}
+
+impl Pattern {
+    /// Reads `"text"`, with `\n`, `\t`, `\"` and `\\` escapes, or `/regex/`.
+    fn parse(argument: &str) -> Option<Pattern> {
+        if let Some(regex) = argument.strip_prefix('/')
+            .and_then(|a| a.strip_suffix('/')) {
+            let mut regex: Vec<char> = regex.chars().collect();
+
+            // Only a match ending at the newest character is new, so the
+            // regex is anchored there, once a `\` escaping nothing at its
+            // end is made one that stands for itself.
+            if regex.last() == Some(&'\\') && !escaped(&regex) {
+                regex.push('\\');
+            }
+
+            if regex.last() != Some(&'$') || escaped(&regex) {
+                regex.push('$');
+            }
+
+            return Some(Pattern::Regex(regex));
+        }
+
+        let quoted = argument.strip_prefix('"')?.strip_suffix('"')?;
+        let mut text = Vec::new();
+        let mut chars = quoted.chars();
+
+        while let Some(c) = chars.next() {
+            text.push(match c {
+                '\\' => match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    c => c,
+                },
+                c => c,
+            });
+        }
+
+        Some(Pattern::Text(text))
+    }
+
+    /// Whether the output since the pattern was set matches, given that it
+    /// didn't before its last character. `whole` says whether `output` is
+    /// all of it, or only the last `WATCHED_CHARS` characters.
+    fn matches(&self, output: &[char], whole: bool) -> bool {
+        match *self {
+            Pattern::Text(ref text) => output.ends_with(text),
+            Pattern::Regex(ref regex) => {
+                (whole || regex.first() != Some(&'^')) && search(regex, output)
+            }
+        }
+    }
+}
+
+/// The program's output, as far as `monitor break-output` needs it: what
+/// it has printed since the pattern was set, if there is one.
+#[derive(Default)]
+struct Watch {
+    pattern: Option<Pattern>,
+    /// The characters printed, at least the last `WATCHED_CHARS` of them.
+    output: Vec<char>,
+    /// Whether characters have been dropped from the start of `output`.
+    dropped: bool,
+    /// Bytes of a character that hasn't all been printed yet.
+    partial: Vec<u8>,
+    matched: bool,
+}
+
+impl Watch {
+    fn set(&mut self, pattern: Option<Pattern>) {
+        *self = Watch { pattern, ..Watch::default() };
+    }
+
+    fn push(&mut self, c: char) {
+        let pattern = match self.pattern {
+            Some(ref pattern) => pattern,
+            None => return,
+        };
+
+        self.output.push(c);
+
+        if pattern.matches(&self.output, !self.dropped) {
+            self.set(None);
+            self.matched = true;
+
+            return;
+        }
+
+        // Dropping half at a time keeps each character's share of the
+        // copying constant.
+        // Text is only found whole, so enough is kept to hold all of it.
+        let keep = match *pattern {
+            Pattern::Text(ref text) => text.len().max(WATCHED_CHARS),
+            Pattern::Regex(_) => WATCHED_CHARS,
+        };
+
+        if self.output.len() >= 2 * keep {
+            self.output.drain(..keep);
+            self.dropped = true;
+        }
+    }
+
+    /// Takes the bytes the program printed, a character at a time once
+    /// each has all arrived.
+    fn feed(&mut self, bytes: &[u8]) {
+        if self.pattern.is_none() {
+            return;
+        }
+
+        self.partial.extend_from_slice(bytes);
+
+        loop {
+            let (valid, invalid) = match std::str::from_utf8(&self.partial) {
+                Ok(text) => (text.len(), None),
+                Err(e) => (e.valid_up_to(), e.error_len()),
+            };
+            let text = String::from_utf8_lossy(&self.partial[..valid])
+                .into_owned();
+
+            for c in text.chars() {
+                self.push(c);
+            }
+
+            if self.pattern.is_none() {
+                return;
+            }
+
+            match invalid {
+                Some(length) => {
+                    self.push('\u{fffd}');
+                    self.partial.drain(..valid + length);
+                }
+                None => {
+                    self.partial.drain(..valid);
+
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// The program's output stream, which shows `Watch` what is written to it.
+struct Watched<W: Write> {
+    inner: W,
+    watch: Watch,
+}
+
+impl<W: Write> Write for Watched<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(bytes)?;
+
+        self.watch.feed(&bytes[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses the hexadecimal numbers in `arguments`, separated by commas.
+fn numbers(arguments: &str) -> Option<Vec<usize>> {
+    arguments.split(',')
+        .map(|n| usize::from_str_radix(n, 16).ok())
+        .collect()
+}
+
+struct Stub<R, W>
+where R: Read, W: Write {
+    interpreter: InterpreterState<R, Watched<W>>,
+    breakpoints: BTreeSet<usize>,
+    /// From `--source-map`, to say where in the original program each stop
+    /// is, since gdb only knows the instruction index.
+    map: Option<SourceMap>,
+    /// The program's source, for where each loop in `loops` starts.
+    source: String,
+    /// Where `step-over` or `step-out` has the next `continue` stop.
+    until: Option<usize>,
+}
+
+impl<R, W> Stub<R, W>
+where R: Read, W: Write {
+    /// `ip`, then `ptr`.
+    fn registers(&self) -> [u64; 2] {
+        [self.interpreter.instruction_pointer() as u64,
+         (self.interpreter.pointer() * CELL_BYTES) as u64]
+    }
+
+    fn read_memory(&self, address: usize, length: usize) -> String {
+        let tape = self.interpreter.tape();
+        let bytes: Vec<u8> = (address..address.saturating_add(length))
+            .map(|a| {
+                let cell = tape.get(a / CELL_BYTES).cloned().unwrap_or(0);
+
+                cell.to_le_bytes()[a % CELL_BYTES]
+            })
+            .collect();
+
+        hex(&bytes)
+    }
+
+    /// Runs one instruction, or until a breakpoint, the end of the program
+    /// or an interrupt, returning the stop reply.
+    fn resume(&mut self, stream: &mut TcpStream, step: bool)
+    -> io::Result<String> {
+        let reply = self.run(stream, step)?;
+        let origin = self.map.as_ref()
+            .zip(self.interpreter.span())
+            .and_then(|(map, span)| map.lookup(span.start));
+
+        if let (true, Some((file, line))) = (reply.starts_with('S'), origin) {
+            eprintln!("stopped at {}:{}", file, line);
+        }
+
+        Ok(reply)
+    }
+
+    fn run(&mut self, stream: &mut TcpStream, step: bool)
+    -> io::Result<String> {
+        let mut steps = 0u64;
+
+        loop {
+            if self.interpreter.span().is_none() {
+                return Ok("W00".to_string());
+            }
+
+            if let Err(e) = self.interpreter.repl() {
+                if brainfuck::io::is_end_of_input(&e) {
+                    return Ok("W00".to_string());
+                }
+
+                eprintln!("{}", e);
+
+                return Ok("W01".to_string());
+            }
+
+            let ip = self.interpreter.instruction_pointer();
+
+            if self.until == Some(ip) {
+                self.until = None;
+
+                return Ok("S05".to_string());
+            }
+
+            if self.output_matched() || step || self.breakpoints.contains(&ip) {
+                return Ok("S05".to_string());
+            }
+
+            steps += 1;
+
+            if steps.is_multiple_of(INTERRUPT_INTERVAL)
+               && interrupted(stream)? {
+                return Ok("S02".to_string());
+            }
+        }
+    }
+
+    /// Whether the output has just matched `break-output`'s pattern.
+    fn output_matched(&mut self) -> bool {
+        let watch = &mut self.interpreter.writer_mut().watch;
+
+        if watch.matched {
+            eprintln!("output matched");
+            watch.matched = false;
+
+            return true;
+        }
+
+        false
+    }
+
+    /// The open loops, innermost first, as `monitor loops` shows them.
+    fn loops(&self) -> String {
+        let loops = self.interpreter.loops();
+
+        if loops.is_empty() {
+            return "not in a loop\n".to_string();
+        }
+
+        loops.iter().rev().enumerate().map(|(i, open)| {
+            let origin = self.map.as_ref()
+                .and_then(|map| map.lookup(open.span.start));
+            let location = match origin {
+                Some((file, line)) => format!("{}:{}", file, line),
+                None => {
+                    let (line, column) =
+                        brainfuck::ir::line_col(&self.source,
+                                                open.span.start);
+
+                    format!("{}:{}", line, column)
+                }
+            };
+
+            format!("#{:<3} loop at {} ({}), iteration {}\n", i,
+                    open.start, location, open.iteration)
+        }).collect()
+    }
+
+    /// Runs a `monitor` command, returning what to show the user.
+    fn command(&mut self, command: &str) -> String {
+        let (name, argument) = command.split_once(' ')
+            .unwrap_or((command, ""));
+        let argument = argument.trim();
+
+        let ip = self.interpreter.instruction_pointer();
+
+        match name {
+            "break-output" => (),
+            "loops" => return self.loops(),
+            "step-over" => {
+                return match self.interpreter.enclosing_loop(ip + 1) {
+                    Some((start, end)) if start == ip => {
+                        self.until = Some(end + 1);
+
+                        "continue will stop after the loop\n".to_string()
+                    }
+                    _ => "not at the start of a loop; use stepi\n".to_string(),
+                };
+            }
+            "step-out" => {
+                return match self.interpreter.enclosing_loop(ip) {
+                    Some((_, end)) => {
+                        self.until = Some(end + 1);
+
+                        "continue will stop once the loop exits\n".to_string()
+                    }
+                    None => "not in a loop\n".to_string(),
+                };
+            }
+            _ => return format!("unknown command '{}'\n", name),
+        }
+
+        let watch = &mut self.interpreter.writer_mut().watch;
+
+        if argument.is_empty() {
+            watch.set(None);
+
+            return "no longer watching the output\n".to_string();
+        }
+
+        match Pattern::parse(argument) {
+            Some(pattern) => {
+                watch.set(Some(pattern));
+
+                format!("will stop once the output matches {}\n", argument)
+            }
+            None => "expected \"text\" or /regex/\n".to_string(),
+        }
+    }
+
+    /// The reply to `packet`, or `None` if gdb is done with the program.
+    fn answer(&mut self, stream: &mut TcpStream, packet: &str)
+    -> io::Result<Option<String>> {
+        let reply = match packet {
+            "?" => "S05".to_string(),
+            "g" => {
+                let [ip, ptr] = self.registers();
+
+                hex(&ip.to_le_bytes()) + &hex(&ptr.to_le_bytes())
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "D" | "k" => return Ok(None),
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=1000;qXfer:features:read+".to_string()
+            }
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                let arguments = &packet["qXfer:features:read:target.xml:"
+                                            .len()..];
+
+                match numbers(arguments).as_deref() {
+                    Some(&[offset, length]) => {
+                        let rest = TARGET_XML.get(offset..).unwrap_or("");
+                        let end = std::cmp::min(length, rest.len());
+
+                        if end < rest.len() {
+                            format!("m{}", &rest[..end])
+                        } else {
+                            format!("l{}", rest)
+                        }
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            _ if packet.starts_with("qRcmd,") => {
+                let bytes = (6..packet.len()).step_by(2)
+                    .map(|i| packet.get(i..i + 2)
+                        .and_then(|b| u8::from_str_radix(b, 16).ok()))
+                    .collect::<Option<Vec<u8>>>();
+
+                match bytes {
+                    Some(bytes) => {
+                        let command = String::from_utf8_lossy(&bytes);
+
+                        hex(self.command(&command).as_bytes())
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            _ if packet.starts_with('c') => self.resume(stream, false)?,
+            _ if packet.starts_with('s') => self.resume(stream, true)?,
+            _ if packet.starts_with('H') => "OK".to_string(),
+            _ if packet.starts_with('p') => {
+                match numbers(&packet[1..]).as_deref() {
+                    Some(&[n]) if n < 2 => {
+                        hex(&self.registers()[n].to_le_bytes())
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            _ if packet.starts_with('m') => {
+                match numbers(&packet[1..]).as_deref() {
+                    Some(&[address, length]) => {
+                        self.read_memory(address, length)
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            _ if packet.starts_with("Z0,") || packet.starts_with("Z1,") => {
+                match numbers(&packet[3..]).as_deref() {
+                    Some(&[ip, _]) => {
+                        self.breakpoints.insert(ip);
+
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            _ if packet.starts_with("z0,") || packet.starts_with("z1,") => {
+                match numbers(&packet[3..]).as_deref() {
+                    Some(&[ip, _]) => {
+                        self.breakpoints.remove(&ip);
+
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            // An empty reply tells gdb the packet isn't supported.
+            _ => String::new(),
+        };
+
+        Ok(Some(reply))
+    }
+}
+
+fn debug<R, W>(stream: &mut TcpStream,
+               interpreter: InterpreterState<R, Watched<W>>,
+               map: Option<SourceMap>, source: String) -> io::Result<()>
+where R: Read, W: Write {
+    let mut stub = Stub { interpreter, breakpoints: BTreeSet::new(), map,
+                          source, until: None };
+
+    while let Some(packet) = read_packet(stream)? {
+        let reply = stub.answer(stream, &packet)?;
+
+        stub.interpreter.writer_mut().flush()?;
+
+        match reply {
+            Some(reply) => write_packet(stream, &reply)?,
+            None => {
+                write_packet(stream, "OK")?;
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn gdb(matches: &ArgMatches) {
+    let ::Loaded { source, program, compiled, .. } = ::parse(matches);
+    let program = if compiled { program } else {
+        ::pipeline(matches).run(program)
+    };
+    let map = ::source_map(matches);
+    let port: u16 = ::number(matches, "port");
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("could not listen on port {}: {}", port, e);
+            std::process::exit(1);
+        }
+    };
+
+    eprintln!("waiting for gdb: target remote localhost:{}", port);
+
+    let mut stream = match listener.accept() {
+        Ok((stream, _)) => stream,
+        Err(e) => {
+            eprintln!("could not accept connection: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let stdout = std::io::stdout();
+    let output = Watched { inner: stdout.lock(), watch: Watch::default() };
+    let result = match matches.value_of("input") {
+        Some(filename) => {
+            let input = ::read_input(filename);
+
+            debug(&mut stream, InterpreterState::new(&program, output,
+                                                     &input[..]), map,
+                  source)
+        }
+        None => debug(&mut stream, InterpreterState::new(&program, output,
+                                                         std::io::stdin()),
+                      map, source),
+    };
+
+    if let Err(e) = result {
+        eprintln!("connection to gdb failed: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,381 @@
+//! `bfi lsp`, a language server speaking JSON-RPC over stdin and stdout.
+//!
+//! It reports unmatched brackets as diagnostics, highlights the bracket
+//! matching the one under the cursor, describes the loops around the cursor
+//! on hover, and runs the open file with the `brainfuck.run` command.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant};
+
+use brainfuck::analysis;
+use brainfuck::frontend::Token;
+use brainfuck::ir::{self, Node, Op, Span};
+use brainfuck::optimize::Registry;
+use brainfuck::{Dialect, ParseError, Program};
+use serde_json::Value;
+
+use Limits;
+
+// The JSON-RPC error for a method the server doesn't implement.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// The JSON-RPC error for a request with bad parameters.
+const INVALID_PARAMS: i64 = -32602;
+
+/// Reads one message, or returns `None` once the client has closed stdin.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        if header.trim().is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length")
+    })?;
+    let mut body = vec![0; length];
+
+    input.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body).map(Some).map_err(io::Error::from)
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value)
+-> io::Result<()> {
+    let body = message.to_string();
+
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn dialect(uri: &str) -> Dialect {
+    if uri.ends_with(".spoon") {
+        Dialect::Spoon
+    } else {
+        Dialect::Brainfuck
+    }
+}
+
+/// The byte offset of an LSP position, whose character counts UTF-16 code
+/// units.
+fn offset(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0);
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let mut start = 0;
+
+    for _ in 0..line {
+        match text[start..].find('\n') {
+            Some(i) => start += i + 1,
+            None => return text.len(),
+        }
+    }
+
+    let mut units = 0;
+
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + i;
+        }
+
+        units += c.len_utf16();
+    }
+
+    text.len()
+}
+
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+fn range(text: &str, span: Span) -> Value {
+    json!({ "start": position(text, span.start),
+            "end": position(text, span.end) })
+}
+
+/// The spans of each matched pair of brackets, innermost pairs first, and
+/// an error for each bracket without a match.
+fn brackets(tokens: &[(Token, Span)]) -> (Vec<(Span, Span)>, Vec<ParseError>) {
+    let mut open = Vec::new();
+    let mut pairs = Vec::new();
+    let mut errors = Vec::new();
+
+    for &(token, span) in tokens {
+        match token {
+            Token::LoopStart => open.push(span),
+            Token::LoopEnd => match open.pop() {
+                Some(start) => pairs.push((start, span)),
+                None => errors.push(ParseError::UnmatchedLoopEnd(span)),
+            },
+            _ => (),
+        }
+    }
+
+    errors.extend(open.into_iter().map(ParseError::UnmatchedLoopStart));
+
+    (pairs, errors)
+}
+
+fn diagnostics(uri: &str, text: &str) -> Value {
+    let errors = match dialect(uri).tokenize(text) {
+        Ok(tokens) => brackets(&tokens).1,
+        Err(e) => vec![e],
+    };
+    let diagnostics: Vec<Value> = errors.iter()
+        .map(|e| {
+            json!({
+                "range": range(text, e.span()),
+                "severity": 1,
+                "source": "bfi",
+                "message": e.diagnostic().message,
+            })
+        })
+        .collect();
+
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// Both brackets of the pair with a bracket at or just before `offset`.
+fn highlights(uri: &str, text: &str, offset: usize) -> Value {
+    let tokens = match dialect(uri).tokenize(text) {
+        Ok(tokens) => tokens,
+        Err(_) => return Value::Null,
+    };
+    let at = |s: Span| s.start <= offset && offset <= s.end;
+    let pair = brackets(&tokens).0.into_iter().find(|&(start, end)| {
+        at(start) || at(end)
+    });
+
+    match pair {
+        Some((start, end)) => json!([
+            { "range": range(text, start), "kind": 1 },
+            { "range": range(text, end), "kind": 1 },
+        ]),
+        None => Value::Null,
+    }
+}
+
+fn find_loop(nodes: &[Node], span: Span) -> Option<&[Node]> {
+    nodes.iter().find_map(|node| match node.op {
+        Op::Loop(ref body) if node.span == span => Some(&body[..]),
+        Op::Loop(ref body) => find_loop(body, span),
+        _ => None,
+    })
+}
+
+/// What running `body` once does, as far as the analysis can tell.
+fn describe(body: &[Node]) -> String {
+    if analysis::is_clear_loop(body) {
+        return "clears the loop cell".to_string();
+    }
+
+    if let Some(targets) = analysis::multiply_loop(body) {
+        let targets: Vec<String> = targets.iter()
+            .map(|&(offset, factor)| format!("{} × cell {:+}", factor, offset))
+            .collect();
+
+        return format!("moves the loop cell into {}", targets.join(", "));
+    }
+
+    match analysis::net_movement(body) {
+        Some(0) => "returns the pointer to where it started each iteration"
+            .to_string(),
+        Some(n) => format!("moves the pointer {:+} each iteration", n),
+        None => "moves the pointer by a varying amount".to_string(),
+    }
+}
+
+/// Describes the loops around `offset`.
+fn hover(uri: &str, text: &str, offset: usize) -> Value {
+    let dialect = dialect(uri);
+    let tokens = match dialect.tokenize(text) {
+        Ok(tokens) => tokens,
+        Err(_) => return Value::Null,
+    };
+    let enclosing: Vec<Span> = brackets(&tokens).0.into_iter()
+        .map(|(start, end)| start.to(end))
+        .filter(|s| s.start <= offset && offset < s.end)
+        .collect();
+    let span = match enclosing.first() {
+        Some(&s) => s,
+        None => return Value::Null,
+    };
+    let (line, column) = ir::line_col(text, span.start);
+    let (end_line, end_column) = ir::line_col(text, span.end);
+    let commands = tokens.iter()
+        .filter(|&&(_, s)| span.start <= s.start && s.end <= span.end)
+        .count();
+    let mut contents = format!("**loop** at depth {}, {}:{} to {}:{}, {} \
+                                commands",
+                               enclosing.len(), line, column, end_line,
+                               end_column, commands);
+    let program = dialect.parse(text).ok();
+
+    if let Some(body) = program.as_ref().and_then(|p| find_loop(&p.nodes,
+                                                                 span)) {
+        contents += "\n\n";
+        contents += &describe(body);
+    }
+
+    json!({
+        "contents": { "kind": "markdown", "value": contents },
+        "range": range(text, span),
+    })
+}
+
+/// Runs the program in `text` under limits, returning what
+/// `super::run_limited` reports.
+fn run(uri: &str, text: &str, input: &str) -> Result<Value, String> {
+    let start = Instant::now();
+    let program: Program = dialect(uri).parse(text)
+        .map_err(|e| e.to_string())?;
+    let program = Registry::default().preset(2).run(program);
+    let limits = Limits {
+        max_steps: Some(100_000_000),
+        timeout: Some(Duration::from_secs(10)),
+        max_output: Some(1 << 16),
+        max_cells: Some(1 << 20),
+    };
+
+    Ok(::run_limited(&program, input.as_bytes(), &limits, start))
+}
+
+fn response(id: Value, result: Result<Value, (i64, String)>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            // Full text on every change.
+            "textDocumentSync": 1,
+            "documentHighlightProvider": true,
+            "hoverProvider": true,
+            "executeCommandProvider": { "commands": ["brainfuck.run"] },
+        },
+        "serverInfo": { "name": "bfi", "version": "0.1.0" },
+    })
+}
+
+pub fn serve() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut documents: HashMap<String, String> = HashMap::new();
+
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("")
+            .to_string();
+        let text = documents.get(&uri).map_or("", |t| &t[..]);
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => Ok(Value::Null),
+            "exit" => return Ok(()),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str()
+                    .unwrap_or("");
+
+                write_message(&mut output, &diagnostics(&uri, text))?;
+                documents.insert(uri, text.to_string());
+                continue;
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                let text = changes.and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                    .unwrap_or("");
+
+                write_message(&mut output, &diagnostics(&uri, text))?;
+                documents.insert(uri, text.to_string());
+                continue;
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                write_message(&mut output, &diagnostics(&uri, ""))?;
+                continue;
+            }
+            "textDocument/documentHighlight" => {
+                let offset = offset(text, &params["position"]);
+
+                Ok(highlights(&uri, text, offset))
+            }
+            "textDocument/hover" => {
+                Ok(hover(&uri, text, offset(text, &params["position"])))
+            }
+            "workspace/executeCommand"
+                if params["command"] == "brainfuck.run" => {
+                let arguments = &params["arguments"];
+                let uri = arguments[0].as_str().unwrap_or("");
+                let input = arguments[1].as_str().unwrap_or("");
+
+                let result = match documents.get(uri) {
+                    Some(text) => run(uri, text, input)
+                        .map_err(|e| (INVALID_PARAMS, e)),
+                    None => Err((INVALID_PARAMS,
+                                 format!("'{}' isn't open", uri))),
+                };
+
+                if let Ok(ref result) = result {
+                    write_message(&mut output, &json!({
+                        "jsonrpc": "2.0",
+                        "method": "window/showMessage",
+                        "params": {
+                            "type": 3,
+                            "message": format!("{} ({})", result["output"]
+                                                   .as_str()
+                                                   .unwrap_or(""),
+                                               result["status"]
+                                                   .as_str()
+                                                   .unwrap_or("")),
+                        },
+                    }))?;
+                }
+
+                result
+            }
+            _ => Err((METHOD_NOT_FOUND,
+                      format!("unknown method '{}'", method))),
+        };
+
+        // Notifications have no id and get no response.
+        if let Some(id) = message.get("id") {
+            write_message(&mut output, &response(id.clone(), result))?;
+        }
+    }
+
+    Ok(())
+}
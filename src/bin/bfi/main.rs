@@ -0,0 +1,3712 @@
+extern crate brainfuck;
+extern crate clap;
+extern crate gif;
+#[cfg(unix)]
+extern crate libc;
+extern crate notify;
+#[macro_use]
+extern crate serde_json;
+#[cfg(windows)]
+extern crate windows_sys;
+
+mod bench;
+mod cache;
+mod debug;
+mod dump;
+mod gdb;
+mod lsp;
+mod man;
+mod perf;
+mod term;
+mod viz;
+mod workers;
+
+use std::cell::{Cell, RefCell};
+use std::io::{BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use brainfuck::checkpoint::{Checkpoint, Policy};
+use brainfuck::diagnostic::Severity;
+use brainfuck::io::{is_end_of_input, BfInput, InputFn, OutputFn};
+use brainfuck::frontend::Token;
+use brainfuck::metrics::{RunMetrics, Status};
+use brainfuck::optimize::{InputFreePrefix, Pipeline, Registry};
+use brainfuck::snapshot::Snapshot;
+use brainfuck::sourcemap::SourceMap;
+use brainfuck::verify::Comparison;
+use brainfuck::vm::Vm;
+use brainfuck::{Diagnostic, Dialect, Extensions, InterpreterState, Node, Op,
+                OutputEncoding, Overflow, ParseError, Program, Span};
+use notify::{RecursiveMode, Watcher};
+
+use term::{CrLf, Newlines, RawMode};
+use workers::{ReadAhead, WriteBehind};
+
+// Loop iterations before `--backend tiered` compiles a loop to native code.
+const DEFAULT_JIT_THRESHOLD: &str = "1000";
+
+// Bytes of output `bfi pipe` holds between two programs before the one
+// writing waits for the one reading to catch up.
+const PIPE_BUFFER: usize = 4096;
+
+// The limits `--sandbox` runs a program under: instructions, seconds,
+// cells and bytes of output.
+const SANDBOX_STEPS: u64 = 100_000_000;
+const SANDBOX_SECONDS: u64 = 10;
+const SANDBOX_CELLS: usize = 65536;
+const SANDBOX_OUTPUT: u64 = 1 << 20;
+
+// What bfi exits with other than 0, which it exits with once a program
+// finishes or runs out of input: when a program can't be loaded or stops
+// with an error, when the arguments are wrong, and when one of a run's
+// limits stops it, and when its output or tape isn't what
+// `--expect-output` or `--diff-against` said.
+const EXIT_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_LIMIT: i32 = 3;
+const EXIT_MISMATCH: i32 = 4;
+
+const EXIT_STATUS_HELP: &str = "EXIT STATUS:
+    0    The program finished, or ran out of input
+    1    It couldn't be loaded, or stopped with an error
+    2    The arguments were wrong
+    3    One of its limits, such as --max-memory, stopped it
+    4    Its output didn't match --expect-output's file, or its tape
+         --diff-against's";
+
+// Cells `--cell` can set, since the tape it starts is built up to the last
+// one before the program runs.
+const PRELOAD_CELLS: usize = 1 << 24;
+
+// Passes that assume the tape starts out all zeros, which it doesn't with
+// `--load-tape`, `--cells` or `--cell`.
+const ZERO_TAPE_PASSES: &[&str] = &["dce", "prefix"];
+
+// Cells in each row `--dump-format hex` writes.
+const HEX_DUMP_CELLS: usize = 16;
+
+// Upper bound on the work `--partial-eval` does before giving up and leaving
+// the rest of the program to run normally.
+const PARTIAL_EVAL_STEPS: u64 = 100_000_000;
+
+fn read_bytes(filename: &str) -> Vec<u8> {
+    match std::fs::read(filename) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("could not read file '{}': {}", filename, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn read_source(filename: &str) -> String {
+    match String::from_utf8(read_bytes(filename)) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("could not read file '{}': {}", filename, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn write_output<C: AsRef<[u8]>>(output: Option<&str>, contents: C) {
+    let contents = contents.as_ref();
+    let result = match output {
+        Some(filename) => std::fs::write(filename, contents),
+        None => std::io::stdout().write_all(contents),
+    };
+
+    if let Err(e) = result {
+        eprintln!("could not write output: {}", e);
+        std::process::exit(1);
+    }
+}
+
+struct Loaded {
+    source: String,
+    program: Program,
+    /// Whether the program came from a `.bfc` file, and so is already
+    /// optimized.
+    compiled: bool,
+    /// Whether the program came from the cache, already optimized as the
+    /// arguments say.
+    cached: bool,
+    /// What to cache the program under once it is optimized, if anything.
+    cache_key: Option<u64>,
+}
+
+/// Parses FILE, or loads it as it is if it was written by `bfi compile`.
+fn parse(matches: &clap::ArgMatches) -> Loaded {
+    parse_file(matches.value_of("FILE").unwrap(), matches)
+}
+
+/// Loads `filename`, exiting if it can't be.
+fn parse_file(filename: &str, matches: &clap::ArgMatches) -> Loaded {
+    match load(filename, matches) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parses `filename` in the dialect given by `--dialect`, unless it was
+/// written by `bfi compile`.
+fn load(filename: &str, matches: &clap::ArgMatches) -> Result<Loaded, String> {
+    load_bytes(filename, read_program(filename)?, matches)
+}
+
+/// Loads `filename` as `load` does, but takes the program from the cache,
+/// already optimized, if it was run with the same arguments before, unless
+/// `--no-cache` says not to.
+fn load_cached(filename: &str, matches: &clap::ArgMatches)
+-> Result<Loaded, String> {
+    let bytes = read_program(filename)?;
+
+    if matches.is_present("no-cache") || brainfuck::bfc::is_compiled(&bytes) {
+        return load_bytes(filename, bytes, matches);
+    }
+
+    let key = cache::key(matches, &bytes);
+
+    // The optimizer has to run for --verbose to report what it changed.
+    if !matches.is_present("verbose") {
+        let source = std::str::from_utf8(&bytes).ok();
+
+        if let Some(program) = source.and_then(|s| cache::get(key, s)) {
+            return Ok(Loaded { source: source.unwrap().to_string(), program,
+                               compiled: false, cached: true,
+                               cache_key: None });
+        }
+    }
+
+    let mut loaded = load_bytes(filename, bytes, matches)?;
+
+    loaded.cache_key = Some(key);
+
+    Ok(loaded)
+}
+
+fn read_program(filename: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(filename)
+        .map_err(|e| format!("could not read file '{}': {}", filename, e))
+}
+
+fn load_bytes(filename: &str, bytes: Vec<u8>, matches: &clap::ArgMatches)
+-> Result<Loaded, String> {
+    if brainfuck::bfc::is_compiled(&bytes) {
+        return match brainfuck::bfc::decode(&bytes) {
+            Ok((program, source)) => Ok(Loaded { source, program,
+                                                 compiled: true,
+                                                 cached: false,
+                                                 cache_key: None }),
+            Err(e) => Err(format!("could not load file '{}': {}", filename,
+                                  e)),
+        };
+    }
+
+    let dialect = Dialect::from_name(matches.value_of("dialect").unwrap())
+        .unwrap();
+    let source = String::from_utf8(bytes)
+        .map_err(|e| format!("could not read file '{}': {}", filename, e))?;
+
+    match dialect.parse_with(&source, extensions(matches)) {
+        Ok(program) => Ok(Loaded { source, program, compiled: false,
+                                   cached: false, cache_key: None }),
+        Err(e) => Err(render(&e.diagnostic(), filename, &source)),
+    }
+}
+
+/// `diagnostic` as it should be printed to stderr, in color if that is a
+/// terminal.
+fn render(diagnostic: &Diagnostic, filename: &str, source: &str) -> String {
+    diagnostic.render(filename, source, std::io::stderr().is_terminal())
+}
+
+/// The map `--source-map` names, if any, exiting if it can't be loaded.
+fn source_map(matches: &clap::ArgMatches) -> Option<SourceMap> {
+    let filename = matches.value_of("source-map")?;
+
+    match SourceMap::parse(&read_source(filename)) {
+        Some(map) => Some(map),
+        None => {
+            eprintln!("'{}' isn't a source map", filename);
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+fn pipeline(matches: &clap::ArgMatches) -> Pipeline {
+    let registry = Registry::default();
+    let mut names: Vec<String> = match matches.values_of("passes") {
+        Some(names) => {
+            names.filter(|n| !n.is_empty()).map(String::from).collect()
+        }
+        None => {
+            let level = matches.value_of("opt-level").unwrap();
+
+            match level.parse() {
+                Ok(l) => registry.preset(l).names().into_iter()
+                    .map(String::from)
+                    .collect(),
+                Err(_) => {
+                    eprintln!("invalid optimization level '{}'", level);
+                    std::process::exit(EXIT_USAGE);
+                }
+            }
+        }
+    };
+
+    if preloaded(matches).is_some() {
+        names.retain(|n| !ZERO_TAPE_PASSES.contains(&n.as_str()));
+    }
+
+    match registry.pipeline(&names) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{} (available: {})", e, registry.names().join(", "));
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// Where a program run by `bfi FILE` reads its input from.
+enum Input<'a> {
+    /// Input known before the program starts, which `--partial-eval` can
+    /// use.
+    Bytes(Vec<u8>),
+    Reader(Box<dyn Read + Send + 'a>),
+}
+
+/// Reads `--input`, exiting if it can't be read.
+fn read_input(filename: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    if let Err(e) = std::fs::File::open(filename)
+        .and_then(|mut f| f.read_to_end(&mut bytes)) {
+        eprintln!("could not read input file '{}': {}", filename, e);
+        std::process::exit(1);
+    }
+
+    bytes
+}
+
+/// `--input-string`'s bytes: its text as UTF-8, with `\n`, `\r`, `\t`,
+/// `\0`, `\\`, `\"`, `\'` and `\xHH` escapes.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+
+        bytes.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => 0,
+            Some(c @ '\\') | Some(c @ '"') | Some(c @ '\'') => c as u8,
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(b) if digits.len() == 2 => b,
+                    _ => return Err(format!("expected two hex digits after \
+                                             '\\x', found '{}'", digits)),
+                }
+            }
+            Some(c) => return Err(format!("unknown escape '\\{}'", c)),
+            None => return Err("'\\' at the end".to_string()),
+        });
+    }
+
+    Ok(bytes)
+}
+
+/// The input given up front, by `--input` or `--input-string`, if any,
+/// with its newlines as `--newline` says.
+fn known_input(matches: &clap::ArgMatches) -> Option<Vec<u8>> {
+    let bytes = match (matches.value_of("input"),
+                       matches.value_of("input-string")) {
+        (Some(filename), _) => read_input(filename),
+        (None, Some(text)) => match unescape(text) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("invalid --input-string: {}", e);
+                std::process::exit(EXIT_USAGE);
+            }
+        },
+        (None, None) => return None,
+    };
+
+    if crlf(matches) != Some(true) {
+        return Some(bytes);
+    }
+
+    let mut translated = Vec::new();
+    let _ = Newlines::translating(&bytes[..], true)
+        .read_to_end(&mut translated);
+
+    Some(translated)
+}
+
+/// Numbers separated by commas or whitespace, as cells.
+fn cell_list(text: &str) -> Result<Vec<u32>, String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|n| !n.is_empty())
+        .map(|n| n.parse().map_err(|_| format!("'{}' isn't a cell", n)))
+        .collect()
+}
+
+/// The cells in a tape file: numbers separated by commas or whitespace if
+/// its name ends in `.csv`, and otherwise a byte per cell.
+fn tape_cells(filename: &str, bytes: &[u8]) -> Result<Vec<u32>, String> {
+    if !filename.ends_with(".csv") {
+        return Ok(bytes.iter().map(|&b| b as u32).collect());
+    }
+
+    cell_list(&String::from_utf8_lossy(bytes))
+}
+
+/// Which of `--load-tape`, `--cells` and `--cell` is given first, if any,
+/// since each starts the tape with cells that may not be zero.
+fn preloaded(matches: &clap::ArgMatches) -> Option<&'static str> {
+    ["load-tape", "cells", "cell"].iter().cloned()
+        .find(|&name| matches.is_present(name))
+}
+
+/// `--cell`'s `INDEX=VALUE`.
+fn cell_setting(setting: &str) -> Result<(usize, u32), Failure> {
+    let parsed = setting.split_once('=').and_then(|(index, value)| {
+        Some((index.trim().parse().ok()?, value.trim().parse().ok()?))
+    });
+
+    parsed.ok_or_else(|| {
+        Failure::Usage(format!("--cell {} isn't INDEX=VALUE", setting))
+    })
+}
+
+/// The cells the tape starts with, if any of `--load-tape`, `--cells` and
+/// `--cell` is given, without the zeros at their end. `--cells` replaces
+/// the first cells of `--load-tape`'s, and each `--cell` sets one after
+/// that. A file ending in `.csv` holds numbers separated by commas or
+/// whitespace; anything else holds a byte per cell.
+fn load_tape(matches: &clap::ArgMatches, limit: Option<usize>)
+-> Result<Option<Vec<u32>>, Failure> {
+    let name = match preloaded(matches) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let mut cells = match matches.value_of("load-tape") {
+        Some(filename) => {
+            let read = std::fs::read(filename).map_err(|e| e.to_string())
+                .and_then(|bytes| tape_cells(filename, &bytes));
+
+            read.map_err(|e| Failure::Error(
+                format!("could not read tape '{}': {}", filename, e)
+            ))?
+        }
+        None => Vec::new(),
+    };
+
+    if let Some(list) = matches.value_of("cells") {
+        let list = cell_list(list)
+            .map_err(|e| Failure::Usage(format!("--cells: {}", e)))?;
+
+        if list.len() > cells.len() {
+            cells.resize(list.len(), 0);
+        }
+
+        cells[..list.len()].copy_from_slice(&list);
+    }
+
+    for setting in matches.values_of("cell").into_iter().flatten() {
+        let (index, value) = cell_setting(setting)?;
+
+        if limit.is_some_and(|limit| index >= limit) {
+            return Err(Failure::Usage(format!(
+                "--cell sets cell {}, but the tape is limited to {}", index,
+                limit.unwrap()
+            )));
+        }
+
+        if index >= PRELOAD_CELLS {
+            return Err(Failure::Usage(format!(
+                "--cell sets cell {}, but can only set the first {}", index,
+                PRELOAD_CELLS
+            )));
+        }
+
+        if index >= cells.len() {
+            cells.resize(index + 1, 0);
+        }
+
+        cells[index] = value;
+    }
+
+    while cells.last() == Some(&0) {
+        cells.pop();
+    }
+
+    match limit {
+        Some(limit) if cells.len() > limit => {
+            Err(Failure::Usage(format!("--{} has {} cells, but the tape is \
+                                        limited to {}", name, cells.len(),
+                                       limit)))
+        }
+        _ => Ok(Some(cells)),
+    }
+}
+
+/// Writes the tape to `--dump-tape`'s file, if it is given, without the
+/// zeros at its end, as `--dump-format` says or else as the file's
+/// extension suggests: numbers separated by commas for `.csv`, rows of
+/// hexadecimal for `.hex`, and a byte per cell otherwise.
+fn dump_tape(matches: &clap::ArgMatches, tape: &[u32]) -> Result<(), Failure> {
+    let filename = match matches.value_of("dump-tape") {
+        Some(f) => f,
+        None => return Ok(()),
+    };
+    let used = tape.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1);
+    let tape = &tape[..used];
+    let format = matches.value_of("dump-format").unwrap_or(
+        if filename.ends_with(".csv") {
+            "csv"
+        } else if filename.ends_with(".hex") {
+            "hex"
+        } else {
+            "binary"
+        }
+    );
+    let contents = match format {
+        "csv" => {
+            let cells: Vec<String> = tape.iter().map(u32::to_string)
+                .collect();
+
+            (cells.join(",") + "\n").into_bytes()
+        }
+        "hex" => tape.chunks(HEX_DUMP_CELLS)
+            .map(|row| {
+                let cells: Vec<String> = row.iter()
+                    .map(|c| format!("{:02x}", c))
+                    .collect();
+
+                cells.join(" ") + "\n"
+            })
+            .collect::<String>()
+            .into_bytes(),
+        _ => match tape.iter().position(|&c| c > 255) {
+            Some(i) => {
+                return Err(Failure::Error(format!(
+                    "could not dump the tape as bytes: cell {} is {}; try \
+                     --dump-format csv or hex", i, tape[i]
+                )));
+            }
+            None => tape.iter().map(|&c| c as u8).collect(),
+        },
+    };
+
+    write_output(Some(filename), contents);
+
+    Ok(())
+}
+
+/// Whether `--newline` says lines end in `\r\n` outside the program, or
+/// `None` if it isn't given, in which case only a Windows console's input
+/// is translated.
+fn crlf(matches: &clap::ArgMatches) -> Option<bool> {
+    matches.value_of("newline").map(|newline| {
+        newline == "crlf" || (newline == "native" && cfg!(windows))
+    })
+}
+
+/// `reader`, with its newlines as `--newline` says.
+fn newlines<R: Read>(matches: &clap::ArgMatches, reader: R) -> Newlines<R> {
+    match crlf(matches) {
+        Some(translate) => Newlines::translating(reader, translate),
+        None => Newlines::new(reader),
+    }
+}
+
+/// Input that never waits: a thread reads ahead from the source, and
+/// whenever nothing it read is waiting, including after the source ends, a
+/// read gives a zero byte.
+struct NonBlocking {
+    receiver: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl NonBlocking {
+    fn spawn<R: Read + Send + 'static>(source: R) -> NonBlocking {
+        NonBlocking { receiver: workers::read_ahead(source),
+                      pending: Vec::new() }
+    }
+}
+
+impl Read for NonBlocking {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            if let Ok(bytes) = self.receiver.try_recv() {
+                self.pending = bytes;
+            }
+        }
+
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        if self.pending.is_empty() {
+            buffer[0] = 0;
+
+            return Ok(1);
+        }
+
+        let length = std::cmp::min(buffer.len(), self.pending.len());
+
+        buffer[..length].copy_from_slice(&self.pending[..length]);
+        self.pending.drain(..length);
+
+        Ok(length)
+    }
+}
+
+fn run(matches: &clap::ArgMatches) {
+    let _raw = if matches.is_present("tty-raw") {
+        Some(RawMode::enable())
+    } else {
+        None
+    };
+
+    if matches.is_present("watch") {
+        return watch(matches);
+    }
+
+    if matches.is_present("stream") {
+        if let Err(failure) = stream(matches) {
+            let _ = std::io::stdout().flush();
+            eprintln!("{}", failure.message());
+            std::process::exit(failure.exit_code());
+        }
+
+        return;
+    }
+
+    let stdin = std::io::stdin();
+    let nonblocking = matches.is_present("input-nonblocking");
+    let input = match known_input(matches) {
+        // All of it is there already, so only its end gives zeros.
+        Some(bytes) if nonblocking => {
+            Input::Reader(Box::new(std::io::Cursor::new(bytes)
+                                       .chain(std::io::repeat(0))))
+        }
+        Some(bytes) => Input::Bytes(bytes),
+        None if nonblocking => {
+            let stdin = newlines(matches, stdin);
+
+            Input::Reader(Box::new(NonBlocking::spawn(stdin)))
+        }
+        None if matches.is_present("io-threads") => {
+            let stdin = newlines(matches, stdin);
+
+            Input::Reader(Box::new(ReadAhead::spawn(stdin,
+                                                    io_deadline(matches))))
+        }
+        None => Input::Reader(Box::new(newlines(matches, stdin))),
+    };
+
+    let filename = matches.value_of("FILE").unwrap();
+    let loaded = load_cached(filename, matches).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(EXIT_ERROR);
+    });
+
+    if let Err(failure) = execute(matches, loaded, input) {
+        let _ = std::io::stdout().flush();
+        eprintln!("{}", failure.message());
+        std::process::exit(failure.exit_code());
+    }
+}
+
+/// Input that keeps a copy of everything read through it.
+struct Recorder<'a, R> {
+    inner: R,
+    recorded: &'a mut Vec<u8>,
+}
+
+impl<'a, R: Read> Read for Recorder<'a, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let length = self.inner.read(buffer)?;
+
+        self.recorded.extend_from_slice(&buffer[..length]);
+
+        Ok(length)
+    }
+}
+
+/// Runs FILE each time it changes, clearing the screen first. Input read
+/// from stdin on the first run is recorded and replayed on later ones.
+fn watch(matches: &clap::ArgMatches) {
+    let filename = matches.value_of("FILE").unwrap();
+    let path = Path::new(filename);
+    let (sender, receiver) = mpsc::channel();
+    // Editors often save by replacing the file, which would end a watch on
+    // the file itself, so this watches its directory.
+    let directory = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let watched = notify::recommended_watcher(sender).and_then(|mut w| {
+        w.watch(directory, RecursiveMode::NonRecursive)?;
+
+        Ok(w)
+    });
+    let _watcher = match watched {
+        Ok(w) => w,
+        Err(e) => {
+            eprintln!("could not watch file '{}': {}", filename, e);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+    let mut recorded: Option<Vec<u8>> = None;
+
+    loop {
+        print!("\x1b[2J\x1b[H");
+        let _ = std::io::stdout().flush();
+
+        match load_cached(filename, matches) {
+            Ok(loaded) => {
+                let result = if let Some(bytes) = known_input(matches) {
+                    execute(matches, loaded, Input::Bytes(bytes))
+                } else if let Some(ref bytes) = recorded {
+                    execute(matches, loaded, Input::Bytes(bytes.clone()))
+                } else {
+                    let stdin = newlines(matches, std::io::stdin());
+                    let mut bytes = Vec::new();
+                    let result = execute(matches, loaded, Input::Reader(
+                        Box::new(Recorder { inner: stdin,
+                                            recorded: &mut bytes })
+                    ));
+
+                    recorded = Some(bytes);
+
+                    result
+                };
+
+                if let Err(failure) = result {
+                    let _ = std::io::stdout().flush();
+                    eprintln!("{}", failure.message());
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+
+        let _ = std::io::stdout().flush();
+        eprintln!("\n[watching '{}' for changes]", filename);
+
+        loop {
+            match receiver.recv() {
+                Ok(Ok(event)) => {
+                    let changed = (event.kind.is_modify()
+                                   || event.kind.is_create())
+                        && event.paths.iter()
+                            .any(|p| p.file_name() == path.file_name());
+
+                    if changed {
+                        break;
+                    }
+                }
+                Ok(Err(e)) => eprintln!("error watching file: {}", e),
+                Err(_) => return,
+            }
+        }
+
+        // Saving can take several writes, so wait for them to settle.
+        while receiver.recv_timeout(Duration::from_millis(50)).is_ok() { }
+    }
+}
+
+/// The longest start of `text` that parses on its own, leaving out a loop
+/// whose `]` hasn't arrived yet or a token cut off at the end, and the
+/// program it parses to.
+fn whole_prefix(text: &str, matches: &clap::ArgMatches)
+-> Result<(usize, Program), ParseError> {
+    let dialect = Dialect::from_name(matches.value_of("dialect").unwrap())
+        .unwrap();
+    let mut end = text.len();
+
+    loop {
+        match dialect.parse_with(&text[..end], extensions(matches)) {
+            Ok(program) => return Ok((end, program)),
+            Err(ParseError::UnmatchedLoopStart(span))
+            | Err(ParseError::IncompleteToken(span)) => end = span.start,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Moves each span in `nodes` `by` bytes on, for a piece of a program
+/// parsed apart from what came before it.
+fn shift_spans(nodes: &mut [Node], by: usize) {
+    for node in nodes {
+        node.span = Span::new(node.span.start + by, node.span.end + by);
+
+        if let Op::Loop(ref mut body) = node.op {
+            shift_spans(body, by);
+        }
+    }
+}
+
+/// Runs FILE as it is read, which is stdin if FILE is `-`: each instruction
+/// as soon as it arrives, and each loop once its `]` has. Pieces of the
+/// program aren't optimized, since passes may assume they start the
+/// program.
+fn stream(matches: &clap::ArgMatches) -> Result<(), Failure> {
+    let filename = matches.value_of("FILE").unwrap();
+
+    if matches.value_of("backend").unwrap() != "interpreter" {
+        return Err(Failure::Usage("--stream needs --backend interpreter"
+                                  .to_string()));
+    }
+
+    if extensions(matches).fork {
+        return Err(Failure::Usage("--stream can't run --ext fork's threads"
+                                  .to_string()));
+    }
+    let mut reader: Box<dyn Read> = if filename == "-" {
+        Box::new(std::io::stdin())
+    } else {
+        match std::fs::File::open(filename) {
+            Ok(f) => Box::new(f),
+            Err(e) => {
+                return Err(Failure::Error(format!("could not read file \
+                                                   '{}': {}", filename, e)));
+            }
+        }
+    };
+    let flush = Flush::from_name(matches.value_of("flush").unwrap());
+    let output = Output::new(flush, crlf(matches) == Some(true));
+    // If the program comes from stdin, its input can't as well.
+    let input: Box<dyn Read + Send> = match known_input(matches) {
+        Some(bytes) => Box::new(std::io::Cursor::new(bytes)),
+        None if filename == "-" => Box::new(std::io::empty()),
+        None => Box::new(newlines(matches, std::io::stdin())),
+    };
+    let input = FlushFirst { inner: input, output: output.clone() };
+    let overflow = Overflow::from_name(matches.value_of("overflow").unwrap())
+        .unwrap();
+    let encoding = OutputEncoding::from_name(
+        matches.value_of("output-encoding").unwrap()
+    ).unwrap();
+    let seed = match matches.value_of("seed") {
+        Some(_) => number(matches, "seed"),
+        None => clock_seed(),
+    };
+    let mut interpreter = InterpreterState::new(&Program::default(), output,
+                                                input)
+        .with_overflow(overflow)
+        .with_strict_pointer(matches.is_present("strict-pointer"))
+        .with_output_encoding(encoding)
+        .with_seed(seed);
+
+    let tape_limit = matches.value_of("max-memory")
+        .map(|_| number(matches, "max-memory"));
+
+    if let Some(cells) = tape_limit {
+        interpreter = interpreter.with_tape_limit(cells);
+    }
+
+    if matches.is_present("max-output") {
+        interpreter = interpreter
+            .with_output_limit(number(matches, "max-output"));
+    }
+
+    if let Some(cells) = load_tape(matches, tape_limit)? {
+        interpreter = interpreter.with_tape(&cells);
+    }
+
+    // The program so far, parsed up to `parsed`, then any bytes of a
+    // character that hasn't all arrived.
+    let mut source = String::new();
+    let mut parsed = 0;
+    let mut undecoded = Vec::new();
+    let mut buffer = [0; 4096];
+    let mut ended = false;
+
+    let result = 'run: loop {
+        while interpreter.span().is_some() {
+            if let Err(e) = interpreter.repl() {
+                if is_end_of_input(&e) {
+                    break 'run Ok(());
+                }
+
+                let span = interpreter.span().unwrap();
+                let message = render(&Diagnostic::error(e.to_string(), span),
+                                     filename, &source);
+
+                break 'run Err(if is_limit(&e) {
+                    Failure::Limit(message)
+                } else {
+                    Failure::Error(message)
+                });
+            }
+        }
+
+        if ended {
+            break Ok(());
+        }
+
+        // Show what the program printed before waiting for more of it.
+        let _ = interpreter.writer_mut().flush();
+
+        let length = match reader.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                continue;
+            }
+            Err(e) => {
+                break Err(Failure::Error(format!("could not read file \
+                                                  '{}': {}", filename, e)));
+            }
+        };
+
+        undecoded.extend_from_slice(&buffer[..length]);
+
+        let valid = match std::str::from_utf8(&undecoded) {
+            Ok(text) => text.len(),
+            Err(e) => e.valid_up_to(),
+        };
+
+        source += std::str::from_utf8(&undecoded[..valid]).unwrap();
+        undecoded.drain(..valid);
+        ended = length == 0;
+
+        if ended && !undecoded.is_empty() {
+            break Err(Failure::Error(format!("could not read file '{}': \
+                                              it isn't valid UTF-8",
+                                             filename)));
+        }
+
+        // Once the file has ended, what's left has to parse as it is.
+        let piece = if ended {
+            Dialect::from_name(matches.value_of("dialect").unwrap()).unwrap()
+                .parse_with(&source[parsed..], extensions(matches))
+                .map(|program| (source.len() - parsed, program))
+        } else {
+            whole_prefix(&source[parsed..], matches)
+        };
+        let (length, mut program) = match piece {
+            Ok(piece) => piece,
+            Err(e) => {
+                let mut diagnostic = e.diagnostic();
+
+                diagnostic.span = Span::new(diagnostic.span.start + parsed,
+                                            diagnostic.span.end + parsed);
+
+                break Err(Failure::Error(render(&diagnostic, filename,
+                                                &source)));
+            }
+        };
+
+        shift_spans(&mut program.nodes, parsed);
+        interpreter.append(&program);
+        parsed += length;
+    };
+
+    dump_tape(matches, interpreter.tape())?;
+
+    result
+}
+
+/// When `--flush` says to flush the program's output.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Flush {
+    EveryWrite,
+    Line,
+    OnExit,
+}
+
+impl Flush {
+    const NAMES: &'static [&'static str] = &["every-write", "line", "on-exit"];
+
+    fn from_name(name: &str) -> Flush {
+        match name {
+            "every-write" => Flush::EveryWrite,
+            "on-exit" => Flush::OnExit,
+            _ => Flush::Line,
+        }
+    }
+}
+
+/// Where `Output` writes: stdout, stdout through a thread for
+/// `--io-threads`, or memory for `--expect-output` to check.
+enum Sink {
+    Stdout(std::io::StdoutLock<'static>),
+    Thread(WriteBehind),
+    Memory(Rc<RefCell<Vec<u8>>>),
+}
+
+impl Write for Sink {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        match *self {
+            Sink::Stdout(ref mut stdout) => stdout.write(bytes),
+            Sink::Thread(ref mut thread) => thread.write(bytes),
+            Sink::Memory(ref memory) => {
+                memory.borrow_mut().extend_from_slice(bytes);
+
+                Ok(bytes.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match *self {
+            Sink::Stdout(ref mut stdout) => stdout.flush(),
+            Sink::Thread(ref mut thread) => thread.flush(),
+            Sink::Memory(_) => Ok(()),
+        }
+    }
+}
+
+/// Stdout behind a buffer that is flushed as `--flush` says, and whenever
+/// the program reads, in case what it wrote was a prompt. Whatever is left
+/// is flushed when the last clone is dropped.
+#[derive(Clone)]
+struct Output {
+    buffer: Rc<RefCell<BufWriter<CrLf<Sink>>>>,
+    flush: Flush,
+}
+
+impl Output {
+    /// Writes each `\n` as `\r\n` if `crlf` is true.
+    fn new(flush: Flush, crlf: bool) -> Output {
+        Output::to(Sink::Stdout(std::io::stdout().lock()), flush, crlf)
+    }
+
+    /// Leaves writing to stdout to a thread, which a write only waits for
+    /// once it is far behind, and not past `deadline`.
+    fn threaded(flush: Flush, crlf: bool, deadline: Option<Instant>)
+    -> Output {
+        let thread = WriteBehind::spawn(std::io::stdout(), deadline);
+
+        Output::to(Sink::Thread(thread), flush, crlf)
+    }
+
+    /// Keeps what is written in memory instead, once it is flushed.
+    fn to_memory(crlf: bool) -> (Output, Rc<RefCell<Vec<u8>>>) {
+        let memory = Rc::new(RefCell::new(Vec::new()));
+
+        (Output::to(Sink::Memory(memory.clone()), Flush::OnExit, crlf),
+         memory)
+    }
+
+    fn to(sink: Sink, flush: Flush, crlf: bool) -> Output {
+        let buffer = BufWriter::new(CrLf::new(sink, crlf));
+
+        Output { buffer: Rc::new(RefCell::new(buffer)), flush }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        let mut buffer = self.buffer.borrow_mut();
+
+        buffer.write_all(bytes)?;
+
+        match self.flush {
+            Flush::EveryWrite => buffer.flush()?,
+            Flush::Line if bytes.contains(&b'\n') => buffer.flush()?,
+            _ => (),
+        }
+
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.buffer.borrow_mut().flush()
+    }
+}
+
+/// Input that flushes `output` before each read.
+struct FlushFirst<R> {
+    inner: R,
+    output: Output,
+}
+
+impl<R: Read> Read for FlushFirst<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let _ = self.output.flush();
+
+        self.inner.read(buffer)
+    }
+}
+
+/// Input or output that counts the bytes passing through it.
+struct Counted<T> {
+    inner: T,
+    count: Rc<Cell<u64>>,
+}
+
+impl<T> Counted<T> {
+    fn new(inner: T) -> (Counted<T>, Rc<Cell<u64>>) {
+        let count = Rc::new(Cell::new(0));
+
+        (Counted { inner, count: count.clone() }, count)
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let length = self.inner.read(buffer)?;
+
+        self.count.set(self.count.get() + length as u64);
+
+        Ok(length)
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        let length = self.inner.write(bytes)?;
+
+        self.count.set(self.count.get() + length as u64);
+
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// When waiting for I/O on a thread stops the program: waiting counts
+/// towards `--sandbox`'s time limit too.
+fn io_deadline(matches: &clap::ArgMatches) -> Option<Instant> {
+    if matches.is_present("sandbox") {
+        Some(Instant::now() + Duration::from_secs(SANDBOX_SECONDS))
+    } else {
+        None
+    }
+}
+
+/// Which of `--sandbox`'s limits a run has reached, if any, after `steps`
+/// instructions. Its tape and output limits stop the program themselves.
+fn sandbox_limit(steps: u64, start: Instant) -> Option<Status> {
+    if steps >= SANDBOX_STEPS {
+        Some(Status::StepLimit)
+    } else if steps.is_multiple_of(4096)
+              && start.elapsed() >= Duration::from_secs(SANDBOX_SECONDS) {
+        // Reading the clock every step would slow the run down.
+        Some(Status::Timeout)
+    } else {
+        None
+    }
+}
+
+/// How often `--checkpoint-every` says to take a checkpoint, if it is
+/// given: a number of instructions, or of seconds if it ends in `s`.
+fn checkpoint_policy(matches: &clap::ArgMatches)
+-> Result<Option<Policy>, Failure> {
+    let every = match matches.value_of("checkpoint-every") {
+        Some(every) => every,
+        None => return Ok(None),
+    };
+    let policy = match every.strip_suffix('s') {
+        Some(seconds) => seconds.parse().ok()
+            .filter(|&s: &f64| s > 0.0 && s.is_finite())
+            .map(|s| Policy::Interval(Duration::from_secs_f64(s))),
+        None => every.parse().ok().filter(|&n| n > 0).map(Policy::Steps),
+    };
+
+    match policy {
+        Some(policy) => Ok(Some(policy)),
+        None => Err(Failure::Usage(format!(
+            "--checkpoint-every takes a number of instructions or of seconds, \
+             such as 1000000 or 30s, not '{}'", every
+        ))),
+    }
+}
+
+/// Where `--checkpoint-every` saves checkpoints and `--resume` reads them.
+fn checkpoint_path(matches: &clap::ArgMatches) -> PathBuf {
+    match matches.value_of("checkpoint-file") {
+        Some(filename) => PathBuf::from(filename),
+        None => {
+            PathBuf::from(format!("{}.checkpoint",
+                                  matches.value_of("FILE").unwrap()))
+        }
+    }
+}
+
+/// Replaces the checkpoint at `path` with `checkpoint`, writing it in full
+/// elsewhere first so a crash partway leaves the last one whole.
+fn save_checkpoint(path: &Path, checkpoint: &Checkpoint)
+-> std::io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+
+    partial.push(".tmp");
+    std::fs::write(&partial, checkpoint.encode())?;
+    std::fs::rename(&partial, path)
+}
+
+/// The checkpoint `--resume` carries on from, if it is given and there is
+/// one yet. Without one, the run starts from the beginning, so the same
+/// command both starts a run and carries it on after a crash.
+fn resume_from(matches: &clap::ArgMatches)
+-> Result<Option<Checkpoint>, Failure> {
+    if !matches.is_present("resume") {
+        return Ok(None);
+    }
+
+    let path = checkpoint_path(matches);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(Failure::Error(format!(
+                "could not read checkpoint '{}': {}", path.display(), e
+            )));
+        }
+    };
+
+    match Checkpoint::decode(&bytes) {
+        Some(checkpoint) => Ok(Some(checkpoint)),
+        None => Err(Failure::Error(format!("could not read checkpoint '{}'",
+                                           path.display()))),
+    }
+}
+
+/// What `--metrics-out` needs to know about a run besides how it ended.
+struct Meter {
+    start: Instant,
+    read: Rc<Cell<u64>>,
+    written: Rc<Cell<u64>>,
+}
+
+impl Meter {
+    /// Writes the run's metrics to the file `--metrics-out` names, if any.
+    fn finish(&self, matches: &clap::ArgMatches, status: Status,
+              instructions: Option<u64>, tape: &[u32]) {
+        let filename = match matches.value_of("metrics-out") {
+            Some(f) => f,
+            None => return,
+        };
+        let tape_cells = tape.iter().rposition(|&c| c != 0)
+            .map_or(0, |i| i + 1);
+        let metrics = RunMetrics {
+            status,
+            instructions,
+            input_bytes: self.read.get(),
+            output_bytes: self.written.get(),
+            tape_cells,
+            wall_time: self.start.elapsed(),
+        };
+
+        write_output(Some(filename), metrics.to_json());
+    }
+}
+
+/// Whether `error` is `--max-memory`'s or `--max-output`'s.
+fn is_limit(error: &std::io::Error) -> bool {
+    matches!(error.kind(), std::io::ErrorKind::OutOfMemory
+                           | std::io::ErrorKind::QuotaExceeded)
+}
+
+/// Why `bfi FILE` didn't finish, which decides what it exits with.
+enum Failure {
+    /// The options given can't be used together.
+    Usage(String),
+    /// The program stopped with an error.
+    Error(String),
+    /// One of the run's limits stopped the program.
+    Limit(String),
+    /// The program's output wasn't what `--expect-output` said.
+    Mismatch(String),
+}
+
+impl Failure {
+    /// A limit if `error` is the tape's or the output's, and otherwise an
+    /// error.
+    fn io(error: std::io::Error) -> Failure {
+        if is_limit(&error) {
+            Failure::Limit(error.to_string())
+        } else {
+            Failure::Error(error.to_string())
+        }
+    }
+
+    fn message(&self) -> &str {
+        match *self {
+            Failure::Usage(ref m) | Failure::Error(ref m)
+            | Failure::Limit(ref m) | Failure::Mismatch(ref m) => m,
+        }
+    }
+
+    fn exit_code(&self) -> i32 {
+        match *self {
+            Failure::Usage(_) => EXIT_USAGE,
+            Failure::Error(_) => EXIT_ERROR,
+            Failure::Limit(_) => EXIT_LIMIT,
+            Failure::Mismatch(_) => EXIT_MISMATCH,
+        }
+    }
+}
+
+/// Checks the output a program wrote to memory against `expected`, the
+/// contents of `--expect-output`'s file.
+fn check_output(matches: &clap::ArgMatches, expected: &[u8], actual: &[u8])
+-> Result<(), Failure> {
+    if expected == actual {
+        return Ok(());
+    }
+
+    Err(Failure::Mismatch(format!(
+        "output doesn't match '{}':\n{}",
+        matches.value_of("expect-output").unwrap(),
+        brainfuck::diff::lines(&String::from_utf8_lossy(expected),
+                               &String::from_utf8_lossy(actual))
+            .trim_end_matches('\n')
+    )))
+}
+
+/// Checks the tape a run left against `--diff-against`'s, if it is given.
+fn diff_tape(matches: &clap::ArgMatches, tape: &[u32], pointer: usize)
+-> Result<(), Failure> {
+    let filename = match matches.value_of("diff-against") {
+        Some(f) => f,
+        None => return Ok(()),
+    };
+    let expected = dump::read_tape(filename).map_err(Failure::Error)?;
+    let diff = dump::diff(&expected, &(tape.to_vec(), Some(pointer)));
+
+    if diff.is_empty() {
+        return Ok(());
+    }
+
+    Err(Failure::Mismatch(format!("tape doesn't match '{}':\n{}", filename,
+                                  diff.trim_end_matches('\n'))))
+}
+
+/// `error` rendered against the source at `span`, where the program
+/// stopped, and where `map` says that part of it was generated from.
+fn stopped_at(matches: &clap::ArgMatches, error: &std::io::Error, span: Span,
+              source: &str, map: Option<&SourceMap>) -> String {
+    let filename = matches.value_of("FILE").unwrap();
+    let mut message = render(&Diagnostic::error(error.to_string(), span),
+                             filename, source);
+
+    if let Some((file, line)) = map.and_then(|m| m.lookup(span.start)) {
+        message += &format!("\nnote: generated from {}:{}", file, line);
+    }
+
+    message
+}
+
+/// Optimizes and runs a program loaded by `bfi FILE`, returning why if it
+/// was stopped by a limit or trap rather than by finishing or running out
+/// of input.
+fn execute(matches: &clap::ArgMatches, loaded: Loaded, input: Input)
+-> Result<(), Failure> {
+    let Loaded { source, program, compiled, cached, cache_key } = loaded;
+    let map = source_map(matches);
+    let overflow = Overflow::from_name(matches.value_of("overflow").unwrap())
+        .unwrap();
+
+    // A compiled program was optimized assuming arithmetic wraps.
+    if compiled && overflow != program.overflow {
+        return Err(Failure::Usage(format!(
+            "--overflow {} can't apply to a compiled program",
+            matches.value_of("overflow").unwrap()
+        )));
+    }
+
+    if let (true, Some(name)) = (compiled, preloaded(matches)) {
+        return Err(Failure::Usage(format!("--{} can't apply to a compiled \
+                                           program, which was optimized \
+                                           for a tape of zeros", name)));
+    }
+
+    let pipeline = if compiled { Pipeline::new() } else { pipeline(matches) };
+    let mut remarks = Vec::new();
+    let mut program = if cached {
+        program.with_overflow(overflow)
+    } else {
+        pipeline.run_with_remarks(program.with_overflow(overflow),
+                                  &mut remarks)
+    };
+
+    if let Some(key) = cache_key {
+        cache::put(key, &program, &source);
+    }
+
+    if matches.is_present("verbose") {
+        let filename = matches.value_of("FILE").unwrap();
+
+        for remark in &remarks {
+            let (line, column) = brainfuck::ir::line_col(&source,
+                                                         remark.span.start);
+            let (end_line, end_column) =
+                brainfuck::ir::line_col(&source, remark.span.end);
+
+            eprintln!("{}:{}:{}-{}:{}: {}: {}", filename, line, column,
+                      end_line, end_column, remark.pass, remark.message);
+        }
+    }
+
+    let input: Box<dyn Read + Send> = match input {
+        Input::Bytes(mut bytes) => {
+            let mut consumed = 0;
+
+            if matches.is_present("partial-eval") {
+                let residual = brainfuck::eval::partially_evaluate(
+                    &program, &bytes, PARTIAL_EVAL_STEPS
+                );
+
+                program = pipeline.run(residual.program);
+                consumed = residual.consumed;
+            }
+
+            Box::new(std::io::Cursor::new(bytes.split_off(consumed)))
+        }
+        Input::Reader(reader) => reader,
+    };
+
+    match matches.value_of("emit") {
+        Some("ir") => {
+            write_output(None, brainfuck::ir::dump(&program, &source));
+
+            return Ok(());
+        }
+        Some("bf") => {
+            let code = brainfuck::lower::to_brainfuck(&program).map_err(|e| {
+                let filename = matches.value_of("FILE").unwrap();
+
+                Failure::Error(render(&Diagnostic::error(e.to_string(),
+                                                         e.span),
+                                      filename, &source))
+            })?;
+
+            write_output(None, code + "\n");
+
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    let sandbox = matches.is_present("sandbox");
+    let encoding = OutputEncoding::from_name(
+        matches.value_of("output-encoding").unwrap()
+    ).unwrap();
+
+    if sandbox && extensions(matches).fork {
+        return Err(Failure::Usage("--sandbox can't limit --ext fork's \
+                                   threads".to_string()));
+    }
+
+    if sandbox && matches.value_of("backend").unwrap() != "interpreter" {
+        return Err(Failure::Usage("--sandbox needs --backend \
+                                   interpreter".to_string()));
+    }
+
+    if extensions(matches).fork {
+        if matches.value_of("backend").unwrap() != "interpreter" {
+            return Err(Failure::Usage("--ext fork needs --backend \
+                                       interpreter".to_string()));
+        }
+
+        if matches.is_present("metrics-out") {
+            return Err(Failure::Usage("--metrics-out can't measure --ext \
+                                       fork".to_string()));
+        }
+
+        if encoding != OutputEncoding::Utf8 {
+            return Err(Failure::Usage("--output-encoding can't apply to \
+                                       --ext fork".to_string()));
+        }
+
+        for &name in &["load-tape", "cells", "cell", "dump-tape",
+                       "expect-output", "io-threads", "checkpoint-every",
+                       "resume", "diff-against"] {
+            if matches.is_present(name) {
+                return Err(Failure::Usage(format!(
+                    "--{} can't apply to --ext fork's threads", name
+                )));
+            }
+        }
+
+        // Threads share stdout, which flushes itself after each line.
+        let stdout = CrLf::new(std::io::stdout(), crlf(matches) == Some(true));
+
+        return brainfuck::fork::run(&program, stdout, input)
+            .map_err(Failure::io);
+    }
+
+    let flush = Flush::from_name(matches.value_of("flush").unwrap());
+    let crlf = crlf(matches) == Some(true);
+    let expected = matches.value_of("expect-output").map(read_bytes);
+    let (output, captured) = match expected {
+        Some(_) => {
+            let (output, memory) = Output::to_memory(crlf);
+
+            (output, Some(memory))
+        }
+        None if matches.is_present("io-threads") => {
+            (Output::threaded(flush, crlf, io_deadline(matches)), None)
+        }
+        None => (Output::new(flush, crlf), None),
+    };
+
+    let resumed = resume_from(matches)?;
+    let mut input = input;
+
+    if let Some(ref checkpoint) = resumed {
+        let skipped = std::io::copy(&mut (&mut input)
+                                        .take(checkpoint.input_bytes),
+                                    &mut std::io::sink())
+            .map_err(Failure::io)?;
+
+        if skipped < checkpoint.input_bytes {
+            return Err(Failure::Error(format!(
+                "the input ends before where the checkpoint was taken, {} \
+                 bytes in", checkpoint.input_bytes
+            )));
+        }
+    }
+
+    let input = FlushFirst { inner: input, output: output.clone() };
+    let (input, read) = Counted::new(input);
+    let (output, written) = Counted::new(output);
+    let tape_limit = match matches.value_of("max-memory") {
+        Some(_) => Some(number(matches, "max-memory")),
+        None if sandbox => Some(SANDBOX_CELLS),
+        None => None,
+    };
+    let output_limit = match matches.value_of("max-output") {
+        Some(_) => Some(number(matches, "max-output")),
+        None if sandbox => Some(SANDBOX_OUTPUT),
+        None => None,
+    };
+    let tape = load_tape(matches, tape_limit)?;
+    let seed = match matches.value_of("seed") {
+        Some(_) => number(matches, "seed"),
+        None => clock_seed(),
+    };
+
+    if matches.value_of("backend").unwrap() == "interpreter" {
+        for &name in &["memoize", "profile-out", "profile-use"] {
+            if matches.is_present(name) {
+                return Err(Failure::Usage(format!(
+                    "--{} needs --backend vm or tiered", name
+                )));
+            }
+        }
+
+        let mut interpreter = InterpreterState::new(&program, output, input)
+            .with_strict_pointer(matches.is_present("strict-pointer"))
+            .with_output_encoding(encoding)
+            .with_seed(seed);
+
+        if let Some(cells) = tape_limit {
+            interpreter = interpreter.with_tape_limit(cells);
+        }
+
+        if let Some(bytes) = output_limit {
+            interpreter = interpreter.with_output_limit(bytes);
+        }
+
+        if let Some(ref cells) = tape {
+            interpreter = interpreter.with_tape(cells);
+        }
+
+        if let Some(ref checkpoint) = resumed {
+            interpreter.restore(checkpoint).map_err(|e| {
+                Failure::Error(format!("could not resume from '{}': {}",
+                                       checkpoint_path(matches).display(), e))
+            })?;
+        }
+
+        if let Some(policy) = checkpoint_policy(matches)? {
+            let path = checkpoint_path(matches);
+            let mut warned = false;
+
+            interpreter = interpreter.with_checkpoints(policy, move |c| {
+                if let Err(e) = save_checkpoint(&path, c) {
+                    if !warned {
+                        eprintln!("warning: could not save checkpoint '{}': \
+                                   {}", path.display(), e);
+                        warned = true;
+                    }
+                }
+            });
+        }
+
+        let meter = Meter { start: Instant::now(), read, written };
+        let mut limit = None;
+        let error = loop {
+            if sandbox {
+                limit = sandbox_limit(interpreter.steps_executed(),
+                                      meter.start);
+
+                if limit.is_some() {
+                    break None;
+                }
+            }
+
+            match interpreter.repl() {
+                Ok(()) => (),
+                // Only I/O on a thread has a deadline to miss.
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    limit = Some(Status::Timeout);
+
+                    break None;
+                }
+                Err(e) => break Some(e),
+            }
+        };
+        let status = match (limit, &error) {
+            (Some(status), _) => status,
+            _ if interpreter.span().is_none() => Status::Finished,
+            (_, Some(e)) if is_end_of_input(e) => Status::EndOfInput,
+            (_, Some(e)) if e.kind() == std::io::ErrorKind::OutOfMemory => {
+                Status::MemoryLimit
+            }
+            (_, Some(e)) if e.kind() == std::io::ErrorKind::QuotaExceeded => {
+                Status::OutputLimit
+            }
+            _ => Status::Error,
+        };
+
+        meter.finish(matches, status, Some(interpreter.steps_executed()),
+                     interpreter.tape());
+        dump_tape(matches, interpreter.tape())?;
+
+        // There is nothing left to carry on from.
+        if status == Status::Finished && (matches.is_present("resume")
+           || matches.is_present("checkpoint-every")) {
+            let _ = std::fs::remove_file(checkpoint_path(matches));
+        }
+
+        if let (Some(filename), Status::Error | Status::MemoryLimit)
+            = (matches.value_of("core-dump"), status) {
+            let snapshot = Snapshot::new(interpreter.tape(),
+                                         interpreter.pointer());
+
+            write_output(Some(filename), snapshot.encode());
+        }
+
+        let message = match (interpreter.span(), error) {
+            (Some(span), Some(e)) if !is_end_of_input(&e) => {
+                Some(stopped_at(matches, &e, span, &source, map.as_ref()))
+            }
+            _ => None,
+        };
+
+        let message = match message {
+            Some(message) => message,
+            None if status.is_limit() => {
+                format!("stopped at the sandbox's {}",
+                        status.name().replace('-', " "))
+            }
+            None => {
+                let _ = interpreter.writer_mut().flush();
+
+                diff_tape(matches, interpreter.tape(), interpreter.pointer())?;
+
+                return match (expected, captured) {
+                    (Some(expected), Some(actual)) => {
+                        check_output(matches, &expected, &actual.borrow())
+                    }
+                    _ => Ok(()),
+                };
+            }
+        };
+
+        return Err(if status.is_limit() {
+            Failure::Limit(message)
+        } else {
+            Failure::Error(message)
+        });
+    }
+
+    for &name in &["strict-pointer", "checkpoint-every", "resume"] {
+        if matches.is_present(name) {
+            return Err(Failure::Usage(format!(
+                "--{} needs --backend interpreter", name
+            )));
+        }
+    }
+
+    let mut vm = Vm::new(&program, output, input)
+        .with_output_encoding(encoding)
+        .with_seed(seed);
+
+    if let Some(cells) = tape_limit {
+        vm = vm.with_tape_limit(cells);
+    }
+
+    if let Some(bytes) = output_limit {
+        vm = vm.with_output_limit(bytes);
+    }
+
+    if let Some(ref cells) = tape {
+        vm = vm.with_tape(cells);
+    }
+
+    if matches.is_present("memoize") {
+        vm = vm.with_memoization();
+    }
+
+    if let Some(filename) = matches.value_of("profile-use") {
+        let profile = match brainfuck::profile::Profile::parse(
+            &read_source(filename)
+        ) {
+            Some(p) => p,
+            None => {
+                return Err(Failure::Error(format!("'{}' isn't a profile",
+                                                  filename)));
+            }
+        };
+
+        if profile.iterations.len() != vm.profile().iterations.len() {
+            eprintln!("warning: '{}' was recorded for a different program \
+                       or optimization level", filename);
+        }
+
+        vm = vm.with_profile(&profile);
+    }
+
+    if matches.is_present("fusion-stats") {
+        for (name, count) in vm.fusion_stats() {
+            eprintln!("fused {}: {}", name, count);
+        }
+    }
+
+    if matches.value_of("backend").unwrap() == "tiered" {
+        let threshold = matches.value_of("jit-threshold").unwrap();
+
+        match threshold.parse() {
+            Ok(t) => vm = vm.with_jit_threshold(t),
+            Err(_) => {
+                return Err(Failure::Usage(format!("invalid JIT threshold \
+                                                   '{}'", threshold)));
+            }
+        }
+    }
+
+    let meter = Meter { start: Instant::now(), read, written };
+    let result = vm.run();
+    let status = match result {
+        Ok(()) => Status::Finished,
+        Err(ref e) if e.kind() == std::io::ErrorKind::OutOfMemory => {
+            Status::MemoryLimit
+        }
+        Err(ref e) if e.kind() == std::io::ErrorKind::QuotaExceeded => {
+            Status::OutputLimit
+        }
+        Err(ref e) if is_end_of_input(e) => Status::EndOfInput,
+        Err(_) => Status::Error,
+    };
+
+    meter.finish(matches, status, None, vm.tape());
+    dump_tape(matches, vm.tape())?;
+
+    if let Some(filename) = matches.value_of("profile-out") {
+        write_output(Some(filename), vm.profile().to_text());
+    }
+
+    if let (Err(e), Status::MemoryLimit | Status::OutputLimit | Status::Error)
+        = (result, status) {
+        let message = match vm.span() {
+            Some(span) => stopped_at(matches, &e, span, &source, map.as_ref()),
+            None => return Err(Failure::io(e)),
+        };
+
+        return Err(if status.is_limit() {
+            Failure::Limit(message)
+        } else {
+            Failure::Error(message)
+        });
+    }
+
+    diff_tape(matches, vm.tape(), vm.pointer())?;
+
+    // Dropping the VM flushes its output, the last clone of it.
+    drop(vm);
+
+    match (expected, captured) {
+        (Some(expected), Some(actual)) => {
+            check_output(matches, &expected, &actual.borrow())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn asm(matches: &clap::ArgMatches) {
+    let filename = matches.value_of("FILE").unwrap();
+
+    match brainfuck::asm::assemble_file(Path::new(filename)) {
+        Ok(output) => write_output(matches.value_of("output"),
+                                   &(output + "\n")),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The language FILE is in, going by its extension unless `--from` says.
+fn source_language<'a>(matches: &'a clap::ArgMatches) -> &'a str {
+    if let Some(from) = matches.value_of("from") {
+        return from;
+    }
+
+    let filename = matches.value_of("FILE").unwrap();
+
+    match Path::new(filename).extension().and_then(|e| e.to_str()) {
+        Some("bfa") => "asm",
+        Some("bf") | Some("b") => "brainfuck",
+        Some("spoon") => "spoon",
+        _ => "mini",
+    }
+}
+
+fn compile(matches: &clap::ArgMatches) {
+    let filename = matches.value_of("FILE").unwrap();
+    let output = matches.value_of("output");
+
+    let result = match source_language(matches) {
+        "asm" => brainfuck::asm::assemble_with_map(&read_source(filename),
+                                                   Path::new(filename))
+            .map(|(source, map)| (source, Some(map)))
+            .map_err(|e| e.to_string()),
+        "mini" => brainfuck::mini::compile_with_map(&read_source(filename),
+                                                    filename)
+            .map(|(source, map)| (source, Some(map)))
+            .map_err(|e| format!("{}:{}", filename, e)),
+        _ => Ok((read_source(filename), None)),
+    };
+    let (source, map) = match result {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(path) = matches.value_of("source-map") {
+        match map {
+            Some(map) => write_output(Some(path), map.to_text()),
+            None => {
+                eprintln!("--source-map needs a program in asm or mini");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if !output.is_some_and(|o| o.ends_with(".bfc")) {
+        return write_output(output, &source);
+    }
+
+    let dialect = match source_language(matches) {
+        "spoon" => Dialect::Spoon,
+        _ => Dialect::Brainfuck,
+    };
+    let program = match dialect.parse_with(&source, extensions(matches)) {
+        Ok(p) => pipeline(matches).run(p),
+        Err(e) => {
+            eprintln!("could not parse file '{}': {}", filename, e);
+            std::process::exit(1);
+        }
+    };
+
+    write_output(output, brainfuck::bfc::encode(&program, &source));
+}
+
+fn decompile(matches: &clap::ArgMatches) {
+    let program = parse(matches).program;
+
+    write_output(matches.value_of("output"),
+                 brainfuck::decompile::decompile(&program));
+}
+
+fn disasm(matches: &clap::ArgMatches) {
+    let filename = matches.value_of("FILE").unwrap();
+    let bytes = read_bytes(filename);
+    let (program, source) = match brainfuck::bfc::decode(&bytes) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            eprintln!("could not load file '{}': {}", filename, e);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+
+    write_output(matches.value_of("output"),
+                 brainfuck::bfc::disassemble(&program, &source));
+}
+
+fn verify(matches: &clap::ArgMatches) {
+    let loaded = parse(matches);
+    let optimized = pipeline(matches).run(loaded.program.clone());
+    let input = match matches.value_of("input") {
+        Some(filename) => read_bytes(filename),
+        None => {
+            let mut bytes = Vec::new();
+
+            if let Err(e) = std::io::stdin().read_to_end(&mut bytes) {
+                eprintln!("could not read input: {}", e);
+                std::process::exit(1);
+            }
+
+            bytes
+        }
+    };
+
+    match brainfuck::verify::verify(&loaded.program, &optimized, &input) {
+        Ok(runs) => {
+            let names: Vec<&str> = runs.iter().map(|r| r.backend).collect();
+
+            println!("{} agree: {} bytes of output, {} cells", names.join(", "),
+                     runs[0].output.len(), runs[0].tape.len());
+        }
+        Err(divergence) => {
+            eprintln!("{}", divergence);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A step count, which may be written like `1e6`.
+fn steps(matches: &clap::ArgMatches, name: &str) -> u64 {
+    let value = matches.value_of(name).unwrap();
+
+    match value.parse::<f64>() {
+        Ok(n) if n >= 0.0 && n.fract() == 0.0 => n as u64,
+        _ => {
+            eprintln!("invalid value '{}' for --{}", value, name);
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// Random printable input, starting with the empty input.
+fn random_inputs(count: usize, max_length: u64, seed: u64) -> Vec<Vec<u8>> {
+    let mut rng = brainfuck::synth::Rng::new(seed);
+
+    (0..count)
+        .map(|i| {
+            let length = if i == 0 { 0 } else { rng.range(0, max_length) };
+
+            (0..length).map(|_| match rng.range(0, 0x5f) {
+                0x5f => b'\n',
+                n => n as u8 + 0x20,
+            }).collect()
+        })
+        .collect()
+}
+
+fn equiv(matches: &clap::ArgMatches) {
+    let pipeline = pipeline(matches);
+    let first = matches.value_of("FIRST").unwrap();
+    let second = matches.value_of("SECOND").unwrap();
+    let a = pipeline.run(parse_file(first, matches).program);
+    let b = pipeline.run(parse_file(second, matches).program);
+    let max_steps = steps(matches, "steps");
+    let mut inputs = Vec::new();
+
+    for source in matches.values_of("inputs").unwrap() {
+        if source == "fuzz" {
+            inputs.extend(random_inputs(number(matches, "count"),
+                                        number(matches, "max-length"),
+                                        number(matches, "seed")));
+        } else {
+            inputs.push(read_bytes(source));
+        }
+    }
+
+    let mut undecided = 0;
+
+    for input in &inputs {
+        match brainfuck::verify::compare_bounded(&a, &b, input, max_steps) {
+            Comparison::Same => (),
+            Comparison::Undecided => undecided += 1,
+            Comparison::Differs { offset, first: x, second: y } => {
+                let printed = |b: Option<u8>| match b {
+                    Some(b) => format!("{:?}", b as char),
+                    None => "nothing more".to_string(),
+                };
+
+                println!("not equivalent: on input {:?}, byte {} of the \
+                          output is {} from '{}' but {} from '{}'",
+                         String::from_utf8_lossy(input), offset, printed(x),
+                         first, printed(y), second);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    print!("equivalent on {} inputs", inputs.len() - undecided);
+
+    if undecided > 0 {
+        print!("; {} more undecided within {} steps", undecided, max_steps);
+    }
+
+    println!();
+}
+
+/// Prints how the optimized IR of SECOND differs from that of FIRST, and
+/// exits with 1 if it does, as `diff` would.
+fn diff(matches: &clap::ArgMatches) {
+    let pipeline = pipeline(matches);
+    let first = parse_file(matches.value_of("FIRST").unwrap(), matches);
+    let second = parse_file(matches.value_of("SECOND").unwrap(), matches);
+    let diff = brainfuck::diff::programs(&pipeline.run(first.program),
+                                         &first.source,
+                                         &pipeline.run(second.program),
+                                         &second.source);
+
+    if !diff.is_empty() {
+        print!("{}", diff);
+        std::process::exit(1);
+    }
+}
+
+/// Runs one program of `bfi pipe`, reading from the program before it, or
+/// stdin if it is the first, and writing to the one after it, or stdout if
+/// it is the last. Stops early if the program after it has finished.
+fn pipe_stage(program: &Program, input: Option<Receiver<u8>>,
+              output: Option<SyncSender<u8>>) {
+    let mut stdin = Newlines::new(std::io::stdin());
+    let stdout = std::io::stdout();
+    let closed = Cell::new(false);
+    let reader = InputFn(|| match input {
+        Some(ref receiver) => receiver.recv().ok(),
+        None => stdin.read_byte().ok().and_then(|b| b),
+    });
+    let writer = OutputFn(|bytes: &[u8]| {
+        let written = match output {
+            Some(ref sender) => bytes.iter().all(|&b| sender.send(b).is_ok()),
+            None => stdout.lock().write_all(bytes).is_ok(),
+        };
+
+        if !written {
+            closed.set(true);
+        }
+    });
+    let mut interpreter = InterpreterState::new(program, writer, reader);
+
+    while !closed.get() && interpreter.repl().is_ok() { }
+}
+
+fn pipe(matches: &clap::ArgMatches) {
+    let filenames: Vec<&str> = matches.values_of("FILES").unwrap().collect();
+    let pipeline = pipeline(matches);
+    let mut programs: Vec<Program> = filenames.iter()
+        .map(|f| {
+            let loaded = parse_file(f, matches);
+
+            if loaded.compiled {
+                loaded.program
+            } else {
+                pipeline.run(loaded.program)
+            }
+        })
+        .collect();
+    let last = programs.pop().unwrap();
+    let mut input = None;
+    let mut stages = Vec::new();
+
+    for program in programs {
+        let (sender, receiver) = mpsc::sync_channel(PIPE_BUFFER);
+        let stage_input = input.replace(receiver);
+
+        stages.push(std::thread::spawn(move || {
+            pipe_stage(&program, stage_input, Some(sender))
+        }));
+    }
+
+    pipe_stage(&last, input, None);
+
+    if let Err(e) = std::io::stdout().flush() {
+        eprintln!("could not write output: {}", e);
+        std::process::exit(1);
+    }
+
+    for stage in stages {
+        let _ = stage.join();
+    }
+}
+
+/// Runs `program` in the interpreter on `input`, returning what it wrote.
+fn capture(program: &Program, input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+
+    {
+        let mut interpreter = InterpreterState::new(program, &mut output,
+                                                    input);
+
+        while interpreter.repl().is_ok() { }
+    }
+
+    output
+}
+
+/// Checks one program in a snapshot directory against its `.out` file, or
+/// overwrites that file with what it printed if `bless` is set. Returns a
+/// description of the failure if it didn't match.
+fn test_one(path: &Path, pipeline: &Pipeline, bless: bool)
+-> Result<(), String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read file: {}", e))?;
+    let program = Dialect::Brainfuck.parse(&source)
+        .map_err(|e| format!("could not parse file: {}", e))?;
+    let input = read_input_for(path)?;
+    let actual = capture(&pipeline.run(program), &input);
+    let expected_path = path.with_extension("out");
+
+    if bless {
+        return std::fs::write(&expected_path, &actual)
+            .map_err(|e| format!("could not write expected output: {}", e));
+    }
+
+    let expected = std::fs::read(&expected_path)
+        .map_err(|e| format!("could not read expected output: {}", e))?;
+
+    if expected == actual {
+        return Ok(());
+    }
+
+    Err(brainfuck::diff::lines(&String::from_utf8_lossy(&expected),
+                               &String::from_utf8_lossy(&actual)))
+}
+
+/// The `.b` and `.bf` files in DIR, sorted by name.
+fn programs_in(matches: &clap::ArgMatches) -> Vec<PathBuf> {
+    let directory = matches.value_of("DIR").unwrap();
+    let mut paths: Vec<_> = match std::fs::read_dir(directory) {
+        Ok(entries) => entries.filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                matches!(p.extension().and_then(|e| e.to_str()),
+                         Some("b") | Some("bf"))
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("could not read directory '{}': {}", directory, e);
+            std::process::exit(1);
+        }
+    };
+
+    paths.sort();
+
+    paths
+}
+
+/// Reads the input for a program in a snapshot or batch directory from its
+/// `.in` file, if it has one.
+fn read_input_for(path: &Path) -> Result<Vec<u8>, String> {
+    match std::fs::read(path.with_extension("in")) {
+        Ok(bytes) => Ok(bytes),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Ok(Vec::new())
+        }
+        Err(e) => Err(format!("could not read input: {}", e)),
+    }
+}
+
+fn test(matches: &clap::ArgMatches) {
+    let paths = programs_in(matches);
+    let pipeline = pipeline(matches);
+    let bless = matches.is_present("bless");
+    let mut failures = Vec::new();
+
+    for path in &paths {
+        match test_one(path, &pipeline, bless) {
+            Ok(()) if bless => println!("{} ... blessed", path.display()),
+            Ok(()) => println!("{} ... ok", path.display()),
+            Err(e) => {
+                println!("{} ... FAILED", path.display());
+                failures.push((path, e));
+            }
+        }
+    }
+
+    for &(path, ref failure) in &failures {
+        println!("\n---- {} ----\n{}", path.display(), failure.trim_end());
+    }
+
+    println!("\n{} passed, {} failed", paths.len() - failures.len(),
+             failures.len());
+
+    if !failures.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Limits on each program `bfi batch` and `bfi serve` run.
+#[derive(Clone, Copy, Default)]
+struct Limits {
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    /// The most bytes of output; the program is stopped instead of
+    /// printing more.
+    max_output: Option<u64>,
+    /// The most cells the tape may grow to; the program is stopped instead
+    /// of writing past them.
+    max_cells: Option<usize>,
+}
+
+/// Runs `program` on `input` until it finishes or hits one of `limits`,
+/// returning a JSON object with its status, output and stats. The timeout
+/// and elapsed time count from `start`, so they can cover parsing and
+/// optimizing the program too.
+fn run_limited(program: &Program, input: &[u8], limits: &Limits,
+               start: Instant) -> serde_json::Value {
+    let mut output = Vec::new();
+    let mut steps = 0;
+    let mut error = None;
+    let (status, cells) = {
+        let mut interpreter = InterpreterState::new(program, &mut output,
+                                                    input);
+
+        if let Some(bytes) = limits.max_output {
+            interpreter = interpreter.with_output_limit(bytes);
+        }
+
+        if let Some(cells) = limits.max_cells {
+            interpreter = interpreter.with_tape_limit(cells);
+        }
+
+        let status = loop {
+            if interpreter.span().is_none() {
+                break "ok";
+            }
+
+            if limits.max_steps.is_some_and(|m| steps >= m) {
+                break "step-limit";
+            }
+
+            if limits.timeout.is_some_and(|t| start.elapsed() >= t) {
+                break "timeout";
+            }
+
+            if let Err(e) = interpreter.repl() {
+                if is_end_of_input(&e) {
+                    break "end-of-input";
+                }
+
+                if e.kind() == std::io::ErrorKind::QuotaExceeded {
+                    break "output-limit";
+                }
+
+                if e.kind() == std::io::ErrorKind::OutOfMemory {
+                    break "memory-limit";
+                }
+
+                if interpreter.span().is_some() {
+                    error = Some(e.to_string());
+
+                    break "error";
+                }
+            }
+
+            steps += 1;
+        };
+        let cells = interpreter.tape().iter()
+            .rposition(|&c| c != 0)
+            .map_or(0, |i| i + 1);
+
+        (status, cells)
+    };
+    let output_bytes = output.len();
+
+    json!({
+        "status": status,
+        "error": error,
+        "output": String::from_utf8_lossy(&output),
+        "stats": {
+            "steps": steps,
+            "output_bytes": output_bytes,
+            "cells": cells,
+            "elapsed_ms": start.elapsed().as_secs_f64() * 1000.0,
+        },
+    })
+}
+
+/// Loads one program of `bfi batch`, and its `.in` file.
+fn batch_load(path: &Path, pipeline: &Pipeline)
+-> Result<(Program, Vec<u8>), String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read file: {}", e))?;
+    let program = Dialect::Brainfuck.parse(&source)
+        .map_err(|e| format!("could not parse file: {}", e))?;
+
+    Ok((pipeline.run(program), read_input_for(path)?))
+}
+
+/// Runs one program of `bfi batch`, returning its entry in the report.
+fn batch_one(path: &Path, loaded: &Result<(Program, Vec<u8>), String>,
+             limits: &Limits) -> serde_json::Value {
+    let path = path.display().to_string();
+
+    match *loaded {
+        Ok((ref program, ref input)) => {
+            let mut result = run_limited(program, input, limits,
+                                         Instant::now());
+
+            result["path"] = json!(path);
+
+            result
+        }
+        Err(ref e) => json!({ "path": path, "status": "error", "error": e }),
+    }
+}
+
+fn batch(matches: &clap::ArgMatches) {
+    let paths = programs_in(matches);
+    let pipeline = pipeline(matches);
+    let loaded: Vec<_> = paths.iter().map(|p| batch_load(p, &pipeline))
+        .collect();
+    let limits = Limits {
+        max_steps: matches.value_of("max-steps")
+            .map(|_| number(matches, "max-steps")),
+        timeout: matches.value_of("timeout")
+            .map(|_| Duration::from_secs_f64(number(matches, "timeout"))),
+        max_output: matches.value_of("max-output")
+            .map(|_| number(matches, "max-output")),
+        ..Limits::default()
+    };
+    let jobs = match matches.value_of("jobs") {
+        Some(_) => number(matches, "jobs"),
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    let next = AtomicUsize::new(0);
+    let mut results = vec![serde_json::Value::Null; paths.len()];
+    let finished = Mutex::new(&mut results);
+
+    std::thread::scope(|scope| {
+        for _ in 0..std::cmp::max(1, jobs) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+
+                if i >= paths.len() {
+                    break;
+                }
+
+                let result = batch_one(&paths[i], &loaded[i], &limits);
+
+                eprintln!("{} ... {}", paths[i].display(), result["status"]
+                    .as_str()
+                    .unwrap());
+                finished.lock().unwrap()[i] = result;
+            });
+        }
+    });
+
+    let mut summary = serde_json::Map::new();
+
+    for result in &results {
+        let status = result["status"].as_str().unwrap().to_string();
+        let count = summary.entry(status).or_insert(json!(0));
+
+        *count = json!(count.as_u64().unwrap() + 1);
+    }
+
+    let report = json!({ "summary": summary, "programs": results });
+
+    write_output(matches.value_of("output"),
+                 serde_json::to_string_pretty(&report).unwrap() + "\n");
+}
+
+/// The most bytes `bfi serve` accepts in a request body.
+const MAX_REQUEST: usize = 1 << 20;
+
+/// Handles a `POST /run` to `bfi serve`, returning the status code and JSON
+/// body of the response.
+fn serve_run(body: &[u8], level: u32, limits: &Limits)
+-> (u16, serde_json::Value) {
+    let start = Instant::now();
+    let request: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(r) => r,
+        Err(e) => return (400, json!({ "error": e.to_string() })),
+    };
+    let source = match request["source"].as_str() {
+        Some(s) => s,
+        None => return (400, json!({ "error": "missing \"source\"" })),
+    };
+    let input = request["input"].as_str().unwrap_or("");
+    let dialect = match request["dialect"].as_str() {
+        Some(name) => match Dialect::from_name(name) {
+            Some(d) => d,
+            None => return (400, json!({
+                "error": format!("unknown dialect '{}'", name),
+            })),
+        },
+        None => Dialect::Brainfuck,
+    };
+
+    let mut registry = Registry::default();
+
+    // Evaluating the start of the program ahead of time is running it, so
+    // it gets no more steps than the program itself.
+    if let Some(max_steps) = limits.max_steps {
+        registry.register("prefix",
+                          move || Box::new(InputFreePrefix { max_steps }));
+    }
+
+    match dialect.parse(source) {
+        Ok(program) => {
+            let program = registry.preset(level).run(program);
+
+            (200, run_limited(&program, input.as_bytes(), limits, start))
+        }
+        Err(e) => (200, json!({ "status": "error", "error": e.to_string() })),
+    }
+}
+
+/// Reads one HTTP request from `stream` and answers it.
+fn serve_one(stream: TcpStream, level: u32, limits: &Limits)
+-> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+
+    reader.read_line(&mut line)?;
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let target = parts.next().unwrap_or("").to_string();
+    let mut length = 0;
+
+    loop {
+        let mut header = String::new();
+
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(usize::MAX);
+            }
+        }
+    }
+
+    let (code, body) = match (&method[..], &target[..]) {
+        (_, "/run") if length > MAX_REQUEST => {
+            (413, json!({ "error": "request too large" }))
+        }
+        ("POST", "/run") => {
+            let mut body = vec![0; length];
+
+            reader.read_exact(&mut body)?;
+            serve_run(&body, level, limits)
+        }
+        // A browser's preflight check before a cross-origin POST.
+        ("OPTIONS", "/run") => (204, serde_json::Value::Null),
+        (_, "/run") => (405, json!({ "error": "use POST" })),
+        _ => (404, json!({ "error": "not found" })),
+    };
+    let reason = match code {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Payload Too Large",
+    };
+    let body = if body.is_null() { String::new() } else { body.to_string() };
+    let mut stream = &stream;
+
+    write!(stream, "HTTP/1.1 {} {}\r\n\
+                    Content-Type: application/json\r\n\
+                    Content-Length: {}\r\n\
+                    Access-Control-Allow-Origin: *\r\n\
+                    Access-Control-Allow-Methods: POST\r\n\
+                    Access-Control-Allow-Headers: Content-Type\r\n\
+                    Connection: close\r\n\r\n{}",
+           code, reason, body.len(), body)?;
+    stream.flush()
+}
+
+fn serve(matches: &clap::ArgMatches) {
+    let address = matches.value_of("bind").unwrap();
+    let level = number(matches, "opt-level");
+    let limits = Limits {
+        max_steps: Some(number(matches, "fuel")),
+        timeout: Some(Duration::from_secs_f64(number(matches, "timeout"))),
+        max_output: Some(number(matches, "max-output")),
+        max_cells: Some(number(matches, "max-memory")),
+    };
+    let listener = match TcpListener::bind(address) {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("could not listen on {}: {}", address, e);
+            std::process::exit(1);
+        }
+    };
+
+    let jobs = match matches.value_of("jobs") {
+        Some(_) => number(matches, "jobs"),
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+
+    if let Ok(address) = listener.local_addr() {
+        eprintln!("listening on http://{}/run", address);
+    }
+
+    // A fixed set of workers, each taking the next connection once it is
+    // done with the last, so a flood of requests waits in the listen queue
+    // rather than starting a thread apiece.
+    std::thread::scope(|scope| {
+        for _ in 0..std::cmp::max(1, jobs) {
+            scope.spawn(|| {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(s) => s,
+                        Err(e) => {
+                            eprintln!("could not accept connection: {}", e);
+                            continue;
+                        }
+                    };
+
+                    if let Err(e) = serve_one(stream, level, &limits) {
+                        eprintln!("error handling request: {}", e);
+                    }
+                }
+            });
+        }
+    });
+}
+
+// Colors `bfi cat` cycles through for brackets, by nesting depth.
+const BRACKET_COLORS: &[&str] = &["1;95", "1;94", "1;96", "1;92", "1;93"];
+
+/// Prints FILE with its commands colored by kind.
+fn cat(matches: &clap::ArgMatches) {
+    let filename = matches.value_of("FILE").unwrap();
+    let source = read_source(filename);
+    let dialect = Dialect::from_name(matches.value_of("dialect").unwrap())
+        .unwrap();
+    let tokens = match dialect.tokenize_with(&source, extensions(matches)) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            eprintln!("could not parse file '{}': {}", filename, e);
+            std::process::exit(1);
+        }
+    };
+    let color = match matches.value_of("color").unwrap() {
+        "always" => true,
+        "never" => false,
+        _ => std::io::stdout().is_terminal(),
+    };
+    // The escape code for each byte of the source; comments are dim.
+    let mut styles = vec!["2"; source.len()];
+    let mut open = Vec::new();
+
+    for &(token, span) in &tokens {
+        let style = match token {
+            Token::Right | Token::Left | Token::Up | Token::Down => "36",
+            Token::Increment | Token::Decrement => "32",
+            Token::Output | Token::Input => "33",
+            Token::Random | Token::Fork => "35",
+            Token::NextTape | Token::PreviousTape => "34",
+            Token::LoopStart => {
+                open.push(span);
+
+                BRACKET_COLORS[(open.len() - 1) % BRACKET_COLORS.len()]
+            }
+            Token::LoopEnd => match open.pop() {
+                Some(_) => BRACKET_COLORS[open.len() % BRACKET_COLORS.len()],
+                None => "1;41",
+            },
+        };
+
+        for s in &mut styles[span.start..span.end] {
+            *s = style;
+        }
+    }
+
+    for span in open {
+        for s in &mut styles[span.start..span.end] {
+            *s = "1;41";
+        }
+    }
+
+    let offsets = matches.is_present("offsets");
+    let width = source.len().to_string().len();
+    let mut output = String::new();
+    let mut current = None;
+
+    for (i, c) in source.char_indices() {
+        if offsets && (i == 0 || source.as_bytes()[i - 1] == b'\n') {
+            output += &format!("{:>1$} | ", i, width);
+        }
+
+        if c == '\n' {
+            if color && current.take().is_some() {
+                output += "\x1b[0m";
+            }
+        } else if color && current != Some(styles[i]) {
+            output += &format!("\x1b[0;{}m", styles[i]);
+            current = Some(styles[i]);
+        }
+
+        output.push(c);
+    }
+
+    if color && current.is_some() {
+        output += "\x1b[0m";
+    }
+
+    write_output(None, output);
+}
+
+/// Parses the value of a numeric argument, exiting if it isn't one.
+fn number<T: std::str::FromStr>(matches: &clap::ArgMatches, name: &str) -> T {
+    let value = matches.value_of(name).unwrap();
+
+    match value.parse() {
+        Ok(n) => n,
+        Err(_) => {
+            eprintln!("invalid value '{}' for --{}", value, name);
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+fn clock_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// `--seed`, or one from the clock, printed so the result can be repeated.
+fn seed(matches: &clap::ArgMatches) -> u64 {
+    match matches.value_of("seed") {
+        Some(_) => number(matches, "seed"),
+        None => {
+            let seed = clock_seed();
+
+            eprintln!("seed: {}", seed);
+
+            seed
+        }
+    }
+}
+
+fn synth(matches: &clap::ArgMatches) {
+    let options = brainfuck::synth::Options {
+        size: number(matches, "size"),
+        max_depth: number(matches, "depth"),
+        max_iterations: number(matches, "iterations"),
+        cells: number(matches, "cells"),
+    };
+
+    if options.max_iterations == 0 || options.cells == 0 {
+        eprintln!("--iterations and --cells must be at least 1");
+        std::process::exit(1);
+    }
+
+    let synthesized = brainfuck::synth::random(&options, seed(matches));
+    let output = matches.value_of("output");
+    let input_output = matches.value_of("input-output")
+        .map(|f| f.to_string())
+        .or_else(|| output.map(|o| {
+            Path::new(o).with_extension("in").to_string_lossy().into_owned()
+        }));
+
+    write_output(output, synthesized.source + "\n");
+
+    if let Some(filename) = input_output {
+        write_output(Some(&filename), &synthesized.input);
+    }
+}
+
+fn analyze(matches: &clap::ArgMatches) {
+    let Loaded { program, compiled, .. } = parse(matches);
+    let program = if compiled { program } else {
+        pipeline(matches).run(program)
+    };
+    let report = brainfuck::analysis::report(&program);
+    let total: usize = report.counts.iter().map(|c| c.1).sum();
+    let (low, high) = report.span;
+
+    println!("instructions: {}", total);
+
+    for &(kind, count) in &report.counts {
+        println!("  {:<12} {}", kind, count);
+    }
+
+    println!("loops: {} ({} balanced, {} unbalanced)",
+             report.balanced_loops + report.unbalanced_loops,
+             report.balanced_loops, report.unbalanced_loops);
+    println!("max nesting: {}", report.max_depth);
+
+    if report.span_exact {
+        println!("tape span: cells {} to {} ({} cells)", low, high,
+                 high - low + 1);
+    } else {
+        println!("tape span: at least cells {} to {} ({} cells); loops \
+                  that move the pointer make the rest unknown", low, high,
+                 high - low + 1);
+    }
+
+    println!("reads input: {}", if report.reads_input { "yes" } else { "no" });
+}
+
+fn lint(matches: &clap::ArgMatches) {
+    let filename = matches.value_of("FILE").unwrap();
+    let Loaded { source, program, .. } = parse(matches);
+    let listed = |option: &str, name: &str| {
+        matches.values_of(option).is_some_and(|mut names| {
+            names.any(|n| n == name)
+        })
+    };
+    let (mut warnings, mut errors) = (0, 0);
+
+    for lint in brainfuck::lint::check(&program) {
+        let mut diagnostic = lint.diagnostic;
+
+        if listed("deny", lint.name) {
+            diagnostic.severity = Severity::Error;
+            errors += 1;
+        } else if listed("allow", lint.name) {
+            continue;
+        } else {
+            warnings += 1;
+        }
+
+        diagnostic.message = format!("{} [{}]", diagnostic.message,
+                                     lint.name);
+        eprintln!("{}\n", render(&diagnostic, filename, &source));
+    }
+
+    if warnings + errors > 0 {
+        eprintln!("{} warnings, {} errors", warnings, errors);
+    }
+
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn obfuscate(matches: &clap::ArgMatches) {
+    let filename = matches.value_of("FILE").unwrap();
+    let source = read_source(filename);
+    let dialect = Dialect::from_name(matches.value_of("dialect").unwrap())
+        .unwrap();
+    let extensions = extensions(matches);
+    let tokens = match dialect.parse_with(&source, extensions) {
+        Ok(_) => dialect.tokenize_with(&source, extensions).unwrap(),
+        Err(e) => {
+            eprintln!("{}", render(&e.diagnostic(), filename, &source));
+            std::process::exit(1);
+        }
+    };
+    let chance = |name| {
+        let percent = number(matches, name);
+
+        if percent > 100 {
+            eprintln!("--{} must be at most 100", name);
+            std::process::exit(1);
+        }
+
+        percent
+    };
+    let options = brainfuck::obfuscate::Options {
+        no_ops: chance("no-ops"),
+        splits: chance("splits"),
+        comments: chance("comments"),
+        width: std::cmp::max(1, number(matches, "width")),
+    };
+
+    write_output(matches.value_of("output"),
+                 brainfuck::obfuscate::obfuscate(&tokens, &options,
+                                                 seed(matches)));
+}
+
+/// The extensions named by `--ext`, if the subcommand has it.
+fn extensions(matches: &clap::ArgMatches) -> Extensions {
+    let mut extensions = Extensions::default();
+
+    for name in matches.values_of("ext").into_iter().flatten() {
+        if !extensions.enable(name) {
+            eprintln!("invalid extension '{}' (available: {})", name,
+                      Extensions::NAMES.join(", "));
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+
+    extensions
+}
+
+fn ext_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("ext")
+        .long("ext")
+        .takes_value(true)
+        .use_delimiter(true)
+        .value_name("NAMES")
+        .help("Reads the commands of these extensions: rand adds ?, which \
+               stores a random byte in the current cell; fork adds \
+               Brainfork's Y; grid[=WIDTH] adds ^ and v, which move \
+               between rows of the tape; tapes adds } and {, which switch \
+               to the next and previous tape")
+}
+
+fn dialect_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("dialect")
+        .long("dialect")
+        .takes_value(true)
+        .possible_values(Dialect::NAMES)
+        .default_value("brainfuck")
+        .help("Language the program is written in")
+}
+
+fn opt_level_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("opt-level")
+        .short("O")
+        .long("opt-level")
+        .takes_value(true)
+        .default_value("2")
+        .help("Optimization preset, from 0 (none) to 3")
+}
+
+fn passes_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("passes")
+        .long("passes")
+        .takes_value(true)
+        .use_delimiter(true)
+        .help("Comma-separated optimization passes to run instead of the \
+               --opt-level preset")
+}
+
+fn source_map_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("source-map")
+        .long("source-map")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("Reports positions in the asm or mini program FILE maps the \
+               brainfuck back to, as written by bfi compile --source-map")
+}
+
+fn out_dir_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("out-dir")
+        .long("out-dir")
+        .takes_value(true)
+        .value_name("DIR")
+        .help("Writes a file in DIR, named as the shell or man expects, \
+               instead of writing to stdout")
+}
+
+fn output_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("output")
+        .short("o")
+        .long("output")
+        .takes_value(true)
+        .help("Writes the result here instead of stdout")
+}
+
+/// The arguments of `bfi FILE`, which `bfi run` takes too.
+fn run_args<'a, 'b>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app
+        .arg(clap::Arg::with_name("FILE")
+             .required(true)
+             .index(1))
+        .arg(dialect_arg())
+        .arg(ext_arg())
+        .arg(clap::Arg::with_name("seed")
+             .long("seed")
+             .takes_value(true)
+             .value_name("N")
+             .help("Seeds the random bytes ? stores, so that runs can be \
+                    repeated; defaults to the clock"))
+        .arg(clap::Arg::with_name("emit")
+             .long("emit")
+             .takes_value(true)
+             .possible_values(&["ir", "bf"])
+             .help("Prints the optimized program instead of running it, as \
+                    IR or as plain brainfuck that any interpreter can run"))
+        .arg(clap::Arg::with_name("input")
+             .long("input")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Reads the program's input from FILE instead of stdin"))
+        .arg(clap::Arg::with_name("input-string")
+             .long("input-string")
+             .takes_value(true)
+             .value_name("TEXT")
+             .conflicts_with("input")
+             .help("Gives the program TEXT as its input instead of stdin, \
+                    with escapes such as \\n and \\x00"))
+        .group(clap::ArgGroup::with_name("known-input")
+               .args(&["input", "input-string"]))
+        .arg(clap::Arg::with_name("newline")
+             .long("newline")
+             .takes_value(true)
+             .possible_values(&["lf", "crlf", "native"])
+             .help("Says how lines end outside the program: with crlf, , \
+                    reads \\r\\n as \\n and . writes \\n as \\r\\n, and \
+                    native is crlf only on Windows. By default, only a \
+                    Windows console's \\r\\n is read as \\n"))
+        .arg(clap::Arg::with_name("input-nonblocking")
+             .long("input-nonblocking")
+             .conflicts_with_all(&["watch", "partial-eval"])
+             .help("Makes , store 0 right away when no input is waiting, \
+                    instead of waiting for some"))
+        .arg(clap::Arg::with_name("io-threads")
+             .long("io-threads")
+             .conflicts_with_all(&["stream", "watch"])
+             .help("Reads input and writes output on threads of their own, \
+                    so the program keeps running while a slow terminal or \
+                    pipe catches up, and --sandbox's time limit stops it \
+                    even while it waits for one"))
+        .arg(clap::Arg::with_name("flush")
+             .long("flush")
+             .takes_value(true)
+             .possible_values(Flush::NAMES)
+             .default_value("line")
+             .help("Flushes the program's output after every write, after \
+                    each line, or only when it exits; output is also \
+                    flushed before each read"))
+        .arg(clap::Arg::with_name("tty-raw")
+             .long("tty-raw")
+             .help("Passes each key to , as it is pressed, without echoing \
+                    it, instead of waiting for Enter"))
+        .arg(clap::Arg::with_name("watch")
+             .long("watch")
+             .help("Runs the program again whenever FILE changes, replaying \
+                    the input it read the first time"))
+        .arg(clap::Arg::with_name("stream")
+             .long("stream")
+             .conflicts_with_all(&["watch", "partial-eval", "emit",
+                                   "input-nonblocking", "sandbox",
+                                   "metrics-out", "core-dump", "memoize",
+                                   "profile-out", "profile-use"])
+             .help("Runs FILE as it is read, which is stdin if FILE is -, \
+                    each loop once its ] arrives, in the interpreter and \
+                    without optimizing"))
+        .arg(clap::Arg::with_name("partial-eval")
+             .long("partial-eval")
+             .requires("known-input")
+             .help("Runs as much of the program as possible at compile time \
+                    using the known input, leaving a residual program"))
+        .arg(clap::Arg::with_name("backend")
+             .long("backend")
+             .takes_value(true)
+             .possible_values(&["interpreter", "vm", "tiered"])
+             .default_value("interpreter")
+             .help("Runs the program in the interpreter, the bytecode VM, \
+                    or the VM with hot loops compiled to native code"))
+        .arg(clap::Arg::with_name("jit-threshold")
+             .long("jit-threshold")
+             .takes_value(true)
+             .value_name("N")
+             .default_value(DEFAULT_JIT_THRESHOLD)
+             .help("Iterations before --backend tiered compiles a loop"))
+        .arg(clap::Arg::with_name("memoize")
+             .long("memoize")
+             .help("Has the VM backends remember what loops without I/O \
+                    left on the tape for each state they started from, and \
+                    skip running them from a state they have seen"))
+        .arg(clap::Arg::with_name("profile-out")
+             .long("profile-out")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Records how hot each loop was to FILE, for \
+                    --profile-use"))
+        .arg(clap::Arg::with_name("profile-use")
+             .long("profile-use")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Has --backend tiered compile the loops that were hot \
+                    in a profile from --profile-out when the program \
+                    starts, at the same --opt-level"))
+        .arg(clap::Arg::with_name("metrics-out")
+             .long("metrics-out")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Writes what the run did to FILE as JSON: how it ended, \
+                    the instructions the interpreter executed, the bytes \
+                    it read and wrote, the cells it used and how long it \
+                    took"))
+        .arg(clap::Arg::with_name("fusion-stats")
+             .long("fusion-stats")
+             .help("Reports the superinstructions the VM backends fused"))
+        .arg(clap::Arg::with_name("max-memory")
+             .long("max-memory")
+             .takes_value(true)
+             .value_name("CELLS")
+             .help("Stops the program with an error if it writes past the \
+                    first CELLS cells, instead of growing the tape"))
+        .arg(clap::Arg::with_name("max-output")
+             .long("max-output")
+             .takes_value(true)
+             .value_name("BYTES")
+             .help("Stops the program, exiting with code 3, instead of \
+                    letting it print more than BYTES"))
+        .arg(clap::Arg::with_name("load-tape")
+             .long("load-tape")
+             .takes_value(true)
+             .conflicts_with("partial-eval")
+             .value_name("FILE")
+             .help("Starts the tape with the cells in FILE: numbers \
+                    separated by commas if it ends in .csv, or else a byte \
+                    per cell"))
+        .arg(clap::Arg::with_name("cells")
+             .long("cells")
+             .takes_value(true)
+             .conflicts_with("partial-eval")
+             .value_name("LIST")
+             .help("Starts the tape with these cells, numbers separated by \
+                    commas such as 72,101,108, in place of the first of \
+                    --load-tape's"))
+        .arg(clap::Arg::with_name("cell")
+             .long("cell")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .conflicts_with("partial-eval")
+             .value_name("INDEX=VALUE")
+             .help("Starts the cell at INDEX with VALUE, after --load-tape \
+                    and --cells; may be given more than once"))
+        .arg(clap::Arg::with_name("dump-tape")
+             .long("dump-tape")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Writes the tape to FILE when the program stops, up to \
+                    its last cell that isn't zero"))
+        .arg(clap::Arg::with_name("dump-format")
+             .long("dump-format")
+             .takes_value(true)
+             .possible_values(&["binary", "csv", "hex"])
+             .requires("dump-tape")
+             .help("Writes --dump-tape's cells as bytes, as numbers \
+                    separated by commas, or as rows of hexadecimal; by \
+                    default, csv if FILE ends in .csv, hex if it ends in \
+                    .hex, and binary otherwise"))
+        .arg(clap::Arg::with_name("diff-against")
+             .long("diff-against")
+             .takes_value(true)
+             .value_name("FILE")
+             .conflicts_with_all(&["stream", "watch"])
+             .help("Checks that the program leaves the tape and pointer as \
+                    they are in FILE, saved by --core-dump, \
+                    --checkpoint-every or --dump-tape from a reference \
+                    run, listing the cells that differ and exiting with \
+                    code 4 if any do"))
+        .arg(clap::Arg::with_name("expect-output")
+             .long("expect-output")
+             .takes_value(true)
+             .value_name("FILE")
+             .conflicts_with_all(&["watch", "stream", "emit"])
+             .help("Checks that the program's output is what FILE holds, \
+                    instead of printing it, and prints how they differ if \
+                    not"))
+        .arg(clap::Arg::with_name("sandbox")
+             .long("sandbox")
+             .help("Runs an untrusted program in the interpreter, stopping \
+                    it after 100,000,000 instructions, 10 seconds, 65536 \
+                    cells unless --max-memory says otherwise, or 1 MiB of \
+                    output unless --max-output does, and exiting with code \
+                    3 if it is stopped"))
+        .arg(clap::Arg::with_name("core-dump")
+             .long("core-dump")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Saves the tape and pointer to FILE if the interpreter \
+                    stops with an error, for bfi dump"))
+        .arg(clap::Arg::with_name("checkpoint-every")
+             .long("checkpoint-every")
+             .takes_value(true)
+             .value_name("N")
+             .conflicts_with_all(&["stream", "watch"])
+             .help("Saves a checkpoint that --resume can carry the run on \
+                    from after every N instructions, or every N seconds if \
+                    N ends in s, such as 30s, each replacing the last, and \
+                    deletes it once the program ends"))
+        .arg(clap::Arg::with_name("checkpoint-file")
+             .long("checkpoint-file")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Where --checkpoint-every saves checkpoints and \
+                    --resume reads them, by default FILE.checkpoint for \
+                    the program's FILE"))
+        .arg(clap::Arg::with_name("resume")
+             .long("resume")
+             .conflicts_with_all(&["stream", "watch", "partial-eval",
+                                   "load-tape", "cells", "cell"])
+             .help("Carries on from the checkpoint in --checkpoint-file, \
+                    if there is one yet, skipping the input the run had \
+                    read; output written before it isn't written again"))
+        .arg(clap::Arg::with_name("output-encoding")
+             .long("output-encoding")
+             .takes_value(true)
+             .possible_values(OutputEncoding::NAMES)
+             .default_value("utf8")
+             .help("Says what . writes for a cell: its character in UTF-8, \
+                    or U+FFFD if it has none; its value modulo 256 as a \
+                    byte; or its value as a byte, stopping with an error if \
+                    it is above 255"))
+        .arg(clap::Arg::with_name("overflow")
+             .long("overflow")
+             .takes_value(true)
+             .possible_values(Overflow::NAMES)
+             .default_value("wrap")
+             .help("Whether adding to a cell wraps around when the result \
+                    doesn't fit, stops the program with an error, or sticks \
+                    at zero or the largest value"))
+        .arg(clap::Arg::with_name("strict-pointer")
+             .long("strict-pointer")
+             .help("Stops the program with an error if the pointer moves \
+                    left of the first cell, instead of growing the tape to \
+                    the left"))
+        .arg(clap::Arg::with_name("no-cache")
+             .long("no-cache")
+             .help("Parses and optimizes FILE even if it has been run with \
+                    the same arguments before, without caching the result \
+                    in ~/.cache/bfi"))
+        .arg(clap::Arg::with_name("verbose")
+             .short("v")
+             .long("verbose")
+             .help("Reports what the optimizer changed"))
+        .arg(source_map_arg())
+        .arg(opt_level_arg())
+        .arg(passes_arg())
+}
+
+/// bfi's arguments and subcommands, which `bfi completions` and `bfi man`
+/// describe as well as `main` parsing them.
+fn app() -> clap::App<'static, 'static> {
+    let lint_names: Vec<&str> = brainfuck::lint::LINTS.iter()
+        .map(|l| l.0)
+        .collect();
+
+    run_args(clap::App::new("bfi")
+        .version("0.1.0")
+        .about("Brainfuck interpreter")
+        .author("Gregory Meyer <gregjm@umich.edu>")
+        .after_help(EXIT_STATUS_HELP)
+        .setting(clap::AppSettings::ArgsNegateSubcommands)
+        .setting(clap::AppSettings::SubcommandsNegateReqs))
+        .subcommand(run_args(clap::SubCommand::with_name("run")
+                             .about("Runs a program, as bfi FILE does")
+                             .after_help(EXIT_STATUS_HELP)))
+        .subcommand(clap::SubCommand::with_name("asm")
+                    .about("Assembles a macro assembly file to brainfuck")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(output_arg()))
+        .subcommand(clap::SubCommand::with_name("cat")
+                    .about("Prints a program with its commands colored by \
+                            kind and its brackets by depth")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(clap::Arg::with_name("offsets")
+                         .long("offsets")
+                         .help("Prefixes each line with its byte offset"))
+                    .arg(clap::Arg::with_name("color")
+                         .long("color")
+                         .takes_value(true)
+                         .possible_values(&["auto", "always", "never"])
+                         .default_value("auto")
+                         .help("Whether to color the output; by default, \
+                                only if stdout is a terminal")))
+        .subcommand(clap::SubCommand::with_name("compile")
+                    .about("Compiles a higher-level language to brainfuck, \
+                            or any program to bytecode if the output file \
+                            ends in .bfc")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(clap::Arg::with_name("from")
+                         .long("from")
+                         .takes_value(true)
+                         .possible_values(&["asm", "mini", "brainfuck",
+                                            "spoon"])
+                         .help("Language the input is written in, if not \
+                                implied by its extension"))
+                    .arg(clap::Arg::with_name("source-map")
+                         .long("source-map")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Writes a map from the brainfuck back to the \
+                                lines of the asm or mini program to FILE, \
+                                for --source-map when running it"))
+                    .arg(opt_level_arg())
+                    .arg(ext_arg())
+                    .arg(passes_arg())
+                    .arg(output_arg()))
+        .subcommand(clap::SubCommand::with_name("decompile")
+                    .about("Lifts a program to readable pseudo-code")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(output_arg()))
+        .subcommand(clap::SubCommand::with_name("disasm")
+                    .about("Lists the instructions in a program compiled by \
+                            bfi compile or cached by bfi FILE, with their \
+                            jump targets and the source each came from")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(output_arg()))
+        .subcommand(clap::SubCommand::with_name("tdiff")
+                    .about("Lists the cells that differ between two tapes \
+                            saved by --core-dump, --checkpoint-every or \
+                            --dump-tape, exiting with 1 if any do")
+                    .arg(clap::Arg::with_name("OLD")
+                         .required(true)
+                         .index(1))
+                    .arg(clap::Arg::with_name("NEW")
+                         .required(true)
+                         .index(2)))
+        .subcommand(clap::SubCommand::with_name("verify")
+                    .about("Checks that every backend gives the same output \
+                            and final tape as the unoptimized interpreter")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(clap::Arg::with_name("input")
+                         .long("input")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Reads the program's input from FILE instead \
+                                of stdin"))
+                    .arg(opt_level_arg())
+                    .arg(passes_arg()))
+        .subcommand(clap::SubCommand::with_name("analyze")
+                    .about("Reports what a program is made of and how much \
+                            of the tape it uses, without running it")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(opt_level_arg())
+                    .arg(passes_arg()))
+        .subcommand(clap::SubCommand::with_name("diff")
+                    .about("Shows how the optimized IR of two programs \
+                            differs")
+                    .arg(clap::Arg::with_name("FIRST")
+                         .required(true)
+                         .index(1))
+                    .arg(clap::Arg::with_name("SECOND")
+                         .required(true)
+                         .index(2))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(opt_level_arg())
+                    .arg(passes_arg()))
+        .subcommand(clap::SubCommand::with_name("equiv")
+                    .about("Runs two programs on the same inputs and checks \
+                            that they print the same, as far as a bounded \
+                            number of steps can tell")
+                    .arg(clap::Arg::with_name("FIRST")
+                         .required(true)
+                         .index(1))
+                    .arg(clap::Arg::with_name("SECOND")
+                         .required(true)
+                         .index(2))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(clap::Arg::with_name("steps")
+                         .long("steps")
+                         .takes_value(true)
+                         .default_value("1e6")
+                         .help("Gives up on a program after this many \
+                                steps on an input"))
+                    .arg(clap::Arg::with_name("inputs")
+                         .long("inputs")
+                         .takes_value(true)
+                         .multiple(true)
+                         .value_name("FILE|fuzz")
+                         .default_value("fuzz")
+                         .help("Files to use as input; fuzz adds random \
+                                printable inputs"))
+                    .arg(clap::Arg::with_name("count")
+                         .long("count")
+                         .takes_value(true)
+                         .default_value("100")
+                         .help("How many inputs fuzz generates"))
+                    .arg(clap::Arg::with_name("max-length")
+                         .long("max-length")
+                         .takes_value(true)
+                         .default_value("16")
+                         .help("The longest input fuzz generates"))
+                    .arg(clap::Arg::with_name("seed")
+                         .long("seed")
+                         .takes_value(true)
+                         .default_value("0")
+                         .help("Seeds the random inputs"))
+                    .arg(opt_level_arg())
+                    .arg(passes_arg()))
+        .subcommand(clap::SubCommand::with_name("batch")
+                    .about("Runs each .b or .bf program in a directory on \
+                            its .in file, several at a time, and reports \
+                            what each printed as JSON")
+                    .arg(clap::Arg::with_name("DIR")
+                         .required(true)
+                         .index(1))
+                    .arg(clap::Arg::with_name("jobs")
+                         .short("j")
+                         .long("jobs")
+                         .takes_value(true)
+                         .value_name("N")
+                         .help("How many programs to run at once; defaults \
+                                to the number of CPUs"))
+                    .arg(clap::Arg::with_name("max-steps")
+                         .long("max-steps")
+                         .takes_value(true)
+                         .value_name("N")
+                         .help("Stops each program after it runs N \
+                                instructions"))
+                    .arg(clap::Arg::with_name("timeout")
+                         .long("timeout")
+                         .takes_value(true)
+                         .value_name("SECONDS")
+                         .help("Stops each program after it runs for \
+                                SECONDS"))
+                    .arg(clap::Arg::with_name("max-output")
+                         .long("max-output")
+                         .takes_value(true)
+                         .value_name("BYTES")
+                         .help("Stops each program instead of letting it \
+                                print more than BYTES"))
+                    .arg(opt_level_arg())
+                    .arg(passes_arg())
+                    .arg(output_arg()))
+        .subcommand(clap::SubCommand::with_name("bench")
+                    .about("Times the bundled benchmark programs on each \
+                            backend at each optimization level")
+                    .arg(clap::Arg::with_name("PROGRAMS")
+                         .multiple(true)
+                         .possible_values(&bench::PROGRAMS.iter()
+                                          .map(|p| p.0)
+                                          .collect::<Vec<_>>())
+                         .help("Benchmarks to run, instead of all of them"))
+                    .arg(clap::Arg::with_name("list")
+                         .long("list")
+                         .help("Lists the bundled benchmarks and exits"))
+                    .arg(clap::Arg::with_name("backends")
+                         .long("backends")
+                         .takes_value(true)
+                         .use_delimiter(true)
+                         .possible_values(bench::BACKENDS)
+                         .default_value("interpreter,vm,tiered")
+                         .help("Backends to compare"))
+                    .arg(clap::Arg::with_name("opt-levels")
+                         .long("opt-levels")
+                         .takes_value(true)
+                         .use_delimiter(true)
+                         .value_name("LEVELS")
+                         .default_value("0,1,2,3")
+                         .help("Optimization presets to compare"))
+                    .arg(clap::Arg::with_name("runs")
+                         .long("runs")
+                         .takes_value(true)
+                         .value_name("N")
+                         .default_value("5")
+                         .help("Times each combination this many times, \
+                                reporting the fastest"))
+                    .arg(clap::Arg::with_name("jit-threshold")
+                         .long("jit-threshold")
+                         .takes_value(true)
+                         .value_name("N")
+                         .default_value(DEFAULT_JIT_THRESHOLD)
+                         .help("Iterations before the tiered backend \
+                                compiles a loop"))
+                    .arg(clap::Arg::with_name("counters")
+                         .long("counters")
+                         .help("Also reports the CPU cycles, instructions \
+                                and cache misses of each fastest run, from \
+                                the hardware's performance counters; needs \
+                                Linux and bfi built with --features perf")))
+        .subcommand(clap::SubCommand::with_name("debug")
+                    .about("Runs debugger commands, such as breakpoints, \
+                            steps and assertions on cells, printing what \
+                            each did as a line of JSON")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(clap::Arg::with_name("script")
+                         .long("script")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Reads the commands from FILE instead of \
+                                stdin, exiting with status 4 if any fails"))
+                    .arg(clap::Arg::with_name("input")
+                         .long("input")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Reads the program's input from FILE; \
+                                without it, the program reads stdin if \
+                                the commands come from --script, or else \
+                                nothing"))
+                    .arg(opt_level_arg().default_value("0"))
+                    .arg(passes_arg()))
+        .subcommand(clap::SubCommand::with_name("dump")
+                    .about("Shows a tape saved by --core-dump or \
+                            --checkpoint-every, or a file of one byte per \
+                            cell, in hex, decimal and ASCII")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(clap::Arg::with_name("from")
+                         .long("from")
+                         .takes_value(true)
+                         .value_name("CELL")
+                         .help("Starts at CELL instead of the first"))
+                    .arg(clap::Arg::with_name("to")
+                         .long("to")
+                         .takes_value(true)
+                         .value_name("CELL")
+                         .help("Stops before CELL instead of after the last \
+                                that isn't zero")))
+        .subcommand(clap::SubCommand::with_name("gdb")
+                    .about("Waits for gdb to connect over TCP and debug a \
+                            program, with the tape as memory and the \
+                            instruction and data pointers as registers")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(clap::Arg::with_name("port")
+                         .long("port")
+                         .takes_value(true)
+                         .value_name("PORT")
+                         .default_value("1234")
+                         .help("Port on localhost to listen on"))
+                    .arg(clap::Arg::with_name("input")
+                         .long("input")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Reads the program's input from FILE instead \
+                                of stdin"))
+                    .arg(source_map_arg())
+                    .arg(opt_level_arg().default_value("0"))
+                    .arg(passes_arg()))
+        .subcommand(clap::SubCommand::with_name("lint")
+                    .about("Warns about loops that never end, never run, or \
+                            leave the pointer somewhere else each time")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(clap::Arg::with_name("allow")
+                         .long("allow")
+                         .takes_value(true)
+                         .value_name("LINTS")
+                         .use_delimiter(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .possible_values(&lint_names)
+                         .help("Doesn't report these lints"))
+                    .arg(clap::Arg::with_name("deny")
+                         .long("deny")
+                         .takes_value(true)
+                         .value_name("LINTS")
+                         .use_delimiter(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .possible_values(&lint_names)
+                         .help("Reports these lints as errors, exiting with \
+                                status 1 if any are found")))
+        .subcommand(clap::SubCommand::with_name("lsp")
+                    .about("Runs a language server over stdin and stdout"))
+        .subcommand(clap::SubCommand::with_name("pipe")
+                    .about("Runs programs at the same time, each reading \
+                            what the one before it prints")
+                    .arg(clap::Arg::with_name("FILES")
+                         .required(true)
+                         .multiple(true)
+                         .index(1))
+                    .arg(dialect_arg())
+                    .arg(opt_level_arg())
+                    .arg(passes_arg()))
+        .subcommand(clap::SubCommand::with_name("serve")
+                    .about("Serves a JSON API for running programs: POST \
+                            {\"source\": ..., \"input\": ...} to /run")
+                    .arg(clap::Arg::with_name("bind")
+                         .long("bind")
+                         .takes_value(true)
+                         .value_name("ADDRESS")
+                         .default_value("127.0.0.1:8080")
+                         .help("Address to listen on"))
+                    .arg(clap::Arg::with_name("jobs")
+                         .short("j")
+                         .long("jobs")
+                         .takes_value(true)
+                         .value_name("N")
+                         .help("How many requests to handle at once, the \
+                                rest waiting their turn; defaults to the \
+                                number of CPUs"))
+                    .arg(clap::Arg::with_name("fuel")
+                         .long("fuel")
+                         .takes_value(true)
+                         .value_name("N")
+                         .default_value("10000000")
+                         .help("Stops each program after it runs N \
+                                instructions"))
+                    .arg(clap::Arg::with_name("timeout")
+                         .long("timeout")
+                         .takes_value(true)
+                         .value_name("SECONDS")
+                         .default_value("5")
+                         .help("Stops each program after it runs for \
+                                SECONDS"))
+                    .arg(clap::Arg::with_name("max-memory")
+                         .long("max-memory")
+                         .takes_value(true)
+                         .value_name("CELLS")
+                         .default_value("65536")
+                         .help("Stops each program if it writes past the \
+                                first CELLS cells"))
+                    .arg(clap::Arg::with_name("max-output")
+                         .long("max-output")
+                         .takes_value(true)
+                         .value_name("BYTES")
+                         .default_value("65536")
+                         .help("Stops each program instead of letting it \
+                                print more than BYTES"))
+                    .arg(opt_level_arg()))
+        .subcommand(clap::SubCommand::with_name("obfuscate")
+                    .about("Rewrites a program as brainfuck that does the \
+                            same but is hard to read")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(clap::Arg::with_name("no-ops")
+                         .long("no-ops")
+                         .takes_value(true)
+                         .value_name("PERCENT")
+                         .default_value("20")
+                         .help("The chance of a pair of commands that \
+                                cancel out after each command"))
+                    .arg(clap::Arg::with_name("splits")
+                         .long("splits")
+                         .takes_value(true)
+                         .value_name("PERCENT")
+                         .default_value("50")
+                         .help("The chance of a run of + or - overshooting \
+                                and coming back"))
+                    .arg(clap::Arg::with_name("comments")
+                         .long("comments")
+                         .takes_value(true)
+                         .value_name("PERCENT")
+                         .default_value("30")
+                         .help("The chance of a comment character after \
+                                each command"))
+                    .arg(clap::Arg::with_name("width")
+                         .long("width")
+                         .takes_value(true)
+                         .value_name("N")
+                         .default_value("72")
+                         .help("The longest a line gets"))
+                    .arg(clap::Arg::with_name("seed")
+                         .long("seed")
+                         .takes_value(true)
+                         .value_name("N")
+                         .help("Seeds the obfuscator, so that the same seed \
+                                gives the same program"))
+                    .arg(output_arg()))
+        .subcommand(clap::SubCommand::with_name("synth")
+                    .about("Generates a random program that always \
+                            terminates, and input for it")
+                    .arg(clap::Arg::with_name("random")
+                         .long("random")
+                         .required(true)
+                         .help("Generates the program at random"))
+                    .arg(clap::Arg::with_name("size")
+                         .long("size")
+                         .takes_value(true)
+                         .value_name("N")
+                         .default_value("256")
+                         .help("Roughly how many commands to generate"))
+                    .arg(clap::Arg::with_name("depth")
+                         .long("depth")
+                         .takes_value(true)
+                         .value_name("N")
+                         .default_value("3")
+                         .help("How deeply loops may nest"))
+                    .arg(clap::Arg::with_name("iterations")
+                         .long("iterations")
+                         .takes_value(true)
+                         .value_name("N")
+                         .default_value("4")
+                         .help("The most times a loop runs each time it is \
+                                entered"))
+                    .arg(clap::Arg::with_name("cells")
+                         .long("cells")
+                         .takes_value(true)
+                         .value_name("N")
+                         .default_value("8")
+                         .help("How many cells the program works on"))
+                    .arg(clap::Arg::with_name("seed")
+                         .long("seed")
+                         .takes_value(true)
+                         .value_name("N")
+                         .help("Seeds the generator, so that the same seed \
+                                gives the same program"))
+                    .arg(output_arg())
+                    .arg(clap::Arg::with_name("input-output")
+                         .long("input-output")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Writes the program's input here; defaults \
+                                to the output file with its extension \
+                                changed to .in")))
+        .subcommand(clap::SubCommand::with_name("viz")
+                    .about("Animates the tape in the terminal as a program \
+                            runs")
+                    .arg(clap::Arg::with_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(dialect_arg())
+                    .arg(ext_arg())
+                    .arg(clap::Arg::with_name("input")
+                         .long("input")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Reads the program's input from FILE; stdin \
+                                is for controlling the animation"))
+                    .arg(clap::Arg::with_name("speed")
+                         .long("speed")
+                         .takes_value(true)
+                         .value_name("STEPS")
+                         .default_value("10")
+                         .help("Instructions to run per second"))
+                    .arg(clap::Arg::with_name("width")
+                         .long("width")
+                         .takes_value(true)
+                         .value_name("CELLS")
+                         .default_value("12")
+                         .help("How many cells to show around the pointer"))
+                    .arg(clap::Arg::with_name("paused")
+                         .long("paused")
+                         .help("Starts paused, to step with 's'"))
+                    .arg(clap::Arg::with_name("export")
+                         .long("export")
+                         .takes_value(true)
+                         .value_name("FILE")
+                         .help("Records the run and writes it to FILE as an \
+                                animated GIF if it ends in .gif, or else \
+                                as an SVG with a layer for each step"))
+                    .arg(clap::Arg::with_name("frames")
+                         .long("frames")
+                         .takes_value(true)
+                         .value_name("N")
+                         .default_value("500")
+                         .help("The most steps --export records"))
+                    .arg(opt_level_arg().default_value("0"))
+                    .arg(passes_arg()))
+        .subcommand(clap::SubCommand::with_name("test")
+                    .about("Runs each .b or .bf program in a directory on \
+                            its .in file, comparing what it prints with its \
+                            .out file")
+                    .arg(clap::Arg::with_name("DIR")
+                         .required(true)
+                         .index(1))
+                    .arg(clap::Arg::with_name("bless")
+                         .long("bless")
+                         .help("Overwrites each .out file with what its \
+                                program printed"))
+                    .arg(opt_level_arg())
+                    .arg(passes_arg()))
+        .subcommand(clap::SubCommand::with_name("cache")
+                    .about("Manages the cache of optimized programs in \
+                            ~/.cache/bfi")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(clap::SubCommand::with_name("clear")
+                                .about("Deletes every cached program")))
+        .subcommand(clap::SubCommand::with_name("completions")
+                    .about("Writes a script that completes bfi's arguments \
+                            in SHELL")
+                    .arg(clap::Arg::with_name("SHELL")
+                         .required(true)
+                         .index(1)
+                         .possible_values(&clap::Shell::variants()))
+                    .arg(out_dir_arg()))
+        .subcommand(clap::SubCommand::with_name("man")
+                    .about("Writes bfi's man page, with a section for each \
+                            subcommand")
+                    .arg(out_dir_arg()))
+}
+
+fn main() {
+    term::use_utf8();
+
+    let matches = app()
+        .get_matches_safe()
+        .unwrap_or_else(|e| match e.kind {
+            clap::ErrorKind::HelpDisplayed
+            | clap::ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                eprintln!("{}", e.message);
+                std::process::exit(EXIT_USAGE);
+            }
+        });
+
+    match matches.subcommand() {
+        ("asm", Some(m)) => asm(m),
+        ("cache", Some(m)) => cache::cache(m),
+        ("cat", Some(m)) => cat(m),
+        ("completions", Some(m)) => man::completions(m),
+        ("compile", Some(m)) => compile(m),
+        ("decompile", Some(m)) => decompile(m),
+        ("disasm", Some(m)) => disasm(m),
+        ("verify", Some(m)) => verify(m),
+        ("equiv", Some(m)) => equiv(m),
+        ("analyze", Some(m)) => analyze(m),
+        ("diff", Some(m)) => diff(m),
+        ("lint", Some(m)) => lint(m),
+        ("man", Some(m)) => man::man(m),
+        ("obfuscate", Some(m)) => obfuscate(m),
+        ("batch", Some(m)) => batch(m),
+        ("bench", Some(m)) => bench::bench(m),
+        ("debug", Some(m)) => debug::debug(m),
+        ("dump", Some(m)) => dump::dump(m),
+        ("tdiff", Some(m)) => dump::tdiff(m),
+        ("gdb", Some(m)) => gdb::gdb(m),
+        ("lsp", Some(_)) => {
+            if let Err(e) = lsp::serve() {
+                eprintln!("language server failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        ("pipe", Some(m)) => pipe(m),
+        ("run", Some(m)) => run(m),
+        ("serve", Some(m)) => serve(m),
+        ("synth", Some(m)) => synth(m),
+        ("test", Some(m)) => test(m),
+        ("viz", Some(m)) => viz::viz(m),
+        _ => run(&matches),
+    }
+}
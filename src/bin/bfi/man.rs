@@ -0,0 +1,201 @@
+//! `bfi completions` and `bfi man`, which describe bfi's arguments for a
+//! shell to complete and for `man` to show, both from the same `clap::App`
+//! that parses them.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use clap::{AppSettings, ArgMatches, ErrorKind, Shell};
+
+// Columns the help in the man page is wrapped to.
+const MAN_WIDTH: usize = 80;
+
+/// The file the shell looks for completions for bfi in.
+fn completions_file(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => "bfi.bash",
+        Shell::Fish => "bfi.fish",
+        Shell::Zsh => "_bfi",
+        Shell::PowerShell => "_bfi.ps1",
+        Shell::Elvish => "bfi.elv",
+    }
+}
+
+/// Writes what `write` writes to `name` in `--out-dir`, or else to stdout.
+fn emit<F>(matches: &ArgMatches, name: &str, write: F)
+    where F: FnOnce(&mut dyn Write) -> io::Result<()>
+{
+    let result = match matches.value_of("out-dir") {
+        Some(dir) => {
+            let path = Path::new(dir).join(name);
+
+            File::create(&path).and_then(|mut f| write(&mut f))
+                .map_err(|e| format!("could not write '{}': {}",
+                                     path.display(), e))
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+
+            write(&mut stdout).map_err(|e| format!("could not write: {}", e))
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(::EXIT_ERROR);
+    }
+}
+
+pub fn completions(matches: &ArgMatches) {
+    let shell: Shell = matches.value_of("SHELL").unwrap().parse().unwrap();
+
+    emit(matches, completions_file(shell), |mut w| {
+        ::app().gen_completions_to("bfi", shell, &mut w);
+
+        Ok(())
+    });
+}
+
+/// What `bfi ARGS... --help` prints, without color.
+fn help(args: &[&str]) -> String {
+    let app = ::app()
+        .global_setting(AppSettings::ColorNever)
+        .set_term_width(MAN_WIDTH);
+    let args = Some("bfi").into_iter().chain(args.iter().cloned())
+        .chain(Some("--help"));
+
+    match app.get_matches_from_safe(args) {
+        Err(ref e) if e.kind == ErrorKind::HelpDisplayed => e.message.clone(),
+        _ => unreachable!("--help always prints help"),
+    }
+}
+
+/// A section of help: its heading, such as `OPTIONS`, and its lines.
+struct Section<'a> {
+    heading: &'a str,
+    lines: Vec<&'a str>,
+}
+
+/// Splits help into the lines before `USAGE:` and the sections from it on.
+fn sections<'a>(help: &'a str) -> (Vec<&'a str>, Vec<Section<'a>>) {
+    let mut preamble = Vec::new();
+    let mut sections: Vec<Section> = Vec::new();
+
+    for line in help.lines() {
+        let heading = line.ends_with(':')
+            && line.chars().all(|c| c.is_ascii_uppercase() || c == ' '
+                                    || c == ':');
+
+        if heading {
+            let heading = line.trim_end_matches(':');
+
+            sections.push(Section { heading, lines: Vec::new() });
+        } else if let Some(section) = sections.last_mut() {
+            let line = line.strip_prefix("    ").unwrap_or(line);
+
+            section.lines.push(line.trim_end());
+        } else if !line.trim().is_empty() {
+            preamble.push(line.trim_end());
+        }
+    }
+
+    for section in &mut sections {
+        while section.lines.last() == Some(&"") {
+            section.lines.pop();
+        }
+    }
+
+    (preamble, sections)
+}
+
+/// `line` with anything roff would take as markup escaped.
+fn escape(line: &str) -> String {
+    let line = line.replace('\\', "\\e").replace('-', "\\-");
+
+    if line.starts_with('.') || line.starts_with('\'') {
+        format!("\\&{}", line)
+    } else {
+        line
+    }
+}
+
+/// Appends `lines` to `page` as they are, without filling them.
+fn verbatim(page: &mut String, lines: &[&str]) {
+    *page += ".nf\n";
+
+    for line in lines {
+        *page += &escape(line);
+        *page += "\n";
+    }
+
+    *page += ".fi\n";
+}
+
+/// Appends the sections of a subcommand's help, under a subheading.
+fn subcommand(page: &mut String, name: &str) {
+    let help = help(&[name]);
+    let (preamble, sections) = sections(&help);
+
+    *page += &format!(".SS \"bfi {}\"\n", name);
+
+    // The first line is the subcommand's name, and the rest are its about.
+    for line in preamble.iter().skip(1) {
+        *page += &escape(line);
+        *page += "\n";
+    }
+
+    for section in sections {
+        *page += &format!(".PP\n.B {}\n", section.heading);
+        verbatim(page, &section.lines);
+    }
+}
+
+/// The man page, in roff.
+fn page() -> String {
+    let help = help(&[]);
+    let (preamble, sections) = sections(&help);
+    let version = preamble.first().cloned().unwrap_or("bfi");
+    let about = preamble.last().cloned().unwrap_or("");
+    let mut page = format!(".TH BFI 1 \"\" \"{}\"\n.SH NAME\nbfi \\- {}\n",
+                           escape(version), escape(about));
+
+    for section in sections {
+        let heading = match section.heading {
+            "USAGE" => "SYNOPSIS",
+            h => h,
+        };
+
+        page += &format!(".SH \"{}\"\n", heading);
+
+        if heading != "SUBCOMMANDS" {
+            verbatim(&mut page, &section.lines);
+
+            continue;
+        }
+
+        // Each line that isn't a continuation starts with a subcommand's
+        // name.
+        let names = section.lines.iter()
+            .filter(|l| !l.starts_with(' '))
+            .filter_map(|l| l.split_whitespace().next())
+            .filter(|&n| n != "help");
+
+        for name in names {
+            subcommand(&mut page, name);
+        }
+    }
+
+    if preamble.len() > 2 {
+        page += &format!(".SH AUTHOR\n{}\n", escape(preamble[1]));
+    }
+
+    page
+}
+
+pub fn man(matches: &ArgMatches) {
+    let page = page();
+
+    emit(matches, "bfi.1", |w| w.write_all(page.as_bytes()));
+}
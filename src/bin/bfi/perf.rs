@@ -0,0 +1,169 @@
+//! Hardware performance counters, which `bfi bench --counters` reads around
+//! each run, so comparisons show what the CPU did as well as how long it
+//! took. They need Linux and bfi built with the `perf` feature, and only
+//! count what the program does outside the kernel.
+
+/// What the counters saw over one run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub cycles: u64,
+    pub instructions: u64,
+    pub cache_misses: u64,
+}
+
+impl Counts {
+    /// Each count, with its name as `bfi bench` labels it.
+    pub fn named(&self) -> [(&'static str, u64); 3] {
+        [("cycles", self.cycles), ("instructions", self.instructions),
+         ("cache-misses", self.cache_misses)]
+    }
+}
+
+#[cfg(all(feature = "perf", target_os = "linux"))]
+mod linux {
+    use super::Counts;
+
+    const PERF_TYPE_HARDWARE: u32 = 0;
+    const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+    const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+    const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+
+    // The flags are bits of one field: disabled, then inherit, pinned,
+    // exclusive, exclude_user, exclude_kernel and exclude_hv.
+    const DISABLED: u64 = 1;
+    const EXCLUDE_KERNEL: u64 = 1 << 5;
+    const EXCLUDE_HV: u64 = 1 << 6;
+
+    // `_IO('$', n)`.
+    const PERF_EVENT_IOC_ENABLE: u64 = 0x2400;
+    const PERF_EVENT_IOC_DISABLE: u64 = 0x2401;
+    const PERF_EVENT_IOC_RESET: u64 = 0x2403;
+
+    /// The first version of `struct perf_event_attr`, which every kernel
+    /// with perf events accepts.
+    #[repr(C)]
+    #[derive(Default)]
+    struct Attr {
+        kind: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        config1: u64,
+    }
+
+    /// A counter for this thread, started disabled.
+    fn open(config: u64) -> std::io::Result<i32> {
+        let attr = Attr {
+            kind: PERF_TYPE_HARDWARE,
+            size: std::mem::size_of::<Attr>() as u32,
+            config,
+            flags: DISABLED | EXCLUDE_KERNEL | EXCLUDE_HV,
+            ..Attr::default()
+        };
+        let fd = unsafe {
+            libc::syscall(libc::SYS_perf_event_open, &attr as *const Attr,
+                          0, -1, -1, 0)
+        };
+
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(fd as i32)
+    }
+
+    pub struct Counters {
+        /// Cycles, instructions and cache misses, in the order of `Counts`.
+        fds: Vec<i32>,
+    }
+
+    impl Counters {
+        pub fn open() -> Result<Counters, String> {
+            let mut counters = Counters { fds: Vec::new() };
+
+            for &config in &[PERF_COUNT_HW_CPU_CYCLES,
+                             PERF_COUNT_HW_INSTRUCTIONS,
+                             PERF_COUNT_HW_CACHE_MISSES] {
+                // Dropping `counters` closes any opened already.
+                let fd = open(config).map_err(|e| {
+                    format!("could not open hardware counters: {}", e)
+                })?;
+
+                counters.fds.push(fd);
+            }
+
+            Ok(counters)
+        }
+
+        fn each(&self, request: u64) {
+            for &fd in &self.fds {
+                unsafe {
+                    libc::ioctl(fd, request as _, 0);
+                }
+            }
+        }
+
+        /// Zeroes the counters and starts them.
+        pub fn start(&mut self) {
+            self.each(PERF_EVENT_IOC_RESET);
+            self.each(PERF_EVENT_IOC_ENABLE);
+        }
+
+        /// Stops the counters and returns what they counted since `start`.
+        pub fn stop(&mut self) -> Counts {
+            self.each(PERF_EVENT_IOC_DISABLE);
+
+            let counts: Vec<u64> = self.fds.iter().map(|&fd| {
+                let mut count = 0u64;
+                let read = unsafe {
+                    libc::read(fd, &mut count as *mut u64 as *mut _, 8)
+                };
+
+                if read == 8 { count } else { 0 }
+            }).collect();
+
+            Counts { cycles: counts[0], instructions: counts[1],
+                     cache_misses: counts[2] }
+        }
+    }
+
+    impl Drop for Counters {
+        fn drop(&mut self) {
+            for &fd in &self.fds {
+                unsafe {
+                    libc::close(fd);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "perf", target_os = "linux"))]
+pub use self::linux::Counters;
+
+/// Stands in for the counters where there are none to read.
+#[cfg(not(all(feature = "perf", target_os = "linux")))]
+pub struct Counters;
+
+#[cfg(not(all(feature = "perf", target_os = "linux")))]
+impl Counters {
+    pub fn open() -> Result<Counters, String> {
+        if cfg!(target_os = "linux") {
+            Err("bfi was built without the perf feature, which hardware \
+                 counters need".to_string())
+        } else {
+            Err("hardware counters need Linux".to_string())
+        }
+    }
+
+    pub fn start(&mut self) { }
+
+    pub fn stop(&mut self) -> Counts {
+        Counts::default()
+    }
+}
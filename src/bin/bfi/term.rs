@@ -0,0 +1,311 @@
+//! Terminal control for the interactive subcommands, through termios on
+//! Unix and the console API on Windows.
+
+use std::io::{IsTerminal, Read, Write};
+#[cfg(any(unix, windows))]
+use std::sync::OnceLock;
+
+#[cfg(windows)]
+use windows_sys::Win32::System::Console;
+
+#[cfg(unix)]
+type Mode = libc::termios;
+
+#[cfg(windows)]
+type Mode = Console::CONSOLE_MODE;
+
+// The settings to go back to, kept where a signal handler or panic hook can
+// reach them.
+#[cfg(any(unix, windows))]
+static ORIGINAL: OnceLock<Mode> = OnceLock::new();
+
+#[cfg(unix)]
+fn get_mode() -> Option<Mode> {
+    unsafe {
+        let mut mode = std::mem::zeroed();
+
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut mode) != 0 {
+            return None;
+        }
+
+        Some(mode)
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(mode: &Mode) -> bool {
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, mode) == 0 }
+}
+
+#[cfg(unix)]
+fn raw(mode: &Mode) -> Mode {
+    let mut raw = *mode;
+
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 1;
+    raw.c_cc[libc::VTIME] = 0;
+
+    raw
+}
+
+#[cfg(windows)]
+fn get_mode() -> Option<Mode> {
+    unsafe {
+        let mut mode = 0;
+        let input = Console::GetStdHandle(Console::STD_INPUT_HANDLE);
+
+        if Console::GetConsoleMode(input, &mut mode) == 0 {
+            return None;
+        }
+
+        Some(mode)
+    }
+}
+
+#[cfg(windows)]
+fn set_mode(mode: &Mode) -> bool {
+    unsafe {
+        let input = Console::GetStdHandle(Console::STD_INPUT_HANDLE);
+
+        Console::SetConsoleMode(input, *mode) != 0
+    }
+}
+
+#[cfg(windows)]
+fn raw(mode: &Mode) -> Mode {
+    *mode & !(Console::ENABLE_LINE_INPUT | Console::ENABLE_ECHO_INPUT)
+}
+
+#[cfg(any(unix, windows))]
+fn restore() {
+    if let Some(original) = ORIGINAL.get() {
+        set_mode(original);
+    }
+}
+
+#[cfg(any(unix, windows))]
+extern "C" fn restore_at_exit() {
+    restore();
+}
+
+#[cfg(unix)]
+extern "C" fn restore_and_reraise(signal: libc::c_int) {
+    restore();
+
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn restore_on_ctrl(_event: u32)
+-> windows_sys::core::BOOL {
+    restore();
+
+    // Lets the default handler end the process.
+    0
+}
+
+#[cfg(windows)]
+extern "C" {
+    fn atexit(callback: extern "C" fn()) -> std::os::raw::c_int;
+}
+
+#[cfg(unix)]
+unsafe fn restore_on_exit() {
+    libc::atexit(restore_at_exit);
+
+    for &signal in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        libc::signal(signal, restore_and_reraise as extern "C" fn(libc::c_int)
+                     as libc::sighandler_t);
+    }
+}
+
+#[cfg(windows)]
+unsafe fn restore_on_exit() {
+    atexit(restore_at_exit);
+    Console::SetConsoleCtrlHandler(Some(restore_on_ctrl), 1);
+}
+
+/// Turns off line buffering and echo on stdin while it is alive, so keys
+/// can be read as they are pressed. Does nothing if stdin isn't a terminal
+/// or the platform has neither termios nor a console.
+///
+/// The terminal is also restored if the process exits, panics or is
+/// interrupted first.
+pub struct RawMode {
+    #[cfg(any(unix, windows))]
+    original: Option<Mode>,
+}
+
+impl RawMode {
+    #[cfg(any(unix, windows))]
+    pub fn enable() -> RawMode {
+        let original = match get_mode() {
+            Some(mode) => mode,
+            None => return RawMode { original: None },
+        };
+
+        if ORIGINAL.set(original).is_ok() {
+            let hook = std::panic::take_hook();
+
+            std::panic::set_hook(Box::new(move |info| {
+                restore();
+                hook(info);
+            }));
+
+            unsafe {
+                restore_on_exit();
+            }
+        }
+
+        if !set_mode(&raw(&original)) {
+            return RawMode { original: None };
+        }
+
+        RawMode { original: Some(original) }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn enable() -> RawMode {
+        RawMode { }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(any(unix, windows))]
+        if let Some(ref original) = self.original {
+            set_mode(original);
+        }
+    }
+}
+
+// The code pages the console had before `use_utf8`.
+#[cfg(windows)]
+static CODE_PAGES: OnceLock<(u32, u32)> = OnceLock::new();
+
+#[cfg(windows)]
+extern "C" fn restore_code_pages() {
+    if let Some(&(input, output)) = CODE_PAGES.get() {
+        unsafe {
+            Console::SetConsoleCP(input);
+            Console::SetConsoleOutputCP(output);
+        }
+    }
+}
+
+/// Has the Windows console read and write UTF-8, as terminals elsewhere
+/// do, until the process exits.
+#[cfg(windows)]
+pub fn use_utf8() {
+    const UTF_8: u32 = 65001;
+
+    unsafe {
+        let pages = (Console::GetConsoleCP(), Console::GetConsoleOutputCP());
+
+        if pages.0 == 0 || CODE_PAGES.set(pages).is_err() {
+            return;
+        }
+
+        atexit(restore_code_pages);
+        Console::SetConsoleCP(UTF_8);
+        Console::SetConsoleOutputCP(UTF_8);
+    }
+}
+
+#[cfg(not(windows))]
+pub fn use_utf8() { }
+
+/// Input that turns the `\r\n` a Windows console gives for Enter, or the
+/// bare `\r` it gives in raw mode, into the `\n` a terminal elsewhere would.
+/// Anything else, including input that isn't from a console, passes through
+/// untouched.
+pub struct Newlines<R> {
+    inner: R,
+    translate: bool,
+    after_return: bool,
+}
+
+impl<R: Read> Newlines<R> {
+    pub fn new(inner: R) -> Newlines<R> {
+        Newlines::translating(inner, cfg!(windows)
+                                     && std::io::stdin().is_terminal())
+    }
+
+    /// Turns `\r\n` and `\r` into `\n` whatever `inner` is if `translate`
+    /// is true, and otherwise passes everything through.
+    pub fn translating(inner: R, translate: bool) -> Newlines<R> {
+        Newlines { inner, translate, after_return: false }
+    }
+}
+
+impl<R: Read> Read for Newlines<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let length = self.inner.read(buffer)?;
+
+            if !self.translate || length == 0 {
+                return Ok(length);
+            }
+
+            let mut kept = 0;
+
+            for i in 0..length {
+                let byte = buffer[i];
+
+                if byte == b'\n' && self.after_return {
+                    self.after_return = false;
+                    continue;
+                }
+
+                self.after_return = byte == b'\r';
+                buffer[kept] = if byte == b'\r' { b'\n' } else { byte };
+                kept += 1;
+            }
+
+            // A read of only the `\n` after a `\r` isn't the end of input.
+            if kept > 0 {
+                return Ok(kept);
+            }
+        }
+    }
+}
+
+/// Output that writes each `\n` as `\r\n` if `translate` is true, the
+/// other half of `Newlines`.
+pub struct CrLf<W> {
+    inner: W,
+    translate: bool,
+}
+
+impl<W: Write> CrLf<W> {
+    pub fn new(inner: W, translate: bool) -> CrLf<W> {
+        CrLf { inner, translate }
+    }
+}
+
+impl<W: Write> Write for CrLf<W> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        if !self.translate {
+            return self.inner.write(bytes);
+        }
+
+        for line in bytes.split_inclusive(|&b| b == b'\n') {
+            match line.strip_suffix(b"\n") {
+                Some(rest) => {
+                    self.inner.write_all(rest)?;
+                    self.inner.write_all(b"\r\n")?;
+                }
+                None => self.inner.write_all(line)?,
+            }
+        }
+
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,418 @@
+//! `bfi viz`, which animates the tape in the terminal as a program runs.
+
+use std::io::{IsTerminal, Read, Write};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use brainfuck::{InterpreterState, Span};
+use brainfuck::interpreter::OpenLoop;
+use clap::ArgMatches;
+
+use term::RawMode;
+
+// Lines of the program's output shown below the tape.
+const OUTPUT_LINES: usize = 10;
+
+// Open loops shown, innermost first, below the source line.
+const LOOP_LINES: usize = 4;
+
+// Rows of a grid tape shown around the pointer's.
+const GRID_ROWS: usize = 7;
+
+// The most cells an exported animation shows.
+const MAX_EXPORT_CELLS: usize = 64;
+
+// Side of each cell in an exported animation, in pixels.
+const CELL_SIZE: usize = 40;
+
+// Side of each cell in an exported GIF, which has no room for text.
+const GIF_CELL_SIZE: usize = 16;
+
+type Interpreter<'a> = InterpreterState<&'a [u8], &'a mut Vec<u8>>;
+
+/// The line of `source` around `span`, with the span in reverse video.
+fn source_line(source: &str, span: Span) -> String {
+    let start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let end = source[span.end..].find('\n').map_or(source.len(),
+                                                   |i| span.end + i);
+    let (line, _) = brainfuck::ir::line_col(source, span.start);
+
+    format!("{:>5} | {}\x1b[7m{}\x1b[0m{}", line, &source[start..span.start],
+            &source[span.start..span.end], &source[span.end..end])
+}
+
+/// The innermost of `loops`, like a call stack, with the line and column
+/// each starts at and how many times round it is.
+fn loop_stack(source: &str, loops: &[OpenLoop]) -> String {
+    let mut text = String::new();
+
+    for open in loops.iter().rev().take(LOOP_LINES) {
+        let (line, column) = brainfuck::ir::line_col(source, open.span.start);
+
+        text += &format!("  in loop at {}:{}, iteration {}\n", line, column,
+                         open.iteration);
+    }
+
+    if loops.len() > LOOP_LINES {
+        text += &format!("  and {} more\n", loops.len() - LOOP_LINES);
+    }
+
+    text
+}
+
+/// `width` cells around the pointer, numbered, with a caret under it.
+fn row(tape: &[u32], pointer: usize, width: usize) -> String {
+    let first = pointer.saturating_sub(width / 2);
+    let cells = first..first + width;
+    let mut text = String::new();
+
+    for i in cells.clone() {
+        text += &format!(" {:>5} ", i);
+    }
+
+    text += "\n";
+
+    for i in cells.clone() {
+        text += &format!("[{:>5}]", tape.get(i).unwrap_or(&0));
+    }
+
+    text += "\n";
+
+    for i in cells {
+        text += if i == pointer { "    ^  " } else { "       " };
+    }
+
+    text + "\n"
+}
+
+/// The rows of a grid tape `columns` wide around the pointer, showing
+/// `width` columns around its column, with the pointer's cell in reverse
+/// video.
+fn grid(tape: &[u32], pointer: usize, columns: usize, width: usize)
+-> String {
+    let (y, x) = (pointer / columns, pointer % columns);
+    let width = std::cmp::min(width, columns);
+    let left = std::cmp::min(x.saturating_sub(width / 2), columns - width);
+    let top = y.saturating_sub(GRID_ROWS / 2);
+    let mut text = "     ".to_string();
+
+    for column in left..left + width {
+        text += &format!(" {:>5} ", column);
+    }
+
+    text += "\n";
+
+    for row in top..top + GRID_ROWS {
+        text += &format!("{:>5}", row);
+
+        for column in left..left + width {
+            let i = row * columns + column;
+            let value = tape.get(i).unwrap_or(&0);
+
+            text += &if i == pointer {
+                format!("[\x1b[7m{:>5}\x1b[0m]", value)
+            } else {
+                format!("[{:>5}]", value)
+            };
+        }
+
+        text += "\n";
+    }
+
+    text
+}
+
+fn render(interpreter: &mut Interpreter, source: &str, width: usize,
+          columns: Option<usize>, status: &str) {
+    let pointer = interpreter.pointer();
+    let mut frame = format!("\x1b[H\x1b[J{}\n\n", status);
+
+    frame += &match columns {
+        Some(columns) => grid(interpreter.tape(), pointer, columns, width),
+        None => row(interpreter.tape(), pointer, width),
+    };
+    frame += "\n";
+
+    if let Some(span) = interpreter.span() {
+        frame += &source_line(source, span);
+    }
+
+    frame += "\n";
+    frame += &loop_stack(source, interpreter.loops());
+    frame += "\noutput:\n";
+
+    let output = String::from_utf8_lossy(interpreter.writer_mut());
+    let lines: Vec<&str> = output.lines().collect();
+
+    frame += &lines[lines.len().saturating_sub(OUTPUT_LINES)..].join("\n");
+    frame += "\n";
+
+    print!("{}", frame);
+    let _ = std::io::stdout().flush();
+}
+
+/// The pointer and tape after each instruction of a recorded run.
+struct Frame {
+    pointer: usize,
+    cells: Vec<u32>,
+}
+
+/// Runs `program` for up to `limit` instructions, recording the first
+/// `MAX_EXPORT_CELLS` cells after each, and returns the frames and how many
+/// cells they should show.
+fn record(program: &::Program, input: &[u8], limit: usize)
+-> (Vec<Frame>, usize) {
+    let mut output = Vec::new();
+    let mut interpreter = InterpreterState::new(program, &mut output, input);
+    let mut frames = Vec::new();
+    let mut width = 1;
+
+    loop {
+        let pointer = interpreter.pointer();
+
+        width = std::cmp::max(width, pointer + 1);
+        frames.push(Frame {
+            pointer,
+            cells: interpreter.tape()[..MAX_EXPORT_CELLS].to_vec(),
+        });
+
+        if frames.len() >= limit || interpreter.span().is_none() {
+            break;
+        }
+
+        if interpreter.repl().is_err() && interpreter.span().is_some() {
+            break;
+        }
+    }
+
+    (frames, std::cmp::min(width, MAX_EXPORT_CELLS))
+}
+
+/// Each frame as a layer that is only visible during its slice of the
+/// animation.
+fn svg(frames: &[Frame], width: usize, delay: f64) -> String {
+    let total = frames.len() as f64 * delay;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" \
+                           width=\"{0}\" height=\"{1}\" \
+                           font-family=\"monospace\" font-size=\"12\" \
+                           text-anchor=\"middle\">\n\
+                           <rect width=\"{0}\" height=\"{1}\" \
+                           fill=\"white\"/>\n",
+                          width * CELL_SIZE, CELL_SIZE * 2 + 20);
+
+    for i in 0..width {
+        svg += &format!("<text x=\"{}\" y=\"14\" fill=\"gray\">{}</text>\n",
+                        i * CELL_SIZE + CELL_SIZE / 2, i);
+    }
+
+    for (n, frame) in frames.iter().enumerate() {
+        let start = n as f64 / frames.len() as f64;
+        let end = (n + 1) as f64 / frames.len() as f64;
+
+        svg += &format!("<g id=\"frame-{}\" visibility=\"hidden\">\n\
+                         <animate attributeName=\"visibility\" \
+                         values=\"hidden;visible;hidden\" \
+                         keyTimes=\"0;{};{}\" dur=\"{}s\" \
+                         calcMode=\"discrete\" \
+                         repeatCount=\"indefinite\"/>\n",
+                        n, start, end, total);
+
+        for (i, &value) in frame.cells[..width].iter().enumerate() {
+            let x = i * CELL_SIZE;
+            let shade = 255 - std::cmp::min(value, 255) / 2;
+
+            svg += &format!("<rect x=\"{0}\" y=\"20\" width=\"{1}\" \
+                             height=\"{1}\" stroke=\"black\" \
+                             fill=\"rgb({2},{2},255)\"/>\n\
+                             <text x=\"{3}\" y=\"{4}\">{5}</text>\n",
+                            x, CELL_SIZE, shade, x + CELL_SIZE / 2,
+                            20 + CELL_SIZE / 2 + 4, value);
+        }
+
+        let x = frame.pointer * CELL_SIZE + CELL_SIZE / 2;
+
+        if frame.pointer < width {
+            svg += &format!("<path d=\"M{} {}l-6 10h12z\" fill=\"red\"/>\n",
+                            x, 22 + CELL_SIZE);
+        }
+
+        svg += "</g>\n";
+    }
+
+    svg + "</svg>\n"
+}
+
+/// Each frame as a row of cells shaded by value, over a red marker under
+/// the pointer.
+fn gif<W: Write>(output: W, frames: &[Frame], width: usize, delay: f64)
+-> Result<(), gif::EncodingError> {
+    // Palette entries 0 to 254 shade from white to black; 255 is red.
+    let mut palette = Vec::with_capacity(256 * 3);
+
+    for i in 0..255u32 {
+        let shade = (255 - i) as u8;
+
+        palette.extend_from_slice(&[shade, shade, shade]);
+    }
+
+    palette.extend_from_slice(&[255, 0, 0]);
+
+    let pixel_width = width * GIF_CELL_SIZE;
+    let height = GIF_CELL_SIZE + GIF_CELL_SIZE / 2;
+    let mut encoder = gif::Encoder::new(output, pixel_width as u16,
+                                        height as u16, &palette)?;
+
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    for frame in frames {
+        let mut pixels = vec![0; pixel_width * height];
+
+        for y in 0..height {
+            for x in 0..pixel_width {
+                let cell = x / GIF_CELL_SIZE;
+
+                pixels[y * pixel_width + x] = if y < GIF_CELL_SIZE {
+                    std::cmp::min(frame.cells[cell], 254) as u8
+                } else if cell == frame.pointer {
+                    255
+                } else {
+                    0
+                };
+            }
+        }
+
+        encoder.write_frame(&gif::Frame {
+            width: pixel_width as u16,
+            height: height as u16,
+            buffer: pixels.into(),
+            delay: std::cmp::max(1, (delay * 100.0).round() as u16),
+            ..gif::Frame::default()
+        })?;
+    }
+
+    Ok(())
+}
+
+fn export(filename: &str, program: &::Program, input: &[u8], limit: usize,
+          delay: f64) {
+    let (frames, width) = record(program, input, limit);
+    let result = if filename.ends_with(".gif") {
+        std::fs::File::create(filename)
+            .map_err(|e| e.to_string())
+            .and_then(|f| {
+                gif(std::io::BufWriter::new(f), &frames, width, delay)
+                    .map_err(|e| e.to_string())
+            })
+    } else {
+        std::fs::write(filename, svg(&frames, width, delay))
+            .map_err(|e| e.to_string())
+    };
+
+    if let Err(e) = result {
+        eprintln!("could not write '{}': {}", filename, e);
+        std::process::exit(1);
+    }
+}
+
+pub fn viz(matches: &ArgMatches) {
+    let ::Loaded { source, program, compiled, .. } = ::parse(matches);
+    let program = if compiled { program } else {
+        ::pipeline(matches).run(program)
+    };
+    let input = matches.value_of("input").map_or(Vec::new(), ::read_input);
+    let width = std::cmp::max(1, ::number(matches, "width"));
+    let extensions = ::extensions(matches);
+    let columns = extensions.grid;
+    let mut speed: f64 = ::number(matches, "speed");
+
+    if !(speed > 0.0 && speed.is_finite()) {
+        eprintln!("--speed must be positive");
+        std::process::exit(1);
+    }
+
+    if let Some(filename) = matches.value_of("export") {
+        let frames = std::cmp::max(1, ::number(matches, "frames"));
+
+        return export(filename, &program, &input, frames, 1.0 / speed);
+    }
+
+    let interactive = std::io::stdin().is_terminal();
+    let mut paused = interactive && matches.is_present("paused");
+    // `sender` lives until the end so that, with no key reader, waiting
+    // for a key times out instead of failing right away.
+    let (sender, keys) = mpsc::channel();
+    let _raw = if interactive { Some(RawMode::enable()) } else { None };
+
+    if interactive {
+        let sender = sender.clone();
+
+        std::thread::spawn(move || {
+            for byte in std::io::stdin().lock().bytes() {
+                if byte.map(|b| sender.send(b)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let mut output = Vec::new();
+    let mut interpreter = InterpreterState::new(&program, &mut output,
+                                                &input[..]);
+    let mut steps = 0u64;
+
+    print!("\x1b[?25l");
+
+    let result = loop {
+        if interpreter.span().is_none() {
+            break "finished".to_string();
+        }
+
+        let mut status = format!("step {}, {} steps/s, {} (space: pause, \
+                                  s: step, +/-: speed, q: quit)", steps,
+                                 speed,
+                                 if paused { "paused" } else { "running" });
+
+        if extensions.tapes {
+            status = format!("tape {}, {}", interpreter.tape_index(), status);
+        }
+
+        render(&mut interpreter, &source, width, columns, &status);
+
+        let key = if paused {
+            keys.recv().ok()
+        } else {
+            keys.recv_timeout(Duration::from_secs_f64(1.0 / speed)).ok()
+        };
+
+        match key {
+            Some(b' ') => {
+                paused = !paused;
+                continue;
+            }
+            Some(b'+') => {
+                speed *= 2.0;
+                continue;
+            }
+            Some(b'-') => {
+                speed /= 2.0;
+                continue;
+            }
+            Some(b'q') => break "quit".to_string(),
+            Some(b's') | Some(b'n') | None => (),
+            Some(_) => continue,
+        }
+
+        if let Err(e) = interpreter.repl() {
+            if interpreter.span().is_some() {
+                break format!("stopped: {}", e);
+            }
+        }
+
+        steps += 1;
+    };
+
+    render(&mut interpreter, &source, width, columns,
+           &format!("step {}, {}", steps, result));
+    print!("\x1b[?25h");
+    let _ = std::io::stdout().flush();
+}
@@ -0,0 +1,200 @@
+//! Threads that read a program's input ahead of it and write its output
+//! behind it, for `--io-threads` and `--input-nonblocking`, so that the
+//! interpreter keeps running while a slow terminal or pipe catches up and
+//! `--sandbox` can stop it while it waits on one.
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender,
+                      TryRecvError, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Chunks that can wait in a channel before whoever sends them waits too.
+const CHUNKS: usize = 64;
+
+// Bytes read from the source at a time.
+const CHUNK_SIZE: usize = 4096;
+
+// How long a write waits between tries once the channel is full and there
+// is a deadline to keep an eye on.
+const RETRY: Duration = Duration::from_millis(1);
+
+/// Starts a thread reading `source` in chunks, which stops at its end or
+/// at an error.
+pub fn read_ahead<R: Read + Send + 'static>(mut source: R)
+-> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::sync_channel(CHUNKS);
+
+    thread::spawn(move || {
+        let mut buffer = [0; CHUNK_SIZE];
+
+        loop {
+            let length = match source.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                    continue
+                }
+                Err(_) => break,
+            };
+
+            if sender.send(buffer[..length].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Input read by a thread. A read waits for the thread, but not past the
+/// deadline, if there is one.
+pub struct ReadAhead {
+    receiver: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    deadline: Option<Instant>,
+}
+
+impl ReadAhead {
+    pub fn spawn<R: Read + Send + 'static>(source: R,
+                                           deadline: Option<Instant>)
+    -> ReadAhead {
+        ReadAhead { receiver: read_ahead(source), pending: Vec::new(),
+                    deadline }
+    }
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        if self.pending.is_empty() {
+            let received = match self.deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(
+                        Instant::now()
+                    );
+
+                    self.receiver.recv_timeout(left)
+                }
+                None => self.receiver.recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            self.pending = match received {
+                Ok(bytes) => bytes,
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut,
+                                              "timed out waiting for input"));
+                }
+            };
+        }
+
+        let length = std::cmp::min(buffer.len(), self.pending.len());
+
+        buffer[..length].copy_from_slice(&self.pending[..length]);
+        self.pending.drain(..length);
+
+        Ok(length)
+    }
+}
+
+/// Output written by a thread, which flushes whenever it has caught up. A
+/// write only waits once the thread is `CHUNKS` writes behind, and not
+/// past the deadline, if there is one. Past it, output the thread has no
+/// room for is dropped: an error writing wouldn't stop the program, but
+/// the sandbox will, the next time it looks at the clock.
+///
+/// Dropping it waits for the thread to write everything sent to it, unless
+/// the deadline has passed, in which case the thread may never catch up.
+pub struct WriteBehind {
+    sender: Option<SyncSender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+    deadline: Option<Instant>,
+}
+
+impl WriteBehind {
+    pub fn spawn<W: Write + Send + 'static>(mut sink: W,
+                                            deadline: Option<Instant>)
+    -> WriteBehind {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(CHUNKS);
+        let thread = thread::spawn(move || {
+            loop {
+                let bytes = match receiver.try_recv() {
+                    Ok(bytes) => bytes,
+                    Err(TryRecvError::Empty) => {
+                        if sink.flush().is_err() {
+                            return;
+                        }
+
+                        match receiver.recv() {
+                            Ok(bytes) => bytes,
+                            Err(_) => return,
+                        }
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                };
+
+                if sink.write_all(&bytes).is_err() {
+                    return;
+                }
+            }
+
+            let _ = sink.flush();
+        });
+
+        WriteBehind { sender: Some(sender), thread: Some(thread), deadline }
+    }
+}
+
+impl Write for WriteBehind {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        // The thread only hangs up after failing to write.
+        let closed = || io::Error::new(io::ErrorKind::BrokenPipe,
+                                       "could not write output");
+        let sender = self.sender.as_ref().unwrap();
+        let mut chunk = bytes.to_vec();
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => {
+                return sender.send(chunk).map(|_| bytes.len())
+                    .map_err(|_| closed());
+            }
+        };
+
+        loop {
+            chunk = match sender.try_send(chunk) {
+                Ok(()) => return Ok(bytes.len()),
+                Err(TrySendError::Disconnected(_)) => return Err(closed()),
+                Err(TrySendError::Full(chunk)) => chunk,
+            };
+
+            if Instant::now() >= deadline {
+                return Ok(bytes.len());
+            }
+
+            thread::sleep(RETRY);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for WriteBehind {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            return;
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Compiling programs from a build script, so an application that bundles
+//! them embeds them already parsed and optimized.
+//!
+//! In `build.rs`, compile each program into `OUT_DIR`:
+//!
+//! ```ignore
+//! extern crate brainfuck;
+//!
+//! fn main() {
+//!     brainfuck::build::compile("programs/hello.b").unwrap();
+//! }
+//! ```
+//!
+//! and then embed one by its file stem with `include_bf!`, which decodes it
+//! without parsing or optimizing anything:
+//!
+//! ```ignore
+//! #[macro_use]
+//! extern crate brainfuck;
+//!
+//! fn main() {
+//!     let hello: brainfuck::Program = include_bf!("hello");
+//! }
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bfc;
+use frontend::Dialect;
+use optimize;
+
+/// Parses the program at `path`, as Spoon if it ends in `.spoon` and as
+/// brainfuck otherwise, optimizes it at the default level and writes it to
+/// `$OUT_DIR/<stem>.bfc`, which it returns. Also has Cargo rerun the build
+/// script when the program changes.
+pub fn compile<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    let path = path.as_ref();
+    let out_dir = std::env::var_os("OUT_DIR").ok_or_else(|| {
+        io::Error::other("OUT_DIR isn't set; compile programs from a build \
+                          script")
+    })?;
+    let stem = path.file_stem().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput,
+                       format!("'{}' names no file", path.display()))
+    })?;
+    let dialect = match path.extension().and_then(|e| e.to_str()) {
+        Some("spoon") => Dialect::Spoon,
+        _ => Dialect::Brainfuck,
+    };
+
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let source = fs::read_to_string(path)?;
+    let program = dialect.parse(&source).map_err(|e| {
+        let name = path.display().to_string();
+
+        io::Error::new(io::ErrorKind::InvalidData,
+                       e.diagnostic().render(&name, &source, false))
+    })?;
+    let mut name = stem.to_os_string();
+
+    name.push(".bfc");
+
+    let output = Path::new(&out_dir).join(name);
+
+    fs::write(&output, bfc::encode(&optimize::optimize(program), &source))?;
+
+    Ok(output)
+}
+
+/// The program that `build::compile` wrote for the file with stem `$name`,
+/// as a `Program`. Panics if the embedded file is corrupt, which it can only
+/// be if something other than `compile` wrote it.
+#[macro_export]
+macro_rules! include_bf {
+    ($name:literal) => {
+        $crate::bfc::decode(include_bytes!(concat!(env!("OUT_DIR"), "/",
+                                                   $name, ".bfc")))
+            .expect(concat!("invalid compiled program '", $name, "'"))
+            .0
+    };
+}
@@ -0,0 +1,288 @@
+//! Checkpoints, which save everything about a run partway through, so that
+//! a long run can carry on from the last one after a crash rather than
+//! from the start. `InterpreterState::with_checkpoints` takes them as a
+//! `Policy` says, and `InterpreterState::restore` carries on from one.
+//!
+//! All integers are little-endian. A file is the magic `BFK\0`, a `u16`
+//! format version, then the fields of `Checkpoint` in order, each number a
+//! `u64`. A list is a `u64` length then its items, a tape a `u8` that is 1
+//! if it was reached, then if so its pointer and its cells and those left
+//! of them, each a list of `u32`s.
+//!
+//! ```
+//! use std::sync::{Arc, Mutex};
+//!
+//! use brainfuck::checkpoint::{Checkpoint, Policy};
+//! use brainfuck::{Dialect, InterpreterState};
+//!
+//! let program = Dialect::Brainfuck.parse("++++[>+++<-]>.").unwrap();
+//! let saved = Arc::new(Mutex::new(Vec::new()));
+//! let sink = saved.clone();
+//! let mut first = InterpreterState::new(&program, Vec::new(), &b""[..])
+//!     .with_checkpoints(Policy::Steps(10), move |checkpoint| {
+//!         *sink.lock().unwrap() = checkpoint.encode();
+//!     });
+//!
+//! // The first run stops partway, as if it had crashed.
+//! for _ in 0..15 {
+//!     first.repl().unwrap();
+//! }
+//!
+//! let checkpoint = Checkpoint::decode(&saved.lock().unwrap()).unwrap();
+//! let mut output = Vec::new();
+//!
+//! {
+//!     let mut second = InterpreterState::new(&program, &mut output,
+//!                                            &b""[..]);
+//!
+//!     second.restore(&checkpoint).unwrap();
+//!     assert_eq!(second.steps_executed(), 10);
+//!
+//!     while second.span().is_some() {
+//!         second.repl().unwrap();
+//!     }
+//! }
+//!
+//! assert_eq!(output, [12]);
+//! ```
+
+use alloc::vec::Vec;
+use core::hash::Hasher;
+
+use io;
+use snapshot::{take, take_u64};
+use tape::Tape;
+
+pub const MAGIC: &[u8] = b"BFK\0";
+pub const VERSION: u16 = 1;
+
+/// When `InterpreterState::with_checkpoints` takes a checkpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Policy {
+    /// After every this many instructions.
+    Steps(u64),
+    /// Once this long has passed since the last, looking at the clock
+    /// every 4096 instructions.
+    #[cfg(feature = "std")]
+    Interval(std::time::Duration),
+}
+
+/// A tape as a checkpoint saves it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SavedTape {
+    pub pointer: usize,
+    /// The cells from the first on, without the zeros at the end.
+    pub cells: Vec<u32>,
+    /// The cells left of the first, nearest first, without the zeros at
+    /// the end.
+    pub left: Vec<u32>,
+}
+
+/// Cells without the zeros at their end.
+fn trimmed(cells: impl Iterator<Item = u32>) -> Vec<u32> {
+    let mut cells: Vec<u32> = cells.collect();
+    let used = cells.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1);
+
+    cells.truncate(used);
+
+    cells
+}
+
+impl SavedTape {
+    pub(crate) fn new<T: Tape>(tape: &T, pointer: usize) -> SavedTape {
+        SavedTape {
+            pointer,
+            cells: trimmed(tape.cells().iter().cloned()),
+            left: trimmed((1..=tape.left_len())
+                .map(|k| tape.get(k.wrapping_neg()))),
+        }
+    }
+
+    /// The tape saved, on a tape like `like`. Cells past its limit are
+    /// dropped, as `Tape::load` drops them.
+    pub(crate) fn to_tape<T: Tape>(&self, like: &T) -> io::Result<T> {
+        let mut tape = like.empty()?;
+
+        tape.load(&self.cells);
+
+        for (k, &cell) in self.left.iter().enumerate() {
+            if cell != 0 && tape.set((k + 1).wrapping_neg(), cell).is_err() {
+                break;
+            }
+        }
+
+        Ok(tape)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Checkpoint {
+    /// A hash of the instructions run, so a checkpoint is only restored
+    /// into the program it was taken of, optimized the same way.
+    pub program: u64,
+    pub instruction_pointer: usize,
+    pub steps: u64,
+    /// Where the generator `?` reads from was.
+    pub rng: u64,
+    /// Bytes of input read before the checkpoint, which a run carrying on
+    /// from it should skip.
+    pub input_bytes: u64,
+    /// Bytes of output written before the checkpoint, which a run carrying
+    /// on from it doesn't write again.
+    pub output_bytes: u64,
+    /// The `start` and `iteration` of each loop open, outermost first, as
+    /// `InterpreterState::loops` has them.
+    pub loops: Vec<(usize, u64)>,
+    /// Which tape is current, with the `tapes` extension.
+    pub current_tape: usize,
+    /// Each tape, counting from 0, or `None` for those not reached yet.
+    pub tapes: Vec<Option<SavedTape>>,
+}
+
+/// Whether `bytes` start like a checkpoint.
+pub fn is_checkpoint(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// 64-bit FNV-1a, which `InterpreterState` hashes its instructions with for
+/// `Checkpoint::program`, as it is the same from one build to the next.
+pub(crate) struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+fn put_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_cells(bytes: &mut Vec<u8>, cells: &[u32]) {
+    put_u64(bytes, cells.len() as u64);
+
+    for cell in cells {
+        bytes.extend_from_slice(&cell.to_le_bytes());
+    }
+}
+
+fn take_cells(bytes: &mut &[u8]) -> Option<Vec<u32>> {
+    let length = take_u64(bytes)? as usize;
+    let cells = take(bytes, length.checked_mul(4)?)?;
+
+    Some(cells.chunks(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
+
+impl Checkpoint {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+        for &value in &[self.program, self.instruction_pointer as u64,
+                        self.steps, self.rng, self.input_bytes,
+                        self.output_bytes, self.loops.len() as u64] {
+            put_u64(&mut bytes, value);
+        }
+
+        for &(start, iteration) in &self.loops {
+            put_u64(&mut bytes, start as u64);
+            put_u64(&mut bytes, iteration);
+        }
+
+        put_u64(&mut bytes, self.current_tape as u64);
+        put_u64(&mut bytes, self.tapes.len() as u64);
+
+        for tape in &self.tapes {
+            match *tape {
+                Some(ref tape) => {
+                    bytes.push(1);
+                    put_u64(&mut bytes, tape.pointer as u64);
+                    put_cells(&mut bytes, &tape.cells);
+                    put_cells(&mut bytes, &tape.left);
+                }
+                None => bytes.push(0),
+            }
+        }
+
+        bytes
+    }
+
+    /// Reads a checkpoint written by `encode`, or returns `None` if `bytes`
+    /// aren't one this version understands.
+    pub fn decode(mut bytes: &[u8]) -> Option<Checkpoint> {
+        if take(&mut bytes, MAGIC.len())? != MAGIC {
+            return None;
+        }
+
+        let version = take(&mut bytes, 2)?;
+
+        if u16::from_le_bytes([version[0], version[1]]) != VERSION {
+            return None;
+        }
+
+        let program = take_u64(&mut bytes)?;
+        let instruction_pointer = take_u64(&mut bytes)? as usize;
+        let steps = take_u64(&mut bytes)?;
+        let rng = take_u64(&mut bytes)?;
+        let input_bytes = take_u64(&mut bytes)?;
+        let output_bytes = take_u64(&mut bytes)?;
+        let loop_count = take_u64(&mut bytes)? as usize;
+
+        // Each loop is 16 bytes, so a count the rest can't hold is caught
+        // before anything is allocated for it.
+        if loop_count > bytes.len() / 16 {
+            return None;
+        }
+
+        let mut loops = Vec::with_capacity(loop_count);
+
+        for _ in 0..loop_count {
+            loops.push((take_u64(&mut bytes)? as usize, take_u64(&mut bytes)?));
+        }
+
+        let current_tape = take_u64(&mut bytes)? as usize;
+        let tape_count = take_u64(&mut bytes)? as usize;
+
+        if tape_count > bytes.len() {
+            return None;
+        }
+
+        let mut tapes = Vec::with_capacity(tape_count);
+
+        for _ in 0..tape_count {
+            tapes.push(match take(&mut bytes, 1)?[0] {
+                0 => None,
+                1 => Some(SavedTape {
+                    pointer: take_u64(&mut bytes)? as usize,
+                    cells: take_cells(&mut bytes)?,
+                    left: take_cells(&mut bytes)?,
+                }),
+                _ => return None,
+            });
+        }
+
+        if !bytes.is_empty() {
+            return None;
+        }
+
+        Some(Checkpoint { program, instruction_pointer, steps, rng,
+                          input_bytes, output_bytes, loops, current_tape,
+                          tapes })
+    }
+}
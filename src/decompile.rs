@@ -0,0 +1,204 @@
+//! Lifts programs to pseudo-code such as `cell[3] += 5 * cell[1]`.
+//!
+//! Cells are addressed absolutely while the pointer position is known. Once a
+//! loop moves the pointer by an amount that depends on the tape, addressing
+//! switches to offsets from a pointer variable `p`.
+
+use std::fmt::Write;
+
+use analysis;
+use ir::{self, Node, Op, Program};
+
+struct Decompiler {
+    output: String,
+    depth: usize,
+    symbolic: bool,
+    offset: isize,
+}
+
+impl Decompiler {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.output.push_str("    ");
+        }
+
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    fn index(&self, offset: isize) -> String {
+        let offset = self.offset + offset;
+
+        if !self.symbolic {
+            offset.to_string()
+        } else if offset > 0 {
+            format!("p + {}", offset)
+        } else if offset < 0 {
+            format!("p - {}", -offset)
+        } else {
+            "p".to_string()
+        }
+    }
+
+    fn cell(&self, offset: isize) -> String {
+        format!("cell[{}]", self.index(offset))
+    }
+
+    /// Makes `p` hold the current pointer position.
+    fn materialize(&mut self) {
+        if !self.symbolic {
+            let text = format!("p = {}", self.offset);
+
+            self.line(&text);
+            self.symbolic = true;
+        } else if self.offset != 0 {
+            let text = if self.offset > 0 {
+                format!("p += {}", self.offset)
+            } else {
+                format!("p -= {}", -self.offset)
+            };
+
+            self.line(&text);
+        }
+
+        self.offset = 0;
+    }
+
+    fn add(&mut self, offset: isize, amount: i32) {
+        let text = if amount < 0 {
+            format!("{} -= {}", self.cell(offset), -(amount as i64))
+        } else {
+            format!("{} += {}", self.cell(offset), amount)
+        };
+
+        self.line(&text);
+    }
+
+    fn multiply_add(&mut self, offset: isize, factor: i32) {
+        let mut text = String::new();
+        let magnitude = (factor as i64).abs();
+
+        write!(text, "{} {}= ", self.cell(offset),
+               if factor < 0 { '-' } else { '+' }).unwrap();
+
+        if magnitude != 1 {
+            write!(text, "{} * ", magnitude).unwrap();
+        }
+
+        text.push_str(&self.cell(0));
+        self.line(&text);
+    }
+
+    fn straight_line(&mut self, nodes: &[Node]) {
+        let effects = analysis::effects(nodes).unwrap();
+
+        for &(offset, amount) in &effects.adds {
+            self.add(offset, amount);
+        }
+
+        self.offset += effects.movement;
+    }
+
+    fn lift_loop(&mut self, body: &[Node]) {
+        if analysis::is_clear_loop(body) {
+            let text = format!("{} = 0", self.cell(0));
+
+            return self.line(&text);
+        }
+
+        if let Some(targets) = analysis::multiply_loop(body) {
+            for (offset, factor) in targets {
+                self.multiply_add(offset, factor);
+            }
+
+            let text = format!("{} = 0", self.cell(0));
+
+            return self.line(&text);
+        }
+
+        let balanced = analysis::is_balanced(body);
+
+        if !balanced {
+            self.materialize();
+        }
+
+        let text = format!("while {} != 0 {{", self.cell(0));
+
+        self.line(&text);
+        self.depth += 1;
+        self.nodes(body);
+
+        if !balanced {
+            self.materialize();
+        }
+
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) {
+        let mut start = 0;
+
+        for (i, node) in nodes.iter().enumerate() {
+            if let Op::Add(_) | Op::AddAt { .. } | Op::Move(_) = node.op {
+                continue;
+            }
+
+            self.straight_line(&nodes[start..i]);
+            start = i + 1;
+
+            let text = match node.op {
+                Op::Output => format!("print({})", self.cell(0)),
+                Op::Print(ref values) => {
+                    format!("print({})", ir::render(values))
+                }
+                Op::Input => format!("{} = read()", self.cell(0)),
+                Op::Random => format!("{} = random()", self.cell(0)),
+                Op::Fork => format!("fork({})", self.cell(0)),
+                Op::SwitchTape(n) => format!("tape {:+}", n),
+                Op::Clear => format!("{} = 0", self.cell(0)),
+                Op::MulAdd { offset, factor } => {
+                    self.multiply_add(offset, factor);
+
+                    continue;
+                }
+                Op::Loop(ref body) => {
+                    self.lift_loop(body);
+
+                    continue;
+                }
+                Op::Scan(step) => {
+                    self.materialize();
+
+                    format!("while {} != 0 {{ p {}= {} }}", self.cell(0),
+                            if step < 0 { '-' } else { '+' },
+                            step.unsigned_abs())
+                }
+                Op::ClearRange(extent) => {
+                    let (low, high) = if extent < 0 { (extent, 0) }
+                                      else { (0, extent) };
+                    let text = format!("cell[{}..={}] = 0", self.index(low),
+                                       self.index(high));
+
+                    self.offset += extent;
+
+                    text
+                }
+                Op::Add(_) | Op::AddAt { .. } | Op::Move(_) => unreachable!(),
+            };
+
+            self.line(&text);
+        }
+
+        self.straight_line(&nodes[start..]);
+    }
+}
+
+pub fn decompile(program: &Program) -> String {
+    let mut decompiler = Decompiler { output: String::new(), depth: 0,
+                                      symbolic: false, offset: 0 };
+
+    decompiler.nodes(&program.nodes);
+
+    decompiler.output
+}
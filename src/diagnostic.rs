@@ -0,0 +1,130 @@
+//! Errors rendered the way compilers show them: a headline, where in the
+//! file it happened, and the line of source with a caret under the span.
+//!
+//! ```
+//! use brainfuck::Dialect;
+//!
+//! let source = "+[>+<";
+//! let error = Dialect::Brainfuck.parse(source).unwrap_err();
+//! let rendered = error.diagnostic().render("add.b", source, false);
+//!
+//! assert_eq!(rendered, "error: no matching ']' for '['\n \
+//!                       --> add.b:1:2\n  \
+//!                       |\n\
+//!                       1 | +[>+<\n  \
+//!                       |  ^ this loop is never closed");
+//! ```
+
+use alloc::string::String;
+
+use ir::{self, Span};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+/// A message about a span of source, with an optional label pointing at it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub label: Option<String>,
+}
+
+/// The offset of the start of the character `offset` falls in, or the
+/// end of `source` if it is past it, so a span from elsewhere can't split a
+/// character.
+fn char_boundary(source: &str, offset: usize) -> usize {
+    (0..=core::cmp::min(offset, source.len())).rev()
+        .find(|&i| source.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(severity: Severity, message: S, span: Span)
+    -> Diagnostic {
+        Diagnostic { severity, message: message.into(), span, label: None }
+    }
+
+    pub fn error<S: Into<String>>(message: S, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message, span)
+    }
+
+    pub fn warning<S: Into<String>>(message: S, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message, span)
+    }
+
+    pub fn with_label<S: Into<String>>(mut self, label: S) -> Diagnostic {
+        self.label = Some(label.into());
+
+        self
+    }
+
+    /// Renders the diagnostic against `source`, read from `filename`, in
+    /// ANSI colors if `color` is set, without a trailing newline. A span
+    /// over several lines is marked to the end of its first, and one that
+    /// splits a character is moved back to its start.
+    ///
+    /// ```
+    /// use brainfuck::diagnostic::Diagnostic;
+    /// use brainfuck::ir::Span;
+    ///
+    /// let error = Diagnostic::error("unexpected", Span::new(1, 2));
+    ///
+    /// assert!(error.render("a.b", "é<", false).ends_with("1 | é<\n  | ^"));
+    /// ```
+    pub fn render(&self, filename: &str, source: &str, color: bool)
+    -> String {
+        let paint = |code: &str, text: &str| if color {
+            format!("{}{}\x1b[0m", code, text)
+        } else {
+            String::from(text)
+        };
+        let blue = "\x1b[1;34m";
+        let start = char_boundary(source, self.span.start);
+        let end = core::cmp::max(start, char_boundary(source, self.span.end));
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line = source[line_start..line_end].trim_end_matches('\r');
+        let (number, column) = ir::line_col(source, start);
+        let number = format!("{}", number);
+        let gutter = " ".repeat(number.len());
+        let marked = source[start..core::cmp::min(end, line_end)].chars()
+            .count();
+        let mut carets = format!("{}{}", " ".repeat(column - 1),
+                                 "^".repeat(core::cmp::max(1, marked)));
+
+        if let Some(ref label) = self.label {
+            carets = format!("{} {}", carets, label);
+        }
+
+        format!("{}{}\n{}{} {}:{}:{}\n{} {}\n{} {} {}\n{} {} {}",
+                paint(self.severity.color(), self.severity.name()),
+                paint("\x1b[1m", &format!(": {}", self.message)),
+                gutter, paint(blue, "-->"), filename, number, column,
+                gutter, paint(blue, "|"),
+                paint(blue, &number), paint(blue, "|"), line,
+                gutter, paint(blue, "|"),
+                paint(self.severity.color(), &carets))
+    }
+}
@@ -0,0 +1,292 @@
+//! Line diffs between expected and actual program output, and structural
+//! diffs between the IR of two programs.
+
+use ir::{self, Node, Op, Program};
+
+enum Edit<T> {
+    Same(T),
+    Removed(T),
+    Added(T),
+}
+
+fn edits<T, F>(old: &[T], new: &[T], same: F) -> Vec<Edit<T>>
+where T: Copy, F: Fn(T, T) -> bool {
+    // lengths[i][j] is the longest common subsequence of old[i..], new[j..]
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if same(old[i], new[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                std::cmp::max(lengths[i + 1][j], lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut edits = Vec::new();
+
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && same(old[i], new[j]) {
+            edits.push(Edit::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len()
+                  || (i < old.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            edits.push(Edit::Removed(old[i]));
+            i += 1;
+        } else {
+            edits.push(Edit::Added(new[j]));
+            j += 1;
+        }
+    }
+
+    edits
+}
+
+/// Renders the lines that differ between `expected` and `actual`, prefixed
+/// with `-` and `+` respectively, with a line of context either side. Empty
+/// if the two are the same.
+pub fn lines(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.split('\n').collect();
+    let new: Vec<&str> = actual.split('\n').collect();
+    let edits = edits(&old, &new, |a, b| a == b);
+    let changed = |k: usize| {
+        !matches!(edits.get(k), Some(&Edit::Same(_)) | None)
+    };
+    let mut out = String::new();
+
+    for (k, edit) in edits.iter().enumerate() {
+        match *edit {
+            Edit::Same(line) => {
+                if changed(k + 1) || (k > 0 && changed(k - 1)) {
+                    out.push_str(&format!(" {}\n", line));
+                }
+            }
+            Edit::Removed(line) => out.push_str(&format!("-{}\n", line)),
+            Edit::Added(line) => out.push_str(&format!("+{}\n", line)),
+        }
+    }
+
+    out
+}
+
+/// Whether two nodes do the same thing, wherever in the source they came
+/// from.
+fn same(a: &Node, b: &Node) -> bool {
+    match (&a.op, &b.op) {
+        (Op::Loop(a), Op::Loop(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+        }
+        (a, b) => a == b,
+    }
+}
+
+fn same_kind(a: &Op, b: &Op) -> bool {
+    core::mem::discriminant(a) == core::mem::discriminant(b)
+}
+
+/// One line of a structural diff.
+struct Line {
+    /// `-`, `+`, `~` for a changed constant, or ` ` for context.
+    sign: char,
+    depth: usize,
+    text: String,
+    location: String,
+    /// Whether to show the line even if it is context far from a change,
+    /// as the start and end of a loop with changes inside are.
+    keep: bool,
+}
+
+/// The source a program was parsed from, to locate its nodes.
+struct Side<'a> {
+    program: &'a Program,
+    source: &'a str,
+}
+
+impl<'a> Side<'a> {
+    fn location(&self, node: &Node) -> String {
+        let (line, column) = ir::line_col(self.source, node.span.start);
+
+        format!("{}:{}", line, column)
+    }
+}
+
+fn whole(sign: char, node: &Node, side: &Side, depth: usize,
+         out: &mut Vec<Line>) {
+    out.push(Line { sign, depth, text: ir::describe(&node.op),
+                    location: side.location(node), keep: false });
+
+    if let Op::Loop(ref body) = node.op {
+        for node in body {
+            whole(sign, node, side, depth + 1, out);
+        }
+
+        out.push(Line { sign, depth, text: "end".to_string(),
+                        location: String::new(), keep: false });
+    }
+}
+
+fn changed(old: &Node, new: &Node, sides: (&Side, &Side), depth: usize,
+           out: &mut Vec<Line>) {
+    let location = format!("{} -> {}", sides.0.location(old),
+                           sides.1.location(new));
+
+    match (&old.op, &new.op) {
+        (Op::Loop(a), Op::Loop(b)) => {
+            out.push(Line { sign: ' ', depth, text: "loop".to_string(),
+                            location, keep: true });
+            nodes(a, b, sides, depth + 1, out);
+            out.push(Line { sign: ' ', depth, text: "end".to_string(),
+                            location: String::new(), keep: true });
+        }
+        (a, b) => out.push(Line {
+            sign: '~',
+            depth,
+            text: format!("{} -> {}", ir::describe(a), ir::describe(b)),
+            location,
+            keep: false,
+        }),
+    }
+}
+
+fn nodes(old: &[Node], new: &[Node], sides: (&Side, &Side), depth: usize,
+         out: &mut Vec<Line>) {
+    let old: Vec<&Node> = old.iter().collect();
+    let new: Vec<&Node> = new.iter().collect();
+    let edits = edits(&old, &new, same);
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+
+    // Pairs up each run of removed nodes with the run of added nodes after
+    // it, so a node that only changed shows as one line.
+    let flush = |removed: &mut Vec<&Node>, added: &mut Vec<&Node>,
+                     out: &mut Vec<Line>| {
+        for i in 0..core::cmp::max(removed.len(), added.len()) {
+            match (removed.get(i), added.get(i)) {
+                (Some(a), Some(b)) if same_kind(&a.op, &b.op) => {
+                    changed(a, b, sides, depth, out);
+                }
+                (a, b) => {
+                    if let Some(a) = a {
+                        whole('-', a, sides.0, depth, out);
+                    }
+
+                    if let Some(b) = b {
+                        whole('+', b, sides.1, depth, out);
+                    }
+                }
+            }
+        }
+
+        removed.clear();
+        added.clear();
+    };
+
+    for edit in edits {
+        match edit {
+            Edit::Same(node) => {
+                flush(&mut removed, &mut added, out);
+                out.push(Line { sign: ' ', depth,
+                                text: ir::describe(&node.op),
+                                location: sides.0.location(node),
+                                keep: false });
+            }
+            Edit::Removed(node) => removed.push(node),
+            Edit::Added(node) => added.push(node),
+        }
+    }
+
+    flush(&mut removed, &mut added, out);
+}
+
+/// Renders how the IR of `new` differs from that of `old`, one op per line
+/// like `ir::dump`: `-` for ops removed, `+` for ops added and `~` for ops
+/// whose constants changed, with a line of context either side and where
+/// each op came from in `old_source` and `new_source`. Loops are compared
+/// by their bodies, and collapsed if nothing in them changed. Empty if the
+/// two programs do the same thing.
+pub fn programs(old: &Program, old_source: &str, new: &Program,
+                new_source: &str) -> String {
+    let old = Side { program: old, source: old_source };
+    let new = Side { program: new, source: new_source };
+    let mut lines = Vec::new();
+
+    nodes(&old.program.nodes, &new.program.nodes, (&old, &new), 0,
+          &mut lines);
+
+    let changed = |k: usize| lines.get(k).is_some_and(|l| l.sign != ' ');
+    let mut out = String::new();
+
+    for (k, line) in lines.iter().enumerate() {
+        let shown = line.sign != ' ' || line.keep || changed(k + 1)
+                    || (k > 0 && changed(k - 1));
+
+        if !shown {
+            continue;
+        }
+
+        let text = format!("{}{:indent$}{}", line.sign, "", line.text,
+                           indent = line.depth * 4);
+
+        if line.location.is_empty() {
+            out.push_str(&format!("{}\n", text));
+        } else {
+            out.push_str(&format!("{:<40} ; {}\n", text, line.location));
+        }
+    }
+
+    out
+}
+
+/// A cell as `tapes` shows it: its value, then its character if that is
+/// printable ASCII.
+fn cell(value: u32) -> String {
+    match core::char::from_u32(value) {
+        Some(c) if c.is_ascii_graphic() => format!("{} '{}'", value, c),
+        _ => value.to_string(),
+    }
+}
+
+/// Renders the cells that differ between the tapes `old` and `new`, after
+/// how many there are, one per line as its index and its value in each.
+/// Cells past the end of a tape are zero. Empty if the two are the same.
+///
+/// ```
+/// use brainfuck::diff;
+///
+/// let diff = diff::tapes(&[72, 0, 5], &[72, 105]);
+/// let lines: Vec<_> = diff.lines().map(str::split_whitespace)
+///     .map(Iterator::collect::<Vec<_>>)
+///     .collect();
+///
+/// assert_eq!(lines[0], ["2", "cells", "differ,", "from", "cell", "1"]);
+/// assert_eq!(lines[2], ["1", "0", "105", "'i'"]);
+/// assert_eq!(lines[3], ["2", "5", "0"]);
+/// assert_eq!(diff::tapes(&[1, 2], &[1, 2, 0]), "");
+/// ```
+pub fn tapes(old: &[u32], new: &[u32]) -> String {
+    let length = std::cmp::max(old.len(), new.len());
+    let at = |tape: &[u32], i: usize| tape.get(i).cloned().unwrap_or(0);
+    let differ: Vec<usize> = (0..length).filter(|&i| at(old, i) != at(new, i))
+        .collect();
+    let first = match differ.first() {
+        Some(&first) => first,
+        None => return String::new(),
+    };
+    let mut out = if differ.len() == 1 {
+        format!("1 cell differs, cell {}\n", first)
+    } else {
+        format!("{} cells differ, from cell {}\n", differ.len(), first)
+    };
+
+    out.push_str(&format!("{:>10}  {:>12}  {:>12}\n", "cell", "old", "new"));
+
+    for i in differ {
+        out.push_str(&format!("{:>10}  {:>12}  {:>12}\n", i,
+                              cell(at(old, i)), cell(at(new, i))));
+    }
+
+    out
+}
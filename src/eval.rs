@@ -0,0 +1,275 @@
+//! Compile-time evaluation of the parts of a program that only depend on
+//! input that is already known.
+//!
+//! The evaluator runs the IR until it needs input it doesn't have, leaves the
+//! tape, reaches an add that traps, or runs out of steps, then builds a
+//! residual program: a `Print` of everything output so far, ops that
+//! recreate the tape, and the rest of the program from where evaluation
+//! stopped. Stopping inside nested loops is fine, because running the
+//! remainder of a loop body followed by the loop itself behaves exactly like
+//! jumping back to the loop's `]`.
+
+use ir::{Node, Op, Overflow, Program, Span};
+use utf8::Chars;
+
+// Evaluation stops rather than touch cells past this index.
+const MAX_CELLS: usize = 1 << 20;
+
+pub struct Residual {
+    pub program: Program,
+    /// Bytes of the known input that the residual program no longer reads.
+    pub consumed: usize,
+    pub steps: u64,
+    /// Whether the whole program ran, leaving only its output and final tape
+    /// to replay.
+    pub finished: bool,
+}
+
+struct Evaluator<'a> {
+    frames: Vec<(&'a [Node], usize)>,
+    tape: Vec<u32>,
+    pointer: usize,
+    output: Vec<u32>,
+    input: &'a [u8],
+    consumed: usize,
+    overflow: Overflow,
+}
+
+impl<'a> Evaluator<'a> {
+    fn index(&self, offset: isize) -> Option<usize> {
+        let index = self.pointer as isize + offset;
+
+        if index < 0 || index as usize >= MAX_CELLS {
+            return None;
+        }
+
+        Some(index as usize)
+    }
+
+    fn cell_mut(&mut self, index: usize) -> &mut u32 {
+        if index >= self.tape.len() {
+            self.tape.resize(index + 1, 0);
+        }
+
+        &mut self.tape[index]
+    }
+
+    fn current(&self) -> u32 {
+        self.tape.get(self.pointer).cloned().unwrap_or(0)
+    }
+
+    /// Returns `false`, leaving the cell alone, if the add would trap.
+    fn add(&mut self, offset: isize, amount: i64) -> bool {
+        let overflow = self.overflow;
+
+        match self.index(offset) {
+            Some(index) => {
+                let cell = self.cell_mut(index);
+
+                match overflow.add(*cell, amount) {
+                    Some(sum) => {
+                        *cell = sum;
+
+                        true
+                    }
+                    None => false,
+                }
+            }
+            None => false,
+        }
+    }
+
+    /// Executes one op, returning `false` if it can't be evaluated now.
+    fn execute(&mut self, op: &Op) -> bool {
+        match *op {
+            Op::Add(n) => self.add(0, n as i64),
+            Op::AddAt { offset, amount } => self.add(offset, amount as i64),
+            Op::Move(n) => match self.index(n) {
+                Some(index) => {
+                    self.pointer = index;
+
+                    true
+                }
+                None => false,
+            },
+            Op::Output => {
+                let value = self.current();
+
+                self.output.push(value);
+
+                true
+            }
+            Op::Print(ref values) => {
+                self.output.extend_from_slice(values);
+
+                true
+            }
+            Op::Input => {
+                let c = match Chars::new(&self.input[self.consumed..]).next() {
+                    Some(Ok(c)) => c,
+                    _ => return false,
+                };
+
+                self.consumed += c.len_utf8();
+
+                let pointer = self.pointer;
+
+                *self.cell_mut(pointer) = c as u32;
+
+                true
+            }
+            // The seed isn't known until the program runs, and threads
+            // can't be run ahead of time.
+            Op::Random | Op::Fork | Op::SwitchTape(_) => false,
+            Op::Clear => {
+                let pointer = self.pointer;
+
+                *self.cell_mut(pointer) = 0;
+
+                true
+            }
+            Op::MulAdd { offset, factor } => {
+                let value = self.current();
+
+                value == 0 || self.add(offset, value as i64 * factor as i64)
+            }
+            Op::Scan(step) => {
+                while self.current() != 0 {
+                    match self.index(step) {
+                        Some(index) => self.pointer = index,
+                        None => return false,
+                    }
+                }
+
+                true
+            }
+            Op::ClearRange(extent) => {
+                let end = match self.index(extent) {
+                    Some(end) => end,
+                    None => return false,
+                };
+                let low = std::cmp::min(self.pointer, end);
+                let high = std::cmp::max(self.pointer, end);
+
+                for cell in self.tape.iter_mut().take(high + 1).skip(low) {
+                    *cell = 0;
+                }
+
+                self.pointer = end;
+
+                true
+            }
+            Op::Loop(_) => unreachable!(),
+        }
+    }
+
+    /// Advances by one op or loop test. Returns `false` once evaluation
+    /// can't continue.
+    fn step(&mut self) -> bool {
+        let (nodes, index) = match self.frames.last() {
+            Some(&f) => f,
+            None => return false,
+        };
+
+        if index == nodes.len() {
+            if self.frames.len() == 1 {
+                self.frames.pop();
+            } else if self.current() != 0 {
+                self.frames.last_mut().unwrap().1 = 0;
+            } else {
+                self.frames.pop();
+                self.frames.last_mut().unwrap().1 += 1;
+            }
+
+            return true;
+        }
+
+        match nodes[index].op {
+            Op::Loop(ref body) => if self.current() != 0 {
+                self.frames.push((body, 0));
+            } else {
+                self.frames.last_mut().unwrap().1 += 1;
+            },
+            ref op => {
+                if !self.execute(op) {
+                    return false;
+                }
+
+                self.frames.last_mut().unwrap().1 += 1;
+            }
+        }
+
+        true
+    }
+
+    fn residual(&self) -> Vec<Node> {
+        let span = Span::default();
+        let mut nodes = Vec::new();
+
+        if !self.output.is_empty() {
+            nodes.push(Node::new(Op::Print(self.output.clone()), span));
+        }
+
+        let mut position = 0;
+
+        for (index, &value) in self.tape.iter().enumerate() {
+            if value == 0 {
+                continue;
+            }
+
+            if index != position {
+                nodes.push(Node::new(Op::Move(index as isize
+                                              - position as isize), span));
+            }
+
+            // Unless arithmetic wraps, values past `i32::MAX` take more
+            // than one add.
+            let mut value = value;
+
+            if self.overflow == Overflow::Wrap {
+                nodes.push(Node::new(Op::Add(value as i32), span));
+                value = 0;
+            }
+
+            while value > 0 {
+                let amount = std::cmp::min(value, i32::MAX as u32);
+
+                nodes.push(Node::new(Op::Add(amount as i32), span));
+                value -= amount;
+            }
+
+            position = index;
+        }
+
+        if self.pointer != position {
+            nodes.push(Node::new(Op::Move(self.pointer as isize
+                                          - position as isize), span));
+        }
+
+        for &(frame, index) in self.frames.iter().rev() {
+            nodes.extend(frame[index..].iter().cloned());
+        }
+
+        nodes
+    }
+}
+
+/// Evaluates `program` against `input` for at most `max_steps` steps and
+/// returns the residual program to run on the rest of the input.
+pub fn partially_evaluate(program: &Program, input: &[u8], max_steps: u64)
+-> Residual {
+    let mut evaluator = Evaluator { frames: vec![(&program.nodes, 0)],
+                                    tape: Vec::new(), pointer: 0,
+                                    output: Vec::new(), input, consumed: 0,
+                                    overflow: program.overflow };
+    let mut steps = 0;
+
+    while steps < max_steps && evaluator.step() {
+        steps += 1;
+    }
+
+    Residual { program: Program::new(evaluator.residual())
+                   .with_overflow(program.overflow),
+               consumed: evaluator.consumed, steps,
+               finished: evaluator.frames.is_empty() }
+}
@@ -0,0 +1,185 @@
+//! C bindings for embedding the interpreter. Build the shared library with
+//! `cargo rustc --release --lib --crate-type cdylib`, which isn't a default
+//! crate type so that `no_std` dependents needn't link one.
+//!
+//! `include/brainfuck.h` declares them; regenerate it with
+//! `cbindgen --config cbindgen.toml --output include/brainfuck.h` after
+//! changing anything here.
+
+use std::cell::Cell;
+use std::io::Write;
+use std::os::raw::{c_char, c_int, c_void};
+use std::rc::Rc;
+
+use frontend::Dialect;
+use interpreter::InterpreterState;
+use io::{self, BfInput, BfOutput};
+use optimize::Registry;
+
+/// The instruction ran and there are more to go.
+pub const BF_OK: c_int = 0;
+/// The program has finished.
+pub const BF_DONE: c_int = 1;
+/// The program stopped because reading or writing failed, or it ran out of
+/// input.
+pub const BF_ERROR: c_int = -1;
+
+/// Returns the next byte of input, or a negative number at the end of it.
+pub type BfReadFn = Option<unsafe extern "C" fn(user: *mut c_void) -> c_int>;
+
+/// Writes `length` bytes of output, returning a negative number if that
+/// failed.
+pub type BfWriteFn = Option<unsafe extern "C" fn(user: *mut c_void,
+                                                 data: *const u8,
+                                                 length: usize) -> c_int>;
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    read: BfReadFn,
+    write: BfWriteFn,
+    user: *mut c_void,
+}
+
+/// Input from the read callback, or stdin if there isn't one.
+struct Input(Rc<Cell<Callbacks>>);
+
+impl BfInput for Input {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let callbacks = self.0.get();
+
+        match callbacks.read {
+            Some(read) => match unsafe { read(callbacks.user) } {
+                n if n < 0 => Ok(None),
+                n => Ok(Some(n as u8)),
+            },
+            None => std::io::stdin().read_byte(),
+        }
+    }
+}
+
+/// Output to the write callback, or stdout if there isn't one.
+struct Output(Rc<Cell<Callbacks>>);
+
+impl BfOutput for Output {
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let callbacks = self.0.get();
+
+        match callbacks.write {
+            Some(write) => {
+                if unsafe { write(callbacks.user, bytes.as_ptr(),
+                                  bytes.len()) } < 0 {
+                    return Err(io::Error::other("write callback failed"));
+                }
+
+                Ok(())
+            }
+            // Flushed right away, since the host may exit without running
+            // Rust's cleanup.
+            None => {
+                let mut stdout = std::io::stdout();
+
+                stdout.write_all(bytes)?;
+                stdout.flush()
+            }
+        }
+    }
+}
+
+/// A program loaded into an interpreter, opaque to C.
+pub struct Bf {
+    interpreter: InterpreterState<Input, Output>,
+    callbacks: Rc<Cell<Callbacks>>,
+}
+
+/// Parses `length` bytes of brainfuck at `source` and optimizes it at the
+/// default level. Returns null if the source isn't valid UTF-8 or doesn't
+/// parse. Until callbacks are set, the program reads stdin and writes
+/// stdout.
+///
+/// # Safety
+///
+/// `source` must point to `length` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bf_new(source: *const c_char, length: usize)
+-> *mut Bf {
+    let bytes = std::slice::from_raw_parts(source as *const u8, length);
+    let program = match std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| Dialect::Brainfuck.parse(s).ok()) {
+        Some(p) => Registry::default().preset(2).run(p),
+        None => return std::ptr::null_mut(),
+    };
+    let callbacks = Rc::new(Cell::new(Callbacks {
+        read: None,
+        write: None,
+        user: std::ptr::null_mut(),
+    }));
+    let interpreter = InterpreterState::new(&program,
+                                            Output(callbacks.clone()),
+                                            Input(callbacks.clone()));
+
+    Box::into_raw(Box::new(Bf { interpreter, callbacks }))
+}
+
+/// Sends the program's input and output through `read` and `write`, which
+/// are passed `user` each time they are called. Either may be null to go
+/// back to stdin or stdout.
+///
+/// # Safety
+///
+/// `bf` must have come from `bf_new` and not yet been freed. The callbacks
+/// must be safe to call with `user` for as long as they are set.
+#[no_mangle]
+pub unsafe extern "C" fn bf_set_io_callbacks(bf: *mut Bf, read: BfReadFn,
+                                             write: BfWriteFn,
+                                             user: *mut c_void) {
+    (*bf).callbacks.set(Callbacks { read, write, user });
+}
+
+/// Runs one instruction, returning `BF_OK`, or `BF_DONE` if the program had
+/// already finished, or `BF_ERROR`.
+///
+/// # Safety
+///
+/// `bf` must have come from `bf_new` and not yet been freed.
+#[no_mangle]
+pub unsafe extern "C" fn bf_step(bf: *mut Bf) -> c_int {
+    let interpreter = &mut (*bf).interpreter;
+
+    if interpreter.span().is_none() {
+        return BF_DONE;
+    }
+
+    match interpreter.repl() {
+        Ok(()) => BF_OK,
+        Err(_) => BF_ERROR,
+    }
+}
+
+/// Runs the program until it finishes, returning `BF_DONE`, or `BF_ERROR`
+/// if it stopped early.
+///
+/// # Safety
+///
+/// `bf` must have come from `bf_new` and not yet been freed.
+#[no_mangle]
+pub unsafe extern "C" fn bf_run(bf: *mut Bf) -> c_int {
+    loop {
+        match bf_step(bf) {
+            BF_OK => (),
+            status => return status,
+        }
+    }
+}
+
+/// Frees a program. Does nothing if `bf` is null.
+///
+/// # Safety
+///
+/// `bf` must be null or have come from `bf_new` and not yet been freed.
+#[no_mangle]
+pub unsafe extern "C" fn bf_free(bf: *mut Bf) {
+    if !bf.is_null() {
+        drop(Box::from_raw(bf));
+    }
+}
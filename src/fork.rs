@@ -0,0 +1,103 @@
+//! Brainfork's threads, for programs using `Y` from the `fork` extension.
+//!
+//! Each `Y` starts a thread running the rest of the program in its own
+//! interpreter, on a copy of the tape. Every thread reads from the same
+//! input and writes to the same output, one value at a time, and a thread
+//! only finishes once all the threads it started have.
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Scope};
+
+use interpreter::InterpreterState;
+use io::is_end_of_input;
+use ir::Program;
+
+/// Input or output shared between threads.
+struct Shared<T>(Arc<Mutex<T>>);
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Shared<T> {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, T> {
+        // A thread that panicked holding the lock can't have left a reader
+        // or writer worse off than an error would.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<R: Read> Read for Shared<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buffer)
+    }
+}
+
+impl<W: Write> Write for Shared<W> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.lock().write(buffer)
+    }
+
+    fn write_all(&mut self, buffer: &[u8]) -> io::Result<()> {
+        self.lock().write_all(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+type Thread<R, W> = InterpreterState<Shared<R>, Shared<W>>;
+
+/// Runs one thread to its end, starting threads for each `Y` it executes,
+/// then waits for them. Returns the first error any of them stopped with.
+fn run_thread<'s, R, W>(scope: &'s Scope<'s, '_>, mut thread: Thread<R, W>)
+-> io::Result<()>
+where R: Read + Send + 's, W: Write + Send + 's {
+    let mut children = Vec::new();
+    let result = loop {
+        let forks = thread.forks();
+
+        if let Err(e) = thread.repl() {
+            break match thread.span() {
+                Some(_) if !is_end_of_input(&e) => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        if !forks {
+            continue;
+        }
+
+        let (reader, writer) = (thread.reader_mut().clone(),
+                                thread.writer_mut().clone());
+
+        if let Some(child) = thread.take_fork(writer, reader) {
+            children.push(scope.spawn(move || run_thread(scope, child)));
+        }
+    };
+
+    children.into_iter().fold(result, |result, child| {
+        let child = child.join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+
+        result.and(child)
+    })
+}
+
+/// Runs `program`, and every thread it starts, to completion. A thread
+/// that reads past the end of input ends there, as if the program had.
+pub fn run<R, W>(program: &Program, writer: W, reader: R) -> io::Result<()>
+where R: Read + Send, W: Write + Send {
+    let writer = Shared(Arc::new(Mutex::new(writer)));
+    let reader = Shared(Arc::new(Mutex::new(reader)));
+    let main = InterpreterState::new(program, writer.clone(), reader);
+    let result = thread::scope(|scope| run_thread(scope, main));
+
+    writer.lock().flush()?;
+
+    result
+}
@@ -0,0 +1,33 @@
+use alloc::vec::Vec;
+
+use frontend::{self, Extensions, Token};
+use ir::Span;
+
+fn to_token(c: char, extensions: Extensions) -> Option<Token> {
+    match c {
+        '>' => Some(Token::Right),
+        '<' => Some(Token::Left),
+        '+' => Some(Token::Increment),
+        '-' => Some(Token::Decrement),
+        '.' => Some(Token::Output),
+        ',' => Some(Token::Input),
+        '[' => Some(Token::LoopStart),
+        ']' => Some(Token::LoopEnd),
+        '?' if extensions.rand => Some(Token::Random),
+        'Y' if extensions.fork => Some(Token::Fork),
+        '^' if extensions.grid.is_some() => Some(Token::Up),
+        'v' if extensions.grid.is_some() => Some(Token::Down),
+        '}' if extensions.tapes => Some(Token::NextTape),
+        '{' if extensions.tapes => Some(Token::PreviousTape),
+        _ => None,
+    }
+}
+
+pub fn tokenize(source: &str, extensions: Extensions)
+-> Vec<(Token, Span)> {
+    frontend::code(source)
+        .filter_map(|(i, c)| {
+            to_token(c, extensions).map(|t| (t, Span::new(i, i + 1)))
+        })
+        .collect()
+}
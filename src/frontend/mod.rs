@@ -0,0 +1,263 @@
+mod brainfuck;
+mod spoon;
+
+use alloc::vec::Vec;
+
+use diagnostic::Diagnostic;
+use ir::{Node, Op, Program, Span};
+
+/// A brainfuck command, independent of how a dialect spells it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Token {
+    Right,
+    Left,
+    Increment,
+    Decrement,
+    Output,
+    Input,
+    LoopStart,
+    LoopEnd,
+    /// `?`, with the `rand` extension.
+    Random,
+    /// `Y`, with the `fork` extension.
+    Fork,
+    /// `^`, with the `grid` extension.
+    Up,
+    /// `v`, with the `grid` extension.
+    Down,
+    /// `}`, with the `tapes` extension.
+    NextTape,
+    /// `{`, with the `tapes` extension.
+    PreviousTape,
+}
+
+impl Token {
+    pub fn as_char(self) -> char {
+        match self {
+            Token::Right => '>',
+            Token::Left => '<',
+            Token::Increment => '+',
+            Token::Decrement => '-',
+            Token::Output => '.',
+            Token::Input => ',',
+            Token::LoopStart => '[',
+            Token::LoopEnd => ']',
+            Token::Random => '?',
+            Token::Fork => 'Y',
+            Token::Up => '^',
+            Token::Down => 'v',
+            Token::NextTape => '}',
+            Token::PreviousTape => '{',
+        }
+    }
+}
+
+/// The characters of `source` and their byte offsets, skipping a leading
+/// `#!` line so that programs can be run as scripts. Offsets still count
+/// from the start of the file, so spans, and the line numbers reported
+/// from them, are unaffected.
+fn code(source: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let start = if source.starts_with("#!") {
+        source.find('\n').map_or(source.len(), |i| i + 1)
+    } else {
+        0
+    };
+
+    source.char_indices().skip_while(move |&(i, _)| i < start)
+}
+
+/// How many cells wide a row is with `--ext grid` and no width given.
+pub const DEFAULT_GRID_WIDTH: usize = 64;
+
+/// Commands beyond the standard eight, each read only if enabled, since
+/// programs written without them may use the same characters as comments.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Extensions {
+    /// `?`, which stores a pseudo-random byte in the current cell.
+    pub rand: bool,
+    /// Brainfork's `Y`, which splits the program into two threads.
+    pub fork: bool,
+    /// `^` and `v`, which move up and down a grid laid over the tape, in
+    /// rows of this many cells. Moving right off the end of a row leads to
+    /// the start of the next.
+    pub grid: Option<usize>,
+    /// `}` and `{`, which switch to the next and previous of several
+    /// tapes, each with its own pointer.
+    pub tapes: bool,
+}
+
+impl Extensions {
+    pub const NAMES: &'static [&'static str] = &["rand", "fork", "grid",
+                                                  "tapes"];
+
+    /// Enables the extension called `name`, or returns false if there is
+    /// none. The grid's width can be given as `grid=WIDTH`.
+    pub fn enable(&mut self, name: &str) -> bool {
+        match name.split_once('=') {
+            Some(("grid", width)) => match width.parse() {
+                Ok(width) if width > 0 => self.grid = Some(width),
+                _ => return false,
+            },
+            _ => match name {
+                "rand" => self.rand = true,
+                "fork" => self.fork = true,
+                "grid" => self.grid = Some(DEFAULT_GRID_WIDTH),
+                "tapes" => self.tapes = true,
+                _ => return false,
+            },
+        }
+
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    Brainfuck,
+    Spoon,
+}
+
+impl Dialect {
+    pub const NAMES: &'static [&'static str] = &["brainfuck", "spoon"];
+
+    pub fn from_name(name: &str) -> Option<Dialect> {
+        match name {
+            "brainfuck" | "bf" => Some(Dialect::Brainfuck),
+            "spoon" => Some(Dialect::Spoon),
+            _ => None,
+        }
+    }
+
+    pub fn tokenize(self, source: &str)
+    -> Result<Vec<(Token, Span)>, ParseError> {
+        self.tokenize_with(source, Extensions::default())
+    }
+
+    /// Tokenizes `source`, reading the commands of `extensions` too. Spoon
+    /// has no spelling for any of them, so ignores them.
+    pub fn tokenize_with(self, source: &str, extensions: Extensions)
+    -> Result<Vec<(Token, Span)>, ParseError> {
+        match self {
+            Dialect::Brainfuck => Ok(brainfuck::tokenize(source, extensions)),
+            Dialect::Spoon => spoon::tokenize(source),
+        }
+    }
+
+    pub fn parse(self, source: &str) -> Result<Program, ParseError> {
+        self.parse_with(source, Extensions::default())
+    }
+
+    pub fn parse_with(self, source: &str, extensions: Extensions)
+    -> Result<Program, ParseError> {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::debug_span!("parse", dialect = ?self,
+                                           bytes = source.len()).entered();
+        let tokens = self.tokenize_with(source, extensions)?;
+
+        lower_grid(&tokens, extensions.grid.unwrap_or(DEFAULT_GRID_WIDTH))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    UnmatchedLoopStart(Span),
+    UnmatchedLoopEnd(Span),
+    IncompleteToken(Span),
+    UnsupportedToken(Span),
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match *self {
+            ParseError::UnmatchedLoopStart(s) => s,
+            ParseError::UnmatchedLoopEnd(s) => s,
+            ParseError::IncompleteToken(s) => s,
+            ParseError::UnsupportedToken(s) => s,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match *self {
+            ParseError::UnmatchedLoopStart(_) => "no matching ']' for '['",
+            ParseError::UnmatchedLoopEnd(_) => "no matching '[' for ']'",
+            ParseError::IncompleteToken(_) => "incomplete token",
+            ParseError::UnsupportedToken(_) => "unsupported token",
+        }
+    }
+
+    /// The error as a `Diagnostic`, labelled with what is wrong at its
+    /// span.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let label = match *self {
+            ParseError::UnmatchedLoopStart(_) => "this loop is never closed",
+            ParseError::UnmatchedLoopEnd(_) => "this ']' closes no loop",
+            ParseError::IncompleteToken(_) => "the source ends inside this \
+                                               token",
+            ParseError::UnsupportedToken(_) => "this token has no brainfuck \
+                                                equivalent",
+        };
+
+        Diagnostic::error(self.message(), self.span()).with_label(label)
+    }
+}
+
+impl core::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{} at byte {}", self.message(), self.span().start)
+    }
+}
+
+impl core::error::Error for ParseError { }
+
+/// Builds the shared IR from a dialect's token stream.
+pub fn lower(tokens: &[(Token, Span)]) -> Result<Program, ParseError> {
+    lower_grid(tokens, DEFAULT_GRID_WIDTH)
+}
+
+/// Builds the shared IR from a dialect's token stream, with `^` and `v`
+/// moving by rows of `width` cells.
+pub fn lower_grid(tokens: &[(Token, Span)], width: usize)
+-> Result<Program, ParseError> {
+    let mut stack: Vec<(Span, Vec<Node>)> = Vec::new();
+    let mut nodes = Vec::new();
+
+    for &(token, span) in tokens {
+        let op = match token {
+            Token::Right => Op::Move(1),
+            Token::Left => Op::Move(-1),
+            Token::Increment => Op::Add(1),
+            Token::Decrement => Op::Add(-1),
+            Token::Output => Op::Output,
+            Token::Input => Op::Input,
+            Token::Random => Op::Random,
+            Token::Fork => Op::Fork,
+            Token::Up => Op::Move(-(width as isize)),
+            Token::Down => Op::Move(width as isize),
+            Token::NextTape => Op::SwitchTape(1),
+            Token::PreviousTape => Op::SwitchTape(-1),
+            Token::LoopStart => {
+                stack.push((span, core::mem::take(&mut nodes)));
+
+                continue;
+            }
+            Token::LoopEnd => {
+                let (start, outer) = match stack.pop() {
+                    Some(s) => s,
+                    None => return Err(ParseError::UnmatchedLoopEnd(span)),
+                };
+                let body = core::mem::replace(&mut nodes, outer);
+
+                nodes.push(Node::new(Op::Loop(body), start.to(span)));
+
+                continue;
+            }
+        };
+
+        nodes.push(Node::new(op, span));
+    }
+
+    match stack.pop() {
+        Some((span, _)) => Err(ParseError::UnmatchedLoopStart(span)),
+        None => Ok(Program::new(nodes)),
+    }
+}
@@ -0,0 +1,56 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use frontend::{self, ParseError, Token};
+use ir::Span;
+
+// Spoon spells each command as a prefix-free string of binary digits; any
+// character other than '0' or '1' is ignored.
+const CODES: &[(&str, Option<Token>)] = &[
+    ("1", Some(Token::Increment)),
+    ("000", Some(Token::Decrement)),
+    ("010", Some(Token::Right)),
+    ("011", Some(Token::Left)),
+    ("0011", Some(Token::LoopEnd)),
+    ("00100", Some(Token::LoopStart)),
+    ("001010", Some(Token::Output)),
+    ("0010110", Some(Token::Input)),
+    // DEBUG and EXIT have no brainfuck equivalent
+    ("00101110", None),
+    ("00101111", None),
+];
+
+pub fn tokenize(source: &str) -> Result<Vec<(Token, Span)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut code = String::new();
+    let mut start = 0;
+
+    for (i, c) in frontend::code(source) {
+        if c != '0' && c != '1' {
+            continue;
+        }
+
+        if code.is_empty() {
+            start = i;
+        }
+
+        code.push(c);
+
+        let span = Span::new(start, i + 1);
+
+        match CODES.iter().find(|&&(bits, _)| bits == code) {
+            Some(&(_, Some(token))) => tokens.push((token, span)),
+            Some(&(_, None)) => return Err(ParseError::UnsupportedToken(span)),
+            None => continue,
+        }
+
+        code.clear();
+    }
+
+    if !code.is_empty() {
+        return Err(ParseError::IncompleteToken(Span::new(start,
+                                                         source.len())));
+    }
+
+    Ok(tokens)
+}
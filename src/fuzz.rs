@@ -0,0 +1,46 @@
+//! Generators of valid programs for fuzzing, enabled by the `arbitrary`
+//! feature. Built from raw fuzzer input, they reach the optimizer and
+//! backends far more often than arbitrary bytes, most of which don't parse.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// The most commands a generated program has.
+pub const MAX_COMMANDS: usize = 4096;
+/// The deepest loops are nested in a generated program.
+pub const MAX_DEPTH: usize = 16;
+
+const COMMANDS: &[char] = &['+', '-', '>', '<', '.', ','];
+
+/// Brainfuck source with balanced brackets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source(pub String);
+
+fn block(u: &mut Unstructured, depth: usize, budget: &mut usize,
+         out: &mut String) -> Result<()> {
+    while *budget > 0 && !u.is_empty() {
+        *budget -= 1;
+
+        match u.int_in_range(0..=7u8)? {
+            6 if depth < MAX_DEPTH => {
+                out.push('[');
+                block(u, depth + 1, budget, out)?;
+                out.push(']');
+            }
+            7 if depth > 0 => return Ok(()),
+            n => out.push(COMMANDS[n as usize % COMMANDS.len()]),
+        }
+    }
+
+    Ok(())
+}
+
+impl<'a> Arbitrary<'a> for Source {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Source> {
+        let mut source = String::new();
+        let mut budget = MAX_COMMANDS;
+
+        block(u, 0, &mut budget, &mut source)?;
+
+        Ok(Source(source))
+    }
+}
@@ -0,0 +1,1180 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+
+use checkpoint::{Checkpoint, Fnv, Policy, SavedTape};
+use io::{self, BfInput, BfOutput, DynInput, DynOutput, OutputEncoding};
+use ir::{Node, Op, Overflow, Program, Span};
+use random::Rng;
+use tape::{Dense, Tape, Tapes, View};
+use utf8::Chars;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Instruction {
+    Add(i32),
+    Move(isize),
+    Output,
+    Input,
+    Random,
+    Fork,
+    SwitchTape(isize),
+    JumpIfZero(usize),
+    JumpIfNonZero(usize),
+    Clear,
+    MulAdd(isize, i32),
+    AddAt(isize, i32),
+    Print(usize),
+    Scan(isize),
+    ClearRange(isize),
+}
+
+/// Where an interpreter is, as its hooks see it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct State<'a> {
+    /// Source span of the instruction executing.
+    pub span: Span,
+    pub instruction_pointer: usize,
+    pub pointer: usize,
+    /// The current tape.
+    pub tape: View<'a>,
+    /// Instructions executed before this one.
+    pub steps: u64,
+}
+
+/// A loop the interpreter is in the body of, as `InterpreterState::loops`
+/// returns it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct OpenLoop {
+    /// Index of its `[`, counting as `instruction_pointer` does.
+    pub start: usize,
+    /// Source span of its `[`.
+    pub span: Span,
+    /// Which time through its body this is, counting from 1.
+    pub iteration: u64,
+}
+
+type Hook = Box<dyn FnMut(&State) + Send>;
+
+type ValueHook = Box<dyn FnMut(&State, u32) + Send>;
+
+#[derive(Default)]
+struct Hooks {
+    instruction: Option<Hook>,
+    output: Option<ValueHook>,
+    input: Option<ValueHook>,
+    loop_enter: Option<Hook>,
+    loop_exit: Option<Hook>,
+}
+
+/// When to take checkpoints, and what to give them to.
+struct Checkpoints {
+    policy: Policy,
+    sink: Box<dyn FnMut(&Checkpoint) + Send>,
+    /// When the last was taken, or checkpoints started.
+    #[cfg(feature = "std")]
+    last: std::time::Instant,
+}
+
+impl Checkpoints {
+    /// Whether one is due after `steps` instructions.
+    fn due(&mut self, steps: u64) -> bool {
+        match self.policy {
+            Policy::Steps(every) => {
+                steps.is_multiple_of(core::cmp::max(every, 1))
+            }
+            #[cfg(feature = "std")]
+            Policy::Interval(interval) => {
+                if !steps.is_multiple_of(4096)
+                   || self.last.elapsed() < interval {
+                    return false;
+                }
+
+                self.last = std::time::Instant::now();
+
+                true
+            }
+        }
+    }
+}
+
+/// Calls one of the hooks, if it is set, with the interpreter's state.
+macro_rules! fire {
+    ($interpreter:expr, $hook:ident $(, $value:expr)*) => {
+        if let Some(mut hook) = $interpreter.hooks.$hook.take() {
+            hook(&$interpreter.state() $(, $value)*);
+            $interpreter.hooks.$hook = Some(hook);
+        }
+    }
+}
+
+pub struct InterpreterState<R, W, T = Dense>
+where R: BfInput, W: BfOutput, T: Tape {
+    data: T,
+    pointer: usize,
+    /// The tapes other than `data`, with the `tapes` extension.
+    tapes: Tapes<T>,
+    overflow: Overflow,
+    strict_pointer: bool,
+    output_encoding: OutputEncoding,
+    /// Bytes of output left before the limit, if there is one.
+    output_left: Option<u64>,
+    rng: Rng,
+    /// The tape and pointer the last `Y` left for a new thread, until
+    /// `take_fork` takes them.
+    forked: Option<(T, usize)>,
+    writer: W,
+    input_iter: Chars<R>,
+    /// Shared with the interpreter's forks, and anything else started from
+    /// the same `Prepared`, until `append` adds to it.
+    code: Arc<Code>,
+    instruction_pointer: usize,
+    steps: u64,
+    /// The loops around the instruction that will execute next, outermost
+    /// first.
+    loops: Vec<OpenLoop>,
+    /// Bytes of input read and of output written so far.
+    input_bytes: u64,
+    output_bytes: u64,
+    checkpoints: Option<Checkpoints>,
+    hooks: Hooks,
+}
+
+/// An interpreter whose input and output are boxed, so that its type doesn't
+/// depend on theirs, for storing in a struct or returning from a function.
+/// It has all of `InterpreterState`'s methods.
+///
+/// ```
+/// use std::sync::mpsc::{self, Sender};
+///
+/// use brainfuck::io::OutputFn;
+/// use brainfuck::{Dialect, Interpreter};
+///
+/// struct Job {
+///     interpreter: Interpreter<'static>,
+/// }
+///
+/// fn job(source: &str, input: &'static [u8], output: Sender<u8>) -> Job {
+///     let program = Dialect::Brainfuck.parse(source).unwrap();
+///     let writer = OutputFn(move |bytes: &[u8]| for &byte in bytes {
+///         let _ = output.send(byte);
+///     });
+///
+///     Job { interpreter: Interpreter::boxed(&program, writer, input) }
+/// }
+///
+/// let (sender, receiver) = mpsc::channel();
+/// let mut job = job(",[.,]", b"hi", sender);
+///
+/// while job.interpreter.repl().is_ok() { }
+///
+/// assert_eq!(receiver.try_iter().collect::<Vec<_>>(), b"hi");
+/// ```
+pub type Interpreter<'a> = InterpreterState<DynInput<'a>, DynOutput<'a>>;
+
+/// A program flattened to the instructions the interpreter runs.
+#[derive(Clone, Debug, Default)]
+struct Code {
+    instructions: Vec<Instruction>,
+    spans: Vec<Span>,
+    texts: Vec<Vec<u32>>,
+    overflow: Overflow,
+}
+
+/// A program prepared for the interpreter, which can start any number of
+/// runs of it, on any thread, without preparing it again. Cloning one
+/// only counts another reference to it, and nothing can change it.
+///
+/// `InterpreterState::new` takes one of these or a `Program`, which it
+/// prepares for that run alone.
+///
+/// ```
+/// use std::thread;
+///
+/// use brainfuck::interpreter::Prepared;
+/// use brainfuck::{Dialect, InterpreterState};
+///
+/// let program = Dialect::Brainfuck.parse(",[.,]").unwrap();
+/// let prepared = Prepared::new(&program);
+/// let runs: Vec<_> = ["hi", "there"].iter().map(|&input| {
+///     let prepared = prepared.clone();
+///
+///     thread::spawn(move || {
+///         let mut output = Vec::new();
+///
+///         {
+///             let mut interpreter = InterpreterState::new(
+///                 &prepared, &mut output, input.as_bytes()
+///             );
+///
+///             while interpreter.repl().is_ok() { }
+///         }
+///
+///         output
+///     })
+/// }).collect();
+/// let outputs: Vec<_> = runs.into_iter().map(|r| r.join().unwrap())
+///     .collect();
+///
+/// assert_eq!(outputs, [&b"hi"[..], &b"there"[..]]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Prepared {
+    code: Arc<Code>,
+}
+
+impl Prepared {
+    pub fn new(program: &Program) -> Prepared {
+        let mut code = Code { overflow: program.overflow, ..Code::default() };
+
+        flatten(&program.nodes, &mut code.instructions, &mut code.spans,
+                &mut code.texts);
+
+        Prepared { code: Arc::new(code) }
+    }
+}
+
+impl<'a> From<&'a Program> for Prepared {
+    fn from(program: &'a Program) -> Prepared {
+        Prepared::new(program)
+    }
+}
+
+impl<'a> From<&'a Prepared> for Prepared {
+    fn from(prepared: &'a Prepared) -> Prepared {
+        prepared.clone()
+    }
+}
+
+fn flatten(nodes: &[Node], instructions: &mut Vec<Instruction>,
+           spans: &mut Vec<Span>, texts: &mut Vec<Vec<u32>>) {
+    for node in nodes {
+        let instruction = match node.op {
+            Op::Add(n) => Instruction::Add(n),
+            Op::Move(n) => Instruction::Move(n),
+            Op::Output => Instruction::Output,
+            Op::Input => Instruction::Input,
+            Op::Random => Instruction::Random,
+            Op::Fork => Instruction::Fork,
+            Op::SwitchTape(n) => Instruction::SwitchTape(n),
+            Op::Clear => Instruction::Clear,
+            Op::MulAdd { offset, factor } => {
+                Instruction::MulAdd(offset, factor)
+            }
+            Op::AddAt { offset, amount } => Instruction::AddAt(offset, amount),
+            Op::Scan(step) => Instruction::Scan(step),
+            Op::ClearRange(extent) => Instruction::ClearRange(extent),
+            Op::Print(ref values) => {
+                texts.push(values.clone());
+
+                Instruction::Print(texts.len() - 1)
+            }
+            Op::Loop(ref body) => {
+                let start = instructions.len();
+
+                instructions.push(Instruction::JumpIfZero(0));
+                spans.push(Span::new(node.span.start, node.span.start + 1));
+
+                flatten(body, instructions, spans, texts);
+
+                let end = instructions.len();
+
+                instructions.push(Instruction::JumpIfNonZero(start));
+                spans.push(Span::new(node.span.end - 1, node.span.end));
+                instructions[start] = Instruction::JumpIfZero(end);
+
+                continue;
+            }
+        };
+
+        instructions.push(instruction);
+        spans.push(node.span);
+    }
+}
+
+impl<R, W> InterpreterState<R, W>
+where R: BfInput, W: BfOutput {
+    /// Starts a run of `program`, a `&Program` or a `&Prepared`, writing to
+    /// `writer` and reading from `input_reader`.
+    pub fn new<P>(program: P, writer: W, input_reader: R)
+    -> InterpreterState<R, W>
+    where P: Into<Prepared> {
+        let code = program.into().code;
+
+        InterpreterState { data: Dense::new(), pointer: 0,
+                           tapes: Tapes::default(),
+                           overflow: code.overflow,
+                           strict_pointer: false,
+                           output_encoding: OutputEncoding::Utf8,
+                           output_left: None,
+                           rng: Rng::new(0),
+                           forked: None, writer,
+                           input_iter: Chars::new(input_reader),
+                           code, instruction_pointer: 0, steps: 0,
+                           loops: Vec::new(), input_bytes: 0,
+                           output_bytes: 0, checkpoints: None,
+                           hooks: Hooks::default() }
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    /// Boxes `writer` and `reader`, which is otherwise what `new` does.
+    pub fn boxed<P, W, R>(program: P, writer: W, reader: R)
+    -> Interpreter<'a>
+    where P: Into<Prepared>, W: BfOutput + Send + 'a,
+          R: BfInput + Send + 'a {
+        InterpreterState::new(program, DynOutput::new(writer),
+                              DynInput::new(reader))
+    }
+}
+
+impl<R, W, T> InterpreterState<R, W, T>
+where R: BfInput, W: BfOutput, T: Tape {
+    /// Runs the program on `tape` in place of the tape it has, which is a
+    /// `Dense` one to start with.
+    pub fn with_tape_of<U>(self, tape: U) -> InterpreterState<R, W, U>
+    where U: Tape {
+        InterpreterState {
+            data: tape,
+            pointer: self.pointer,
+            tapes: Tapes::default(),
+            overflow: self.overflow,
+            strict_pointer: self.strict_pointer,
+            output_encoding: self.output_encoding,
+            output_left: self.output_left,
+            rng: self.rng,
+            forked: None,
+            writer: self.writer,
+            input_iter: self.input_iter,
+            code: self.code,
+            instruction_pointer: self.instruction_pointer,
+            steps: self.steps,
+            loops: self.loops,
+            input_bytes: self.input_bytes,
+            output_bytes: self.output_bytes,
+            checkpoints: self.checkpoints,
+            hooks: self.hooks,
+        }
+    }
+
+    /// Stops the program with an `OutOfMemory` error, rather than growing
+    /// the tape past `cells` cells, if it writes to a cell past them.
+    pub fn with_tape_limit(mut self, cells: usize)
+    -> InterpreterState<R, W, T> {
+        self.data.set_limit(cells);
+
+        self
+    }
+
+    /// Stops the program with a `QuotaExceeded` error, rather than writing
+    /// more than `bytes` bytes of output. The `.` that would have gone past
+    /// the limit writes nothing and is left as the next to execute.
+    ///
+    /// ```
+    /// use brainfuck::{Dialect, InterpreterState};
+    ///
+    /// let program = Dialect::Brainfuck.parse("+[.]").unwrap();
+    /// let mut output = Vec::new();
+    /// let error = {
+    ///     let mut interpreter = InterpreterState::new(&program, &mut output,
+    ///                                                 &b""[..])
+    ///         .with_output_limit(3);
+    ///
+    ///     loop {
+    ///         if let Err(e) = interpreter.repl() {
+    ///             break e;
+    ///         }
+    ///     }
+    /// };
+    ///
+    /// assert_eq!(error.kind(), std::io::ErrorKind::QuotaExceeded);
+    /// assert_eq!(output, [1, 1, 1]);
+    /// ```
+    pub fn with_output_limit(mut self, bytes: u64)
+    -> InterpreterState<R, W, T> {
+        self.output_left = Some(bytes);
+
+        self
+    }
+
+    /// Starts the tape with `cells` as its first cells, rather than all
+    /// zeros. A tape limit set afterwards cuts off any cells past it.
+    ///
+    /// ```
+    /// use brainfuck::{Dialect, InterpreterState};
+    ///
+    /// let program = Dialect::Brainfuck.parse("[->+<]").unwrap();
+    /// let mut interpreter = InterpreterState::new(&program, Vec::new(),
+    ///                                             &[][..])
+    ///     .with_tape(&[3, 4]);
+    ///
+    /// while interpreter.span().is_some() {
+    ///     interpreter.repl().unwrap();
+    /// }
+    ///
+    /// assert_eq!(interpreter.tape()[..2], [0, 7]);
+    /// ```
+    pub fn with_tape(mut self, cells: &[u32]) -> InterpreterState<R, W, T> {
+        self.data.load(cells);
+
+        self
+    }
+
+    /// Sets what adding to a cell does when the result doesn't fit, in place
+    /// of the program's own policy. Under `Overflow::Trap`, the instruction
+    /// that overflowed is left as the next to execute, so `span` locates it.
+    pub fn with_overflow(mut self, overflow: Overflow)
+    -> InterpreterState<R, W, T> {
+        self.overflow = overflow;
+
+        self
+    }
+
+    /// Stops the program with an error, rather than growing the tape to
+    /// the left, if it moves left of the first cell or touches a cell
+    /// there. The instruction that did is left as the next to execute.
+    ///
+    /// ```
+    /// use brainfuck::{Dialect, InterpreterState};
+    ///
+    /// let program = Dialect::Brainfuck.parse("+<++[>]").unwrap();
+    /// let mut interpreter = InterpreterState::new(&program, Vec::new(),
+    ///                                             &[][..]);
+    ///
+    /// while interpreter.span().is_some() {
+    ///     interpreter.repl().unwrap();
+    /// }
+    ///
+    /// assert_eq!(interpreter.pointer(), 1);
+    /// assert_eq!(interpreter.tape().left().last(), Some(&2));
+    ///
+    /// let mut strict = InterpreterState::new(&program, Vec::new(), &[][..])
+    ///     .with_strict_pointer(true);
+    ///
+    /// assert!(strict.repl().is_ok());
+    /// assert!(strict.repl().is_err());
+    /// ```
+    pub fn with_strict_pointer(mut self, strict: bool)
+    -> InterpreterState<R, W, T> {
+        self.strict_pointer = strict;
+
+        self
+    }
+
+    /// Sets what `.` writes for a cell, which is otherwise its character in
+    /// UTF-8. If `encoding` can't write it, the `.` is left as the next
+    /// instruction to execute.
+    ///
+    /// ```
+    /// use brainfuck::{Dialect, InterpreterState, OutputEncoding};
+    ///
+    /// let program = Dialect::Brainfuck.parse("-.").unwrap();
+    /// let mut output = Vec::new();
+    ///
+    /// {
+    ///     let mut interpreter = InterpreterState::new(&program, &mut output,
+    ///                                                 &[][..])
+    ///         .with_output_encoding(OutputEncoding::Modulo256);
+    ///
+    ///     while interpreter.repl().is_ok() { }
+    /// }
+    ///
+    /// assert_eq!(output, [255]);
+    /// ```
+    pub fn with_output_encoding(mut self, encoding: OutputEncoding)
+    -> InterpreterState<R, W, T> {
+        self.output_encoding = encoding;
+
+        self
+    }
+
+    /// Seeds the generator `?` reads from, which otherwise starts from 0, so
+    /// the same seed gives the same run.
+    pub fn with_seed(mut self, seed: u64) -> InterpreterState<R, W, T> {
+        self.rng = Rng::new(seed);
+
+        self
+    }
+
+    /// Takes a checkpoint as `policy` says, after the instruction that makes
+    /// one due, and gives it to `sink`, which might write it to a file. See
+    /// the `checkpoint` module.
+    pub fn with_checkpoints<F>(mut self, policy: Policy, sink: F)
+    -> InterpreterState<R, W, T>
+    where F: FnMut(&Checkpoint) + Send + 'static {
+        self.checkpoints = Some(Checkpoints {
+            policy,
+            sink: Box::new(sink),
+            #[cfg(feature = "std")]
+            last: std::time::Instant::now(),
+        });
+
+        self
+    }
+
+    /// Calls `hook` before each instruction executes.
+    ///
+    /// ```
+    /// use std::sync::mpsc;
+    ///
+    /// use brainfuck::{Dialect, InterpreterState};
+    ///
+    /// let program = Dialect::Brainfuck.parse("++[-]").unwrap();
+    /// let (sender, receiver) = mpsc::channel();
+    /// let mut interpreter = InterpreterState::new(&program, Vec::new(),
+    ///                                             &[][..])
+    ///     .on_instruction(move |state| {
+    ///         sender.send(state.span.start).unwrap();
+    ///     });
+    ///
+    /// while interpreter.repl().is_ok() { }
+    ///
+    /// let starts: Vec<usize> = receiver.try_iter().collect();
+    ///
+    /// assert_eq!(starts, [0, 1, 2, 3, 4, 3, 4]);
+    /// ```
+    pub fn on_instruction<F>(mut self, hook: F) -> InterpreterState<R, W, T>
+    where F: FnMut(&State) + Send + 'static {
+        self.hooks.instruction = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Calls `hook` with each value the program writes, after writing it.
+    pub fn on_output<F>(mut self, hook: F) -> InterpreterState<R, W, T>
+    where F: FnMut(&State, u32) + Send + 'static {
+        self.hooks.output = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Calls `hook` with each value the program reads, once it is in the
+    /// current cell.
+    pub fn on_input<F>(mut self, hook: F) -> InterpreterState<R, W, T>
+    where F: FnMut(&State, u32) + Send + 'static {
+        self.hooks.input = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Calls `hook` when a `[` starts running its loop, but not when the
+    /// loop goes round again or is skipped.
+    pub fn on_loop_enter<F>(mut self, hook: F) -> InterpreterState<R, W, T>
+    where F: FnMut(&State) + Send + 'static {
+        self.hooks.loop_enter = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Calls `hook` when a `]` ends its loop.
+    pub fn on_loop_exit<F>(mut self, hook: F) -> InterpreterState<R, W, T>
+    where F: FnMut(&State) + Send + 'static {
+        self.hooks.loop_exit = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Adds `program` after the instructions already loaded, so a program
+    /// can run as it arrives, a piece at a time. Once the program has run
+    /// off the end, `repl` picks up with the new instructions. Each piece
+    /// must be whole, with every loop it opens closed, and its spans should
+    /// carry on from the last piece's if they are to locate anything.
+    ///
+    /// ```
+    /// use brainfuck::{Dialect, InterpreterState};
+    ///
+    /// let first = Dialect::Brainfuck.parse("++").unwrap();
+    /// let second = Dialect::Brainfuck.parse("[>+<-]").unwrap();
+    /// let mut interpreter = InterpreterState::new(&first, Vec::new(),
+    ///                                             &[][..]);
+    ///
+    /// while interpreter.span().is_some() {
+    ///     interpreter.repl().unwrap();
+    /// }
+    ///
+    /// interpreter.append(&second);
+    ///
+    /// while interpreter.span().is_some() {
+    ///     interpreter.repl().unwrap();
+    /// }
+    ///
+    /// assert_eq!(interpreter.tape()[..2], [0, 2]);
+    /// ```
+    pub fn append(&mut self, program: &Program) {
+        let code = Arc::make_mut(&mut self.code);
+
+        flatten(&program.nodes, &mut code.instructions, &mut code.spans,
+                &mut code.texts);
+    }
+
+    /// Source span of the instruction that will execute next, if any.
+    pub fn span(&self) -> Option<Span> {
+        self.code.spans.get(self.instruction_pointer).cloned()
+    }
+
+    /// Whether the instruction that will execute next reads input.
+    pub fn reads_input(&self) -> bool {
+        self.code.instructions.get(self.instruction_pointer)
+            == Some(&Instruction::Input)
+    }
+
+    /// Whether the instruction that will execute next is a `Y`, after which
+    /// `take_fork` has a thread to return.
+    pub fn forks(&self) -> bool {
+        self.code.instructions.get(self.instruction_pointer)
+            == Some(&Instruction::Fork)
+    }
+
+    /// Where the program reads input from. Bytes taken from it here won't
+    /// be seen by the program.
+    pub fn reader_mut(&mut self) -> &mut R {
+        self.input_iter.get_mut()
+    }
+
+    /// Where the program writes output to.
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// The current tape. A `Dense` one dereferences to its cells, as far
+    /// as the furthest written to so far.
+    pub fn tape(&self) -> &T {
+        &self.data
+    }
+
+    /// Which tape is current, counting from 0, with the `tapes` extension.
+    pub fn tape_index(&self) -> usize {
+        self.tapes.current()
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    /// Which instruction will execute next, counting from 0 through the
+    /// program's ops in order, where a loop is its `[`, its body, then its
+    /// `]`. It is past the last once the program has ended.
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
+
+    /// The `[` and `]` of the innermost loop around the instruction at
+    /// `index`, counting as `instruction_pointer` does, if it is in one. An
+    /// instruction is in a loop if it comes after its `[`, up to and
+    /// including its `]`.
+    ///
+    /// ```
+    /// use brainfuck::{Dialect, InterpreterState};
+    ///
+    /// let program = Dialect::Brainfuck.parse("+[>[-]<-]").unwrap();
+    /// let interpreter = InterpreterState::new(&program, Vec::new(),
+    ///                                         &[][..]);
+    ///
+    /// assert_eq!(interpreter.enclosing_loop(1), None);
+    /// assert_eq!(interpreter.enclosing_loop(2), Some((1, 8)));
+    /// assert_eq!(interpreter.enclosing_loop(4), Some((3, 5)));
+    /// assert_eq!(interpreter.enclosing_loop(6), Some((1, 8)));
+    /// ```
+    pub fn enclosing_loop(&self, index: usize) -> Option<(usize, usize)> {
+        (0..index).rev().find_map(|i| match self.code.instructions.get(i) {
+            Some(&Instruction::JumpIfZero(end)) if end >= index => {
+                Some((i, end))
+            }
+            _ => None,
+        })
+    }
+
+    /// The loops whose bodies the program is running, outermost first,
+    /// like a call stack with how many times round each has been.
+    ///
+    /// ```
+    /// use brainfuck::{Dialect, InterpreterState};
+    ///
+    /// let program = Dialect::Brainfuck.parse("++[>+[-]<-]").unwrap();
+    /// let mut interpreter = InterpreterState::new(&program, Vec::new(),
+    ///                                             &[][..]);
+    ///
+    /// // Up to the `-` in the inner loop, the second time round the outer.
+    /// while interpreter.loops().len() < 2
+    ///       || interpreter.loops()[0].iteration < 2 {
+    ///     interpreter.repl().unwrap();
+    /// }
+    ///
+    /// let starts: Vec<_> = interpreter.loops().iter()
+    ///     .map(|l| (l.start, l.iteration))
+    ///     .collect();
+    ///
+    /// assert_eq!(starts, [(2, 2), (5, 1)]);
+    /// ```
+    pub fn loops(&self) -> &[OpenLoop] {
+        &self.loops
+    }
+
+    /// How many instructions have executed, not counting any that failed.
+    pub fn steps_executed(&self) -> u64 {
+        self.steps
+    }
+
+    /// The cells up to `radius` either side of the pointer, in brackets,
+    /// after the index of the first.
+    ///
+    /// ```
+    /// use brainfuck::{Dialect, InterpreterState};
+    ///
+    /// let program = Dialect::Brainfuck.parse("+>++>+++<").unwrap();
+    /// let mut interpreter = InterpreterState::new(&program, Vec::new(),
+    ///                                             &[][..]);
+    ///
+    /// while interpreter.repl().is_ok() { }
+    ///
+    /// assert_eq!(interpreter.tape_window(1), "0: 1 [2] 3");
+    /// assert_eq!(interpreter.tape_window(0), "1: [2]");
+    /// assert_eq!(interpreter.tape_window(3), "0: 1 [2] 3 0 0");
+    /// ```
+    pub fn tape_window(&self, radius: usize) -> String {
+        let first = self.pointer.saturating_sub(radius);
+        let last = self.pointer.saturating_add(radius);
+        let cells: Vec<String> = (first..=last).map(|i| if i == self.pointer {
+            format!("[{}]", self.cell(i))
+        } else {
+            format!("{}", self.cell(i))
+        }).collect();
+
+        format!("{}: {}", first, cells.join(" "))
+    }
+
+    /// Fails in strict pointer mode if `index` is left of the first cell,
+    /// where it wraps around to the top of the address space.
+    fn check_index(&self, index: usize) -> io::Result<()> {
+        if self.strict_pointer && (index as isize) < 0 {
+            return Err(io::pointer_out_of_bounds());
+        }
+
+        Ok(())
+    }
+
+    fn move_pointer(&mut self, offset: isize) -> io::Result<()> {
+        let pointer = self.data.offset(self.pointer, offset)?;
+
+        self.check_index(pointer)?;
+        self.pointer = pointer;
+
+        Ok(())
+    }
+
+    fn cell(&self, index: usize) -> u32 {
+        self.data.get(index)
+    }
+
+    fn cell_mut(&mut self, index: usize) -> io::Result<&mut u32> {
+        self.data.get_mut(index)
+    }
+
+    fn dereference(&self) -> u32 {
+        self.cell(self.pointer)
+    }
+
+    fn dereference_mut(&mut self) -> io::Result<&mut u32> {
+        let pointer = self.pointer;
+
+        self.cell_mut(pointer)
+    }
+
+    /// Adds `amount` to the cell at `index`, as the overflow policy says.
+    fn add(&mut self, index: usize, amount: i64) -> io::Result<()> {
+        self.check_index(index)?;
+
+        let overflow = self.overflow;
+        let cell = self.cell_mut(index)?;
+
+        *cell = overflow.add(*cell, amount)
+            .ok_or_else(|| io::cell_overflow(amount < 0))?;
+
+        Ok(())
+    }
+
+    fn multiply_add(&mut self, offset: isize, factor: i32) -> io::Result<()> {
+        let value = self.dereference();
+
+        if value == 0 {
+            return Ok(());
+        }
+
+        let index = self.data.offset(self.pointer, offset)?;
+
+        self.add(index, value as i64 * factor as i64)
+    }
+
+    fn write(&mut self) -> io::Result<()> {
+        let value = self.dereference();
+
+        self.write_value(value)
+    }
+
+    fn state(&self) -> State<'_> {
+        State {
+            span: self.code.spans[self.instruction_pointer],
+            instruction_pointer: self.instruction_pointer,
+            pointer: self.pointer,
+            tape: View::new(&self.data),
+            steps: self.steps,
+        }
+    }
+
+    fn write_value(&mut self, value: u32) -> io::Result<()> {
+        let written = io::write_value(&mut self.writer, value,
+                                      self.output_encoding,
+                                      &mut self.output_left)?;
+
+        self.output_bytes += written as u64;
+        fire!(self, output, value);
+
+        Ok(())
+    }
+
+    fn read(&mut self) -> io::Result<()> {
+        let value = match self.input_iter.next() {
+            Some(r) => r?,
+            None => return Err(io::end_of_input()),
+        };
+
+        self.input_bytes += value.len_utf8() as u64;
+
+        let value = value as u32;
+
+        *self.dereference_mut()? = value;
+        fire!(self, input, value);
+
+        Ok(())
+    }
+
+    fn fork(&mut self) -> io::Result<()> {
+        let pointer = self.data.offset(self.pointer, 1)?;
+        let mut tape = self.data.try_clone()?;
+
+        self.check_index(pointer)?;
+        tape.set(pointer, 1)?;
+        *self.dereference_mut()? = 0;
+        self.forked = Some((tape, pointer));
+
+        Ok(())
+    }
+
+    /// The thread started by the `Y` that just executed, if any, as a new
+    /// interpreter reading from `reader` and writing to `writer`. It starts
+    /// after the `Y`, with the same settings as this one, and its own
+    /// seed for `?` drawn from this one's.
+    ///
+    /// A thread left untaken is dropped when the next `Y` executes, so
+    /// that without a caller running threads, as `fork::run` does, `Y`
+    /// only zeroes the current cell.
+    pub fn take_fork<R2, W2>(&mut self, writer: W2, reader: R2)
+    -> Option<InterpreterState<R2, W2, T>>
+    where R2: BfInput, W2: BfOutput {
+        let (data, pointer) = self.forked.take()?;
+
+        Some(InterpreterState {
+            data,
+            pointer,
+            tapes: self.tapes.clone(),
+            overflow: self.overflow,
+            strict_pointer: self.strict_pointer,
+            output_encoding: self.output_encoding,
+            output_left: self.output_left,
+            rng: Rng::new(self.rng.next_u64()),
+            forked: None,
+            writer,
+            input_iter: Chars::new(reader),
+            code: self.code.clone(),
+            instruction_pointer: self.instruction_pointer,
+            steps: 0,
+            loops: self.loops.clone(),
+            input_bytes: 0,
+            output_bytes: 0,
+            checkpoints: None,
+            hooks: Hooks::default(),
+        })
+    }
+
+    fn jump_if_zero(&mut self, target: usize) {
+        if self.dereference() == 0 {
+            self.instruction_pointer = target;
+        } else {
+            self.loops.push(OpenLoop {
+                start: self.instruction_pointer,
+                span: self.code.spans[self.instruction_pointer],
+                iteration: 1,
+            });
+            fire!(self, loop_enter);
+        }
+    }
+
+    fn jump_if_nonzero(&mut self, target: usize) {
+        if self.dereference() != 0 {
+            self.instruction_pointer = target;
+
+            if let Some(open) = self.loops.last_mut() {
+                open.iteration += 1;
+            }
+
+            #[cfg(feature = "tracing")]
+            ::tracing::trace!(start = self.code.spans[target].start,
+                              "loop iteration");
+        } else {
+            self.loops.pop();
+            fire!(self, loop_exit);
+        }
+    }
+
+    pub fn repl(&mut self) -> io::Result<()> {
+        let instruction = match self.code.instructions
+            .get(self.instruction_pointer) {
+            Some(&i) => i,
+            None => {
+                return Err(io::Error::other("no instructions in buffer"));
+            }
+        };
+
+        fire!(self, instruction);
+
+        match instruction {
+            Instruction::Add(n) => {
+                let pointer = self.pointer;
+
+                self.add(pointer, n as i64)?;
+            }
+            Instruction::Move(n) => self.move_pointer(n)?,
+            Instruction::Output => self.write()?,
+            Instruction::Input => self.read()?,
+            Instruction::Random => {
+                let value = self.rng.byte();
+
+                *self.dereference_mut()? = value as u32;
+            }
+            Instruction::Fork => self.fork()?,
+            Instruction::SwitchTape(n) => {
+                self.tapes.switch(n, &mut self.data, &mut self.pointer)?
+            }
+            Instruction::JumpIfZero(target) => self.jump_if_zero(target),
+            Instruction::JumpIfNonZero(target) => self.jump_if_nonzero(target),
+            Instruction::Clear => *self.dereference_mut()? = 0,
+            Instruction::MulAdd(offset, factor) => {
+                self.multiply_add(offset, factor)?
+            }
+            Instruction::Print(text) => {
+                for i in 0..self.code.texts[text].len() {
+                    let value = self.code.texts[text][i];
+
+                    self.write_value(value)?;
+                }
+            }
+            Instruction::Scan(step) => {
+                let pointer = self.data.scan(self.pointer, step)?;
+
+                self.check_index(pointer)?;
+                self.pointer = pointer;
+            }
+            Instruction::ClearRange(extent) => {
+                let end = self.data.offset(self.pointer, extent)?;
+
+                self.check_index(end)?;
+                self.data.clear_range(self.pointer, extent)?;
+                self.move_pointer(extent)?;
+            }
+            Instruction::AddAt(offset, amount) => {
+                let index = self.data.offset(self.pointer, offset)?;
+
+                self.add(index, amount as i64)?;
+            }
+        }
+
+        self.instruction_pointer += 1;
+        self.steps += 1;
+
+        if self.checkpoints.is_some() {
+            self.take_checkpoint();
+        }
+
+        Ok(())
+    }
+
+    /// Gives the sink a checkpoint, if one is due.
+    fn take_checkpoint(&mut self) {
+        let steps = self.steps;
+
+        if !self.checkpoints.as_mut().is_some_and(|c| c.due(steps)) {
+            return;
+        }
+
+        let checkpoint = self.checkpoint();
+
+        if let Some(ref mut checkpoints) = self.checkpoints {
+            (checkpoints.sink)(&checkpoint);
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = Fnv::default();
+
+        self.code.instructions.hash(&mut hasher);
+        self.code.texts.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    /// Everything about the run so far, for `restore` to carry on from.
+    pub fn checkpoint(&self) -> Checkpoint {
+        let current = self.tapes.current();
+        let mut tapes: Vec<_> = self.tapes.parked().iter()
+            .map(|t| t.as_ref().map(|&(ref tape, pointer)| {
+                SavedTape::new(tape, pointer)
+            }))
+            .collect();
+
+        if tapes.len() <= current {
+            tapes.resize(current + 1, None);
+        }
+
+        tapes[current] = Some(SavedTape::new(&self.data, self.pointer));
+
+        Checkpoint {
+            program: self.fingerprint(),
+            instruction_pointer: self.instruction_pointer,
+            steps: self.steps,
+            rng: self.rng.state(),
+            input_bytes: self.input_bytes,
+            output_bytes: self.output_bytes,
+            loops: self.loops.iter().map(|l| (l.start, l.iteration))
+                .collect(),
+            current_tape: current,
+            tapes,
+        }
+    }
+
+    /// Carries on from `checkpoint`, as if the instructions it saw had just
+    /// run here. The input and output they read and wrote are not read or
+    /// written again: the reader should start `checkpoint.input_bytes` in.
+    ///
+    /// Fails, changing nothing, if `checkpoint` was taken of a different
+    /// program, or of this one optimized differently.
+    pub fn restore(&mut self, checkpoint: &Checkpoint) -> io::Result<()> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData,
+                                               message);
+
+        if checkpoint.program != self.fingerprint() {
+            return Err(invalid("the checkpoint is of a different program"));
+        }
+
+        let loops_valid = checkpoint.loops.iter().all(|&(start, _)| {
+            match self.code.instructions.get(start) {
+                Some(&Instruction::JumpIfZero(end)) => {
+                    start < checkpoint.instruction_pointer
+                    && checkpoint.instruction_pointer <= end
+                }
+                _ => false,
+            }
+        });
+
+        if checkpoint.instruction_pointer > self.code.instructions.len()
+           || !loops_valid {
+            return Err(invalid("the checkpoint is corrupt"));
+        }
+
+        let mut tapes = Vec::with_capacity(checkpoint.tapes.len());
+
+        for saved in &checkpoint.tapes {
+            tapes.push(match *saved {
+                Some(ref saved) => {
+                    Some((saved.to_tape(&self.data)?, saved.pointer))
+                }
+                None => None,
+            });
+        }
+
+        let (data, pointer) = tapes.get_mut(checkpoint.current_tape)
+            .and_then(Option::take)
+            .ok_or_else(|| invalid("the checkpoint is corrupt"))?;
+
+        self.data = data;
+        self.pointer = pointer;
+        self.tapes = Tapes::from_parked(tapes, checkpoint.current_tape);
+        self.instruction_pointer = checkpoint.instruction_pointer;
+        self.steps = checkpoint.steps;
+        self.rng = Rng::new(checkpoint.rng);
+        self.input_bytes = checkpoint.input_bytes;
+        self.output_bytes = checkpoint.output_bytes;
+        self.loops = checkpoint.loops.iter()
+            .map(|&(start, iteration)| OpenLoop {
+                start,
+                span: self.code.spans[start],
+                iteration,
+            })
+            .collect();
+
+        Ok(())
+    }
+}
+
+impl<R, T> InterpreterState<R, Vec<u8>, T>
+where R: BfInput, T: Tape {
+    /// Runs the program lazily, only as far as it takes to produce each
+    /// byte of output, rather than writing the output anywhere. Output
+    /// already in the writer comes first.
+    ///
+    /// ```
+    /// use brainfuck::{Dialect, InterpreterState};
+    ///
+    /// let program = Dialect::Brainfuck.parse("+[.+]").unwrap();
+    /// let mut interpreter = InterpreterState::new(&program, Vec::new(),
+    ///                                             &b""[..]);
+    /// let first: Vec<u8> = interpreter.outputs()
+    ///     .take(3)
+    ///     .collect::<Result<_, _>>()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(first, [1, 2, 3]);
+    /// ```
+    pub fn outputs(&mut self) -> Outputs<'_, R, T> {
+        Outputs { interpreter: self, next: 0, failed: false }
+    }
+}
+
+/// An iterator over the bytes a program writes, returned by
+/// `InterpreterState::outputs`. It ends when the program does, or after
+/// returning the error that stopped it.
+pub struct Outputs<'a, R, T = Dense>
+where R: BfInput + 'a, T: Tape + 'a {
+    interpreter: &'a mut InterpreterState<R, Vec<u8>, T>,
+    /// The next byte of the writer to return.
+    next: usize,
+    failed: bool,
+}
+
+impl<'a, R, T> Iterator for Outputs<'a, R, T>
+where R: BfInput, T: Tape {
+    type Item = io::Result<u8>;
+
+    fn next(&mut self) -> Option<io::Result<u8>> {
+        loop {
+            if let Some(&byte) = self.interpreter.writer.get(self.next) {
+                self.next += 1;
+
+                return Some(Ok(byte));
+            }
+
+            self.interpreter.writer.clear();
+            self.next = 0;
+
+            if self.failed || self.interpreter.span().is_none() {
+                return None;
+            }
+
+            if let Err(e) = self.interpreter.repl() {
+                self.failed = true;
+
+                return Some(Err(e));
+            }
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! Program input and output, for the interpreter and VM.
+//!
+//! With the `std` feature, errors are `std::io::Error` and anything that
+//! implements `Read` or `Write` can be a program's input or output.
+//! Without it, byte slices are inputs and `Vec<u8>` is an output.
+//!
+//! Closures can be either through `InputFn` and `OutputFn`, which saves
+//! embedders a fake pipe when input comes from a channel or output goes to
+//! a widget:
+//!
+//! ```
+//! use std::sync::mpsc;
+//!
+//! use brainfuck::io::{InputFn, OutputFn};
+//! use brainfuck::{Dialect, InterpreterState};
+//!
+//! let (sender, receiver) = mpsc::channel();
+//! let program = Dialect::Brainfuck.parse(",+.").unwrap();
+//! let mut output = Vec::new();
+//!
+//! sender.send(b'a').unwrap();
+//!
+//! {
+//!     let mut interpreter = InterpreterState::new(
+//!         &program,
+//!         OutputFn(|bytes: &[u8]| output.extend_from_slice(bytes)),
+//!         InputFn(|| receiver.recv().ok()),
+//!     );
+//!
+//!     while interpreter.repl().is_ok() { }
+//! }
+//!
+//! assert_eq!(output, b"b");
+//! ```
+
+use alloc::boxed::Box;
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+pub use self::error::{Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+mod error {
+    use core::fmt;
+
+    /// The subset of `std::io::ErrorKind` the interpreter reports.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum ErrorKind {
+        InvalidData,
+        OutOfMemory,
+        QuotaExceeded,
+        UnexpectedEof,
+        Other,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: &'static str,
+    }
+
+    impl Error {
+        pub fn new(kind: ErrorKind, message: &'static str) -> Error {
+            Error { kind, message }
+        }
+
+        pub fn other(message: &'static str) -> Error {
+            Error::new(ErrorKind::Other, message)
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.message)
+        }
+    }
+
+    impl core::error::Error for Error { }
+
+    pub type Result<T> = core::result::Result<T, Error>;
+}
+
+/// Where a program's `,` reads from.
+pub trait BfInput {
+    /// The next byte of input, or `None` once there is no more.
+    fn read_byte(&mut self) -> Result<Option<u8>>;
+}
+
+/// Where a program's `.` writes to.
+pub trait BfOutput {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()>;
+}
+
+/// Input from a closure returning each byte, or `None` at the end.
+pub struct InputFn<F>(pub F);
+
+impl<F: FnMut() -> Option<u8>> BfInput for InputFn<F> {
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        Ok((self.0)())
+    }
+}
+
+/// Output to a closure taking each write.
+pub struct OutputFn<F>(pub F);
+
+impl<F: FnMut(&[u8])> BfOutput for OutputFn<F> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        (self.0)(bytes);
+
+        Ok(())
+    }
+}
+
+/// Input of any type, boxed so the type doesn't show in the interpreter's,
+/// as `Interpreter` has it.
+pub struct DynInput<'a>(pub Box<dyn BfInput + Send + 'a>);
+
+impl<'a> DynInput<'a> {
+    pub fn new<R: BfInput + Send + 'a>(reader: R) -> DynInput<'a> {
+        DynInput(Box::new(reader))
+    }
+}
+
+impl<'a> BfInput for DynInput<'a> {
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        self.0.read_byte()
+    }
+}
+
+/// Output of any type, boxed as `DynInput` is.
+pub struct DynOutput<'a>(pub Box<dyn BfOutput + Send + 'a>);
+
+impl<'a> DynOutput<'a> {
+    pub fn new<W: BfOutput + Send + 'a>(writer: W) -> DynOutput<'a> {
+        DynOutput(Box::new(writer))
+    }
+}
+
+impl<'a> BfOutput for DynOutput<'a> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.0.write_bytes(bytes)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> BfInput for R {
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        let mut byte = [0];
+
+        loop {
+            return match self.read(&mut byte) {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some(byte[0])),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> BfOutput for W {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.write_all(bytes)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl BfInput for &[u8] {
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        match self.split_first() {
+            Some((&first, rest)) => {
+                *self = rest;
+
+                Ok(Some(first))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl BfInput for ::alloc::collections::VecDeque<u8> {
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        Ok(self.pop_front())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl BfOutput for ::alloc::vec::Vec<u8> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.extend_from_slice(bytes);
+
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<I: BfInput + ?Sized> BfInput for &mut I {
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        (**self).read_byte()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<O: BfOutput + ?Sized> BfOutput for &mut O {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        (**self).write_bytes(bytes)
+    }
+}
+
+/// The error for a write to a cell past the tape's limit, which would have
+/// needed `attempted` cells.
+#[cfg(feature = "std")]
+pub(crate) fn tape_limit_exceeded(attempted: usize, limit: usize) -> Error {
+    Error::new(ErrorKind::OutOfMemory,
+               format!("tape limit exceeded: tried to grow the tape to {} \
+                        cells, but the limit is {}", attempted, limit))
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn tape_limit_exceeded(_attempted: usize, _limit: usize) -> Error {
+    Error::new(ErrorKind::OutOfMemory, "tape limit exceeded")
+}
+
+/// The error for a `,` with no input left to read.
+pub(crate) fn end_of_input() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "no input in buffer")
+}
+
+/// Whether `error` is a `,` finding no input left, which ends a run as the
+/// end of the program does, rather than an error reading it.
+pub fn is_end_of_input(error: &Error) -> bool {
+    error.kind() == ErrorKind::UnexpectedEof
+}
+
+/// The error for adding to a cell under `Overflow::Trap` when the result
+/// doesn't fit.
+pub(crate) fn cell_overflow(negative: bool) -> Error {
+    let message = if negative {
+        "cell underflow: decremented below zero"
+    } else {
+        "cell overflow: incremented past the largest value"
+    };
+
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// The error for moving left of the first cell in strict pointer mode.
+pub(crate) fn pointer_out_of_bounds() -> Error {
+    Error::new(ErrorKind::InvalidData,
+               "pointer out of bounds: moved left of the first cell")
+}
+
+/// The error for moving past the last cell of a tape with a set length.
+pub(crate) fn pointer_past_end() -> Error {
+    Error::new(ErrorKind::InvalidData,
+               "pointer out of bounds: moved past the last cell")
+}
+
+/// The error for writing more output than a program's output limit allows.
+pub(crate) fn output_limit_exceeded() -> Error {
+    Error::new(ErrorKind::QuotaExceeded,
+               "output limit exceeded: no more bytes may be written")
+}
+
+/// What `.` writes for a cell, which matters once cells can hold more than
+/// a byte.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OutputEncoding {
+    /// The character with the cell's value as its code point, in UTF-8, or
+    /// U+FFFD if there is no such character.
+    #[default]
+    Utf8,
+    /// The cell's value modulo 256, as one byte.
+    Modulo256,
+    /// The cell's value as one byte, stopping the program with an error if
+    /// it is above 255.
+    Error,
+}
+
+impl OutputEncoding {
+    pub const NAMES: &'static [&'static str] = &["utf8", "modulo-256",
+                                                 "error"];
+
+    pub fn from_name(name: &str) -> Option<OutputEncoding> {
+        match name {
+            "utf8" => Some(OutputEncoding::Utf8),
+            "modulo-256" => Some(OutputEncoding::Modulo256),
+            "error" => Some(OutputEncoding::Error),
+            _ => None,
+        }
+    }
+}
+
+/// The error for writing a cell above 255 under `OutputEncoding::Error`.
+#[cfg(feature = "std")]
+fn not_a_byte(value: u32) -> Error {
+    Error::new(ErrorKind::InvalidData,
+               format!("cannot output {}: it doesn't fit in a byte", value))
+}
+
+#[cfg(not(feature = "std"))]
+fn not_a_byte(_value: u32) -> Error {
+    Error::new(ErrorKind::InvalidData, "cannot output a value above 255")
+}
+
+/// Writes `value` as `encoding` says, which is what `.` does, taking the
+/// bytes from those `left` to write, if it is limited. Errors writing are
+/// reported on stderr if there is one and otherwise ignored, so they don't
+/// stop the program, but a value `encoding` can't write is an error, as is
+/// one with too many bytes for the limit, which is then not written at all.
+/// Returns how many bytes `value` came to.
+pub(crate) fn write_value<O: BfOutput>(output: &mut O, value: u32,
+                                       encoding: OutputEncoding,
+                                       left: &mut Option<u64>)
+-> Result<usize> {
+    let mut buffer = [0; 4];
+    let bytes = match encoding {
+        OutputEncoding::Utf8 => {
+            let c = core::char::from_u32(value)
+                .unwrap_or(core::char::REPLACEMENT_CHARACTER);
+
+            c.encode_utf8(&mut buffer).as_bytes()
+        }
+        OutputEncoding::Modulo256 => {
+            buffer[0] = value as u8;
+
+            &buffer[..1]
+        }
+        OutputEncoding::Error => {
+            if value > 255 {
+                return Err(not_a_byte(value));
+            }
+
+            buffer[0] = value as u8;
+
+            &buffer[..1]
+        }
+    };
+
+    if let Some(ref mut left) = *left {
+        *left = left.checked_sub(bytes.len() as u64)
+            .ok_or_else(output_limit_exceeded)?;
+    }
+
+    if let Err(_e) = output.write_bytes(bytes) {
+        #[cfg(feature = "std")]
+        eprintln!("error while writing: {}", _e);
+    }
+
+    Ok(bytes.len())
+}
@@ -0,0 +1,192 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Byte range `[start, end)` in the source an IR node was lowered from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span::new(core::cmp::min(self.start, other.start),
+                  core::cmp::max(self.end, other.end))
+    }
+}
+
+/// What adding to a cell does when the result doesn't fit in it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Overflow {
+    /// Wraps around, so that decrementing zero gives the largest value.
+    #[default]
+    Wrap,
+    /// Stops the program with an error.
+    Trap,
+    /// Sticks at zero or the largest value.
+    Saturate,
+}
+
+impl Overflow {
+    pub const NAMES: &'static [&'static str] = &["wrap", "trap", "saturate"];
+
+    pub fn from_name(name: &str) -> Option<Overflow> {
+        match name {
+            "wrap" => Some(Overflow::Wrap),
+            "trap" => Some(Overflow::Trap),
+            "saturate" => Some(Overflow::Saturate),
+            _ => None,
+        }
+    }
+
+    /// The result of adding `amount` to `cell`, or `None` if it traps.
+    pub fn add(self, cell: u32, amount: i64) -> Option<u32> {
+        let sum = cell as i64 + amount;
+
+        match self {
+            Overflow::Wrap => Some(sum as u32),
+            Overflow::Trap if sum < 0 || sum > u32::MAX as i64 => None,
+            Overflow::Trap => Some(sum as u32),
+            Overflow::Saturate => Some(sum.clamp(0, u32::MAX as i64) as u32),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Op {
+    Add(i32),
+    Move(isize),
+    Output,
+    Input,
+    /// Stores a pseudo-random byte in the current cell
+    Random,
+    /// Starts a thread running the rest of the program on a copy of the
+    /// tape, with its pointer one cell right, where it stores 1. Zeroes
+    /// the current cell for the thread that forked.
+    Fork,
+    /// Switches to the tape this many after the current one, with the
+    /// pointer where it was last left on that tape
+    SwitchTape(isize),
+    Loop(Vec<Node>),
+    Clear,
+    /// `cell[p + offset] += factor * cell[p]`
+    MulAdd { offset: isize, factor: i32 },
+    /// `cell[p + offset] += amount`, without moving the pointer
+    AddAt { offset: isize, amount: i32 },
+    /// Output computed ahead of time, written as if by one `.` per value
+    Print(Vec<u32>),
+    /// Moves by the step until the current cell is zero, like `[>]`
+    Scan(isize),
+    /// Zeroes the cells from `p` to `p + extent` inclusive, then moves to
+    /// `p + extent`, like `[-]>[-]>[-]`
+    ClearRange(isize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Node {
+    pub op: Op,
+    pub span: Span,
+}
+
+impl Node {
+    pub fn new(op: Op, span: Span) -> Node {
+        Node { op, span }
+    }
+}
+
+/// The dialect-independent form every frontend lowers to.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Program {
+    pub nodes: Vec<Node>,
+    /// What the program's arithmetic does on overflow, which passes
+    /// preserve and backends default to.
+    pub overflow: Overflow,
+}
+
+impl Program {
+    pub fn new(nodes: Vec<Node>) -> Program {
+        Program { nodes, overflow: Overflow::Wrap }
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Program {
+        self.overflow = overflow;
+
+        self
+    }
+}
+
+/// 1-based line and column of a byte offset into `source`.
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..core::cmp::min(offset, source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rfind('\n').map_or(before.chars().count(), |i| {
+        before[i + 1..].chars().count()
+    }) + 1;
+
+    (line, column)
+}
+
+/// One op as `dump` shows it, without its body if it is a loop.
+pub fn describe(op: &Op) -> String {
+    match *op {
+        Op::Add(n) => format!("add {:+}", n),
+        Op::Move(n) => format!("move {:+}", n),
+        Op::Output => "output".to_string(),
+        Op::Input => "input".to_string(),
+        Op::Random => "random".to_string(),
+        Op::Fork => "fork".to_string(),
+        Op::SwitchTape(n) => format!("switch-tape {:+}", n),
+        Op::Loop(_) => "loop".to_string(),
+        Op::Clear => "clear".to_string(),
+        Op::MulAdd { offset, factor } => {
+            format!("mul-add offset={:+} factor={}", offset, factor)
+        }
+        Op::AddAt { offset, amount } => {
+            format!("add-at offset={:+} amount={:+}", offset, amount)
+        }
+        Op::Print(ref values) => format!("print {}", render(values)),
+        Op::Scan(step) => format!("scan step={:+}", step),
+        Op::ClearRange(extent) => format!("clear-range extent={:+}", extent),
+    }
+}
+
+/// Quotes output values as a string literal if they are all valid
+/// characters, or lists them otherwise.
+pub fn render(values: &[u32]) -> String {
+    match values.iter().map(|&v| core::char::from_u32(v))
+        .collect::<Option<String>>() {
+        Some(s) => format!("{:?}", s),
+        None => format!("{:?}", values),
+    }
+}
+
+fn dump_nodes(nodes: &[Node], source: &str, depth: usize, out: &mut String) {
+    for node in nodes {
+        let text = format!("{:indent$}{}", "", describe(&node.op),
+                           indent = depth * 4);
+        let (line, column) = line_col(source, node.span.start);
+        let (end_line, end_column) = line_col(source, node.span.end);
+
+        out.push_str(&format!("{:<40} ; {}:{}-{}:{}\n", text, line, column,
+                              end_line, end_column));
+
+        if let Op::Loop(ref body) = node.op {
+            dump_nodes(body, source, depth + 1, out);
+            out.push_str(&format!("{:indent$}end\n", "", indent = depth * 4));
+        }
+    }
+}
+
+/// Renders `program` one op per line, each annotated with the source range
+/// it was lowered from.
+pub fn dump(program: &Program, source: &str) -> String {
+    let mut out = String::new();
+
+    dump_nodes(&program.nodes, source, 0, &mut out);
+
+    out
+}
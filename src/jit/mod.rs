@@ -0,0 +1,129 @@
+//! Native code for hot loops.
+//!
+//! The VM hands a loop's bytecode to `compile`, which returns a function
+//! taking a pointer to the current cell and a `Context`. Compiled code never
+//! grows the tape: before each straight-line run of instructions it checks
+//! that every cell the run touches is inside `[begin, end)`, and if not it
+//! returns `RESUME` with `resume` set to the run's first instruction so the
+//! VM can execute it instead.
+//!
+//! Targets without a JIT still build this module so the VM needn't care,
+//! but never call into it.
+
+#![cfg_attr(not(all(unix, target_arch = "x86_64")), allow(dead_code))]
+
+#[cfg(all(unix, target_arch = "x86_64"))]
+mod x86_64;
+
+use core::ffi::c_void;
+
+use vm::Instruction;
+
+/// The loop ran until its cell was zero.
+pub const FINISHED: u64 = 0;
+/// The VM should continue at `Context::resume`.
+pub const RESUME: u64 = 1;
+/// A callback failed; the VM's I/O state holds the error.
+pub const FAILED: u64 = 2;
+
+/// Shared with native code, which relies on the field offsets.
+#[repr(C)]
+pub struct Context {
+    pub begin: *mut u32,
+    pub end: *mut u32,
+    /// Set on return to the current cell.
+    pub pointer: *mut u32,
+    /// Set on return to the instruction to resume at, for `RESUME`.
+    pub resume: u64,
+    /// Passed as the first argument to every callback.
+    pub io: *mut c_void,
+}
+
+/// Addresses of the functions native code calls for I/O.
+pub struct Callbacks {
+    /// `extern "C" fn(io, value: u32) -> i64`, negative if writing failed
+    pub output: usize,
+    /// `extern "C" fn(io, text: u64) -> i64`, writing one of the VM's
+    /// texts, negative if writing failed
+    pub print: usize,
+    /// `extern "C" fn(io) -> i64`, negative if reading failed
+    pub input: usize,
+    /// `extern "C" fn(io) -> u32`, the value for `?`
+    pub random: usize,
+}
+
+type Entry = extern "C" fn(*mut u32, *mut Context) -> u64;
+
+/// A compiled loop, living in its own executable mapping.
+pub struct Native {
+    memory: *mut u8,
+    length: usize,
+}
+
+impl Native {
+    /// Runs the loop starting at `cell`.
+    ///
+    /// # Safety
+    ///
+    /// `cell` must lie in `[context.begin, context.end)`, which must be a
+    /// live allocation, and `context.io` must be what the callbacks this
+    /// loop was compiled with expect.
+    pub unsafe fn call(&self, cell: *mut u32, context: &mut Context) -> u64 {
+        let entry: Entry = core::mem::transmute(self.memory);
+
+        entry(cell, context)
+    }
+}
+
+/// Compiles the loop whose first instruction is `code[start]`, or returns
+/// `None` if this target has no JIT or the loop can't be encoded.
+#[cfg(all(unix, target_arch = "x86_64"))]
+pub fn compile(code: &[Instruction], start: usize, callbacks: &Callbacks)
+-> Option<Native> {
+    let bytes = x86_64::assemble(code, start, callbacks)?;
+
+    Native::map(&bytes)
+}
+
+#[cfg(not(all(unix, target_arch = "x86_64")))]
+pub fn compile(_: &[Instruction], _: usize, _: &Callbacks) -> Option<Native> {
+    None
+}
+
+#[cfg(all(unix, target_arch = "x86_64"))]
+impl Native {
+    fn map(bytes: &[u8]) -> Option<Native> {
+        unsafe {
+            let memory = libc::mmap(core::ptr::null_mut(), bytes.len(),
+                                    libc::PROT_READ | libc::PROT_WRITE,
+                                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                                    -1, 0);
+
+            if memory == libc::MAP_FAILED {
+                return None;
+            }
+
+            let native = Native { memory: memory as *mut u8,
+                                  length: bytes.len() };
+
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), native.memory,
+                                          bytes.len());
+
+            if libc::mprotect(memory, bytes.len(),
+                              libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return None;
+            }
+
+            Some(native)
+        }
+    }
+}
+
+impl Drop for Native {
+    fn drop(&mut self) {
+        #[cfg(all(unix, target_arch = "x86_64"))]
+        unsafe {
+            libc::munmap(self.memory as *mut c_void, self.length);
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Machine code generation for x86-64 with the System V calling convention.
+//!
+//! Compiled code keeps the current cell in `rbx`, the `Context` in `r12`
+//! and the tape bounds in `r13` and `r14`. All four are callee-saved, so
+//! they survive calls into the I/O callbacks.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use jit::{Callbacks, FAILED, RESUME};
+use vm::Instruction;
+
+const PROLOGUE: &[u8] = &[
+    0x53,                         // push rbx
+    0x41, 0x54,                   // push r12
+    0x41, 0x55,                   // push r13
+    0x41, 0x56,                   // push r14
+    0x41, 0x57,                   // push r15, keeping rsp 16-byte aligned
+    0x48, 0x89, 0xFB,             // mov rbx, rdi
+    0x49, 0x89, 0xF4,             // mov r12, rsi
+    0x4D, 0x8B, 0x2C, 0x24,       // mov r13, [r12]
+    0x4D, 0x8B, 0x74, 0x24, 0x08, // mov r14, [r12 + 8]
+];
+
+const EPILOGUE: &[u8] = &[
+    0x49, 0x89, 0x5C, 0x24, 0x10, // mov [r12 + 16], rbx
+    0x41, 0x5F,                   // pop r15
+    0x41, 0x5E,                   // pop r14
+    0x41, 0x5D,                   // pop r13
+    0x41, 0x5C,                   // pop r12
+    0x5B,                         // pop rbx
+    0xC3,                         // ret
+];
+
+// mov rdi, [r12 + 32], loading `Context::io` as the first argument
+const LOAD_IO: &[u8] = &[0x49, 0x8B, 0x7C, 0x24, 0x20];
+// cmp dword [rbx], 0
+const TEST_CELL: &[u8] = &[0x83, 0x3B, 0x00];
+
+enum Exit {
+    Resume(usize),
+    Failed,
+}
+
+#[derive(Default)]
+struct Assembler {
+    bytes: Vec<u8>,
+    exits: Vec<(usize, Exit)>,
+}
+
+/// Byte displacement of the cell `offset` cells away.
+fn displacement(offset: isize) -> Option<i32> {
+    offset.checked_mul(4).and_then(|d| i32::try_from(d).ok())
+}
+
+impl Assembler {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn imm32(&mut self, value: i32) {
+        self.emit(&value.to_le_bytes());
+    }
+
+    fn imm64(&mut self, value: u64) {
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// Emits `opcode` followed by a rel32 to be patched later, returning
+    /// where the rel32 is.
+    fn jump(&mut self, opcode: &[u8]) -> usize {
+        self.emit(opcode);
+        self.imm32(0);
+
+        self.bytes.len() - 4
+    }
+
+    fn jump_to_exit(&mut self, opcode: &[u8], exit: Exit) {
+        let at = self.jump(opcode);
+
+        self.exits.push((at, exit));
+    }
+
+    fn patch(&mut self, at: usize, target: usize) {
+        let relative = target as i32 - (at + 4) as i32;
+
+        self.bytes[at..at + 4].copy_from_slice(&relative.to_le_bytes());
+    }
+
+    fn call(&mut self, function: usize) {
+        self.emit(&[0x48, 0xB8]); // mov rax, imm64
+        self.imm64(function as u64);
+        self.emit(&[0xFF, 0xD0]); // call rax
+    }
+
+    /// Leaves for the VM at `ip` unless the cells from `low` to `high`
+    /// (relative to `rbx`) are all on the tape.
+    fn check_bounds(&mut self, low: isize, high: isize, ip: usize)
+    -> Option<()> {
+        self.emit(&[0x48, 0x8D, 0x83]); // lea rax, [rbx + disp32]
+        self.imm32(displacement(low)?);
+        self.emit(&[0x4C, 0x39, 0xE8]); // cmp rax, r13
+        self.jump_to_exit(&[0x0F, 0x82], Exit::Resume(ip)); // jb
+        self.emit(&[0x48, 0x8D, 0x83]); // lea rax, [rbx + disp32]
+        self.imm32(displacement(high)?);
+        self.emit(&[0x4C, 0x39, 0xF0]); // cmp rax, r14
+        self.jump_to_exit(&[0x0F, 0x83], Exit::Resume(ip)); // jae
+
+        Some(())
+    }
+
+    fn instruction(&mut self, instruction: Instruction, callbacks: &Callbacks)
+    -> Option<()> {
+        match instruction {
+            Instruction::Add(n) => {
+                self.emit(&[0x81, 0x03]); // add dword [rbx], imm32
+                self.imm32(n);
+            }
+            Instruction::Move(n) => {
+                self.emit(&[0x48, 0x81, 0xC3]); // add rbx, imm32
+                self.imm32(displacement(n)?);
+            }
+            Instruction::AddAt(offset, amount) => {
+                self.emit(&[0x81, 0x83]); // add dword [rbx + disp32], imm32
+                self.imm32(displacement(offset)?);
+                self.imm32(amount);
+            }
+            Instruction::Clear => {
+                self.emit(&[0xC7, 0x03]); // mov dword [rbx], imm32
+                self.imm32(0);
+            }
+            Instruction::MulAdd(offset, factor) => {
+                self.emit(&[0x8B, 0x03]); // mov eax, [rbx]
+                self.emit(&[0x69, 0xC0]); // imul eax, eax, imm32
+                self.imm32(factor);
+                self.emit(&[0x01, 0x83]); // add [rbx + disp32], eax
+                self.imm32(displacement(offset)?);
+            }
+            Instruction::Output => {
+                self.emit(LOAD_IO);
+                self.emit(&[0x8B, 0x33]); // mov esi, [rbx]
+                self.call(callbacks.output);
+                self.emit(&[0x48, 0x85, 0xC0]); // test rax, rax
+                self.jump_to_exit(&[0x0F, 0x88], Exit::Failed); // js
+            }
+            Instruction::Print(text) => {
+                self.emit(LOAD_IO);
+                self.emit(&[0x48, 0xBE]); // mov rsi, imm64
+                self.imm64(text as u64);
+                self.call(callbacks.print);
+                self.emit(&[0x48, 0x85, 0xC0]); // test rax, rax
+                self.jump_to_exit(&[0x0F, 0x88], Exit::Failed); // js
+            }
+            Instruction::Input => {
+                self.emit(LOAD_IO);
+                self.call(callbacks.input);
+                self.emit(&[0x48, 0x85, 0xC0]); // test rax, rax
+                self.jump_to_exit(&[0x0F, 0x88], Exit::Failed); // js
+                self.emit(&[0x89, 0x03]); // mov [rbx], eax
+            }
+            Instruction::Random => {
+                self.emit(LOAD_IO);
+                self.call(callbacks.random);
+                self.emit(&[0x89, 0x03]); // mov [rbx], eax
+            }
+            Instruction::ClearRange(extent) => {
+                self.emit(&[0x48, 0x8D, 0xBB]); // lea rdi, [rbx + disp32]
+                self.imm32(displacement(core::cmp::min(extent, 0))?);
+                self.emit(&[0xB9]); // mov ecx, imm32
+                self.imm32(i32::try_from(extent.checked_abs()? + 1).ok()?);
+                self.emit(&[0x31, 0xC0]); // xor eax, eax
+                self.emit(&[0xF3, 0xAB]); // rep stosd
+                self.emit(&[0x48, 0x81, 0xC3]); // add rbx, imm32
+                self.imm32(displacement(extent)?);
+            }
+            // Both swap out the tape the loop runs on, so it stays with the
+            // VM.
+            Instruction::Fork | Instruction::SwitchTape(_) => return None,
+            _ => unreachable!(),
+        }
+
+        Some(())
+    }
+
+    /// Emits a scan, which only ever needs the cell it is on. The cell it
+    /// starts on was bounds checked by whatever came before it.
+    fn scan(&mut self, step: isize, ip: usize) -> Option<()> {
+        let test = self.jump(&[0xE9]); // jmp
+        let body = self.bytes.len();
+
+        self.emit(&[0x48, 0x81, 0xC3]); // add rbx, imm32
+        self.imm32(displacement(step)?);
+        self.emit(&[0x4C, 0x39, 0xEB]); // cmp rbx, r13
+        self.jump_to_exit(&[0x0F, 0x82], Exit::Resume(ip)); // jb
+        self.emit(&[0x4C, 0x39, 0xF3]); // cmp rbx, r14
+        self.jump_to_exit(&[0x0F, 0x83], Exit::Resume(ip)); // jae
+
+        let here = self.bytes.len();
+
+        self.patch(test, here);
+        self.emit(TEST_CELL);
+
+        let back = self.jump(&[0x0F, 0x85]); // jne
+
+        self.patch(back, body);
+
+        Some(())
+    }
+
+    /// Emits the straight-line run starting at `code[ip]`, returning the
+    /// index of the first instruction after it.
+    fn block(&mut self, code: &[Instruction], ip: usize,
+             callbacks: &Callbacks) -> Option<usize> {
+        let mut end = ip;
+        let mut parts = Vec::new();
+
+        while let Some(&instruction) = code.get(end) {
+            if instruction.is_jump() {
+                break;
+            }
+
+            if let Instruction::Scan(_) = instruction {
+                break;
+            }
+
+            let (first, second) = instruction.split();
+
+            parts.push(first);
+            parts.extend(second);
+            end += 1;
+        }
+
+        let mut position: isize = 0;
+        let (mut low, mut high) = (0, 0);
+
+        for &part in &parts {
+            let touched = match part {
+                Instruction::Move(n) => {
+                    position = position.checked_add(n)?;
+
+                    position
+                }
+                Instruction::AddAt(offset, _) |
+                Instruction::MulAdd(offset, _) => {
+                    position.checked_add(offset)?
+                }
+                Instruction::ClearRange(extent) => {
+                    low = core::cmp::min(low, position);
+                    high = core::cmp::max(high, position);
+                    position = position.checked_add(extent)?;
+
+                    position
+                }
+                _ => position,
+            };
+
+            low = core::cmp::min(low, touched);
+            high = core::cmp::max(high, touched);
+        }
+
+        self.check_bounds(low, high, ip)?;
+
+        for part in parts {
+            self.instruction(part, callbacks)?;
+        }
+
+        Some(end)
+    }
+}
+
+/// Assembles the loop starting at `code[start]` into a function matching
+/// `jit::Entry`.
+pub fn assemble(code: &[Instruction], start: usize, callbacks: &Callbacks)
+-> Option<Vec<u8>> {
+    let end = match code[start] {
+        Instruction::Loop { end, .. } | Instruction::Native { end, .. } => end,
+        _ => return None,
+    };
+
+    let mut assembler = Assembler::default();
+    let mut loops = Vec::new();
+    let mut ip = start;
+
+    assembler.emit(PROLOGUE);
+
+    while ip <= end {
+        match code[ip] {
+            Instruction::Loop { .. } | Instruction::Native { .. } => {
+                assembler.emit(TEST_CELL);
+
+                let exit = assembler.jump(&[0x0F, 0x84]); // je
+
+                loops.push((assembler.bytes.len(), exit));
+                ip += 1;
+            }
+            Instruction::EndLoop { .. } => {
+                let (body, exit) = loops.pop()?;
+
+                assembler.emit(TEST_CELL);
+
+                let back = assembler.jump(&[0x0F, 0x85]); // jne
+                let after = assembler.bytes.len();
+
+                assembler.patch(back, body);
+                assembler.patch(exit, after);
+                ip += 1;
+            }
+            Instruction::Scan(step) => {
+                assembler.scan(step, ip)?;
+                ip += 1;
+            }
+            _ => ip = assembler.block(&code[..end], ip, callbacks)?,
+        }
+    }
+
+    assembler.emit(&[0x31, 0xC0]); // xor eax, eax
+
+    let epilogue = assembler.bytes.len();
+
+    assembler.emit(EPILOGUE);
+
+    for (at, exit) in core::mem::take(&mut assembler.exits) {
+        let stub = assembler.bytes.len();
+
+        assembler.patch(at, stub);
+
+        match exit {
+            Exit::Resume(ip) => {
+                // mov qword [r12 + 24], imm32
+                assembler.emit(&[0x49, 0xC7, 0x44, 0x24, 0x18]);
+                assembler.imm32(i32::try_from(ip).ok()?);
+                assembler.emit(&[0xB8]); // mov eax, imm32
+                assembler.imm32(RESUME as i32);
+            }
+            Exit::Failed => {
+                assembler.emit(&[0xB8]); // mov eax, imm32
+                assembler.imm32(FAILED as i32);
+            }
+        }
+
+        let jump = assembler.jump(&[0xE9]); // jmp
+
+        assembler.patch(jump, epilogue);
+    }
+
+    Some(assembler.bytes)
+}
@@ -0,0 +1,92 @@
+//! The parser, IR, interpreter and VM only need `alloc`, so they build
+//! without the default `std` feature. Everything else needs `std`.
+//!
+//! With the `tracing` feature, parsing, each optimization pass and each VM
+//! run are `tracing` spans, and loop iterations, JIT compilation and
+//! memoized loops are events at `trace` and `debug` level, for embedders
+//! that already collect them. It works without `std` too.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+// Without `std`, `core` is declared implicitly.
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(feature = "async")]
+extern crate futures_core;
+#[cfg(feature = "wasm")]
+extern crate js_sys;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(feature = "python")]
+extern crate pyo3;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod asm;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(feature = "std")]
+pub mod bfc;
+#[cfg(feature = "std")]
+pub mod build;
+pub mod checkpoint;
+#[cfg(feature = "std")]
+pub mod decompile;
+pub mod diagnostic;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod eval;
+#[cfg(feature = "std")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod fork;
+pub mod frontend;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+pub mod interpreter;
+pub mod io;
+pub mod ir;
+mod jit;
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod lower;
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod mini;
+#[cfg(feature = "std")]
+pub mod obfuscate;
+#[cfg(feature = "std")]
+pub mod optimize;
+pub mod poll;
+pub mod profile;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod random;
+pub mod snapshot;
+pub mod sourcemap;
+#[cfg(feature = "std")]
+pub mod synth;
+pub mod tape;
+pub mod utf8;
+#[cfg(feature = "std")]
+pub mod verify;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use diagnostic::Diagnostic;
+pub use frontend::{Dialect, Extensions, ParseError};
+pub use interpreter::{Interpreter, InterpreterState};
+pub use io::{BfInput, BfOutput, OutputEncoding};
+pub use ir::{Node, Op, Overflow, Program, Span};
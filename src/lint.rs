@@ -0,0 +1,158 @@
+//! Warnings about code that is probably a mistake, found without running
+//! it.
+//!
+//! Each lint has a name, so that it can be allowed or turned into an error.
+//! A loop at the very start of a program never runs either, but that is
+//! how brainfuck programs write comments, so it isn't reported.
+//!
+//! ```
+//! use brainfuck::Dialect;
+//! use brainfuck::lint;
+//!
+//! let program = Dialect::Brainfuck.parse("+[>+<][-]").unwrap();
+//! let lints = lint::check(&program);
+//! let names: Vec<&str> = lints.iter().map(|l| l.name).collect();
+//!
+//! assert_eq!(names, ["infinite-loop", "unreachable"]);
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use analysis;
+use diagnostic::Diagnostic;
+use ir::{Node, Op, Program, Span};
+
+/// Each lint's name and what it reports.
+pub const LINTS: &[(&str, &str)] = &[
+    ("infinite-loop", "a loop whose body never changes the loop cell, so \
+                       it never ends once entered"),
+    ("unbalanced-loop", "a loop that leaves the pointer somewhere else \
+                         each iteration, other than a scan like [>]"),
+    ("unreachable", "a loop straight after another, where the cell is \
+                     always zero, so it never runs"),
+];
+
+/// A lint that found something, with a warning about it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Lint {
+    pub name: &'static str,
+    pub diagnostic: Diagnostic,
+}
+
+impl Lint {
+    fn new(name: &'static str, message: String, span: Span, label: &str)
+    -> Lint {
+        Lint { name, diagnostic: Diagnostic::warning(message, span)
+                                     .with_label(label) }
+    }
+}
+
+/// Whether running `nodes`, starting `start` cells right of the cell in
+/// question, could change it, or `None` if where they move the pointer
+/// can't be told.
+fn changes(nodes: &[Node], start: isize) -> Option<bool> {
+    let mut position = start;
+
+    for node in nodes {
+        let changed = match node.op {
+            Op::Add(n) => position == 0 && n != 0,
+            Op::Move(n) => {
+                position = position.checked_add(n)?;
+                false
+            }
+            Op::Output | Op::Print(_) => false,
+            Op::Input | Op::Random | Op::Fork | Op::Clear => position == 0,
+            Op::AddAt { offset, .. } | Op::MulAdd { offset, .. } => {
+                position.checked_add(offset)? == 0
+            }
+            Op::ClearRange(extent) => {
+                let end = position.checked_add(extent)?;
+                let changed = core::cmp::min(position, end) <= 0
+                              && 0 <= core::cmp::max(position, end);
+
+                position = end;
+                changed
+            }
+            Op::Loop(ref body) => {
+                if position == 0 {
+                    return Some(true);
+                }
+
+                if analysis::net_movement(body)? != 0 {
+                    return None;
+                }
+
+                changes(body, position)?
+            }
+            Op::Scan(_) | Op::SwitchTape(_) => return None,
+        };
+
+        if changed {
+            return Some(true);
+        }
+    }
+
+    Some(false)
+}
+
+fn opening(span: Span) -> Span {
+    Span::new(span.start, span.start + 1)
+}
+
+fn check_loop(body: &[Node], span: Span, lints: &mut Vec<Lint>) {
+    let movement = analysis::net_movement(body);
+
+    if movement == Some(0) && changes(body, 0) == Some(false) {
+        lints.push(Lint::new("infinite-loop",
+                             "loop body never changes the loop cell".into(),
+                             opening(span),
+                             "this loop never ends once it starts"));
+    }
+
+    let scan = body.iter().all(|n| matches!(n.op, Op::Move(_)));
+
+    if movement != Some(0) && !scan {
+        let message = match movement {
+            Some(n) => format!("loop moves the pointer {:+} each iteration",
+                               n),
+            None => "loop moves the pointer by a varying amount".into(),
+        };
+
+        lints.push(Lint::new("unbalanced-loop", message, opening(span),
+                             "unbalanced pointer movement"));
+    }
+}
+
+fn walk(nodes: &[Node], top: bool, lints: &mut Vec<Lint>) {
+    for (i, node) in nodes.iter().enumerate() {
+        let body = match node.op {
+            Op::Loop(ref body) if !(top && i == 0) => body,
+            _ => continue,
+        };
+        let after_loop = i > 0 && matches!(nodes[i - 1].op,
+                                           Op::Loop(_) | Op::Clear);
+
+        // What is in a loop that never runs is most likely a comment.
+        if after_loop {
+            lints.push(Lint::new("unreachable",
+                                 "loop can never run".into(), node.span,
+                                 "the cell is always zero here"));
+            continue;
+        }
+
+        check_loop(body, node.span, lints);
+        walk(body, false, lints);
+    }
+}
+
+/// Runs every lint on `program`, returning what they found in the order it
+/// appears in the source.
+pub fn check(program: &Program) -> Vec<Lint> {
+    let mut lints = Vec::new();
+
+    walk(&program.nodes, true, &mut lints);
+    lints.sort_by_key(|l| l.diagnostic.span.start);
+
+    lints
+}
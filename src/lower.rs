@@ -0,0 +1,251 @@
+//! Lowers an optimized program back to plain brainfuck, which any
+//! interpreter can run.
+//!
+//! Each op is written the shortest simple way: a `Clear` as `[-]`, each
+//! `MulAdd` run and the `Clear` after it as the multiply loop it came from,
+//! and a `Print` as adds and `.`s on a cell known to be zero, which is
+//! cleared again afterwards. Fused adds don't each move out and back: the
+//! pointer only goes where the program expects it before an op that reads
+//! it there.
+//!
+//! ```
+//! use brainfuck::lower;
+//! use brainfuck::optimize::Registry;
+//! use brainfuck::Dialect;
+//!
+//! let program = Dialect::Brainfuck.parse("++ > +++ [-<++>] >+<<+- .")
+//!     .unwrap();
+//! let optimized = Registry::default().preset(2).run(program);
+//!
+//! assert_eq!(lower::to_brainfuck(&optimized).unwrap(),
+//!            "++>+++[-<++>]>+<<.");
+//! ```
+
+use std::fmt;
+
+use ir::{Node, Op, Program, Span};
+
+// Columns the output is wrapped to.
+const WIDTH: usize = 72;
+
+/// An op that plain brainfuck can't express where it is, such as a `MulAdd`
+/// without the `Clear` that would make it a loop.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unlowerable {
+    pub span: Span,
+    pub message: &'static str,
+}
+
+impl fmt::Display for Unlowerable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can't lower to brainfuck: {}", self.message)
+    }
+}
+
+impl std::error::Error for Unlowerable { }
+
+struct Lowerer {
+    code: String,
+    /// How far right of where the program expects it the pointer is, since
+    /// moves are only written once something needs the pointer in place.
+    drift: isize,
+    /// Whether the cell the program expects the pointer at is zero.
+    zero: bool,
+}
+
+impl Lowerer {
+    fn repeat(&mut self, c: char, times: u64) {
+        self.code.extend(std::iter::repeat_n(c, times as usize));
+    }
+
+    fn add(&mut self, amount: i64) {
+        if amount < 0 {
+            self.repeat('-', amount.unsigned_abs());
+        } else {
+            self.repeat('+', amount as u64);
+        }
+    }
+
+    /// Moves the pointer to `offset` from where the program expects it.
+    fn go(&mut self, offset: isize) {
+        let by = offset - self.drift;
+
+        if by < 0 {
+            self.repeat('<', by.unsigned_abs() as u64);
+        } else {
+            self.repeat('>', by as u64);
+        }
+
+        self.drift = offset;
+    }
+
+    /// A run of `MulAdd`s, and the `Clear` it must end with, as a loop.
+    fn multiply(&mut self, nodes: &[Node]) -> Result<usize, Unlowerable> {
+        let targets: Vec<_> = nodes.iter()
+            .map_while(|n| match n.op {
+                Op::MulAdd { offset, factor } => Some((offset, factor)),
+                _ => None,
+            })
+            .collect();
+
+        match nodes.get(targets.len()) {
+            Some(&Node { op: Op::Clear, .. }) => (),
+            _ => return Err(Unlowerable {
+                span: nodes[0].span,
+                message: "a multiply isn't followed by a clear of its cell",
+            }),
+        }
+
+        self.go(0);
+        self.code.push_str("[-");
+
+        for (offset, factor) in targets.iter().cloned() {
+            self.go(offset);
+            self.add(factor as i64);
+        }
+
+        self.go(0);
+        self.code.push(']');
+
+        Ok(targets.len() + 1)
+    }
+
+    fn print(&mut self, values: &[u32], span: Span)
+    -> Result<(), Unlowerable> {
+        if !self.zero {
+            return Err(Unlowerable {
+                span,
+                message: "output computed ahead of time needs a cell known \
+                          to be zero",
+            });
+        }
+
+        let mut cell = 0;
+
+        self.go(0);
+
+        for &value in values {
+            self.add(value as i64 - cell as i64);
+            self.code.push('.');
+            cell = value;
+        }
+
+        if cell != 0 {
+            self.code.push_str("[-]");
+        }
+
+        Ok(())
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) -> Result<(), Unlowerable> {
+        let mut i = 0;
+
+        while i < nodes.len() {
+            let node = &nodes[i];
+
+            i += 1;
+
+            match node.op {
+                Op::Add(amount) => {
+                    self.go(0);
+                    self.add(amount as i64);
+                }
+                Op::AddAt { offset, amount } => {
+                    self.go(offset);
+                    self.add(amount as i64);
+
+                    continue;
+                }
+                Op::Move(n) => {
+                    self.drift -= n;
+                    // The cell now under the pointer could be anything.
+                    self.zero = n == 0 && self.zero;
+
+                    continue;
+                }
+                Op::MulAdd { .. } => {
+                    i += self.multiply(&nodes[i - 1..])? - 1;
+                    self.zero = true;
+
+                    continue;
+                }
+                Op::Print(ref values) => {
+                    self.print(values, node.span)?;
+
+                    continue;
+                }
+                Op::Output => {
+                    self.go(0);
+                    self.code.push('.');
+
+                    continue;
+                }
+                _ => self.go(0),
+            }
+
+            self.zero = false;
+
+            match node.op {
+                Op::Input => self.code.push(','),
+                Op::Random => self.code.push('?'),
+                Op::Fork => self.code.push('Y'),
+                Op::SwitchTape(n) => {
+                    let c = if n < 0 { '{' } else { '}' };
+
+                    self.repeat(c, n.unsigned_abs() as u64);
+                }
+                Op::Clear => {
+                    self.code.push_str("[-]");
+                    self.zero = true;
+                }
+                Op::Scan(step) => {
+                    self.code.push('[');
+                    self.go(step);
+                    self.drift = 0;
+                    self.code.push(']');
+                    self.zero = true;
+                }
+                Op::ClearRange(extent) => {
+                    let step = extent.signum();
+
+                    self.code.push_str("[-]");
+
+                    for _ in 0..extent.unsigned_abs() {
+                        self.go(step);
+                        self.drift = 0;
+                        self.code.push_str("[-]");
+                    }
+
+                    self.zero = true;
+                }
+                Op::Loop(ref body) => {
+                    self.code.push('[');
+                    self.nodes(body)?;
+                    self.go(0);
+                    self.code.push(']');
+                    self.zero = true;
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `program` as plain brainfuck, wrapped to 72 columns, or the first op it
+/// can't be written as.
+pub fn to_brainfuck(program: &Program) -> Result<String, Unlowerable> {
+    let mut lowerer = Lowerer { code: String::new(), drift: 0, zero: true };
+
+    // The pointer is left where the last op needed it, since nothing could
+    // tell where the program left it.
+    lowerer.nodes(&program.nodes)?;
+
+    let code = lowerer.code.as_bytes();
+    let lines: Vec<_> = code.chunks(WIDTH)
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect();
+
+    Ok(lines.join("\n"))
+}
@@ -0,0 +1,96 @@
+//! What a run of a program did, for harnesses that run many programs and
+//! aggregate the results, as `bfi --metrics-out` writes them.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use brainfuck::metrics::{RunMetrics, Status};
+//!
+//! let metrics = RunMetrics {
+//!     status: Status::Finished,
+//!     instructions: Some(120),
+//!     input_bytes: 0,
+//!     output_bytes: 13,
+//!     tape_cells: 8,
+//!     wall_time: Duration::from_millis(2),
+//! };
+//!
+//! assert!(metrics.to_json().contains("\"status\": \"finished\""));
+//! assert!(metrics.to_json().contains("\"wall_time_ms\": 2,"));
+//! ```
+
+use alloc::string::String;
+use core::time::Duration;
+
+/// How a run ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Status {
+    /// It ran off the end of the program.
+    Finished,
+    /// It read past the end of its input.
+    EndOfInput,
+    /// It wrote past the tape limit.
+    MemoryLimit,
+    /// It executed as many instructions as it was allowed.
+    StepLimit,
+    /// It ran for as long as it was allowed.
+    Timeout,
+    /// It wrote more output than it was allowed.
+    OutputLimit,
+    /// Something else stopped it, such as a trap.
+    Error,
+}
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Finished => "finished",
+            Status::EndOfInput => "end-of-input",
+            Status::MemoryLimit => "memory-limit",
+            Status::StepLimit => "step-limit",
+            Status::Timeout => "timeout",
+            Status::OutputLimit => "output-limit",
+            Status::Error => "error",
+        }
+    }
+
+    /// Whether one of the run's limits stopped it.
+    pub fn is_limit(self) -> bool {
+        matches!(self, Status::MemoryLimit | Status::StepLimit
+                       | Status::Timeout | Status::OutputLimit)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RunMetrics {
+    pub status: Status,
+    /// Instructions executed, if the backend counts them. The VM doesn't,
+    /// since counting would slow it down.
+    pub instructions: Option<u64>,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// How many cells of the tape were in use when it ended, as far as the
+    /// last that isn't zero.
+    pub tape_cells: usize,
+    /// How long the program ran for, not counting parsing or optimizing.
+    pub wall_time: Duration,
+}
+
+impl RunMetrics {
+    /// The metrics as a JSON object, with the wall time in milliseconds.
+    pub fn to_json(&self) -> String {
+        let instructions = match self.instructions {
+            Some(n) => format!("{}", n),
+            None => "null".into(),
+        };
+
+        format!("{{\n  \"status\": \"{}\",\n  \"instructions\": {},\n  \
+                 \"input_bytes\": {},\n  \"output_bytes\": {},\n  \
+                 \"tape_cells\": {},\n  \"wall_time_ms\": {},\n  \
+                 \"limit_reached\": {}\n}}\n",
+                self.status.name(), instructions, self.input_bytes,
+                self.output_bytes, self.tape_cells,
+                self.wall_time.as_secs_f64() * 1000.0,
+                self.status.is_limit())
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+
+use mini::{error, CompileError, Expr, Stmt, StmtKind};
+use sourcemap::SourceMap;
+
+// Constants larger than this are built with a multiplication loop.
+const DIRECT_ADD_LIMIT: u64 = 16;
+
+/// Emits brainfuck while tracking where the pointer is. Every cell that is
+/// not allocated is zero, so temporaries must be cleared before being freed.
+struct Generator {
+    output: String,
+    position: usize,
+    used: Vec<bool>,
+    variables: HashMap<String, usize>,
+    line: usize,
+    file: String,
+    map: SourceMap,
+}
+
+impl Generator {
+    fn alloc(&mut self) -> usize {
+        match self.used.iter().position(|&u| !u) {
+            Some(cell) => {
+                self.used[cell] = true;
+
+                cell
+            }
+            None => {
+                self.used.push(true);
+
+                self.used.len() - 1
+            }
+        }
+    }
+
+    /// Attributes what is generated from here on to `line`.
+    fn at_line(&mut self, line: usize) {
+        self.line = line;
+        self.map.push(self.output.len(), &self.file, line);
+    }
+
+    fn free(&mut self, cell: usize) {
+        self.used[cell] = false;
+    }
+
+    fn variable(&self, name: &str) -> Result<usize, CompileError> {
+        match self.variables.get(name) {
+            Some(&cell) => Ok(cell),
+            None => error(self.line, format!("undefined variable '{}'", name)),
+        }
+    }
+
+    fn move_to(&mut self, cell: usize) {
+        let (command, distance) = if cell > self.position {
+            ('>', cell - self.position)
+        } else {
+            ('<', self.position - cell)
+        };
+
+        self.output.extend(std::iter::repeat_n(command, distance));
+        self.position = cell;
+    }
+
+    fn add_direct(&mut self, cell: usize, amount: i64) {
+        let command = if amount > 0 { '+' } else { '-' };
+
+        self.move_to(cell);
+        self.output.extend(std::iter::repeat_n(command,
+                                               amount.unsigned_abs() as usize));
+    }
+
+    fn add(&mut self, cell: usize, amount: i64) {
+        let magnitude = amount.unsigned_abs();
+
+        if magnitude <= DIRECT_ADD_LIMIT {
+            return self.add_direct(cell, amount);
+        }
+
+        let factor = (magnitude as f64).sqrt() as u64;
+        let quotient = magnitude / factor;
+        let remainder = magnitude - factor * quotient;
+        let sign = amount.signum();
+        let counter = self.alloc();
+
+        self.add_direct(counter, factor as i64);
+        self.while_nonzero(counter, |g| {
+            g.add_direct(cell, sign * quotient as i64);
+            g.add_direct(counter, -1);
+
+            Ok(())
+        }).unwrap();
+        self.add_direct(cell, sign * remainder as i64);
+        self.free(counter);
+    }
+
+    fn clear(&mut self, cell: usize) {
+        self.move_to(cell);
+        self.output.push_str("[-]");
+    }
+
+    fn while_nonzero<F>(&mut self, cell: usize, body: F)
+    -> Result<(), CompileError>
+    where F: FnOnce(&mut Generator) -> Result<(), CompileError> {
+        self.move_to(cell);
+        self.output.push('[');
+        body(self)?;
+        self.move_to(cell);
+        self.output.push(']');
+
+        Ok(())
+    }
+
+    /// Adds `from` into each target (scaled by its factor), zeroing `from`.
+    fn move_add(&mut self, from: usize, targets: &[(usize, i64)]) {
+        self.while_nonzero(from, |g| {
+            for &(cell, factor) in targets {
+                g.add(cell, factor);
+            }
+
+            g.add_direct(from, -1);
+
+            Ok(())
+        }).unwrap();
+    }
+
+    fn copy_add(&mut self, from: usize, to: usize, sign: i64) {
+        let scratch = self.alloc();
+
+        self.move_add(from, &[(to, sign), (scratch, 1)]);
+        self.move_add(scratch, &[(from, 1)]);
+        self.free(scratch);
+    }
+
+    /// Zeroes `cell`, which must hold the value of `expr`, without looping
+    /// once per unit of its value. Expressions are pure, so evaluating one
+    /// again with the opposite sign cancels it exactly.
+    fn cancel(&mut self, cell: usize, expr: &Expr) -> Result<(), CompileError> {
+        self.add_expr(expr, cell, -1)
+    }
+
+    /// Adds `sign` to `target` if `value` (holding `expr`) is nonzero and
+    /// zeroes `value`.
+    fn add_truth(&mut self, value: usize, expr: &Expr, target: usize,
+                 sign: i64) -> Result<(), CompileError> {
+        self.while_nonzero(value, |g| {
+            g.add(target, sign);
+            g.cancel(value, expr)
+        })
+    }
+
+    fn add_expr(&mut self, expr: &Expr, target: usize, sign: i64)
+    -> Result<(), CompileError> {
+        match *expr {
+            Expr::Number(n) => self.add(target, sign * n as i64),
+            Expr::Var(ref name) => {
+                let cell = self.variable(name)?;
+
+                self.copy_add(cell, target, sign);
+            }
+            Expr::Add(ref lhs, ref rhs) => {
+                self.add_expr(lhs, target, sign)?;
+                self.add_expr(rhs, target, sign)?;
+            }
+            Expr::Sub(ref lhs, ref rhs) => {
+                self.add_expr(lhs, target, sign)?;
+                self.add_expr(rhs, target, -sign)?;
+            }
+            Expr::Ne(ref lhs, ref rhs) => {
+                let difference = Expr::Sub(lhs.clone(), rhs.clone());
+                let value = self.eval(&difference)?;
+
+                self.add_truth(value, &difference, target, sign)?;
+                self.free(value);
+            }
+            Expr::Eq(ref lhs, ref rhs) => {
+                let difference = Expr::Sub(lhs.clone(), rhs.clone());
+                let value = self.eval(&difference)?;
+
+                self.add(target, sign);
+                self.add_truth(value, &difference, target, -sign)?;
+                self.free(value);
+            }
+            Expr::Not(ref operand) => {
+                let value = self.eval(operand)?;
+
+                self.add(target, sign);
+                self.add_truth(value, operand, target, -sign)?;
+                self.free(value);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<usize, CompileError> {
+        let cell = self.alloc();
+
+        self.add_expr(expr, cell, 1)?;
+
+        Ok(cell)
+    }
+
+    fn assign(&mut self, cell: usize, expr: &Expr, sign: i64, replace: bool)
+    -> Result<(), CompileError> {
+        let value = self.eval(expr)?;
+
+        if replace {
+            self.clear(cell);
+        }
+
+        self.move_add(value, &[(cell, sign)]);
+        self.free(value);
+
+        Ok(())
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), CompileError> {
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        self.at_line(stmt.line);
+
+        match stmt.kind {
+            StmtKind::Let(ref name, ref expr) => {
+                if self.variables.contains_key(name) {
+                    return error(stmt.line, format!("variable '{}' is \
+                                                     already defined", name));
+                }
+
+                let value = self.eval(expr)?;
+                let cell = self.alloc();
+
+                self.variables.insert(name.clone(), cell);
+                self.clear(cell);
+                self.move_add(value, &[(cell, 1)]);
+                self.free(value);
+            }
+            StmtKind::Assign(ref name, ref expr) => {
+                let cell = self.variable(name)?;
+
+                self.assign(cell, expr, 1, true)?;
+            }
+            StmtKind::AddAssign(ref name, ref expr) => {
+                let cell = self.variable(name)?;
+
+                self.assign(cell, expr, 1, false)?;
+            }
+            StmtKind::SubAssign(ref name, ref expr) => {
+                let cell = self.variable(name)?;
+
+                self.assign(cell, expr, -1, false)?;
+            }
+            StmtKind::Read(ref name) => {
+                let cell = self.variable(name)?;
+
+                self.move_to(cell);
+                self.output.push(',');
+            }
+            StmtKind::Print(ref expr) => {
+                let value = self.eval(expr)?;
+
+                self.move_to(value);
+                self.output.push('.');
+                self.cancel(value, expr)?;
+                self.free(value);
+            }
+            StmtKind::PrintStr(ref string) => {
+                let cell = self.alloc();
+                let mut previous = 0;
+
+                for c in string.chars() {
+                    self.add(cell, c as i64 - previous);
+                    self.move_to(cell);
+                    self.output.push('.');
+                    previous = c as i64;
+                }
+
+                self.clear(cell);
+                self.free(cell);
+            }
+            StmtKind::While(ref condition, ref body) => {
+                let flag = self.eval(condition)?;
+
+                self.while_nonzero(flag, |g| {
+                    g.cancel(flag, condition)?;
+                    g.block(body)?;
+                    g.at_line(stmt.line);
+                    g.add_expr(condition, flag, 1)
+                })?;
+                self.free(flag);
+            }
+            StmtKind::If(ref condition, ref then, ref otherwise) => {
+                let flag = self.eval(condition)?;
+                let other = if otherwise.is_empty() {
+                    None
+                } else {
+                    let cell = self.alloc();
+
+                    self.add(cell, 1);
+
+                    Some(cell)
+                };
+
+                self.while_nonzero(flag, |g| {
+                    g.cancel(flag, condition)?;
+                    g.block(then)?;
+                    g.at_line(stmt.line);
+
+                    if let Some(cell) = other {
+                        g.add(cell, -1);
+                    }
+
+                    Ok(())
+                })?;
+                self.free(flag);
+
+                if let Some(cell) = other {
+                    self.while_nonzero(cell, |g| {
+                        g.add(cell, -1);
+                        g.block(otherwise)?;
+                        g.at_line(stmt.line);
+
+                        Ok(())
+                    })?;
+                    self.free(cell);
+                }
+            }
+        }
+
+        self.output.push('\n');
+
+        Ok(())
+    }
+}
+
+pub fn generate(stmts: &[Stmt], file: &str)
+-> Result<(String, SourceMap), CompileError> {
+    let mut generator = Generator { output: String::new(), position: 0,
+                                    used: Vec::new(),
+                                    variables: HashMap::new(), line: 1,
+                                    file: file.to_string(),
+                                    map: SourceMap::default() };
+
+    generator.block(stmts)?;
+
+    Ok((generator.output, generator.map))
+}
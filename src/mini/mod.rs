@@ -0,0 +1,393 @@
+//! A tiny imperative language that compiles to brainfuck.
+//!
+//! ```text
+//! // comments run to the end of the line
+//! let n = 5;
+//! let c = 'a';
+//! while n != 0 {
+//!     print c;
+//!     c += 1;
+//!     n -= 1;
+//! }
+//! read c;
+//! if c == 'y' { print "yes\n"; } else { print "no\n"; }
+//! ```
+//!
+//! Every value is an unsigned cell, so subtraction below zero wraps exactly
+//! as it would in the generated brainfuck.
+
+mod codegen;
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use sourcemap::SourceMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CompileError { }
+
+fn error<T, S: Into<String>>(line: usize, message: S)
+-> Result<T, CompileError> {
+    Err(CompileError { line, message: message.into() })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Number(u32),
+    Var(String),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StmtKind {
+    Let(String, Expr),
+    Assign(String, Expr),
+    AddAssign(String, Expr),
+    SubAssign(String, Expr),
+    Read(String),
+    Print(Expr),
+    PrintStr(String),
+    While(Expr, Vec<Stmt>),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Number(u32),
+    Str(String),
+    Punct(&'static str),
+}
+
+const PUNCTUATION: &[&str] = &[
+    "==", "!=", "+=", "-=", "=", "+", "-", "!", "(", ")", "{", "}", ";",
+];
+
+fn escape(chars: &mut Peekable<Chars>, line: usize)
+-> Result<char, CompileError> {
+    match chars.next() {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('0') => Ok('\0'),
+        Some(c @ '\\') | Some(c @ '\'') | Some(c @ '"') => Ok(c),
+        Some(c) => error(line, format!("unknown escape '\\{}'", c)),
+        None => error(line, "unexpected end of input in escape"),
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, CompileError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+
+    while let Some(&c) = chars.peek() {
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '/' {
+            chars.next();
+
+            if chars.next() != Some('/') {
+                return error(line, "expected '//' to start a comment");
+            }
+
+            while chars.peek().is_some_and(|&c| c != '\n') {
+                chars.next();
+            }
+        } else if c.is_ascii_digit() {
+            let mut digits = String::new();
+
+            while let Some(&d) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                digits.push(d);
+                chars.next();
+            }
+
+            match digits.parse() {
+                Ok(n) => tokens.push((Token::Number(n), line)),
+                Err(_) => return error(line, format!("'{}' is too large",
+                                                     digits)),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut ident = String::new();
+
+            while let Some(&d) = chars.peek()
+                .filter(|&&c| c.is_ascii_alphanumeric() || c == '_') {
+                ident.push(d);
+                chars.next();
+            }
+
+            tokens.push((Token::Ident(ident), line));
+        } else if c == '\'' {
+            chars.next();
+
+            let value = match chars.next() {
+                Some('\\') => escape(&mut chars, line)?,
+                Some(c) if c != '\'' => c,
+                _ => return error(line, "empty character literal"),
+            };
+
+            if chars.next() != Some('\'') {
+                return error(line, "unterminated character literal");
+            }
+
+            tokens.push((Token::Number(value as u32), line));
+        } else if c == '"' {
+            chars.next();
+
+            let mut string = String::new();
+
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => string.push(escape(&mut chars, line)?),
+                    Some('\n') | None => {
+                        return error(line, "unterminated string");
+                    }
+                    Some(c) => string.push(c),
+                }
+            }
+
+            tokens.push((Token::Str(string), line));
+        } else {
+            let rest: String = chars.clone().take(2).collect();
+
+            match PUNCTUATION.iter().find(|p| rest.starts_with(*p)) {
+                Some(p) => {
+                    for _ in 0..p.len() {
+                        chars.next();
+                    }
+
+                    tokens.push((Token::Punct(p), line));
+                }
+                None => {
+                    return error(line, format!("unexpected character '{}'",
+                                               c));
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.tokens.get(self.position)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |t| t.1)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|t| &t.0)
+    }
+
+    fn next(&mut self) -> Result<Token, CompileError> {
+        match self.tokens.get(self.position) {
+            Some(t) => {
+                self.position += 1;
+
+                Ok(t.0.clone())
+            }
+            None => error(self.line(), "unexpected end of input"),
+        }
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Punct(p)) if p == punct => {
+                self.position += 1;
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), CompileError> {
+        if !self.eat(punct) {
+            return error(self.line(), format!("expected '{}'", punct));
+        }
+
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        match self.next()? {
+            Token::Ident(name) => Ok(name),
+            _ => error(self.line(), "expected a variable name"),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        match self.next()? {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Ident(name) => Ok(Expr::Var(name)),
+            Token::Punct("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+
+                Ok(expr)
+            }
+            _ => error(self.line(), "expected an expression"),
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+
+        self.primary()
+    }
+
+    fn additive(&mut self) -> Result<Expr, CompileError> {
+        let mut lhs = self.unary()?;
+
+        loop {
+            if self.eat("+") {
+                lhs = Expr::Add(Box::new(lhs), Box::new(self.unary()?));
+            } else if self.eat("-") {
+                lhs = Expr::Sub(Box::new(lhs), Box::new(self.unary()?));
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        let mut lhs = self.additive()?;
+
+        loop {
+            if self.eat("==") {
+                lhs = Expr::Eq(Box::new(lhs), Box::new(self.additive()?));
+            } else if self.eat("!=") {
+                lhs = Expr::Ne(Box::new(lhs), Box::new(self.additive()?));
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+
+        let mut stmts = Vec::new();
+
+        while !self.eat("}") {
+            stmts.push(self.stmt()?);
+        }
+
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        let name = self.ident()?;
+
+        let kind = match name.as_str() {
+            "let" => {
+                let var = self.ident()?;
+                self.expect("=")?;
+
+                StmtKind::Let(var, self.expr()?)
+            }
+            "read" => StmtKind::Read(self.ident()?),
+            "print" => match self.peek() {
+                Some(&Token::Str(_)) => match self.next()? {
+                    Token::Str(s) => StmtKind::PrintStr(s),
+                    _ => unreachable!(),
+                },
+                _ => StmtKind::Print(self.expr()?),
+            },
+            "while" => {
+                let condition = self.expr()?;
+
+                return Ok(Stmt { kind: StmtKind::While(condition,
+                                                       self.block()?),
+                                 line });
+            }
+            "if" => {
+                let condition = self.expr()?;
+                let then = self.block()?;
+                let otherwise = match self.peek() {
+                    Some(Token::Ident(e)) if e == "else" => {
+                        self.position += 1;
+
+                        self.block()?
+                    }
+                    _ => Vec::new(),
+                };
+
+                return Ok(Stmt { kind: StmtKind::If(condition, then,
+                                                    otherwise),
+                                 line });
+            }
+            _ => if self.eat("=") {
+                StmtKind::Assign(name, self.expr()?)
+            } else if self.eat("+=") {
+                StmtKind::AddAssign(name, self.expr()?)
+            } else if self.eat("-=") {
+                StmtKind::SubAssign(name, self.expr()?)
+            } else {
+                return error(line, format!("expected a statement, found \
+                                            '{}'", name));
+            },
+        };
+
+        self.expect(";")?;
+
+        Ok(Stmt { kind, line })
+    }
+}
+
+pub fn parse(source: &str) -> Result<Vec<Stmt>, CompileError> {
+    let mut parser = Parser { tokens: tokenize(source)?, position: 0 };
+    let mut stmts = Vec::new();
+
+    while parser.peek().is_some() {
+        stmts.push(parser.stmt()?);
+    }
+
+    Ok(stmts)
+}
+
+/// Compiles a mini-language program to brainfuck source.
+pub fn compile(source: &str) -> Result<String, CompileError> {
+    compile_with_map(source, "").map(|(output, _)| output)
+}
+
+/// Compiles a mini-language program like `compile`, along with a map from
+/// the output back to the line of each statement, naming `file` as where
+/// they came from.
+pub fn compile_with_map(source: &str, file: &str)
+-> Result<(String, SourceMap), CompileError> {
+    codegen::generate(&parse(source)?, file)
+}
@@ -0,0 +1,143 @@
+//! The opposite of minifying: rewrites a program into brainfuck that does
+//! the same thing but is much harder to read.
+//!
+//! Runs of `+` or `-` overshoot and come back, pairs that cancel out are
+//! slipped between commands, and the whole thing is buried in random
+//! comment characters and line breaks. Arithmetic is only preserved where
+//! cells wrap, since an overshoot may pass the largest value a cell holds.
+
+use frontend::Token;
+use ir::Span;
+use synth::Rng;
+
+/// How heavily to obfuscate, with each chance out of 100.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// The chance of a pair that cancels out, such as `><`, after each
+    /// command.
+    pub no_ops: u64,
+    /// The chance of splitting a run of `+` or `-` so that it overshoots.
+    pub splits: u64,
+    /// The chance of a comment character after each command.
+    pub comments: u64,
+    /// Lines are broken at random before reaching this width.
+    pub width: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { no_ops: 20, splits: 50, comments: 30, width: 72 }
+    }
+}
+
+// Pairs of commands that leave the tape and pointer as they were. `<>` is
+// left out, since it moves left of the first cell there.
+const NO_OPS: &[&str] = &["+-", "-+", "><"];
+
+// Characters brainfuck ignores, leaving out `#` and `!`, which some
+// interpreters read as a breakpoint and the start of a script's first line.
+const COMMENTS: &[u8] = b"abcdefghijklmnopqrstuvwxyz\
+                          ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                          0123456789 ~^|/\\_=:;'\"()";
+
+struct Obfuscator<'a> {
+    options: &'a Options,
+    rng: Rng,
+    output: String,
+    column: usize,
+}
+
+impl<'a> Obfuscator<'a> {
+    fn chance(&mut self, percent: u64) -> bool {
+        self.rng.range(0, 99) < percent
+    }
+
+    fn push(&mut self, c: char) {
+        // Breaks early at random, so lines aren't all the same length.
+        if self.column >= self.options.width
+           || (self.column > self.options.width / 2 && self.chance(2)) {
+            self.output.push('\n');
+            self.column = 0;
+        }
+
+        self.output.push(c);
+        self.column += 1;
+    }
+
+    fn command(&mut self, c: char) {
+        self.push(c);
+
+        if self.chance(self.options.no_ops) {
+            let pair = NO_OPS[self.rng.range(0, NO_OPS.len() as u64 - 1)
+                              as usize];
+
+            for c in pair.chars() {
+                self.push(c);
+            }
+        }
+
+        if self.chance(self.options.comments) {
+            let index = self.rng.range(0, COMMENTS.len() as u64 - 1);
+
+            self.push(COMMENTS[index as usize] as char);
+        }
+    }
+
+    /// `count` of `c`, which is `+` or `-`, as `count + extra` of it and
+    /// `extra` of the other, interleaved.
+    fn run(&mut self, c: char, count: usize) {
+        let other = if c == '+' { '-' } else { '+' };
+        let extra = if self.chance(self.options.splits) {
+            self.rng.range(1, 4) as usize
+        } else {
+            0
+        };
+        let mut commands: Vec<char> = std::iter::repeat_n(c, count + extra)
+            .chain(std::iter::repeat_n(other, extra))
+            .collect();
+
+        // Shuffles the overshoot and the way back, keeping the start of
+        // the run as it was.
+        for i in (count..commands.len()).rev() {
+            let j = self.rng.range(count as u64, i as u64) as usize;
+
+            commands.swap(i, j);
+        }
+
+        for c in commands {
+            self.command(c);
+        }
+    }
+}
+
+/// Rewrites the commands of a program, as returned by `Dialect::tokenize`,
+/// as obfuscated brainfuck. The same seed gives the same program.
+pub fn obfuscate(tokens: &[(Token, Span)], options: &Options, seed: u64)
+-> String {
+    let mut obfuscator = Obfuscator {
+        options,
+        rng: Rng::new(seed),
+        output: String::new(),
+        column: 0,
+    };
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let token = tokens[i].0;
+
+        if token == Token::Increment || token == Token::Decrement {
+            let count = tokens[i..].iter().take_while(|&&(t, _)| t == token)
+                .count();
+
+            obfuscator.run(token.as_char(), count);
+            i += count;
+        } else {
+            obfuscator.command(token.as_char());
+            i += 1;
+        }
+    }
+
+    obfuscator.output.push('\n');
+
+    obfuscator.output
+}
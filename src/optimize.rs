@@ -0,0 +1,810 @@
+//! The optimizer is a pipeline of named passes over the IR.
+//!
+//! Passes are looked up by name in a [`Registry`], which starts out holding
+//! the built-in passes and accepts custom ones:
+//!
+//! ```
+//! use brainfuck::optimize::{Pass, Registry};
+//! use brainfuck::Program;
+//!
+//! struct Nothing;
+//!
+//! impl Pass for Nothing {
+//!     fn name(&self) -> &str { "nothing" }
+//!     fn run(&self, program: Program) -> Program { program }
+//! }
+//!
+//! let mut registry = Registry::default();
+//! registry.register("nothing", || Box::new(Nothing));
+//!
+//! let pipeline = registry.pipeline(&["rle", "nothing"]).unwrap();
+//! ```
+
+use std::collections::HashMap;
+
+use analysis;
+use eval;
+use ir::{Node, Op, Overflow, Program, Span};
+
+pub const DEFAULT_LEVEL: u32 = 2;
+
+/// A note from a pass about something it changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Remark {
+    pub pass: String,
+    pub span: Span,
+    pub message: String,
+}
+
+pub trait Pass {
+    fn name(&self) -> &str;
+    fn run(&self, program: Program) -> Program;
+
+    /// Like `run`, but also records what the pass changed.
+    fn run_with_remarks(&self, program: Program, _remarks: &mut Vec<Remark>)
+    -> Program {
+        self.run(program)
+    }
+}
+
+/// Applies `f` to the body of every loop, innermost first, then to `nodes`.
+pub fn map_blocks<F>(nodes: Vec<Node>, f: &F) -> Vec<Node>
+where F: Fn(Vec<Node>) -> Vec<Node> {
+    let nodes = nodes.into_iter()
+        .map(|node| match node.op {
+            Op::Loop(body) => Node::new(Op::Loop(map_blocks(body, f)),
+                                        node.span),
+            op => Node::new(op, node.span),
+        })
+        .collect();
+
+    f(nodes)
+}
+
+/// Replaces loops for which `lower` returns `Some`, innermost first.
+pub fn map_loops<F>(nodes: Vec<Node>, lower: &F) -> Vec<Node>
+where F: Fn(&[Node], &Node) -> Option<Vec<Node>> {
+    map_blocks(nodes, &|nodes: Vec<Node>| {
+        let mut lowered = Vec::with_capacity(nodes.len());
+
+        for node in nodes {
+            let replacement = match node.op {
+                Op::Loop(ref body) => lower(body, &node),
+                _ => None,
+            };
+
+            match replacement {
+                Some(r) => lowered.extend(r),
+                None => lowered.push(node),
+            }
+        }
+
+        lowered
+    })
+}
+
+/// Whether adding `a` then `b` to a cell is the same as adding their sum
+/// under `overflow`. Unless arithmetic wraps, `+-` at the largest value
+/// isn't a no-op, so only amounts of the same sign combine.
+fn combines(overflow: Overflow, a: i32, b: i32) -> bool {
+    overflow == Overflow::Wrap
+        || ((a > 0) == (b > 0) && a.checked_add(b).is_some())
+}
+
+/// Whether a loop that `analysis` recognizes as a clear or multiply loop
+/// behaves the way it assumes under `overflow`: unless arithmetic wraps,
+/// `[+]` never reaches zero and `[->+-<]` isn't a no-op on the target.
+fn loop_holds(body: &[Node], overflow: Overflow) -> bool {
+    overflow == Overflow::Wrap
+        || (analysis::is_monotonic(body)
+            && analysis::effects(body).is_some_and(|e| e.at(0) == -1))
+}
+
+/// Merges runs of `+`/`-` and `>`/`<` into single ops.
+pub struct RunLength;
+
+fn run_length(nodes: Vec<Node>, overflow: Overflow) -> Vec<Node> {
+    let mut merged: Vec<Node> = Vec::with_capacity(nodes.len());
+
+    for node in nodes {
+        let cancelled = match (merged.last_mut(), &node.op) {
+            (Some(&mut Node { op: Op::Add(ref mut a), ref mut span }),
+             &Op::Add(b)) if combines(overflow, *a, b) => {
+                *a = a.wrapping_add(b);
+                *span = span.to(node.span);
+
+                *a == 0
+            }
+            (Some(&mut Node { op: Op::Move(ref mut a), ref mut span }),
+             &Op::Move(b)) => {
+                *a += b;
+                *span = span.to(node.span);
+
+                *a == 0
+            }
+            _ => {
+                merged.push(node);
+
+                continue;
+            }
+        };
+
+        if cancelled {
+            merged.pop();
+        }
+    }
+
+    merged
+}
+
+impl Pass for RunLength {
+    fn name(&self) -> &str {
+        "rle"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        let overflow = program.overflow;
+
+        Program {
+            nodes: map_blocks(program.nodes,
+                              &|nodes| run_length(nodes, overflow)),
+            ..program
+        }
+    }
+}
+
+/// Replaces `[-]` and `[+]` with `Clear`, or only `[-]` unless the program's
+/// arithmetic wraps.
+pub struct ClearLoops;
+
+impl Pass for ClearLoops {
+    fn name(&self) -> &str {
+        "clear"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        let overflow = program.overflow;
+        let nodes = map_loops(program.nodes, &|body: &[Node], node: &Node| {
+            if analysis::is_clear_loop(body) && loop_holds(body, overflow) {
+                Some(vec![Node::new(Op::Clear, node.span)])
+            } else {
+                None
+            }
+        });
+
+        Program { nodes, ..program }
+    }
+}
+
+/// Replaces loops like `[->++<]` with `MulAdd`s followed by `Clear`.
+pub struct MultiplyLoops;
+
+impl Pass for MultiplyLoops {
+    fn name(&self) -> &str {
+        "mul"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        let overflow = program.overflow;
+        let nodes = map_loops(program.nodes, &|body: &[Node], node: &Node| {
+            if !loop_holds(body, overflow) {
+                return None;
+            }
+
+            analysis::multiply_loop(body).map(|targets| {
+                targets.into_iter()
+                    .map(|(offset, factor)| Op::MulAdd { offset, factor })
+                    .chain(std::iter::once(Op::Clear))
+                    .map(|op| Node::new(op, node.span))
+                    .collect()
+            })
+        });
+
+        Program { nodes, ..program }
+    }
+}
+
+/// Rewrites straight-line code so that `>>+++<<` becomes a single
+/// `AddAt { offset: 2, amount: 3 }`: within a block, adds are addressed
+/// relative to where the pointer was at the start of the block, and the
+/// pointer only physically moves before an op that needs it there.
+pub struct OffsetFusion;
+
+#[derive(Default)]
+struct Block {
+    adds: Vec<(isize, i32, Span)>,
+    movement: isize,
+    movement_span: Option<Span>,
+}
+
+impl Block {
+    /// Whether adding `amount` at `offset` can join the adds so far.
+    fn fits(&self, offset: isize, amount: i32, overflow: Overflow) -> bool {
+        let offset = self.movement + offset;
+
+        self.adds.iter()
+            .find(|a| a.0 == offset)
+            .is_none_or(|a| combines(overflow, a.1, amount))
+    }
+
+    fn add(&mut self, offset: isize, amount: i32, span: Span) {
+        let offset = self.movement + offset;
+
+        match self.adds.iter_mut().find(|a| a.0 == offset) {
+            Some(a) => {
+                a.1 = a.1.wrapping_add(amount);
+                a.2 = a.2.to(span);
+            }
+            None => self.adds.push((offset, amount, span)),
+        }
+    }
+
+    fn flush(&mut self, into: &mut Vec<Node>) {
+        for (offset, amount, span) in self.adds.drain(..) {
+            let op = match (offset, amount) {
+                (_, 0) => continue,
+                (0, _) => Op::Add(amount),
+                _ => Op::AddAt { offset, amount },
+            };
+
+            into.push(Node::new(op, span));
+        }
+
+        if let Some(span) = self.movement_span.take() {
+            if self.movement != 0 {
+                into.push(Node::new(Op::Move(self.movement), span));
+            }
+        }
+
+        self.movement = 0;
+    }
+}
+
+fn fuse_offsets(nodes: Vec<Node>, overflow: Overflow) -> Vec<Node> {
+    let mut fused = Vec::with_capacity(nodes.len());
+    let mut block = Block::default();
+
+    for node in nodes {
+        match node.op {
+            Op::Add(amount) | Op::AddAt { amount, .. } => {
+                let offset = match node.op {
+                    Op::AddAt { offset, .. } => offset,
+                    _ => 0,
+                };
+
+                if !block.fits(offset, amount, overflow) {
+                    block.flush(&mut fused);
+                }
+
+                block.add(offset, amount, node.span);
+            }
+            Op::Move(n) => {
+                block.movement += n;
+                block.movement_span = Some(match block.movement_span {
+                    Some(s) => s.to(node.span),
+                    None => node.span,
+                });
+            }
+            _ => {
+                block.flush(&mut fused);
+                fused.push(node);
+            }
+        }
+    }
+
+    block.flush(&mut fused);
+
+    fused
+}
+
+impl Pass for OffsetFusion {
+    fn name(&self) -> &str {
+        "offset"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        let overflow = program.overflow;
+
+        Program {
+            nodes: map_blocks(program.nodes,
+                              &|nodes| fuse_offsets(nodes, overflow)),
+            ..program
+        }
+    }
+}
+
+/// Replaces scans like `[>]` with `Scan` and runs of neighbouring clears
+/// like `[-]>[-]>[-]` with `ClearRange`, which backends run in bulk.
+pub struct BulkTape;
+
+fn clear_ranges(nodes: Vec<Node>) -> Vec<Node> {
+    let mut lowered: Vec<Node> = Vec::with_capacity(nodes.len());
+
+    for node in nodes {
+        if node.op == Op::Clear && lowered.len() >= 2 {
+            let n = lowered.len();
+            let step = match lowered[n - 1].op {
+                Op::Move(s) if s == 1 || s == -1 => s,
+                _ => 0,
+            };
+            let extent = match lowered[n - 2].op {
+                Op::Clear => Some(0),
+                Op::ClearRange(e) if e.signum() == step => Some(e),
+                _ => None,
+            };
+
+            if let (Some(extent), true) = (extent, step != 0) {
+                let span = lowered.pop().unwrap().span;
+                let range = lowered.last_mut().unwrap();
+
+                range.op = Op::ClearRange(extent + step);
+                range.span = range.span.to(span).to(node.span);
+
+                continue;
+            }
+        }
+
+        lowered.push(node);
+    }
+
+    lowered
+}
+
+impl Pass for BulkTape {
+    fn name(&self) -> &str {
+        "bulk"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        let nodes = map_loops(program.nodes, &|body: &[Node], node: &Node| {
+            match *body {
+                [Node { op: Op::Move(step), .. }] => {
+                    Some(vec![Node::new(Op::Scan(step), node.span)])
+                }
+                _ => None,
+            }
+        });
+
+        Program { nodes: map_blocks(nodes, &clear_ranges), ..program }
+    }
+}
+
+/// Removes loops that are never entered because their cell is known to be
+/// zero, and code that follows a loop that provably never exits.
+pub struct DeadCode;
+
+/// What is known about cell values, relative to where the pointer was when
+/// tracking started.
+struct Known {
+    cells: HashMap<isize, Option<u32>>,
+    rest: Option<u32>,
+    position: isize,
+}
+
+impl Known {
+    fn with_rest(rest: Option<u32>) -> Known {
+        Known { cells: HashMap::new(), rest, position: 0 }
+    }
+
+    fn get(&self, offset: isize) -> Option<u32> {
+        *self.cells.get(&(self.position + offset)).unwrap_or(&self.rest)
+    }
+
+    fn set(&mut self, offset: isize, value: Option<u32>) {
+        self.cells.insert(self.position + offset, value);
+    }
+
+    /// Adds to a cell, which is unknown afterwards if the add traps.
+    fn add(&mut self, offset: isize, amount: i64, overflow: Overflow) {
+        let value = self.get(offset).and_then(|v| overflow.add(v, amount));
+
+        self.set(offset, value);
+    }
+}
+
+/// Whether a loop entered with a nonzero cell can never leave: its body
+/// only does arithmetic and output, ends where it started, and never
+/// touches the loop cell.
+fn never_exits(body: &[Node]) -> bool {
+    let mut movement = 0;
+
+    for node in body {
+        match node.op {
+            Op::Add(_) if movement == 0 => return false,
+            Op::AddAt { offset, .. } if movement + offset == 0 => return false,
+            Op::Add(_) | Op::AddAt { .. } | Op::Output => (),
+            Op::Move(n) => movement += n,
+            _ => return false,
+        }
+    }
+
+    movement == 0
+}
+
+fn eliminate(nodes: Vec<Node>, known: &mut Known, overflow: Overflow,
+             remarks: &mut Vec<Remark>) -> Vec<Node> {
+    let mut kept = Vec::with_capacity(nodes.len());
+    let mut nodes = nodes.into_iter();
+
+    while let Some(node) = nodes.next() {
+        match node.op {
+            Op::Add(n) => known.add(0, n as i64, overflow),
+            Op::AddAt { offset, amount } => {
+                known.add(offset, amount as i64, overflow)
+            }
+            Op::Move(n) => known.position += n,
+            Op::Input | Op::Random => known.set(0, None),
+            Op::Clear => known.set(0, Some(0)),
+            Op::MulAdd { offset, factor } => match known.get(0) {
+                Some(v) => known.add(offset, v as i64 * factor as i64,
+                                     overflow),
+                None => known.set(offset, None),
+            },
+            Op::Output | Op::Print(_) => (),
+            // The thread this starts runs the code that follows too, on
+            // a different tape.
+            Op::Fork | Op::SwitchTape(_) => *known = Known::with_rest(None),
+            Op::Scan(_) => {
+                *known = Known::with_rest(None);
+                known.set(0, Some(0));
+            }
+            Op::ClearRange(extent) => {
+                let (low, high) = if extent < 0 { (extent, 0) }
+                                  else { (0, extent) };
+
+                for offset in low..high + 1 {
+                    known.set(offset, Some(0));
+                }
+
+                known.position += extent;
+            }
+            Op::Loop(body) => {
+                let entry = known.get(0);
+
+                if entry == Some(0) {
+                    remarks.push(Remark {
+                        pass: "dce".to_string(),
+                        span: node.span,
+                        message: "removed loop that is never entered"
+                            .to_string(),
+                    });
+
+                    continue;
+                }
+
+                let diverges = entry.is_some() && never_exits(&body);
+                let body = eliminate(body, &mut Known::with_rest(None),
+                                     overflow, remarks);
+
+                kept.push(Node::new(Op::Loop(body), node.span));
+
+                if diverges {
+                    let rest = nodes.by_ref()
+                        .map(|n| n.span)
+                        .fold(None, |s: Option<Span>, n| {
+                            Some(s.map_or(n, |s| s.to(n)))
+                        });
+
+                    if let Some(span) = rest {
+                        remarks.push(Remark {
+                            pass: "dce".to_string(),
+                            span,
+                            message: "removed code after a loop that never \
+                                      exits".to_string(),
+                        });
+                    }
+
+                    break;
+                }
+
+                *known = Known::with_rest(None);
+                known.set(0, Some(0));
+
+                continue;
+            }
+        }
+
+        kept.push(node);
+    }
+
+    kept
+}
+
+impl Pass for DeadCode {
+    fn name(&self) -> &str {
+        "dce"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        self.run_with_remarks(program, &mut Vec::new())
+    }
+
+    fn run_with_remarks(&self, program: Program, remarks: &mut Vec<Remark>)
+    -> Program {
+        let nodes = eliminate(program.nodes, &mut Known::with_rest(Some(0)),
+                              program.overflow, remarks);
+
+        Program { nodes, ..program }
+    }
+}
+
+/// Runs the program at compile time up to its first `,`, which can't depend
+/// on anything but the program itself, and replaces that prefix with its
+/// output and the tape it leaves behind.
+pub struct InputFreePrefix {
+    pub max_steps: u64,
+}
+
+impl Default for InputFreePrefix {
+    fn default() -> InputFreePrefix {
+        InputFreePrefix { max_steps: 10_000_000 }
+    }
+}
+
+impl Pass for InputFreePrefix {
+    fn name(&self) -> &str {
+        "prefix"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        self.run_with_remarks(program, &mut Vec::new())
+    }
+
+    fn run_with_remarks(&self, program: Program, remarks: &mut Vec<Remark>)
+    -> Program {
+        let residual = eval::partially_evaluate(&program, &[],
+                                                self.max_steps);
+        let message = if residual.finished {
+            format!("evaluated the whole program in {} steps",
+                    residual.steps)
+        } else {
+            format!("evaluated {} steps before the first input",
+                    residual.steps)
+        };
+        let span = program.nodes.iter()
+            .map(|n| n.span)
+            .fold(Span::default(), Span::to);
+
+        remarks.push(Remark { pass: "prefix".to_string(), span, message });
+
+        residual.program
+    }
+}
+
+#[derive(Default)]
+pub struct Pipeline {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    pub fn push(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|p| p.name()).collect()
+    }
+
+    pub fn run(&self, program: Program) -> Program {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::debug_span!("optimize").entered();
+
+        self.passes.iter().fold(program, |program, pass| {
+            #[cfg(feature = "tracing")]
+            let _span = ::tracing::debug_span!("pass", name = pass.name())
+                .entered();
+
+            pass.run(program)
+        })
+    }
+
+    pub fn run_with_remarks(&self, program: Program,
+                            remarks: &mut Vec<Remark>) -> Program {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::debug_span!("optimize").entered();
+
+        self.passes.iter().fold(program, |program, pass| {
+            #[cfg(feature = "tracing")]
+            let _span = ::tracing::debug_span!("pass", name = pass.name())
+                .entered();
+
+            pass.run_with_remarks(program, remarks)
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownPass(pub String);
+
+impl std::fmt::Display for UnknownPass {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown optimization pass '{}'", self.0)
+    }
+}
+
+impl std::error::Error for UnknownPass { }
+
+type Factory = Box<dyn Fn() -> Box<dyn Pass>>;
+
+pub struct Registry {
+    factories: Vec<(String, Factory)>,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut registry = Registry { factories: Vec::new() };
+
+        registry.register("rle", || Box::new(RunLength));
+        registry.register("clear", || Box::new(ClearLoops));
+        registry.register("mul", || Box::new(MultiplyLoops));
+        registry.register("dce", || Box::new(DeadCode));
+        registry.register("offset", || Box::new(OffsetFusion));
+        registry.register("bulk", || Box::new(BulkTape));
+        registry.register("prefix", || Box::new(InputFreePrefix::default()));
+
+        registry
+    }
+}
+
+impl Registry {
+    /// Adds a pass, replacing any existing pass of the same name.
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where F: Fn() -> Box<dyn Pass> + 'static {
+        self.factories.retain(|(n, _)| n != name);
+        self.factories.push((name.to_string(), Box::new(factory)));
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.factories.iter().map(|(n, _)| n.as_str()).collect()
+    }
+
+    pub fn create(&self, name: &str) -> Result<Box<dyn Pass>, UnknownPass> {
+        self.factories.iter()
+            .find(|&(n, _)| n == name)
+            .map(|(_, factory)| factory())
+            .ok_or_else(|| UnknownPass(name.to_string()))
+    }
+
+    pub fn pipeline<S: AsRef<str>>(&self, names: &[S])
+    -> Result<Pipeline, UnknownPass> {
+        let mut pipeline = Pipeline::new();
+
+        for name in names {
+            pipeline.push(self.create(name.as_ref())?);
+        }
+
+        Ok(pipeline)
+    }
+
+    /// The passes run at each `--opt-level`; levels above 3 are treated as 3.
+    pub fn preset(&self, level: u32) -> Pipeline {
+        let names: &[&str] = match level {
+            0 => &[],
+            1 => &["rle"],
+            2 => &["rle", "clear", "mul", "dce", "offset", "bulk"],
+            _ => &["rle", "clear", "mul", "dce", "offset", "bulk", "prefix"],
+        };
+
+        self.pipeline(names).unwrap()
+    }
+}
+
+pub fn optimize(program: Program) -> Program {
+    Registry::default().preset(DEFAULT_LEVEL).run(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use frontend::Dialect;
+    use ir::{Op, Overflow, Program};
+    use super::{InputFreePrefix, Pass, Registry};
+
+    fn optimized(source: &str, overflow: Overflow, passes: &[&str])
+    -> Program {
+        let program = Dialect::Brainfuck.parse(source).unwrap()
+            .with_overflow(overflow);
+
+        Registry::default().pipeline(passes).unwrap().run(program)
+    }
+
+    /// The top-level ops `passes` leave of `source`.
+    fn ops(source: &str, passes: &[&str]) -> Vec<Op> {
+        optimized(source, Overflow::Wrap, passes).nodes.into_iter()
+            .map(|n| n.op)
+            .collect()
+    }
+
+    #[test]
+    fn offset_fusion() {
+        assert_eq!(ops(">>+++<<", &["rle", "offset"]),
+                   [Op::AddAt { offset: 2, amount: 3 }]);
+        assert_eq!(ops(">>+++<+", &["rle", "offset"]),
+                   [Op::AddAt { offset: 2, amount: 3 },
+                    Op::AddAt { offset: 1, amount: 1 }, Op::Move(1)]);
+        // The pointer has to be in place for the `.`.
+        assert_eq!(ops(">+.<+", &["rle", "offset"]),
+                   [Op::AddAt { offset: 1, amount: 1 }, Op::Move(1),
+                    Op::Output, Op::AddAt { offset: -1, amount: 1 },
+                    Op::Move(-1)]);
+    }
+
+    #[test]
+    fn offset_fusion_keeps_overflow() {
+        let program = optimized(">+<>-<", Overflow::Trap, &["rle", "offset"]);
+        let ops: Vec<Op> = program.nodes.into_iter().map(|n| n.op).collect();
+
+        // Under `Trap`, `+-` at the largest value isn't a no-op.
+        assert_eq!(ops, [Op::AddAt { offset: 1, amount: 1 }, Op::Move(1),
+                         Op::Add(-1), Op::Move(-1)]);
+    }
+
+    #[test]
+    fn dead_code() {
+        // The tape starts out zero, so the first loop is never entered.
+        assert_eq!(ops("[->+<]+.", &["rle", "dce"]), [Op::Add(1), Op::Output]);
+
+        // Nothing after a loop that can't leave its nonzero cell runs.
+        let kept = ops("+[>+<].", &["rle", "dce"]);
+
+        assert_eq!(kept.len(), 2);
+        assert!(matches!(kept[1], Op::Loop(_)));
+    }
+
+    #[test]
+    fn dead_code_remarks() {
+        let program = Dialect::Brainfuck.parse("[-]+[>+<].").unwrap();
+        let mut remarks = Vec::new();
+
+        Registry::default().pipeline(&["dce"]).unwrap()
+            .run_with_remarks(program, &mut remarks);
+
+        let messages: Vec<_> = remarks.iter()
+            .map(|r| (r.span.start, r.message.as_str()))
+            .collect();
+
+        assert_eq!(messages, [(0, "removed loop that is never entered"),
+                              (9, "removed code after a loop that never \
+                                   exits")]);
+    }
+
+    #[test]
+    fn input_free_prefix() {
+        // The whole program runs ahead of time, leaving its output and the
+        // tape it ends with.
+        assert_eq!(ops("+++++[>+++++++++++++<-]>.", &["prefix"]),
+                   [Op::Print(vec![65]), Op::Move(1), Op::Add(65)]);
+
+        // Only what comes before the first `,` does.
+        let ops = ops("++.,[.,]", &["prefix"]);
+
+        assert_eq!(ops[..3], [Op::Print(vec![2]), Op::Add(2), Op::Input]);
+        assert!(matches!(ops[3], Op::Loop(_)));
+    }
+
+    #[test]
+    fn input_free_prefix_stops_at_its_limit() {
+        let program = Dialect::Brainfuck.parse("+[]").unwrap();
+        let prefix = InputFreePrefix { max_steps: 1000 };
+        let ops: Vec<Op> = prefix.run(program).nodes.into_iter()
+            .map(|n| n.op)
+            .collect();
+
+        assert_eq!(ops, [Op::Add(1), Op::Loop(Vec::new())]);
+    }
+
+    #[test]
+    fn bulk_tape() {
+        assert_eq!(ops("[-]>[-]>[-]", &["clear", "bulk"]),
+                   [Op::ClearRange(2)]);
+        assert_eq!(ops("[-]<[-]", &["clear", "bulk"]), [Op::ClearRange(-1)]);
+        // A clear two cells away isn't part of the range.
+        assert_eq!(ops("[-]>[-]>>[-]", &["rle", "clear", "bulk"]),
+                   [Op::ClearRange(1), Op::Move(2), Op::Clear]);
+        assert_eq!(ops("[>][<<]", &["rle", "bulk"]),
+                   [Op::Scan(1), Op::Scan(-2)]);
+    }
+}
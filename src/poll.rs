@@ -0,0 +1,140 @@
+//! An interpreter that never blocks, for embedders that drive it from their
+//! own event loop, such as a GUI's, without a thread or an async runtime.
+//!
+//! `PollInterpreter::run_until_blocked` runs the program until it writes
+//! output, needs input that hasn't been fed to it yet, or has run a given
+//! amount of instructions, its fuel, and says which it was. Between calls the
+//! embedder feeds it input as it arrives.
+//!
+//! ```
+//! use brainfuck::Dialect;
+//! use brainfuck::poll::{Blocked, PollInterpreter};
+//!
+//! let program = Dialect::Brainfuck.parse(",+.,+.").unwrap();
+//! let mut interpreter = PollInterpreter::new(&program);
+//!
+//! assert_eq!(interpreter.run_until_blocked(100).unwrap(),
+//!            Blocked::NeedsInput);
+//!
+//! interpreter.feed(b"ab");
+//!
+//! assert_eq!(interpreter.run_until_blocked(100).unwrap(),
+//!            Blocked::Produced(b"b".to_vec()));
+//! assert_eq!(interpreter.run_until_blocked(100).unwrap(),
+//!            Blocked::Produced(b"c".to_vec()));
+//! assert_eq!(interpreter.run_until_blocked(100).unwrap(), Blocked::Finished);
+//! ```
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use interpreter::{InterpreterState, Prepared};
+use io;
+use tape::{Dense, Tape};
+use utf8;
+
+/// Why `PollInterpreter::run_until_blocked` returned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Blocked {
+    /// The next instruction reads input, and not enough has been fed for a
+    /// whole character.
+    NeedsInput,
+    /// The program wrote these bytes.
+    Produced(Vec<u8>),
+    /// The program ran all the instructions it was given fuel for.
+    OutOfFuel,
+    /// The program ended.
+    Finished,
+}
+
+pub struct PollInterpreter<T = Dense> where T: Tape {
+    /// Runs on input already fed and collects output not yet returned.
+    state: InterpreterState<VecDeque<u8>, Vec<u8>, T>,
+    end_of_input: bool,
+}
+
+impl PollInterpreter {
+    /// Polls a run of `program`, a `&Program` or a `&Prepared`.
+    pub fn new<P: Into<Prepared>>(program: P) -> PollInterpreter {
+        PollInterpreter::from_state(
+            InterpreterState::new(program, Vec::new(), VecDeque::new())
+        )
+    }
+}
+
+impl<T> PollInterpreter<T> where T: Tape {
+    /// Polls an interpreter already set up with a tape, overflow policy and
+    /// so on. Input already in its reader and output already in its writer
+    /// are kept.
+    pub fn from_state(state: InterpreterState<VecDeque<u8>, Vec<u8>, T>)
+    -> PollInterpreter<T> {
+        PollInterpreter { state, end_of_input: false }
+    }
+
+    pub fn state(&self) -> &InterpreterState<VecDeque<u8>, Vec<u8>, T> {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self)
+    -> &mut InterpreterState<VecDeque<u8>, Vec<u8>, T> {
+        &mut self.state
+    }
+
+    /// Adds `bytes` to the end of the input.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.state.reader_mut().extend(bytes);
+    }
+
+    /// Marks the end of the input, after which reading past what has been
+    /// fed fails as it does for any other reader, rather than blocking.
+    pub fn close_input(&mut self) {
+        self.end_of_input = true;
+    }
+
+    /// Runs at most `fuel` instructions, stopping early once one writes
+    /// output, the next needs input that hasn't been fed, or the program
+    /// ends. Calling it again picks up where it stopped.
+    ///
+    /// An error stops the program at the instruction that failed, as
+    /// `InterpreterState::repl` does; output written before it is returned
+    /// by the next call.
+    ///
+    /// ```
+    /// use brainfuck::Dialect;
+    /// use brainfuck::poll::{Blocked, PollInterpreter};
+    ///
+    /// let program = Dialect::Brainfuck.parse("+[]").unwrap();
+    /// let mut interpreter = PollInterpreter::new(&program);
+    ///
+    /// assert_eq!(interpreter.run_until_blocked(1000).unwrap(),
+    ///            Blocked::OutOfFuel);
+    /// assert_eq!(interpreter.state().steps_executed(), 1000);
+    /// ```
+    pub fn run_until_blocked(&mut self, fuel: u64) -> io::Result<Blocked> {
+        let mut remaining = fuel;
+
+        loop {
+            let output = self.state.writer_mut();
+
+            if !output.is_empty() {
+                return Ok(Blocked::Produced(core::mem::take(output)));
+            }
+
+            if self.state.span().is_none() {
+                return Ok(Blocked::Finished);
+            }
+
+            if self.state.reads_input() && !self.end_of_input
+               && !utf8::has_char(self.state.reader_mut()) {
+                return Ok(Blocked::NeedsInput);
+            }
+
+            if remaining == 0 {
+                return Ok(Blocked::OutOfFuel);
+            }
+
+            remaining -= 1;
+            self.state.repl()?;
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Runtime profiles, which record how hot each loop of a program was on a
+//! representative run so a later run can compile the hot ones right away.
+//!
+//! A profile is text: a header line, then how many times each loop jumped
+//! back to its start, one loop per line in the order they open.
+//!
+//! ```
+//! use brainfuck::profile::Profile;
+//!
+//! let profile = Profile { iterations: vec![9, 90] };
+//! let text = profile.to_text();
+//!
+//! assert_eq!(text, "bfi profile\n9\n90\n");
+//! assert_eq!(Profile::parse(&text), Some(profile));
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const HEADER: &str = "bfi profile";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Profile {
+    /// How many times each loop jumped back to its start, in the order the
+    /// loops open.
+    pub iterations: Vec<u64>,
+}
+
+impl Profile {
+    /// Reads a profile written by `to_text`, or returns `None` if `text`
+    /// isn't one.
+    pub fn parse(text: &str) -> Option<Profile> {
+        let mut lines = text.lines();
+
+        if lines.next() != Some(HEADER) {
+            return None;
+        }
+
+        let iterations = lines.filter(|l| !l.trim().is_empty())
+            .map(|l| l.trim().parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+
+        Some(Profile { iterations })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", HEADER);
+
+        for count in &self.iterations {
+            text += &format!("{}\n", count);
+        }
+
+        text
+    }
+}
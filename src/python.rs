@@ -0,0 +1,133 @@
+//! Python bindings, enabled by the `python` feature. The `brainfuck`
+//! extension module is the library built with `cargo rustc --release --lib
+//! --crate-type cdylib --features python`, renamed to `brainfuck.so`.
+//!
+//! ```python
+//! from brainfuck import Brainfuck
+//!
+//! bf = Brainfuck()
+//! bf.run(",[.,]", input=b"cat")  # b"cat"
+//!
+//! bf.load("++>+++")
+//! for output in bf:  # one instruction at a time
+//!     print(bf.pointer, bf.tape)
+//! ```
+
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use frontend::Dialect;
+use interpreter::InterpreterState;
+use io::{self, BfOutput};
+use optimize::Registry;
+
+/// Output collected since it was last handed to Python.
+#[derive(Clone, Default)]
+struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl BfOutput for Buffer {
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+
+        Ok(())
+    }
+}
+
+/// An interpreter that Python code loads programs into and runs, either
+/// all at once or by iterating over it one instruction at a time.
+#[pyclass(unsendable)]
+pub struct Brainfuck {
+    interpreter: Option<InterpreterState<Cursor<Vec<u8>>, Buffer>>,
+    output: Buffer,
+    /// Whether to run the optimizer over loaded programs, which makes
+    /// iterating over them step through optimized ops rather than commands.
+    optimize: bool,
+}
+
+#[pymethods]
+impl Brainfuck {
+    #[new]
+    #[pyo3(signature = (optimize = true))]
+    fn new(optimize: bool) -> Brainfuck {
+        Brainfuck { interpreter: None, output: Buffer::default(), optimize }
+    }
+
+    /// Loads `source` to run on `input`, replacing any program that was
+    /// loaded before.
+    #[pyo3(signature = (source, input = Vec::new()))]
+    fn load(&mut self, source: &str, input: Vec<u8>) -> PyResult<()> {
+        let mut program = Dialect::Brainfuck.parse(source)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+        if self.optimize {
+            program = Registry::default().preset(2).run(program);
+        }
+
+        self.output = Buffer::default();
+        self.interpreter = Some(InterpreterState::new(&program,
+                                                      self.output.clone(),
+                                                      Cursor::new(input)));
+
+        Ok(())
+    }
+
+    /// Loads `source` and runs it to completion on `input`, returning
+    /// everything it printed.
+    #[pyo3(signature = (source, input = Vec::new()))]
+    fn run<'py>(&mut self, py: Python<'py>, source: &str, input: Vec<u8>)
+    -> PyResult<Bound<'py, PyBytes>> {
+        self.load(source, input)?;
+
+        if let Some(ref mut interpreter) = self.interpreter {
+            while interpreter.repl().is_ok() { }
+        }
+
+        Ok(PyBytes::new(py, &self.output.0.borrow()))
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Runs one instruction, returning what it printed, or stops once the
+    /// program has finished.
+    fn __next__<'py>(&mut self, py: Python<'py>)
+    -> Option<Bound<'py, PyBytes>> {
+        let interpreter = self.interpreter.as_mut()?;
+        let before = self.output.0.borrow().len();
+
+        interpreter.repl().ok()?;
+
+        Some(PyBytes::new(py, &self.output.0.borrow()[before..]))
+    }
+
+    /// Everything the loaded program has printed so far.
+    #[getter]
+    fn output<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.output.0.borrow())
+    }
+
+    /// The value of every cell up to the last nonzero one.
+    #[getter]
+    fn tape(&self) -> Vec<u32> {
+        let tape = self.interpreter.as_ref().map_or(&[][..], |i| i.tape());
+        let length = tape.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1);
+
+        tape[..length].to_vec()
+    }
+
+    #[getter]
+    fn pointer(&self) -> usize {
+        self.interpreter.as_ref().map_or(0, |i| i.pointer())
+    }
+}
+
+#[pymodule]
+fn brainfuck(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Brainfuck>()
+}
@@ -0,0 +1,108 @@
+//! Snapshots, which save the tape and pointer of a run so they can be
+//! looked at afterwards, as `bfi --core-dump` writes them and `bfi dump`
+//! shows them.
+//!
+//! All integers are little-endian. A file is the magic `BFT\0`, a `u16`
+//! format version, the pointer as a `u64`, a `u64` cell count, then each
+//! cell as a `u32`.
+//!
+//! ```
+//! use brainfuck::snapshot::Snapshot;
+//!
+//! let snapshot = Snapshot::new(&[72, 105, 0, 0], 1);
+//!
+//! assert_eq!(snapshot.tape, [72, 105]);
+//! assert_eq!(Snapshot::decode(&snapshot.encode()), Some(snapshot));
+//! ```
+
+use alloc::vec::Vec;
+
+pub const MAGIC: &[u8] = b"BFT\0";
+pub const VERSION: u16 = 1;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Snapshot {
+    pub pointer: usize,
+    /// The tape as far as the last cell that isn't zero, or the pointer if
+    /// that is further. Every cell past it is zero.
+    pub tape: Vec<u32>,
+}
+
+/// Whether `bytes` start like a snapshot.
+pub fn is_snapshot(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub(crate) fn take<'a>(bytes: &mut &'a [u8], length: usize)
+-> Option<&'a [u8]> {
+    if bytes.len() < length {
+        return None;
+    }
+
+    let (taken, rest) = bytes.split_at(length);
+
+    *bytes = rest;
+
+    Some(taken)
+}
+
+pub(crate) fn take_u64(bytes: &mut &[u8]) -> Option<u64> {
+    let mut buffer = [0; 8];
+
+    buffer.copy_from_slice(take(bytes, 8)?);
+
+    Some(u64::from_le_bytes(buffer))
+}
+
+impl Snapshot {
+    /// Saves `tape`, dropping the zeros at its end.
+    pub fn new(tape: &[u32], pointer: usize) -> Snapshot {
+        let used = tape.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1);
+        let length = core::cmp::min(tape.len(),
+                                    core::cmp::max(used,
+                                                   pointer.saturating_add(1)));
+
+        Snapshot { pointer, tape: tape[..length].to_vec() }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.pointer as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.tape.len() as u64).to_le_bytes());
+
+        for cell in &self.tape {
+            bytes.extend_from_slice(&cell.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// Reads a snapshot written by `encode`, or returns `None` if `bytes`
+    /// aren't one this version understands.
+    pub fn decode(mut bytes: &[u8]) -> Option<Snapshot> {
+        if take(&mut bytes, MAGIC.len())? != MAGIC {
+            return None;
+        }
+
+        let version = take(&mut bytes, 2)?;
+
+        if u16::from_le_bytes([version[0], version[1]]) != VERSION {
+            return None;
+        }
+
+        let pointer = take_u64(&mut bytes)? as usize;
+        let length = take_u64(&mut bytes)? as usize;
+
+        if bytes.len() != length.checked_mul(4)? {
+            return None;
+        }
+
+        let tape = bytes.chunks(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+
+        Some(Snapshot { pointer, tape })
+    }
+}
@@ -0,0 +1,129 @@
+//! Source maps, which record where in the original source each part of a
+//! program compiled by `asm` or `mini` came from, so errors and debuggers
+//! can point there instead of at the generated brainfuck.
+//!
+//! A source map is text: a header line, the files lines came from, one per
+//! line after `file`, then entries of three numbers: the byte offset in the
+//! generated brainfuck where a run of code starts, the index of its file,
+//! and the line in that file. Each entry covers the code up to the next.
+//!
+//! ```
+//! use brainfuck::sourcemap::SourceMap;
+//!
+//! let mut map = SourceMap::default();
+//!
+//! map.push(0, "main.bfa", 1);
+//! map.push(8, "lib.bfa", 4);
+//! map.push(12, "main.bfa", 2);
+//!
+//! assert_eq!(map.lookup(9), Some(("lib.bfa", 4)));
+//! assert_eq!(map.lookup(100), Some(("main.bfa", 2)));
+//! assert_eq!(SourceMap::parse(&map.to_text()), Some(map));
+//! ```
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+const HEADER: &str = "bfi source map";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Entry {
+    /// Byte offset in the generated brainfuck.
+    pub offset: usize,
+    /// Index into `SourceMap::files`.
+    pub file: usize,
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SourceMap {
+    pub files: Vec<String>,
+    /// In order of offset.
+    pub entries: Vec<Entry>,
+}
+
+impl SourceMap {
+    /// Records that the generated code from `offset` on comes from `line`
+    /// of `file`, until the next entry. Offsets must not decrease.
+    pub fn push(&mut self, offset: usize, file: &str, line: usize) {
+        let file = match self.files.iter().position(|f| f == file) {
+            Some(i) => i,
+            None => {
+                self.files.push(file.to_string());
+
+                self.files.len() - 1
+            }
+        };
+
+        if let Some(last) = self.entries.last_mut() {
+            if last.file == file && last.line == line {
+                return;
+            }
+
+            // Code that came from nowhere is covered by what comes next.
+            if last.offset == offset {
+                *last = Entry { offset, file, line };
+
+                return;
+            }
+        }
+
+        self.entries.push(Entry { offset, file, line });
+    }
+
+    /// The file and line the generated code at `offset` came from, if any.
+    pub fn lookup(&self, offset: usize) -> Option<(&str, usize)> {
+        let i = self.entries.partition_point(|e| e.offset <= offset)
+            .checked_sub(1)?;
+        let entry = self.entries[i];
+
+        Some((&self.files[entry.file], entry.line))
+    }
+
+    /// Reads a source map written by `to_text`, or returns `None` if `text`
+    /// isn't one.
+    pub fn parse(text: &str) -> Option<SourceMap> {
+        let mut lines = text.lines();
+        let mut map = SourceMap::default();
+
+        if lines.next() != Some(HEADER) {
+            return None;
+        }
+
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            if let Some(file) = line.strip_prefix("file ") {
+                map.files.push(file.to_string());
+
+                continue;
+            }
+
+            let numbers = line.split_whitespace()
+                .map(|n| n.parse().ok())
+                .collect::<Option<Vec<usize>>>()?;
+
+            match numbers[..] {
+                [offset, file, line] if file < map.files.len() => {
+                    map.entries.push(Entry { offset, file, line });
+                }
+                _ => return None,
+            }
+        }
+
+        Some(map)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", HEADER);
+
+        for file in &self.files {
+            text += &format!("file {}\n", file);
+        }
+
+        for entry in &self.entries {
+            text += &format!("{} {} {}\n", entry.offset, entry.file,
+                             entry.line);
+        }
+
+        text
+    }
+}
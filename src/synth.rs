@@ -0,0 +1,176 @@
+//! Random programs that are guaranteed to terminate, for stress-testing
+//! interpreters.
+//!
+//! Every loop counts down a cell of its own, set to a small constant just
+//! before the loop and left alone by everything inside it. The first
+//! `max_depth` cells hold those counters, one per nesting level, and the
+//! rest of the generated code only touches the cells after them. Since a
+//! loop only exits once its counter is zero, each counter is already zero
+//! the next time a loop at that level starts.
+//!
+//! Data cells also stay within a byte and never wrap, so programs behave
+//! the same on interpreters with 8-bit cells. The generator
+//! keeps bounds on each cell and only emits arithmetic that can't leave
+//! them. Inside a loop, where a later iteration may see a value read by an
+//! earlier one, the bounds widen to cover any input on entry and only
+//! ever widen further until the loop ends.
+
+pub use random::Rng;
+
+/// The shape of a generated program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// Roughly how many commands the program has.
+    pub size: usize,
+    /// The deepest loops are nested.
+    pub max_depth: usize,
+    /// The most times any one loop runs each time it is entered.
+    pub max_iterations: u32,
+    /// How many data cells the program works on.
+    pub cells: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { size: 256, max_depth: 3, max_iterations: 4, cells: 8 }
+    }
+}
+
+/// A generated program and input holding exactly as many bytes as it reads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Synthesized {
+    pub source: String,
+    pub input: Vec<u8>,
+}
+
+// Input bytes are printable ASCII.
+const INPUT_LOW: i64 = 0x20;
+const INPUT_HIGH: i64 = 0x7e;
+// The largest value a data cell can hold.
+const MAX_VALUE: i64 = 0xff;
+
+struct Generator<'a> {
+    options: &'a Options,
+    rng: Rng,
+    source: String,
+    input: Vec<u8>,
+    pointer: usize,
+    /// Bounds on each data cell's value from here to the end of the
+    /// program.
+    bounds: Vec<(i64, i64)>,
+}
+
+impl<'a> Generator<'a> {
+    fn move_to(&mut self, cell: usize) {
+        let (command, distance) = if cell > self.pointer {
+            ('>', cell - self.pointer)
+        } else {
+            ('<', self.pointer - cell)
+        };
+
+        self.source.extend(std::iter::repeat_n(command, distance));
+        self.pointer = cell;
+    }
+
+    fn data_cell(&mut self) -> usize {
+        self.options.max_depth
+            + self.rng.range(0, self.options.cells as u64 - 1) as usize
+    }
+
+    /// Generates straight-line code and loops until the size budget runs
+    /// out or, below the top level, the block randomly ends. Each command
+    /// runs `runs` times, which decides how much input `,` needs.
+    fn block(&mut self, depth: usize, runs: u64) {
+        while self.source.len() < self.options.size {
+            match self.rng.range(0, 9) {
+                0..=2 => self.arithmetic(depth, runs),
+                3 | 4 => {
+                    let cell = self.data_cell();
+
+                    self.move_to(cell);
+                }
+                5 => self.source.push('.'),
+                6 => self.read(depth, runs),
+                7 | 8 if depth < self.options.max_depth => {
+                    self.counted_loop(depth, runs)
+                }
+                9 if depth > 0 => return,
+                _ => (),
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, depth: usize, runs: u64) {
+        let count = self.rng.range(1, 8) as i64;
+        let total = count * runs as i64;
+        let (low, high) = self.bounds[self.pointer - self.options.max_depth];
+        let (command, bounds) = if self.rng.range(0, 1) == 0 {
+            let low = if depth == 0 { low + count } else { low };
+
+            ('+', (low, high + total))
+        } else {
+            let high = if depth == 0 { high - count } else { high };
+
+            ('-', (low - total, high))
+        };
+
+        if bounds.0 >= 0 && bounds.1 <= MAX_VALUE {
+            self.source.extend(std::iter::repeat_n(command, count as usize));
+            self.bounds[self.pointer - self.options.max_depth] = bounds;
+        }
+    }
+
+    fn read(&mut self, depth: usize, runs: u64) {
+        self.source.push(',');
+
+        if depth == 0 {
+            self.bounds[self.pointer - self.options.max_depth] =
+                (INPUT_LOW, INPUT_HIGH);
+        }
+
+        for _ in 0..runs {
+            let byte = self.rng.range(INPUT_LOW as u64, INPUT_HIGH as u64);
+
+            self.input.push(byte as u8);
+        }
+    }
+
+    fn counted_loop(&mut self, depth: usize, runs: u64) {
+        let iterations =
+            self.rng.range(1, u64::from(self.options.max_iterations));
+        let start = self.pointer;
+
+        for bounds in &mut self.bounds {
+            *bounds = (std::cmp::min(bounds.0, INPUT_LOW),
+                       std::cmp::max(bounds.1, INPUT_HIGH));
+        }
+
+        self.move_to(depth);
+        self.source.extend(std::iter::repeat_n('+', iterations as usize));
+        self.source.push('[');
+        self.move_to(start);
+        self.block(depth + 1, runs * iterations);
+        self.move_to(depth);
+        self.source.push_str("-]");
+        self.move_to(start);
+    }
+}
+
+/// Generates a random program from `seed` that always terminates, along
+/// with the input it reads.
+pub fn random(options: &Options, seed: u64) -> Synthesized {
+    let mut generator = Generator {
+        options,
+        rng: Rng::new(seed),
+        source: String::new(),
+        input: Vec::new(),
+        pointer: 0,
+        bounds: vec![(0, 0); options.cells],
+    };
+    let first = generator.data_cell();
+
+    generator.move_to(first);
+    generator.block(0, 1);
+
+    Synthesized { source: generator.source, input: generator.input }
+}
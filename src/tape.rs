@@ -0,0 +1,800 @@
+//! Tapes, which the backends keep their cells on.
+//!
+//! A `Tape` reads as zero past the cells it holds, so only writing to one
+//! may need to grow it. `Dense` is the tape the interpreter and VM start
+//! with. `Sparse` only stores the cells written to, for programs that
+//! wander far. `Fixed` and `Mmap` have a set length, and a `Bounds` policy
+//! for moving off either end.
+//!
+//! ```
+//! use brainfuck::tape::{Bounds, Fixed, Tape};
+//! use brainfuck::{Dialect, InterpreterState};
+//!
+//! let program = Dialect::Brainfuck.parse("<+++").unwrap();
+//! let mut interpreter = InterpreterState::new(&program, Vec::new(),
+//!                                             &[][..])
+//!     .with_tape_of(Fixed::new(4, Bounds::Wrap));
+//!
+//! while interpreter.span().is_some() {
+//!     interpreter.repl().unwrap();
+//! }
+//!
+//! assert_eq!(interpreter.pointer(), 3);
+//! assert_eq!(interpreter.tape().get(3), 3);
+//! ```
+
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+use io;
+
+/// Cells a `Dense` tape starts with.
+pub const INITIAL_CELLS: usize = 65536;
+
+/// What moving the pointer off an end of a tape with a set length does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bounds {
+    /// The pointer comes back on at the other end.
+    Wrap,
+    /// The move fails, leaving the pointer where it was.
+    Error,
+}
+
+/// The cells of a tape, and where the pointer can move on it.
+pub trait Tape: Clone {
+    /// The cell at `index`. Cells that were never written to are zero.
+    fn get(&self, index: usize) -> u32;
+
+    /// The cell at `index`, to write to, growing the tape to hold it if it
+    /// has to. Fails if the tape can't.
+    fn get_mut(&mut self, index: usize) -> io::Result<&mut u32>;
+
+    fn set(&mut self, index: usize, value: u32) -> io::Result<()> {
+        *self.get_mut(index)? = value;
+
+        Ok(())
+    }
+
+    /// Where a pointer at `pointer` is after moving `by` cells. Unless the
+    /// tape says otherwise, moving left of the first cell wraps around to
+    /// the end of the address space, which stands for the cells left of
+    /// it.
+    fn offset(&self, pointer: usize, by: isize) -> io::Result<usize> {
+        Ok(pointer.wrapping_add(by as usize))
+    }
+
+    /// How many cells the tape holds. Every cell past them is zero.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// How many cells left of the first the tape holds, which are at the
+    /// indices moving left of it wraps around to. Only a tape that grows to
+    /// the left has any.
+    fn left_len(&self) -> usize {
+        0
+    }
+
+    /// The cells the tape holds, copied only if it doesn't keep them in a
+    /// row.
+    fn cells(&self) -> Cow<'_, [u32]>;
+
+    /// Stops the tape holding more than `cells` cells, dropping any past
+    /// them. Writing past them fails from then on.
+    fn set_limit(&mut self, cells: usize);
+
+    /// A tape of all zeros, with the same limit and bounds as this one.
+    /// Fails with `OutOfMemory` if there is no memory for it.
+    fn empty(&self) -> io::Result<Self>;
+
+    /// A copy of the tape, failing as `empty` does rather than panicking
+    /// as `clone` may.
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+
+    /// Writes `cells` over the first cells of the tape, dropping any it
+    /// can't hold.
+    fn load(&mut self, cells: &[u32]) {
+        for (index, &cell) in cells.iter().enumerate() {
+            if self.set(index, cell).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Where a scan that moves by `step` from `start` until it finds a zero
+    /// cell stops.
+    fn scan(&self, start: usize, step: isize) -> io::Result<usize> {
+        let mut pointer = start;
+
+        while self.get(pointer) != 0 {
+            pointer = self.offset(pointer, step)?;
+        }
+
+        Ok(pointer)
+    }
+
+    /// Zeroes the cells from `start` to `start + extent` inclusive.
+    fn clear_range(&mut self, start: usize, extent: isize)
+    -> io::Result<()> {
+        let step = if extent < 0 { -1 } else { 1 };
+        let mut index = start;
+
+        for i in 0..=extent.unsigned_abs() {
+            if self.get(index) != 0 {
+                self.set(index, 0)?;
+            }
+
+            if i < extent.unsigned_abs() {
+                index = self.offset(index, step)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A tape in one block of memory, which doubles in length to hold a cell
+/// past either end. Cells left of the first, where the pointer goes after
+/// moving left of it, come before it in the block.
+///
+/// ```
+/// use brainfuck::tape::{Dense, Tape};
+///
+/// let mut tape = Dense::new();
+///
+/// tape.set(0, 1).unwrap();
+/// tape.set(2usize.wrapping_neg(), 2).unwrap();
+///
+/// assert_eq!(tape.get(0), 1);
+/// assert_eq!(tape.get(2usize.wrapping_neg()), 2);
+/// assert_eq!(tape.left()[tape.left().len() - 2..], [2, 0]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Dense {
+    cells: Vec<u32>,
+    /// Index in `cells` of the first cell.
+    origin: usize,
+    limit: Option<usize>,
+}
+
+impl Dense {
+    pub fn new() -> Dense {
+        Dense { cells: vec![0; INITIAL_CELLS], origin: 0, limit: None }
+    }
+
+    /// The cells left of the first that have been made room for, leftmost
+    /// first.
+    pub fn left(&self) -> &[u32] {
+        &self.cells[..self.origin]
+    }
+
+    /// Every cell, including those left of the first, and the index of the
+    /// first among them.
+    pub(crate) fn all_mut(&mut self) -> (&mut [u32], usize) {
+        (&mut self.cells, self.origin)
+    }
+
+    /// Where `index` is in `cells`, if it is there.
+    fn physical(&self, index: usize) -> Option<usize> {
+        let physical = self.origin.wrapping_add(index);
+
+        if physical < self.cells.len() {
+            Some(physical)
+        } else {
+            None
+        }
+    }
+
+    /// Doubles the tape on the side `index` is until it is in it, but
+    /// never past its limit. Fails if `index` is past the limit.
+    fn grow(&mut self, index: usize) -> io::Result<()> {
+        let limit = self.limit.unwrap_or(usize::MAX);
+
+        if (index as isize) < 0 {
+            let distance = index.wrapping_neg();
+            let needed = self.cells.len()
+                .saturating_add(distance - self.origin);
+
+            if needed > limit {
+                return Err(io::tape_limit_exceeded(needed, limit));
+            }
+
+            let mut left = core::cmp::max(1, self.origin);
+
+            while left < distance {
+                left = left.saturating_mul(2);
+            }
+
+            let added = core::cmp::min(left - self.origin,
+                                       limit - self.cells.len());
+
+            self.cells.splice(0..0, core::iter::repeat_n(0, added));
+            self.origin += added;
+
+            return Ok(());
+        }
+
+        let physical = self.origin.saturating_add(index);
+
+        if physical >= limit {
+            return Err(io::tape_limit_exceeded(physical.saturating_add(1),
+                                               limit));
+        }
+
+        let mut length = core::cmp::max(1, self.cells.len());
+
+        while length <= physical {
+            length = length.saturating_mul(2);
+        }
+
+        self.cells.resize(core::cmp::min(length, limit), 0);
+
+        Ok(())
+    }
+}
+
+impl Default for Dense {
+    fn default() -> Dense {
+        Dense::new()
+    }
+}
+
+/// The cells from the first on.
+impl Deref for Dense {
+    type Target = [u32];
+
+    fn deref(&self) -> &[u32] {
+        &self.cells[self.origin..]
+    }
+}
+
+impl DerefMut for Dense {
+    fn deref_mut(&mut self) -> &mut [u32] {
+        &mut self.cells[self.origin..]
+    }
+}
+
+impl Tape for Dense {
+    fn get(&self, index: usize) -> u32 {
+        self.physical(index).map_or(0, |i| self.cells[i])
+    }
+
+    fn get_mut(&mut self, index: usize) -> io::Result<&mut u32> {
+        if self.physical(index).is_none() {
+            self.grow(index)?;
+        }
+
+        let physical = self.origin.wrapping_add(index);
+
+        Ok(&mut self.cells[physical])
+    }
+
+    /// The cells from the first on, not counting those left of it.
+    fn len(&self) -> usize {
+        self.cells.len() - self.origin
+    }
+
+    fn left_len(&self) -> usize {
+        self.origin
+    }
+
+    fn cells(&self) -> Cow<'_, [u32]> {
+        Cow::Borrowed(&self.cells[self.origin..])
+    }
+
+    fn set_limit(&mut self, cells: usize) {
+        self.limit = Some(cells);
+        self.cells.truncate(cells);
+        self.origin = core::cmp::min(self.origin, self.cells.len());
+    }
+
+    fn empty(&self) -> io::Result<Dense> {
+        let length = core::cmp::min(INITIAL_CELLS,
+                                    self.limit.unwrap_or(usize::MAX));
+
+        Ok(Dense { cells: vec![0; length], origin: 0, limit: self.limit })
+    }
+
+    fn load(&mut self, cells: &[u32]) {
+        let room = self.limit.unwrap_or(usize::MAX) - self.origin;
+        let cells = &cells[..core::cmp::min(cells.len(), room)];
+        let end = self.origin + cells.len();
+
+        if self.cells.len() < end {
+            self.cells.resize(end, 0);
+        }
+
+        self.cells[self.origin..end].copy_from_slice(cells);
+    }
+
+    fn scan(&self, start: usize, step: isize) -> io::Result<usize> {
+        let cells = &self.cells;
+        let origin = self.origin;
+        let start = match self.physical(start) {
+            Some(physical) => physical,
+            None => return Ok(start),
+        };
+
+        Ok(match step {
+            1 => find_zero_forward(&cells[start..])
+                .map_or(cells.len(), |i| start + i)
+                .wrapping_sub(origin),
+            -1 => find_zero_backward(&cells[..start + 1])
+                .unwrap_or(usize::MAX)
+                .wrapping_sub(origin),
+            _ => {
+                let mut pointer = start.wrapping_sub(origin);
+
+                while self.get(pointer) != 0 {
+                    pointer = pointer.wrapping_add(step as usize);
+                }
+
+                pointer
+            }
+        })
+    }
+
+    fn clear_range(&mut self, start: usize, extent: isize)
+    -> io::Result<()> {
+        // Where the range is in `cells`, which may be off either end.
+        let start = self.origin as i128 + start as isize as i128;
+        let (low, high) = if extent < 0 {
+            (start + extent as i128, start)
+        } else {
+            (start, start + extent as i128)
+        };
+        let low = core::cmp::max(low, 0);
+        let high = core::cmp::min(high + 1, self.cells.len() as i128);
+
+        if low < high {
+            self.cells[low as usize..high as usize].fill(0);
+        }
+
+        Ok(())
+    }
+}
+
+// Cells compared at once while searching for a zero. The comparison in
+// `has_zero` has no early exit, which lets it compile to SIMD compares.
+const LANES: usize = 16;
+
+fn has_zero(chunk: &[u32]) -> bool {
+    chunk.iter().fold(false, |found, &c| found | (c == 0))
+}
+
+fn find_zero_forward(cells: &[u32]) -> Option<usize> {
+    let mut base = 0;
+
+    for chunk in cells.chunks(LANES) {
+        if has_zero(chunk) {
+            return chunk.iter().position(|&c| c == 0).map(|i| base + i);
+        }
+
+        base += chunk.len();
+    }
+
+    None
+}
+
+fn find_zero_backward(cells: &[u32]) -> Option<usize> {
+    let mut end = cells.len();
+
+    for chunk in cells.rchunks(LANES) {
+        end -= chunk.len();
+
+        if has_zero(chunk) {
+            return chunk.iter().rposition(|&c| c == 0).map(|i| end + i);
+        }
+    }
+
+    None
+}
+
+/// A tape that only stores the cells written to, so cells far apart cost
+/// no more than cells side by side. Each access is a lookup, though.
+///
+/// ```
+/// use brainfuck::tape::{Sparse, Tape};
+///
+/// let mut tape = Sparse::default();
+///
+/// tape.set(1 << 40, 7).unwrap();
+///
+/// assert_eq!(tape.get(1 << 40), 7);
+/// assert_eq!(tape.get(0), 0);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Sparse {
+    cells: BTreeMap<usize, u32>,
+    limit: Option<usize>,
+}
+
+impl Tape for Sparse {
+    fn get(&self, index: usize) -> u32 {
+        self.cells.get(&index).cloned().unwrap_or(0)
+    }
+
+    fn get_mut(&mut self, index: usize) -> io::Result<&mut u32> {
+        // Past here is where moving left of the first cell wraps to.
+        if (index as isize) < 0 {
+            return Err(io::pointer_out_of_bounds());
+        }
+
+        if let Some(limit) = self.limit {
+            if index >= limit {
+                return Err(io::tape_limit_exceeded(index + 1, limit));
+            }
+        }
+
+        Ok(self.cells.entry(index).or_insert(0))
+    }
+
+    fn len(&self) -> usize {
+        self.cells.keys().next_back().map_or(0, |&i| i + 1)
+    }
+
+    fn cells(&self) -> Cow<'_, [u32]> {
+        let mut cells = vec![0; self.len()];
+
+        for (&index, &cell) in &self.cells {
+            cells[index] = cell;
+        }
+
+        Cow::Owned(cells)
+    }
+
+    fn set_limit(&mut self, cells: usize) {
+        self.limit = Some(cells);
+        self.cells.retain(|&i, _| i < cells);
+    }
+
+    fn empty(&self) -> io::Result<Sparse> {
+        Ok(Sparse { cells: BTreeMap::new(), limit: self.limit })
+    }
+}
+
+/// Where a pointer on a tape of `length` cells is after moving `by` cells
+/// from `pointer`, under `bounds`.
+fn bounded(pointer: usize, by: isize, length: usize, bounds: Bounds)
+-> io::Result<usize> {
+    match bounds {
+        Bounds::Wrap if length == 0 => Err(io::pointer_past_end()),
+        Bounds::Wrap => {
+            let moved = (pointer as i128 + by as i128)
+                .rem_euclid(length as i128);
+
+            Ok(moved as usize)
+        }
+        Bounds::Error => match pointer.checked_add_signed(by) {
+            Some(moved) if moved < length => Ok(moved),
+            Some(_) => Err(io::pointer_past_end()),
+            None => Err(io::pointer_out_of_bounds()),
+        },
+    }
+}
+
+/// A tape of a set number of cells, as many implementations of brainfuck
+/// have, with 30,000 the usual number.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Fixed {
+    cells: Vec<u32>,
+    bounds: Bounds,
+}
+
+impl Fixed {
+    pub fn new(cells: usize, bounds: Bounds) -> Fixed {
+        Fixed { cells: vec![0; cells], bounds }
+    }
+}
+
+impl Tape for Fixed {
+    fn get(&self, index: usize) -> u32 {
+        self.cells.get(index).cloned().unwrap_or(0)
+    }
+
+    fn get_mut(&mut self, index: usize) -> io::Result<&mut u32> {
+        self.cells.get_mut(index).ok_or_else(io::pointer_past_end)
+    }
+
+    fn offset(&self, pointer: usize, by: isize) -> io::Result<usize> {
+        bounded(pointer, by, self.cells.len(), self.bounds)
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn cells(&self) -> Cow<'_, [u32]> {
+        Cow::Borrowed(&self.cells)
+    }
+
+    fn set_limit(&mut self, cells: usize) {
+        self.cells.truncate(cells);
+    }
+
+    fn empty(&self) -> io::Result<Fixed> {
+        Ok(Fixed::new(self.cells.len(), self.bounds))
+    }
+}
+
+/// A tape of a set number of cells, mapped from the system as zeros. The
+/// system only gives cells memory once they are written to, so the tape can
+/// be far longer than what a program uses of it.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Mmap {
+    memory: *mut u32,
+    /// Bytes mapped.
+    mapped: usize,
+    length: usize,
+    /// Cells as far as the furthest written to.
+    used: usize,
+    bounds: Bounds,
+}
+
+// The mapping belongs to the tape alone, as a `Vec`'s memory does.
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+    pub fn new(cells: usize, bounds: Bounds) -> io::Result<Mmap> {
+        let too_long = || io::Error::new(io::ErrorKind::OutOfMemory,
+                                         "could not map the tape");
+        let mapped = cells.checked_mul(4).ok_or_else(too_long)?;
+        let mapped = core::cmp::max(mapped, 1);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS
+                    | libc::MAP_NORESERVE;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+        let memory = unsafe {
+            libc::mmap(core::ptr::null_mut(), mapped,
+                       libc::PROT_READ | libc::PROT_WRITE, flags, -1, 0)
+        };
+
+        if memory == libc::MAP_FAILED {
+            return Err(too_long());
+        }
+
+        Ok(Mmap { memory: memory as *mut u32, mapped, length: cells,
+                  used: 0, bounds })
+    }
+
+    fn as_slice(&self) -> &[u32] {
+        unsafe { core::slice::from_raw_parts(self.memory, self.used) }
+    }
+}
+
+/// Panics if the system won't map another tape; `Tape::try_clone` fails
+/// instead.
+#[cfg(unix)]
+impl Clone for Mmap {
+    fn clone(&self) -> Mmap {
+        self.try_clone().expect("could not map the tape")
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.memory as *mut libc::c_void, self.mapped);
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Tape for Mmap {
+    fn get(&self, index: usize) -> u32 {
+        if index >= self.length {
+            return 0;
+        }
+
+        unsafe { *self.memory.add(index) }
+    }
+
+    fn get_mut(&mut self, index: usize) -> io::Result<&mut u32> {
+        if index >= self.length {
+            return Err(io::pointer_past_end());
+        }
+
+        self.used = core::cmp::max(self.used, index + 1);
+
+        Ok(unsafe { &mut *self.memory.add(index) })
+    }
+
+    fn offset(&self, pointer: usize, by: isize) -> io::Result<usize> {
+        bounded(pointer, by, self.length, self.bounds)
+    }
+
+    fn len(&self) -> usize {
+        self.used
+    }
+
+    fn cells(&self) -> Cow<'_, [u32]> {
+        Cow::Borrowed(self.as_slice())
+    }
+
+    fn set_limit(&mut self, cells: usize) {
+        self.length = core::cmp::min(self.length, cells);
+        self.used = core::cmp::min(self.used, cells);
+    }
+
+    fn empty(&self) -> io::Result<Mmap> {
+        Mmap::new(self.length, self.bounds)
+    }
+
+    fn try_clone(&self) -> io::Result<Mmap> {
+        let mut clone = self.empty()?;
+
+        clone.load(self.as_slice());
+        clone.used = self.used;
+
+        Ok(clone)
+    }
+}
+
+/// What `View` reads of a tape, which unlike `Tape` can be a trait object.
+trait Readable {
+    fn cell(&self, index: usize) -> u32;
+    fn length(&self) -> usize;
+    fn row(&self) -> Cow<'_, [u32]>;
+}
+
+impl<T: Tape> Readable for T {
+    fn cell(&self, index: usize) -> u32 {
+        self.get(index)
+    }
+
+    fn length(&self) -> usize {
+        self.len()
+    }
+
+    fn row(&self) -> Cow<'_, [u32]> {
+        self.cells()
+    }
+}
+
+/// A tape of any kind, borrowed to read, as hooks see the current one in
+/// `State`. Nothing is copied unless `cells` has to.
+///
+/// ```
+/// use std::sync::mpsc;
+///
+/// use brainfuck::tape::Sparse;
+/// use brainfuck::{Dialect, InterpreterState};
+///
+/// let program = Dialect::Brainfuck.parse("+++.").unwrap();
+/// let (sender, receiver) = mpsc::channel();
+/// let mut interpreter = InterpreterState::new(&program, Vec::new(),
+///                                             &[][..])
+///     .with_tape_of(Sparse::default())
+///     .on_output(move |state, _| {
+///         sender.send((state.tape.get(0), state.tape.len())).unwrap();
+///     });
+///
+/// while interpreter.repl().is_ok() { }
+///
+/// assert_eq!(receiver.recv().unwrap(), (3, 1));
+/// ```
+#[derive(Clone, Copy)]
+pub struct View<'a> {
+    tape: &'a dyn Readable,
+}
+
+impl<'a> View<'a> {
+    pub fn new<T: Tape>(tape: &'a T) -> View<'a> {
+        View { tape }
+    }
+
+    /// The cell at `index`. Cells that were never written to are zero.
+    pub fn get(&self, index: usize) -> u32 {
+        self.tape.cell(index)
+    }
+
+    /// How many cells the tape holds. Every cell past them is zero.
+    pub fn len(&self) -> usize {
+        self.tape.length()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The cells the tape holds, copied only if it doesn't keep them in a
+    /// row, as `Tape::cells` returns them.
+    pub fn cells(&self) -> Cow<'a, [u32]> {
+        self.tape.row()
+    }
+}
+
+impl fmt::Debug for View<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.cells().iter()).finish()
+    }
+}
+
+impl PartialEq for View<'_> {
+    fn eq(&self, other: &View) -> bool {
+        self.len() == other.len()
+            && (0..self.len()).all(|i| self.get(i) == other.get(i))
+    }
+}
+
+impl Eq for View<'_> { }
+
+/// The tapes other than the current one, with the `tapes` extension. The
+/// current tape and its pointer live with the backend, which swaps them
+/// with one of these to switch.
+#[derive(Clone, Debug)]
+pub struct Tapes<T> {
+    /// Each tape and its pointer, or `None` for the current one and those
+    /// not reached yet.
+    tapes: Vec<Option<(T, usize)>>,
+    current: usize,
+}
+
+impl<T> Default for Tapes<T> {
+    fn default() -> Tapes<T> {
+        Tapes { tapes: Vec::new(), current: 0 }
+    }
+}
+
+impl<T: Tape> Tapes<T> {
+    /// Which tape is current, counting from 0.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Each tape and its pointer, or `None` for the current one and those
+    /// not reached yet.
+    pub(crate) fn parked(&self) -> &[Option<(T, usize)>] {
+        &self.tapes
+    }
+
+    /// Tapes as `parked` returns them, with `current` current.
+    pub(crate) fn from_parked(tapes: Vec<Option<(T, usize)>>, current: usize)
+    -> Tapes<T> {
+        Tapes { tapes, current }
+    }
+
+    /// Moves `by` tapes along, creating tapes like the current one the
+    /// first time they are reached. Fails, without switching, if that is
+    /// before the first tape.
+    pub fn switch(&mut self, by: isize, tape: &mut T, pointer: &mut usize)
+    -> io::Result<()> {
+        let target = self.current.checked_add_signed(by)
+            .ok_or_else(|| io::Error::other("no tape before the first"))?;
+
+        if target == self.current {
+            return Ok(());
+        }
+
+        while self.tapes.len() <= core::cmp::max(target, self.current) {
+            self.tapes.push(None);
+        }
+
+        let (next, next_pointer) = match self.tapes[target].take() {
+            Some(next) => next,
+            None => (tape.empty()?, 0),
+        };
+
+        self.tapes[self.current] = Some((core::mem::replace(tape, next),
+                                         core::mem::replace(pointer,
+                                                            next_pointer)));
+        self.current = target;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use alloc::collections::VecDeque;
+
+use io::{BfInput, Error, ErrorKind, Result};
+
+/// Decodes a byte stream into `char`s one at a time, so interactive input is
+/// consumed no faster than the program asks for it.
+pub struct Chars<R: BfInput> {
+    reader: R,
+}
+
+impl<R: BfInput> Chars<R> {
+    pub fn new(reader: R) -> Chars<R> {
+        Chars { reader }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn next_byte(&mut self) -> Option<Result<u8>> {
+        self.reader.read_byte().transpose()
+    }
+}
+
+/// Whether `input` starts with a whole character, or with a byte no
+/// character starts with, so that reading one won't run out of bytes.
+pub(crate) fn has_char(input: &VecDeque<u8>) -> bool {
+    let width = match input.front() {
+        Some(&first) => match first.leading_ones() {
+            2 => 2,
+            3 => 3,
+            4 => 4,
+            _ => 1,
+        },
+        None => return false,
+    };
+
+    input.len() >= width
+}
+
+fn invalid() -> Error {
+    Error::new(ErrorKind::InvalidData, "buffer did not contain valid UTF-8")
+}
+
+impl<R: BfInput> Iterator for Chars<R> {
+    type Item = Result<char>;
+
+    fn next(&mut self) -> Option<Result<char>> {
+        let first = match self.next_byte()? {
+            Ok(b) => b,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let width = match first.leading_ones() {
+            0 => return Some(Ok(first as char)),
+            2 => 2,
+            3 => 3,
+            4 => 4,
+            _ => return Some(Err(invalid())),
+        };
+
+        let mut buffer = [first, 0, 0, 0];
+
+        for byte in buffer[1..width].iter_mut() {
+            *byte = match self.next_byte() {
+                Some(Ok(b)) => b,
+                Some(Err(e)) => return Some(Err(e)),
+                None => return Some(Err(invalid())),
+            };
+        }
+
+        match core::str::from_utf8(&buffer[..width]) {
+            Ok(s) => s.chars().next().map(Ok),
+            Err(_) => Some(Err(invalid())),
+        }
+    }
+}
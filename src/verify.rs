@@ -0,0 +1,259 @@
+//! Differential testing: running one program on every backend and checking
+//! that they all agree with the unoptimized interpreter, or running two
+//! programs and checking that they print the same.
+
+use std::fmt;
+
+use ir::Program;
+use interpreter::InterpreterState;
+use vm::Vm;
+
+/// What one backend did with the program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Run {
+    pub backend: &'static str,
+    pub output: Vec<u8>,
+    /// The tape with trailing zero cells trimmed, which only differ in how
+    /// far each backend happened to grow it.
+    pub tape: Vec<u32>,
+    pub pointer: usize,
+}
+
+fn trimmed(tape: &[u32]) -> Vec<u32> {
+    let length = tape.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1);
+
+    tape[..length].to_vec()
+}
+
+fn interpret(backend: &'static str, program: &Program, input: &[u8]) -> Run {
+    let mut output = Vec::new();
+    let (tape, pointer) = {
+        let mut interpreter = InterpreterState::new(program, &mut output,
+                                                    input);
+
+        while interpreter.repl().is_ok() { }
+
+        (trimmed(interpreter.tape()), interpreter.pointer())
+    };
+
+    Run { backend, output, tape, pointer }
+}
+
+fn execute(backend: &'static str, program: &Program, input: &[u8],
+           jit_threshold: Option<u64>) -> Run {
+    let mut output = Vec::new();
+    let (tape, pointer) = {
+        let mut vm = Vm::new(program, &mut output, input);
+
+        if let Some(threshold) = jit_threshold {
+            vm = vm.with_jit_threshold(threshold);
+        }
+
+        let _ = vm.run();
+
+        (trimmed(vm.tape()), vm.pointer())
+    };
+
+    Run { backend, output, tape, pointer }
+}
+
+/// Runs `program` unoptimized in the interpreter, then `optimized` in the
+/// interpreter, the VM, and the VM with every loop compiled as soon as it
+/// repeats.
+pub fn run_all(program: &Program, optimized: &Program, input: &[u8])
+-> Vec<Run> {
+    vec![
+        interpret("reference", program, input),
+        interpret("interpreter", optimized, input),
+        execute("vm", optimized, input, None),
+        execute("jit", optimized, input, Some(1)),
+    ]
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// The outputs differ at `offset`; `None` means the output ended.
+    Output {
+        backend: &'static str,
+        offset: usize,
+        expected: Option<u8>,
+        actual: Option<u8>,
+    },
+    Tape {
+        backend: &'static str,
+        index: usize,
+        expected: u32,
+        actual: u32,
+    },
+    Pointer {
+        backend: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+fn byte(b: Option<u8>) -> String {
+    match b {
+        Some(b) => format!("{:?} (0x{:02x})", b as char, b),
+        None => "end of output".to_string(),
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Divergence::Output { backend, offset, expected, actual } => {
+                write!(f, "{}: output differs at byte {}: expected {}, got {}",
+                       backend, offset, byte(expected), byte(actual))
+            }
+            Divergence::Tape { backend, index, expected, actual } => {
+                write!(f, "{}: cell {} differs: expected {}, got {}", backend,
+                       index, expected, actual)
+            }
+            Divergence::Pointer { backend, expected, actual } => {
+                write!(f, "{}: pointer differs: expected {}, got {}", backend,
+                       expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Divergence { }
+
+/// The first way `actual` differs from `expected`, if any.
+pub fn compare(expected: &Run, actual: &Run) -> Option<Divergence> {
+    let backend = actual.backend;
+
+    if expected.output != actual.output {
+        let offset = expected.output.iter().zip(&actual.output)
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| std::cmp::min(expected.output.len(),
+                                             actual.output.len()));
+
+        return Some(Divergence::Output {
+            backend, offset,
+            expected: expected.output.get(offset).cloned(),
+            actual: actual.output.get(offset).cloned(),
+        });
+    }
+
+    if expected.tape != actual.tape {
+        let length = std::cmp::max(expected.tape.len(), actual.tape.len());
+        let cell = |tape: &[u32], i| tape.get(i).cloned().unwrap_or(0);
+        let index = (0..length)
+            .find(|&i| cell(&expected.tape, i) != cell(&actual.tape, i))
+            .unwrap();
+
+        return Some(Divergence::Tape {
+            backend, index,
+            expected: cell(&expected.tape, index),
+            actual: cell(&actual.tape, index),
+        });
+    }
+
+    if expected.pointer != actual.pointer {
+        return Some(Divergence::Pointer { backend,
+                                          expected: expected.pointer,
+                                          actual: actual.pointer });
+    }
+
+    None
+}
+
+/// Runs every backend and returns their runs if they all agree with the
+/// reference, or the first divergence.
+pub fn verify(program: &Program, optimized: &Program, input: &[u8])
+-> Result<Vec<Run>, Divergence> {
+    let runs = run_all(program, optimized, input);
+
+    for run in &runs[1..] {
+        if let Some(divergence) = compare(&runs[0], run) {
+            return Err(divergence);
+        }
+    }
+
+    Ok(runs)
+}
+
+/// How two programs' output compared on one input when each could run for
+/// a limited number of steps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// Both stopped, with the same output.
+    Same,
+    /// The outputs differ at `offset`, where `None` means one's output
+    /// ended there.
+    Differs {
+        offset: usize,
+        first: Option<u8>,
+        second: Option<u8>,
+    },
+    /// One ran out of steps before the outputs could be told apart.
+    Undecided,
+}
+
+/// The output of `program` after at most `max_steps` steps, and whether it
+/// stopped by then.
+fn bounded(program: &Program, input: &[u8], max_steps: u64)
+-> (Vec<u8>, bool) {
+    let mut output = Vec::new();
+    let stopped = {
+        let mut interpreter = InterpreterState::new(program, &mut output,
+                                                    input);
+        let mut steps = 0;
+
+        loop {
+            if steps == max_steps {
+                break false;
+            }
+
+            if interpreter.repl().is_err() {
+                break true;
+            }
+
+            steps += 1;
+        }
+    };
+
+    (output, stopped)
+}
+
+/// Runs both programs on `input` for at most `max_steps` steps each and
+/// compares what they print. A program still running may yet print more,
+/// so its output only settles the comparison where it already differs.
+pub fn compare_bounded(first: &Program, second: &Program, input: &[u8],
+                       max_steps: u64) -> Comparison {
+    let (a, a_stopped) = bounded(first, input, max_steps);
+    let (b, b_stopped) = bounded(second, input, max_steps);
+    let common = std::cmp::min(a.len(), b.len());
+    let offset = (0..common).find(|&i| a[i] != b[i]).or_else(|| {
+        // One printed more than the other, which has stopped.
+        let longer = (a.len() > common && b_stopped)
+            || (b.len() > common && a_stopped);
+
+        if longer { Some(common) } else { None }
+    });
+
+    match offset {
+        Some(offset) => Comparison::Differs {
+            offset,
+            first: a.get(offset).cloned(),
+            second: b.get(offset).cloned(),
+        },
+        None if a_stopped && b_stopped => Comparison::Same,
+        None => Comparison::Undecided,
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Comparison::Same => write!(f, "same output"),
+            Comparison::Differs { offset, first, second } => {
+                write!(f, "output differs at byte {}: the first printed {}, \
+                           the second {}", offset, byte(first), byte(second))
+            }
+            Comparison::Undecided => write!(f, "undecided"),
+        }
+    }
+}
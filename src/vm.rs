@@ -0,0 +1,877 @@
+//! A bytecode VM for running whole programs as fast as possible.
+//!
+//! Unlike `InterpreterState`, which executes one instruction per call so
+//! callers can single-step it, `Vm::run` dispatches in a tight loop until
+//! the program ends, and common pairs of instructions are fused into
+//! superinstructions to make fewer trips through it.
+//!
+//! With a JIT threshold set, the VM also counts how often each loop jumps
+//! back to its start. Once a loop is hot it is compiled to native code, and
+//! its first instruction is patched so later entries run the native loop
+//! instead. Native code returns to the VM whenever it needs a cell outside
+//! the tape, so the VM stays in charge of growing it.
+//!
+//! With memoization on, a loop whose cells are fixed relative to where it
+//! starts, that does no I/O and has loops of its own, remembers what it
+//! left in those cells for each state it started from. Entering it again
+//! from a state it has seen copies the result instead of running it.
+//!
+//! Given a profile from an earlier run, loops that reached the threshold
+//! there are compiled before the program starts, instead of after warming
+//! up again.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+
+use analysis;
+use io::{self, BfInput, BfOutput, OutputEncoding};
+use ir::{Node, Op, Overflow, Program, Span};
+use jit;
+use profile::Profile;
+use random::Rng;
+use tape::{Dense, Tape, Tapes};
+use utf8::Chars;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Instruction {
+    Add(i32),
+    Move(isize),
+    Output,
+    Input,
+    Random,
+    Fork,
+    SwitchTape(isize),
+    Clear,
+    MulAdd(isize, i32),
+    AddAt(isize, i32),
+    Print(usize),
+    Scan(isize),
+    ClearRange(isize),
+    /// Skips to `end` if the current cell is zero.
+    Loop { end: usize, id: usize },
+    /// Jumps back to `start` if the current cell is nonzero.
+    EndLoop { start: usize, id: usize },
+    /// A `Loop` whose body has been compiled to native code.
+    Native { end: usize, id: usize },
+    // Superinstructions, each replacing a common pair to save a dispatch.
+    AddMove(i32, isize),
+    MoveAdd(isize, i32),
+    /// `Clear` followed by `Add`
+    Set(i32),
+    ClearMove(isize),
+}
+
+// Only the JIT looks at these, so they go unused on targets without one.
+#[cfg_attr(not(all(unix, target_arch = "x86_64")), allow(dead_code))]
+impl Instruction {
+    pub(crate) fn is_jump(self) -> bool {
+        matches!(self, Instruction::Loop { .. } | Instruction::EndLoop { .. } |
+                       Instruction::Native { .. })
+    }
+
+    /// The instructions a superinstruction stands for, or this instruction
+    /// alone.
+    pub(crate) fn split(self) -> (Instruction, Option<Instruction>) {
+        match self {
+            Instruction::AddMove(n, m) => {
+                (Instruction::Add(n), Some(Instruction::Move(m)))
+            }
+            Instruction::MoveAdd(m, n) => {
+                (Instruction::Move(m), Some(Instruction::Add(n)))
+            }
+            Instruction::Set(n) => {
+                (Instruction::Clear, Some(Instruction::Add(n)))
+            }
+            Instruction::ClearMove(m) => {
+                (Instruction::Clear, Some(Instruction::Move(m)))
+            }
+            i => (i, None),
+        }
+    }
+
+    fn superinstruction(self) -> Option<&'static str> {
+        match self {
+            Instruction::AddMove(..) => Some("add+move"),
+            Instruction::MoveAdd(..) => Some("move+add"),
+            Instruction::Set(_) => Some("clear+add"),
+            Instruction::ClearMove(_) => Some("clear+move"),
+            _ => None,
+        }
+    }
+}
+
+fn fuse(first: Instruction, second: Instruction) -> Option<Instruction> {
+    match (first, second) {
+        (Instruction::Add(n), Instruction::Move(m)) => {
+            Some(Instruction::AddMove(n, m))
+        }
+        (Instruction::Move(m), Instruction::Add(n)) => {
+            Some(Instruction::MoveAdd(m, n))
+        }
+        (Instruction::Clear, Instruction::Add(n)) => Some(Instruction::Set(n)),
+        (Instruction::Clear, Instruction::Move(m)) => {
+            Some(Instruction::ClearMove(m))
+        }
+        _ => None,
+    }
+}
+
+// The most cells a memoized loop may touch.
+const MAX_MEMO_CELLS: isize = 16;
+
+// The most results a memoized loop keeps.
+const MAX_MEMO_RESULTS: usize = 1 << 12;
+
+/// What a loop left in the cells from `low` to `high`, relative to its loop
+/// cell, for each state of them it started from.
+struct Memo {
+    low: isize,
+    high: isize,
+    results: BTreeMap<Vec<u32>, Vec<u32>>,
+    /// The state the loop started from, while running it for the first
+    /// time.
+    pending: Option<Vec<u32>>,
+}
+
+struct LoopInfo {
+    start: usize,
+    end: usize,
+    iterations: u64,
+    /// Iterations in the profile given to `Vm::with_profile`.
+    profiled: u64,
+    native: Option<jit::Native>,
+    /// Set for loops that can be memoized.
+    memo: Option<Memo>,
+}
+
+struct Io<R, W>
+where R: BfInput, W: BfOutput {
+    writer: W,
+    input: Chars<R>,
+    texts: Vec<Vec<u32>>,
+    rng: Rng,
+    encoding: OutputEncoding,
+    /// Bytes of output left before the limit, if there is one.
+    output_left: Option<u64>,
+    error: Option<io::Error>,
+}
+
+impl<R, W> Io<R, W>
+where R: BfInput, W: BfOutput {
+    fn write_value(&mut self, value: u32) -> io::Result<()> {
+        io::write_value(&mut self.writer, value, self.encoding,
+                        &mut self.output_left)?;
+
+        Ok(())
+    }
+
+    fn print(&mut self, text: usize) -> io::Result<()> {
+        for i in 0..self.texts[text].len() {
+            let value = self.texts[text][i];
+
+            self.write_value(value)?;
+        }
+
+        Ok(())
+    }
+
+    fn read(&mut self) -> io::Result<u32> {
+        match self.input.next() {
+            Some(r) => Ok(r? as u32),
+            None => Err(io::end_of_input()),
+        }
+    }
+}
+
+extern "C" fn random_callback<R, W>(io: *mut c_void) -> u32
+where R: BfInput, W: BfOutput {
+    let io = unsafe { &mut *(io as *mut Io<R, W>) };
+
+    io.rng.byte() as u32
+}
+
+extern "C" fn output_callback<R, W>(io: *mut c_void, value: u32) -> i64
+where R: BfInput, W: BfOutput {
+    let io = unsafe { &mut *(io as *mut Io<R, W>) };
+
+    match io.write_value(value) {
+        Ok(()) => 0,
+        Err(e) => {
+            io.error = Some(e);
+
+            -1
+        }
+    }
+}
+
+extern "C" fn print_callback<R, W>(io: *mut c_void, text: u64) -> i64
+where R: BfInput, W: BfOutput {
+    let io = unsafe { &mut *(io as *mut Io<R, W>) };
+
+    match io.print(text as usize) {
+        Ok(()) => 0,
+        Err(e) => {
+            io.error = Some(e);
+
+            -1
+        }
+    }
+}
+
+extern "C" fn input_callback<R, W>(io: *mut c_void) -> i64
+where R: BfInput, W: BfOutput {
+    let io = unsafe { &mut *(io as *mut Io<R, W>) };
+
+    match io.read() {
+        Ok(value) => value as i64,
+        Err(e) => {
+            io.error = Some(e);
+
+            -1
+        }
+    }
+}
+
+fn memo(body: &[Node]) -> Option<Memo> {
+    if !body.iter().any(|n| matches!(n.op, Op::Loop(_))) {
+        return None;
+    }
+
+    let (low, high) = analysis::footprint(body)?;
+
+    if high - low >= MAX_MEMO_CELLS {
+        return None;
+    }
+
+    Some(Memo { low, high, results: BTreeMap::new(), pending: None })
+}
+
+fn flatten(nodes: &[Node], code: &mut Vec<Instruction>, spans: &mut Vec<Span>,
+           loops: &mut Vec<LoopInfo>, texts: &mut Vec<Vec<u32>>) {
+    for node in nodes {
+        let instruction = match node.op {
+            Op::Add(n) => Instruction::Add(n),
+            Op::Move(n) => Instruction::Move(n),
+            Op::Output => Instruction::Output,
+            Op::Input => Instruction::Input,
+            Op::Random => Instruction::Random,
+            Op::Fork => Instruction::Fork,
+            Op::SwitchTape(n) => Instruction::SwitchTape(n),
+            Op::Clear => Instruction::Clear,
+            Op::MulAdd { offset, factor } => {
+                Instruction::MulAdd(offset, factor)
+            }
+            Op::AddAt { offset, amount } => Instruction::AddAt(offset, amount),
+            Op::Scan(step) => Instruction::Scan(step),
+            Op::ClearRange(extent) => Instruction::ClearRange(extent),
+            Op::Print(ref values) => {
+                texts.push(values.clone());
+
+                Instruction::Print(texts.len() - 1)
+            }
+            Op::Loop(ref body) => {
+                let start = code.len();
+                let id = loops.len();
+
+                loops.push(LoopInfo { start, end: 0, iterations: 0,
+                                      profiled: 0, native: None,
+                                      memo: memo(body) });
+                code.push(Instruction::Loop { end: 0, id });
+                spans.push(Span::new(node.span.start, node.span.start + 1));
+
+                flatten(body, code, spans, loops, texts);
+
+                let end = code.len();
+
+                code.push(Instruction::EndLoop { start, id });
+                spans.push(Span::new(node.span.end - 1, node.span.end));
+                code[start] = Instruction::Loop { end, id };
+                loops[id].end = end;
+
+                continue;
+            }
+        };
+
+        let fused = code.last().and_then(|&last| fuse(last, instruction));
+
+        match fused {
+            Some(f) => {
+                *code.last_mut().unwrap() = f;
+
+                let last = spans.last_mut().unwrap();
+
+                *last = last.to(node.span);
+            }
+            None => {
+                code.push(instruction);
+                spans.push(node.span);
+            }
+        }
+    }
+}
+
+pub struct Vm<R, W>
+where R: BfInput, W: BfOutput {
+    tape: Dense,
+    pointer: usize,
+    /// The tapes other than `tape`, with the `tapes` extension.
+    tapes: Tapes<Dense>,
+    io: Io<R, W>,
+    code: Vec<Instruction>,
+    /// Where in the source each instruction in `code` came from.
+    spans: Vec<Span>,
+    loops: Vec<LoopInfo>,
+    jit_threshold: Option<u64>,
+    memoize: bool,
+    overflow: Overflow,
+    /// The instruction the program stopped at with an error.
+    failed: Option<usize>,
+}
+
+impl<R, W> Vm<R, W>
+where R: BfInput, W: BfOutput {
+    /// Adds to cells as `program.overflow` says. Native code only wraps, so
+    /// under another policy no loop is compiled, whatever the JIT threshold.
+    pub fn new(program: &Program, writer: W, input_reader: R) -> Vm<R, W> {
+        let mut code = Vec::new();
+        let mut spans = Vec::new();
+        let mut loops = Vec::new();
+        let mut texts = Vec::new();
+
+        flatten(&program.nodes, &mut code, &mut spans, &mut loops,
+                &mut texts);
+
+        Vm { tape: Dense::new(), pointer: 0, tapes: Tapes::default(),
+             io: Io { writer, input: Chars::new(input_reader), texts,
+                      rng: Rng::new(0), encoding: OutputEncoding::Utf8,
+                      output_left: None, error: None },
+             code, spans, loops, jit_threshold: None, memoize: false,
+             overflow: program.overflow, failed: None }
+    }
+
+    /// Compiles loops to native code once they have jumped back to their
+    /// start `threshold` times (at least once). Has no effect on targets
+    /// without a JIT.
+    pub fn with_jit_threshold(mut self, threshold: u64) -> Vm<R, W> {
+        self.jit_threshold = Some(core::cmp::max(1, threshold));
+
+        self
+    }
+
+    /// Memoizes the loops that can be, which run in the VM from then on
+    /// rather than being compiled. Worth it when the same loop keeps
+    /// starting from the same few states.
+    pub fn with_memoization(mut self) -> Vm<R, W> {
+        self.memoize = true;
+
+        self
+    }
+
+    /// Compiles the loops that reached the JIT threshold in `profile` as
+    /// soon as the program starts. Loops the profile doesn't cover are
+    /// left to warm up as usual.
+    pub fn with_profile(mut self, profile: &Profile) -> Vm<R, W> {
+        for (info, &count) in self.loops.iter_mut()
+            .zip(&profile.iterations) {
+            info.profiled = count;
+        }
+
+        self
+    }
+
+    /// Sets what `.` writes for a cell, which is otherwise its character in
+    /// UTF-8.
+    pub fn with_output_encoding(mut self, encoding: OutputEncoding)
+    -> Vm<R, W> {
+        self.io.encoding = encoding;
+
+        self
+    }
+
+    /// Seeds the generator `?` reads from, which otherwise starts from 0, so
+    /// the same seed gives the same run.
+    pub fn with_seed(mut self, seed: u64) -> Vm<R, W> {
+        self.io.rng = Rng::new(seed);
+
+        self
+    }
+
+    /// Starts the tape with `cells` as its first cells, rather than all
+    /// zeros. A tape limit set afterwards cuts off any cells past it.
+    pub fn with_tape(mut self, cells: &[u32]) -> Vm<R, W> {
+        self.tape.load(cells);
+
+        self
+    }
+
+    /// Stops the program with an `OutOfMemory` error, rather than growing
+    /// the tape past `cells` cells, if it writes to a cell past them.
+    pub fn with_tape_limit(mut self, cells: usize) -> Vm<R, W> {
+        self.tape.set_limit(cells);
+
+        self
+    }
+
+    /// Stops the program with a `QuotaExceeded` error, rather than writing
+    /// more than `bytes` bytes of output, as `InterpreterState` does.
+    pub fn with_output_limit(mut self, bytes: u64) -> Vm<R, W> {
+        self.io.output_left = Some(bytes);
+
+        self
+    }
+
+    pub fn tape(&self) -> &[u32] {
+        &self.tape
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    /// Source span of the instruction `run` stopped at with an error, if it
+    /// did. That of a compiled loop is the whole loop, since native code
+    /// doesn't say where in it the error was.
+    pub fn span(&self) -> Option<Span> {
+        let ip = self.failed?;
+
+        match self.code[ip] {
+            Instruction::Native { end, .. } => {
+                Some(self.spans[ip].to(self.spans[end]))
+            }
+            _ => Some(self.spans[ip]),
+        }
+    }
+
+    /// How many times each loop has jumped back to its start. A compiled
+    /// loop stops counting, having already reached the JIT threshold.
+    pub fn profile(&self) -> Profile {
+        Profile { iterations: self.loops.iter().map(|l| l.iterations)
+                                  .collect() }
+    }
+
+    /// How many of each superinstruction the program was compiled to.
+    pub fn fusion_stats(&self) -> Vec<(&'static str, usize)> {
+        let mut stats: Vec<(&'static str, usize)> = Vec::new();
+
+        for name in self.code.iter().filter_map(|i| i.superinstruction()) {
+            match stats.iter_mut().find(|s| s.0 == name) {
+                Some(s) => s.1 += 1,
+                None => stats.push((name, 1)),
+            }
+        }
+
+        stats.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        stats
+    }
+
+    fn cell(&self, index: usize) -> u32 {
+        self.tape.get(index)
+    }
+
+    fn cell_mut(&mut self, index: usize) -> io::Result<&mut u32> {
+        self.tape.get_mut(index)
+    }
+
+    /// Adds `amount` to a cell, as the overflow policy says.
+    fn add(&mut self, offset: isize, amount: i64) -> io::Result<()> {
+        let index = self.pointer.wrapping_add(offset as usize);
+        let overflow = self.overflow;
+        let cell = self.cell_mut(index)?;
+
+        *cell = overflow.add(*cell, amount)
+            .ok_or_else(|| io::cell_overflow(amount < 0))?;
+
+        Ok(())
+    }
+
+    fn memoized(&self, id: usize) -> bool {
+        self.memoize && self.loops[id].memo.is_some()
+    }
+
+    /// The cells a memoized loop touches, or `None` if some are left of the
+    /// first cell.
+    fn memo_cells(&self, id: usize) -> Option<Vec<u32>> {
+        let memo = self.loops[id].memo.as_ref().unwrap();
+        let low = (self.pointer as isize).checked_add(memo.low)?;
+
+        if low < 0 {
+            return None;
+        }
+
+        Some((memo.low..=memo.high)
+             .map(|o| self.cell(self.pointer.wrapping_add(o as usize)))
+             .collect())
+    }
+
+    /// Copies what a memoized loop left from the state it is starting from,
+    /// returning whether it had seen that state. Otherwise the state is
+    /// kept for `remember` when the loop ends.
+    fn recall(&mut self, id: usize) -> io::Result<bool> {
+        let cells = match self.memo_cells(id) {
+            Some(cells) => cells,
+            None => return Ok(false),
+        };
+        let memo = self.loops[id].memo.as_mut().unwrap();
+        let result = match memo.results.get(&cells) {
+            Some(result) => result.clone(),
+            None => {
+                memo.pending = Some(cells);
+
+                return Ok(false);
+            }
+        };
+        let low = memo.low;
+
+        for (i, (&old, &new)) in cells.iter().zip(&result).enumerate() {
+            if old != new {
+                let index = self.pointer.wrapping_add((low + i as isize)
+                                                      as usize);
+
+                *self.cell_mut(index)? = new;
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        ::tracing::trace!(id, "recalled loop");
+
+        Ok(true)
+    }
+
+    fn remember(&mut self, id: usize) {
+        let cells = self.memo_cells(id);
+        let memo = self.loops[id].memo.as_mut().unwrap();
+
+        if let (Some(state), Some(cells)) = (memo.pending.take(), cells) {
+            if memo.results.len() < MAX_MEMO_RESULTS {
+                memo.results.insert(state, cells);
+            }
+        }
+    }
+
+    fn compile(&mut self, id: usize) {
+        if self.overflow != Overflow::Wrap {
+            return;
+        }
+
+        let callbacks = jit::Callbacks {
+            output: output_callback::<R, W> as *const () as usize,
+            print: print_callback::<R, W> as *const () as usize,
+            input: input_callback::<R, W> as *const () as usize,
+            random: random_callback::<R, W> as *const () as usize,
+        };
+        let start = self.loops[id].start;
+
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!(id, iterations = self.loops[id].iterations,
+                          "compiling loop");
+
+        if let Some(native) = jit::compile(&self.code, start, &callbacks) {
+            self.loops[id].native = Some(native);
+            self.code[start] = Instruction::Native { end: self.loops[id].end,
+                                                     id };
+        }
+    }
+
+    /// Runs a compiled loop, returning the instruction to continue at.
+    fn run_native(&mut self, id: usize, end: usize)
+    -> io::Result<usize> {
+        let (cells, origin) = self.tape.all_mut();
+        let current = origin.wrapping_add(self.pointer);
+
+        // Cells off the tape are zero, so the loop wouldn't run.
+        if current >= cells.len() {
+            return Ok(end + 1);
+        }
+
+        let begin = cells.as_mut_ptr();
+        let mut context = jit::Context {
+            begin,
+            end: unsafe { begin.add(cells.len()) },
+            pointer: core::ptr::null_mut(),
+            resume: 0,
+            io: &mut self.io as *mut Io<R, W> as *mut c_void,
+        };
+        let native = self.loops[id].native.as_ref().unwrap();
+        let status = unsafe {
+            native.call(begin.add(current), &mut context)
+        };
+
+        self.pointer = (((context.pointer as isize)
+                         .wrapping_sub(begin as isize) / 4) as usize)
+            .wrapping_sub(origin);
+
+        match status {
+            jit::FINISHED => Ok(end + 1),
+            jit::RESUME => Ok(context.resume as usize),
+            _ => Err(self.io.error.take().unwrap()),
+        }
+    }
+
+    /// Runs the program to completion, or until reading input fails or the
+    /// tape reaches its limit, after which `span` says where it stopped.
+    pub fn run(&mut self) -> io::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::info_span!("execute", backend = "vm",
+                                          instructions = self.code.len())
+            .entered();
+        let mut ip = 0;
+
+        if let Some(threshold) = self.jit_threshold {
+            for id in 0..self.loops.len() {
+                if self.loops[id].profiled >= threshold
+                   && self.loops[id].native.is_none() && !self.memoized(id) {
+                    self.compile(id);
+                }
+            }
+        }
+
+        let result = self.execute(&mut ip);
+
+        self.failed = result.as_ref().err().map(|_| ip);
+
+        result
+    }
+
+    /// Runs the program from instruction `ip`, leaving `ip` at the
+    /// instruction that failed, if one did.
+    fn execute(&mut self, ip: &mut usize) -> io::Result<()> {
+        while let Some(&instruction) = self.code.get(*ip) {
+            match instruction {
+                Instruction::Add(n) => self.add(0, n as i64)?,
+                Instruction::Move(n) => {
+                    self.pointer = self.pointer.wrapping_add(n as usize);
+                }
+                Instruction::Output => {
+                    let value = self.cell(self.pointer);
+
+                    self.io.write_value(value)?;
+                }
+                Instruction::Input => {
+                    let value = self.io.read()?;
+
+                    *self.cell_mut(self.pointer)? = value;
+                }
+                Instruction::Random => {
+                    let value = self.io.rng.byte();
+
+                    *self.cell_mut(self.pointer)? = value as u32;
+                }
+                Instruction::SwitchTape(n) => {
+                    self.tapes.switch(n, &mut self.tape, &mut self.pointer)?
+                }
+                Instruction::Fork => {
+                    return Err(io::Error::other("Y needs the interpreter"));
+                }
+                Instruction::Clear => *self.cell_mut(self.pointer)? = 0,
+                Instruction::MulAdd(offset, factor) => {
+                    let value = self.cell(self.pointer);
+
+                    if value != 0 {
+                        self.add(offset, value as i64 * factor as i64)?;
+                    }
+                }
+                Instruction::AddAt(offset, amount) => {
+                    self.add(offset, amount as i64)?
+                }
+                Instruction::Print(text) => self.io.print(text)?,
+                Instruction::Scan(step) => {
+                    self.pointer = self.tape.scan(self.pointer, step)?;
+                }
+                Instruction::ClearRange(extent) => {
+                    self.tape.clear_range(self.pointer, extent)?;
+                    self.pointer = self.pointer.wrapping_add(extent as usize);
+                }
+                Instruction::AddMove(n, m) => {
+                    self.add(0, n as i64)?;
+                    self.pointer = self.pointer.wrapping_add(m as usize);
+                }
+                Instruction::MoveAdd(m, n) => {
+                    self.pointer = self.pointer.wrapping_add(m as usize);
+                    self.add(0, n as i64)?;
+                }
+                Instruction::Set(n) => {
+                    *self.cell_mut(self.pointer)? = 0;
+                    self.add(0, n as i64)?;
+                }
+                Instruction::ClearMove(m) => {
+                    *self.cell_mut(self.pointer)? = 0;
+                    self.pointer = self.pointer.wrapping_add(m as usize);
+                }
+                Instruction::Loop { end, id } => {
+                    if self.cell(self.pointer) == 0
+                       || (self.memoized(id) && self.recall(id)?) {
+                        *ip = end;
+                    }
+                }
+                Instruction::EndLoop { start, id } => {
+                    if self.cell(self.pointer) != 0 {
+                        *ip = start;
+                        self.loops[id].iterations += 1;
+                        #[cfg(feature = "tracing")]
+                        ::tracing::trace!(id, iterations = self.loops[id]
+                                                               .iterations,
+                                          "loop iteration");
+
+                        if Some(self.loops[id].iterations)
+                            == self.jit_threshold && !self.memoized(id) {
+                            self.compile(id);
+
+                            continue;
+                        }
+                    } else if self.memoized(id) {
+                        self.remember(id);
+                    }
+                }
+                Instruction::Native { end, id } => {
+                    *ip = self.run_native(id, end)?;
+
+                    continue;
+                }
+            }
+
+            *ip += 1;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frontend::Dialect;
+    use interpreter::InterpreterState;
+    use ir::{Overflow, Program, Span};
+    use optimize::Registry;
+    use tape::INITIAL_CELLS;
+    use super::Vm;
+
+    /// The bundled programs, and what each reads.
+    const PROGRAMS: &[(&str, &str, &str)] = &[
+        ("hello_world", include_str!("../hello_world.bf"), ""),
+        ("hello_world_2", include_str!("../hello_world_2.bf"), ""),
+        ("392quine", include_str!("../392quine.bf"), ""),
+        ("rot13", include_str!("../rot13.bf"), "Hello, World!\n"),
+        ("sum", include_str!("../sum.bf"), ""),
+        ("io_test", include_str!("../io_test.bf"), "ab\n"),
+        ("squares", include_str!("bin/bfi/bench/squares.bf"), ""),
+        ("sierpinski", include_str!("bin/bfi/bench/sierpinski.bf"), ""),
+        ("loops", include_str!("bin/bfi/bench/loops.bf"), ""),
+    ];
+
+    /// What a run left behind: the output, the cells left of the first and
+    /// those from it on, each without the zeros at its far end, and the
+    /// pointer.
+    #[derive(Debug, PartialEq, Eq)]
+    struct Run {
+        output: Vec<u8>,
+        left: Vec<u32>,
+        right: Vec<u32>,
+        pointer: usize,
+    }
+
+    impl Run {
+        fn new(output: Vec<u8>, left: &[u32], right: &[u32], pointer: usize)
+        -> Run {
+            let left = left.iter().position(|&c| c != 0)
+                .map_or(&[][..], |i| &left[i..]);
+            let length = right.iter().rposition(|&c| c != 0)
+                .map_or(0, |i| i + 1);
+
+            Run { output, left: left.to_vec(),
+                  right: right[..length].to_vec(), pointer }
+        }
+    }
+
+    fn interpret(program: &Program, input: &str) -> Run {
+        let mut output = Vec::new();
+        let mut interpreter = InterpreterState::new(program, &mut output,
+                                                    input.as_bytes());
+
+        while interpreter.repl().is_ok() { }
+
+        let tape = interpreter.tape();
+        let (left, right) = (tape.left().to_vec(), tape.to_vec());
+        let pointer = interpreter.pointer();
+
+        drop(interpreter);
+
+        Run::new(output, &left, &right, pointer)
+    }
+
+    fn execute(program: &Program, input: &str, jit_threshold: Option<u64>)
+    -> Run {
+        let mut output = Vec::new();
+        let mut vm = Vm::new(program, &mut output, input.as_bytes());
+
+        if let Some(threshold) = jit_threshold {
+            vm = vm.with_jit_threshold(threshold);
+        }
+
+        let _ = vm.run();
+
+        let (left, right) = (vm.tape.left().to_vec(), vm.tape.to_vec());
+        let pointer = vm.pointer;
+
+        drop(vm);
+
+        Run::new(output, &left, &right, pointer)
+    }
+
+    /// Checks that the interpreter, the VM and the VM compiling every loop
+    /// that repeats all do what the unoptimized interpreter does with
+    /// `source`, at every optimization level.
+    fn agree(name: &str, source: &str, input: &str) {
+        let program = Dialect::Brainfuck.parse(source).unwrap();
+        let expected = interpret(&program, input);
+        let registry = Registry::default();
+
+        for level in 0..4 {
+            let optimized = registry.preset(level).run(program.clone());
+
+            assert_eq!(interpret(&optimized, input), expected,
+                       "{} on the interpreter at -O{}", name, level);
+            assert_eq!(execute(&optimized, input, None), expected,
+                       "{} on the VM at -O{}", name, level);
+            assert_eq!(execute(&optimized, input, Some(1)), expected,
+                       "{} on the tiered VM at -O{}", name, level);
+        }
+    }
+
+    #[test]
+    fn bundled_programs() {
+        for &(name, source, input) in PROGRAMS {
+            agree(name, source, input);
+        }
+    }
+
+    #[test]
+    fn growing_left() {
+        // Carries a counter left a cell at a time, so that compiled loops
+        // have to hand back to the VM to grow the tape.
+        agree("walk", "++++++++[<++++++++>-]<[[-<+>]<-]", "");
+        agree("scan", "+<+<+<+<+[>]<[<]<<+", "");
+        agree("clear", "<+<+<+<+>>>[-]<[-]<[-]<[-]", "");
+    }
+
+    #[test]
+    fn growing_right() {
+        // Starts just short of the cells the tape first has.
+        let far = ">".repeat(INITIAL_CELLS - 6);
+
+        agree("walk", &(far.clone() + "++++++++++[[->+<]>-]"), "");
+        agree("scan", &(far + "+>+>+>+>+>+>+>+<<<<<<<[>]<[<]>+"), "");
+        agree("clear", ">+>+>+>+<<<[-]>[-]>[-]>[-]", "");
+    }
+
+    #[test]
+    fn failing_span() {
+        let program = Dialect::Brainfuck.parse("+>-+").unwrap()
+            .with_overflow(Overflow::Trap);
+        let mut vm = Vm::new(&program, Vec::new(), &b""[..]);
+
+        assert!(vm.run().is_err());
+        assert_eq!(vm.span(), Some(Span::new(2, 3)));
+    }
+}
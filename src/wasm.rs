@@ -0,0 +1,107 @@
+//! JavaScript bindings for a browser playground, enabled by the `wasm`
+//! feature.
+//!
+//! `Session` runs a program a slice at a time so the page can stay
+//! responsive between slices, handing each slice's output to a callback.
+
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use js_sys::{Function, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+use frontend::Dialect;
+use interpreter::InterpreterState;
+use io::{self, BfOutput};
+use optimize::Registry;
+
+/// Output collected since it was last handed to JavaScript.
+#[derive(Clone, Default)]
+struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl BfOutput for Buffer {
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+
+        Ok(())
+    }
+}
+
+fn interpreter(source: &str, input: &[u8], output: Buffer)
+-> Result<InterpreterState<Cursor<Vec<u8>>, Buffer>, JsValue> {
+    let program = Dialect::Brainfuck.parse(source)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let program = Registry::default().preset(2).run(program);
+
+    Ok(InterpreterState::new(&program, output, Cursor::new(input.to_vec())))
+}
+
+/// Runs `source` to completion on `input`, returning everything it printed.
+#[wasm_bindgen]
+pub fn run(source: &str, input: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let output = Buffer::default();
+    let mut interpreter = interpreter(source, input, output.clone())?;
+
+    while interpreter.repl().is_ok() { }
+
+    let bytes = output.0.borrow().clone();
+
+    Ok(bytes)
+}
+
+/// A program being run incrementally.
+#[wasm_bindgen]
+pub struct Session {
+    interpreter: InterpreterState<Cursor<Vec<u8>>, Buffer>,
+    output: Buffer,
+    on_output: Function,
+    running: bool,
+}
+
+#[wasm_bindgen]
+impl Session {
+    /// Loads `source` to run on `input`, calling `on_output` with a
+    /// `Uint8Array` of whatever each call to `step` printed.
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str, input: &[u8], on_output: Function)
+    -> Result<Session, JsValue> {
+        let output = Buffer::default();
+        let interpreter = interpreter(source, input, output.clone())?;
+
+        Ok(Session { interpreter, output, on_output, running: true })
+    }
+
+    /// Runs up to `steps` instructions, returning whether the program is
+    /// still running afterwards.
+    pub fn step(&mut self, steps: u32) -> Result<bool, JsValue> {
+        for _ in 0..steps {
+            if !self.running {
+                break;
+            }
+
+            self.running = self.interpreter.repl().is_ok();
+        }
+
+        let bytes = std::mem::take(&mut *self.output.0.borrow_mut());
+
+        if !bytes.is_empty() {
+            self.on_output.call1(&JsValue::NULL,
+                                 &Uint8Array::from(&bytes[..]))?;
+        }
+
+        Ok(self.running)
+    }
+
+    /// The value of every cell the program has touched so far.
+    pub fn tape(&self) -> Vec<u32> {
+        let tape = self.interpreter.tape();
+        let length = tape.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1);
+
+        tape[..length].to_vec()
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.interpreter.pointer()
+    }
+}